@@ -0,0 +1,6 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+"$TOAST" --read-local-cache false --write-local-cache false > output.txt
+grep '1\.2\.3' output.txt
+rm output.txt
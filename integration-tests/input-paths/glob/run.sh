@@ -0,0 +1,15 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+mkdir -p foo/baz
+echo qux > foo/qux.txt
+echo quux > foo/baz/quux.txt
+echo corge > foo/baz/corge.md
+"$TOAST" --read-local-cache false --write-local-cache false > output.txt
+grep 'foo/qux\.txt' output.txt
+grep 'foo/baz/quux\.txt' output.txt
+if grep 'corge' output.txt; then
+  exit 1
+fi
+rm output.txt
+rm -r foo
@@ -0,0 +1,12 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+# Paths in the toastfile should be relative to the toastfile, not the working directory.
+(cd foo/bar && "$TOAST" --read-local-cache false --write-local-cache false)
+grep Hello output.txt
+rm output.txt
+
+# The same should hold when the toastfile is given as a relative path.
+(cd foo && "$TOAST" --file ../toast.yml --read-local-cache false --write-local-cache false)
+grep Hello output.txt
+rm output.txt
@@ -1,67 +1,153 @@
-use crate::{failure, failure::Failure, toastfile::Task};
+use crate::{json::Value, toastfile::Command, toastfile::Task};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
+    borrow::Cow,
     collections::HashMap,
     io,
-    io::Read,
+    io::{ErrorKind, Read},
     path::{Path, PathBuf},
+    str::from_utf8,
 };
 
 #[cfg(unix)]
 use std::os::unix::ffi::OsStrExt;
 
-#[cfg(windows)]
-use std::os::windows::ffi::OsStrExt;
-
-// Bump this if we need to invalidate all existing caches for some reason.
+// Bump this if we need to invalidate all existing caches for some reason. [tag:cache_version]
 const CACHE_VERSION: usize = 0;
 
+// We add this prefix to cache keys because Docker has a rule that tags cannot be 64-byte
+// hexadecimal strings. See this for more details: https://github.com/moby/moby/issues/20972
+const IMAGE_TAG_PREFIX: &str = "toast-";
+
+// The length of a hash in hexadecimal
+const HASH_LENGTH: usize = 64;
+
+// The maximum number of bytes `hash_read_incremental` hashes between calls to its callback
+const HASH_CHUNK_SIZE: usize = 64 * 1024;
+
+// The block size of SHA-256 in bytes, which determines how keys are padded for HMAC
+const HMAC_BLOCK_SIZE: usize = 64;
+
+// The alphabet of the URL-safe variant of Base64 (RFC 4648, section 5)
+const BASE64URL_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// How cache keys are encoded in image tags. The encodings produce keys of different lengths, so a
+// key in one encoding never matches a key in another. Switching encodings just causes cache misses.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyEncoding {
+    Hex,       // 64 characters
+    Base64Url, // 43 characters, without padding
+}
+
 // This trait is implemented by things we can take a cryptographic hash of, such as strings and
 // paths.
 pub trait CryptoHash {
-    // Compute a cryptographic hash. The guarantees:
+    // Compute a cryptographic hash in hexadecimal without allocating. The guarantees:
     //   1. For all `x`, `hash_str(x)` = `hash_str(x)`.
     //   1. For all known `x` and `y`, `x` != `y` implies `hash_str(x)` != `hash_str(y)`.
-    fn crypto_hash(&self) -> String;
+    fn crypto_hash_hex(&self) -> [u8; HASH_LENGTH];
+
+    // Compute a cryptographic hash as a string. The guarantees are the same as above.
+    fn crypto_hash(&self) -> String {
+        // The `unwrap` is safe since hexadecimal digits are valid UTF-8.
+        from_utf8(&self.crypto_hash_hex()).unwrap().to_owned()
+    }
+}
+
+// Encode some bytes with the URL-safe variant of Base64, without padding.
+fn base64url(bytes: &[u8]) -> String {
+    let mut result = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0_u32, |group, (i, byte)| {
+            group | u32::from(*byte) << (16 - 8 * i)
+        });
+
+        // Each byte contributes 8 bits, so a chunk of `n` bytes needs `n + 1` characters.
+        for i in 0..=chunk.len() {
+            result.push(char::from(
+                BASE64URL_ALPHABET[(group >> (18 - 6 * i) & 0b11_1111) as usize],
+            ));
+        }
+    }
+
+    result
+}
+
+// Compute the hash of some bytes in hexadecimal.
+fn hash_bytes(bytes: &[u8]) -> [u8; HASH_LENGTH] {
+    hex_digest(Sha256::digest(bytes))
+}
+
+// Encode a SHA-256 digest in hexadecimal.
+fn hex_digest<T: AsRef<[u8]>>(digest: T) -> [u8; HASH_LENGTH] {
+    let mut hex = [0; HASH_LENGTH];
+
+    // The `unwrap` is safe since a SHA-256 digest is exactly half as long as its encoding.
+    hex::encode_to_slice(digest, &mut hex).unwrap();
+
+    hex
 }
 
 impl CryptoHash for str {
-    fn crypto_hash(&self) -> String {
-        hex::encode(Sha256::digest(self.as_bytes()))
+    fn crypto_hash_hex(&self) -> [u8; HASH_LENGTH] {
+        hash_bytes(self.as_bytes())
     }
 }
 
 impl CryptoHash for String {
-    fn crypto_hash(&self) -> String {
-        hex::encode(Sha256::digest(self.as_bytes()))
+    fn crypto_hash_hex(&self) -> [u8; HASH_LENGTH] {
+        hash_bytes(self.as_bytes())
     }
 }
 
 #[cfg(unix)]
-fn path_as_bytes(path: &Path) -> Vec<u8> {
-    path.as_os_str().as_bytes().to_vec()
+fn path_as_bytes(path: &Path) -> Cow<'_, [u8]> {
+    Cow::Borrowed(path.as_os_str().as_bytes())
 }
 
+// On Windows, paths are hashed as UTF-8 with forward slashes, which is how they're written in
+// archives and how they'd be spelled on other platforms, so the cache keys for the same files are
+// the same everywhere. Paths from the host which aren't valid Unicode are rejected before they get
+// here [ref:windows_paths_unicode], and the rest come from the toastfile, which is Unicode.
 #[cfg(windows)]
-fn path_as_bytes(path: &Path) -> Vec<u8> {
-    path.as_os_str()
-        .encode_wide()
-        .flat_map(|c| c.to_le_bytes())
-        .collect()
+fn path_as_bytes(path: &Path) -> Cow<'_, [u8]> {
+    Cow::Owned(path.to_string_lossy().replace('\\', "/").into_bytes())
 }
 
 impl CryptoHash for Path {
-    fn crypto_hash(&self) -> String {
-        hex::encode(Sha256::digest(&path_as_bytes(&self)))
+    fn crypto_hash_hex(&self) -> [u8; HASH_LENGTH] {
+        hash_bytes(&path_as_bytes(self))
     }
 }
 
 impl CryptoHash for PathBuf {
-    fn crypto_hash(&self) -> String {
-        hex::encode(Sha256::digest(&path_as_bytes(&self)))
+    fn crypto_hash_hex(&self) -> [u8; HASH_LENGTH] {
+        hash_bytes(&path_as_bytes(self))
+    }
+}
+
+// A hash in hexadecimal which lives on the stack. Hashing it is equivalent to hashing the
+// corresponding string, so it can stand in for an intermediate result when computing another hash.
+struct Hash([u8; HASH_LENGTH]);
+
+impl CryptoHash for Hash {
+    fn crypto_hash_hex(&self) -> [u8; HASH_LENGTH] {
+        hash_bytes(&self.0)
     }
 }
 
+// This is like `combine`, except the result lives on the stack.
+fn combine_hash<X: CryptoHash + ?Sized, Y: CryptoHash + ?Sized>(x: &X, y: &Y) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(x.crypto_hash_hex());
+    hasher.update(y.crypto_hash_hex());
+    Hash(hex_digest(hasher.finalize()))
+}
+
 // Combine two strings into a hash. The guarantees:
 //   1. For all `x` and `y`, `combine(x, y)` = `combine(x, y)`.
 //   2. For all known `x1`, `x2`, `y1`, and `y2`,
@@ -69,38 +155,179 @@ impl CryptoHash for PathBuf {
 //   3. For all known `x1`, `x2`, `y1`, and `y2`,
 //      `y1` != `y2` implies `combine(x1, y1)` != `combine(x2, y2)`.
 pub fn combine<X: CryptoHash + ?Sized, Y: CryptoHash + ?Sized>(x: &X, y: &Y) -> String {
-    format!("{}{}", x.crypto_hash(), y.crypto_hash()).crypto_hash()
+    // The `unwrap` is safe since hexadecimal digits are valid UTF-8.
+    from_utf8(&combine_hash(x, y).0).unwrap().to_owned()
 }
 
 // Compute a cryptographic hash of a readable object (e.g., a file). This function does not need to
 // load all the data in memory at the same time. The guarantees are the same as those of
 // `crypto_hash`.
-pub fn hash_read<R: Read>(input: &mut R) -> Result<String, Failure> {
+pub fn hash_read<R: Read>(input: &mut R) -> io::Result<String> {
+    // The `unwrap` is safe since the callback never asks to stop.
+    hash_read_incremental(input, |_| true).map(Option::unwrap)
+}
+
+// Compute the same hash as `hash_read`, but read the input in chunks of at most `HASH_CHUNK_SIZE`
+// bytes. After each chunk, the callback is given the size of the chunk. If it returns `false`, the
+// rest of the input is skipped and the result is `None`. This lets the caller report progress and
+// stop promptly when hashing a huge file.
+pub fn hash_read_incremental<R: Read, F: FnMut(u64) -> bool>(
+    input: &mut R,
+    mut callback: F,
+) -> io::Result<Option<String>> {
     let mut hasher = Sha256::new();
-    io::copy(input, &mut hasher).map_err(failure::system("Unable to compute hash."))?;
-    Ok(hex::encode(hasher.finalize()))
+    let mut buffer = [0; HASH_CHUNK_SIZE];
+
+    loop {
+        let size = match input.read(&mut buffer) {
+            Ok(0) => return Ok(Some(hex::encode(hasher.finalize()))),
+            Ok(size) => size,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+
+        hasher.update(&buffer[..size]);
+
+        if !callback(size as u64) {
+            return Ok(None);
+        }
+    }
+}
+
+// Compute an HMAC-SHA256 (RFC 2104) of a message in hexadecimal. Unlike the other hashes here, this
+// one depends on a secret key, so it can't be forged by someone who doesn't know the key.
+pub fn hmac(key: &[u8], message: &[u8]) -> String {
+    // Keys longer than a block are hashed first, and shorter keys are padded with zeros.
+    let mut block = [0_u8; HMAC_BLOCK_SIZE];
+    if key.len() > HMAC_BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.iter().map(|byte| byte ^ 0x36).collect::<Vec<_>>());
+    inner.update(message);
+
+    let mut outer = Sha256::new();
+    outer.update(block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<_>>());
+    outer.update(inner.finalize());
+
+    hex::encode(outer.finalize())
+}
+
+// The following functions define how each entry in an archive of input files contributes to the
+// cache key. Any change to their behavior invalidates existing caches, so it must be accompanied by
+// a bump to `CACHE_VERSION` [ref:cache_version]. The `*_entry_hash_stable` tests pin the current
+// behavior to make such changes hard to miss.
+
+// Compute the hash of a file in an archive from its path (relative to the container filesystem
+// root), the hash of its contents, and whether it's executable.
+pub fn file_entry_hash(path: &Path, contents_hash: &str, executable: bool) -> String {
+    combine(
+        &combine_hash(&Hash(path.crypto_hash_hex()), contents_hash),
+        if executable { "+x" } else { "-x" },
+    )
+}
+
+// Compute the hash of a file in an archive whose mode was given explicitly rather than derived from
+// the filesystem. The mode is incorporated in full, not just whether it's executable.
+pub fn file_entry_hash_with_mode(path: &Path, contents_hash: &str, mode: u32) -> String {
+    combine(
+        &file_entry_hash(path, contents_hash, mode & 0o111 != 0),
+        &format!("{:o}", mode),
+    )
+}
+
+// Incorporate the modification time (in seconds since the Unix epoch) of a file into the hash of
+// its archive entry. This is only done for files whose modification times are preserved.
+pub fn file_entry_hash_with_mtime(entry_hash: &str, mtime: u64) -> String {
+    combine(entry_hash, &format!("{}", mtime))
+}
+
+// Compute the hash of a symbolic link in an archive from its path (relative to the container
+// filesystem root) and the path it points to.
+pub fn symlink_entry_hash(path: &Path, target: &Path) -> String {
+    combine(path, target)
+}
+
+// Compute the hash of a directory in an archive from its path (relative to the container filesystem
+// root). Only its name is relevant.
+pub fn directory_entry_hash(path: &Path) -> String {
+    path.crypto_hash()
+}
+
+// Compute the hash of a directory in an archive whose mode was taken from the filesystem rather
+// than set to the default.
+pub fn directory_entry_hash_with_mode(path: &Path, mode: u32) -> String {
+    combine(&directory_entry_hash(path), &format!("{:o}", mode))
 }
 
+// A description and a hash of each thing that was incorporated into a cache key, in order
+pub type KeyInputs = Vec<(String, Option<String>)>;
+
 // Determine the image name for a task based on the name of the image for the previous task in the
 // schedule (or the base image, if this is the first task).
 pub fn image_name(
     previous_image: &str,
     docker_repo: &str,
+    key_encoding: KeyEncoding,
     task: &Task,
     input_files_hash: &str,
     environment: &HashMap<String, String>,
 ) -> String {
-    // If there are no environment variables, no input paths, and no command to run, we can just use
-    // the image from the previous task.
-    if task.environment.is_empty() && task.input_paths.is_empty() && task.command.is_empty() {
-        return previous_image.to_owned();
+    image_name_and_inputs(
+        previous_image,
+        docker_repo,
+        key_encoding,
+        task,
+        input_files_hash,
+        environment,
+    )
+    .0
+}
+
+// This is like `image_name`, except it also returns a description and a hash of everything that was
+// incorporated into the cache key, in order. The values of environment variables have no hash,
+// since a hash of a short secret (e.g., a PIN) could be reversed by brute force. If the task reuses
+// the image from the previous task, there are no such inputs.
+pub fn image_name_and_inputs(
+    previous_image: &str,
+    docker_repo: &str,
+    key_encoding: KeyEncoding,
+    task: &Task,
+    input_files_hash: &str,
+    environment: &HashMap<String, String>,
+) -> (String, KeyInputs) {
+    // If there are no environment variables, no input files, and no command to run, we can just use
+    // the image from the previous task. With `use_entrypoint`, the image's entrypoint runs even if
+    // there's no command.
+    if task.environment.is_empty()
+        && task.input_paths.is_empty()
+        && task.optional_input_paths.is_empty()
+        && task.input_paths_from.is_none()
+        && task.generated_input_files.is_empty()
+        && task.command.is_empty()
+        && !task.use_entrypoint
+    {
+        return (previous_image.to_owned(), vec![]);
     }
 
+    let mut inputs = vec![];
+
     // Start with a hash of the cache version.
     let mut cache_key: String = format!("{}", CACHE_VERSION).crypto_hash();
+    inputs.push((
+        format!("cache version {}", CACHE_VERSION),
+        Some(cache_key.clone()),
+    ));
 
     // Incorporate the previous image.
     cache_key = combine(&cache_key, previous_image);
+    inputs.push((
+        format!("previous image {}", previous_image),
+        Some(previous_image.crypto_hash()),
+    ));
 
     // Incorporate the environment variables.
     let mut environment_hash = String::new();
@@ -112,33 +339,142 @@ pub fn image_name(
 
         // The value [ref:environment_valid]
         environment_hash = combine(&environment_hash, &environment[variable]);
+        inputs.push((format!("environment variable {}", variable), None));
     }
     cache_key = combine(&cache_key, &environment_hash);
 
     // Incorporate the input paths and contents.
     cache_key = combine(&cache_key, input_files_hash);
+    inputs.push(("input files".to_owned(), Some(input_files_hash.to_owned())));
 
     // Incorporate the location.
     cache_key = combine(&cache_key, &task.location);
+    inputs.push((
+        format!("location {}", task.location.to_string_lossy()),
+        Some(task.location.crypto_hash()),
+    ));
 
     // Incorporate the user.
     cache_key = combine(&cache_key, &task.user);
+    inputs.push((format!("user {}", task.user), Some(task.user.crypto_hash())));
+
+    // Incorporate how the command is run, if that's been overridden. The default isn't
+    // incorporated, so the cache keys for tasks which don't override it are unaffected.
+    if let Some(shell) = &task.shell {
+        cache_key = combine(&cache_key, &format!("shell {}", shell));
+        inputs.push((format!("shell {}", shell), Some(shell.crypto_hash())));
+    }
+    if task.use_entrypoint {
+        cache_key = combine(&cache_key, "entrypoint");
+        inputs.push(("entrypoint".to_owned(), Some("entrypoint".crypto_hash())));
+    }
+
+    // Incorporate the exit codes which count as success, if there are any. A cached image may come
+    // from a command which exited with one of them, so it's only valid for tasks which allow it.
+    if !task.allowed_exit_codes.is_empty() {
+        let mut codes = task.allowed_exit_codes.clone();
+        codes.sort_unstable();
+        codes.dedup();
+        let codes = codes
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        cache_key = combine(&cache_key, &format!("allowed exit codes {}", codes));
+        inputs.push((
+            format!("allowed exit codes {}", codes),
+            Some(codes.crypto_hash()),
+        ));
+    }
+
+    // Incorporate the outputs the command is expected to produce, if there are any. A cached image
+    // was only checked for the outputs that were expected when it was created.
+    if !task.expect_outputs.is_empty() {
+        let mut paths = task
+            .expect_outputs
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        paths.sort_unstable();
+        paths.dedup();
+        let paths = paths.join(",");
+        cache_key = combine(&cache_key, &format!("expected outputs {}", paths));
+        inputs.push((
+            format!("expected outputs {}", paths),
+            Some(paths.crypto_hash()),
+        ));
+    }
+
+    // Incorporate the limits on which input files are copied, if there are any. The input files
+    // hash already reflects which files were left out, but this keeps the key from depending on
+    // whether any files happen to exceed the limits.
+    if let Some(size) = task.excluded_larger_than {
+        cache_key = combine(&cache_key, &format!("excluded larger than {}", size));
+        inputs.push((
+            format!("excluded larger than {}", size),
+            Some(size.to_string().crypto_hash()),
+        ));
+    }
+    if let Some(cutoff) = task.excluded_mtime_before {
+        cache_key = combine(&cache_key, &format!("excluded mtime before {}", cutoff));
+        inputs.push((
+            format!("excluded mtime before {}", cutoff),
+            Some(cutoff.to_string().crypto_hash()),
+        ));
+    }
+
+    // Incorporate the command. A list of arguments is marked as such and rendered as JSON, so it
+    // can't be confused with a shell command. Shell commands are incorporated as they are, so their
+    // cache keys are unaffected by the existence of the other form.
+    match &task.command {
+        Command::Shell(command) => {
+            cache_key = combine(&cache_key, command);
+            inputs.push(("command".to_owned(), Some(command.crypto_hash())));
+        }
+        Command::Exec(args) => {
+            let args = Value::Array(args.iter().cloned().map(Value::String).collect()).to_string();
+            cache_key = combine(&combine(&cache_key, "exec"), &args);
+            inputs.push((format!("command {}", args), Some(args.crypto_hash())));
+        }
+    }
+
+    // Encode the cache key. The `unwrap` is safe since `cache_key` is a hash in hexadecimal.
+    let cache_key = match key_encoding {
+        KeyEncoding::Hex => cache_key,
+        KeyEncoding::Base64Url => base64url(&hex::decode(cache_key).unwrap()),
+    };
 
-    // Incorporate the command.
-    cache_key = combine(&cache_key, &task.command);
+    // Construct the image name.
+    (
+        format!("{}:{}{}", docker_repo, IMAGE_TAG_PREFIX, cache_key),
+        inputs,
+    )
+}
 
-    // We add this "toast-" prefix because Docker has a rule that tags cannot be 64-byte hexadecimal
-    // strings. See this for more details: https://github.com/moby/moby/issues/20972
-    format!("{}:toast-{}", docker_repo, cache_key)
+// Extract the cache key from the name of an image produced by `image_name`. Returns `None` if the
+// image wasn't produced by `image_name` (e.g., if it's the base image).
+pub fn image_key(image: &str) -> Option<&str> {
+    image
+        .rsplit_once(':')
+        .and_then(|(_, tag)| tag.strip_prefix(IMAGE_TAG_PREFIX))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        cache::{combine, hash_read, image_name, CryptoHash},
-        toastfile::{Task, DEFAULT_LOCATION, DEFAULT_USER},
+        cache::{
+            base64url, combine, directory_entry_hash, directory_entry_hash_with_mode,
+            file_entry_hash, file_entry_hash_with_mode, file_entry_hash_with_mtime, hash_read,
+            hash_read_incremental, hmac, image_key, image_name, image_name_and_inputs,
+            symlink_entry_hash, CryptoHash, KeyEncoding, HASH_CHUNK_SIZE,
+        },
+        toastfile::{Command, Task, DEFAULT_LOCATION, DEFAULT_USER},
+    };
+    use sha2::{Digest, Sha256};
+    use std::{
+        collections::{BTreeMap, HashMap},
+        path::Path,
     };
-    use std::{collections::HashMap, path::Path};
 
     #[test]
     fn hash_str_pure() {
@@ -166,6 +502,16 @@ mod tests {
         );
     }
 
+    #[cfg(windows)]
+    #[test]
+    fn hash_path_separators() {
+        assert_eq!(
+            Path::new("foo\\bar").crypto_hash(),
+            Path::new("foo/bar").crypto_hash(),
+        );
+        assert_eq!(Path::new("foo/bar").crypto_hash(), "foo/bar".crypto_hash());
+    }
+
     #[test]
     fn combine_pure() {
         assert_eq!(combine("foo", "bar"), combine("foo", "bar"));
@@ -200,6 +546,190 @@ mod tests {
         assert_ne!(hash_read(&mut str1).unwrap(), hash_read(&mut str2).unwrap());
     }
 
+    #[test]
+    fn hash_read_incremental_matches_one_shot() {
+        let data = (0..HASH_CHUNK_SIZE * 3 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+
+        let mut chunks = vec![];
+        let hash = hash_read_incremental(&mut &data[..], |size| {
+            chunks.push(size);
+            true
+        })
+        .unwrap();
+
+        assert_eq!(hash, Some(hex::encode(Sha256::digest(&data))));
+        assert!(chunks.iter().all(|&size| size <= HASH_CHUNK_SIZE as u64));
+        assert_eq!(chunks.iter().sum::<u64>(), data.len() as u64);
+    }
+
+    #[test]
+    fn hash_read_incremental_stop() {
+        let data = vec![0; HASH_CHUNK_SIZE * 3];
+
+        let mut calls = 0;
+        let hash = hash_read_incremental(&mut &data[..], |_| {
+            calls += 1;
+            false
+        })
+        .unwrap();
+
+        assert_eq!(hash, None);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn file_entry_hash_stable() {
+        assert_eq!(
+            file_entry_hash(Path::new("scratch/foo"), &"bar".crypto_hash(), false),
+            "b3a0d33960163f1a201c5d28ce4d98718af1d69e1bbd155396399df65f4625c6",
+        );
+    }
+
+    #[test]
+    fn file_entry_hash_with_mode_stable() {
+        assert_eq!(
+            file_entry_hash_with_mode(Path::new("scratch/foo"), &"bar".crypto_hash(), 0o755),
+            "f438d61c1c73ff5cc15ccff5c28c3ba5148bfb001be25fd2a9723072522bc258",
+        );
+    }
+
+    #[test]
+    fn file_entry_hash_with_mode_mode() {
+        assert_ne!(
+            file_entry_hash_with_mode(Path::new("foo"), "bar", 0o755),
+            file_entry_hash_with_mode(Path::new("foo"), "bar", 0o700),
+        );
+    }
+
+    #[test]
+    fn file_entry_hash_path() {
+        assert_ne!(
+            file_entry_hash(Path::new("foo"), "bar", false),
+            file_entry_hash(Path::new("baz"), "bar", false),
+        );
+    }
+
+    #[test]
+    fn file_entry_hash_contents() {
+        assert_ne!(
+            file_entry_hash(Path::new("foo"), "bar", false),
+            file_entry_hash(Path::new("foo"), "baz", false),
+        );
+    }
+
+    #[test]
+    fn file_entry_hash_executable() {
+        assert_ne!(
+            file_entry_hash(Path::new("foo"), "bar", false),
+            file_entry_hash(Path::new("foo"), "bar", true),
+        );
+    }
+
+    #[test]
+    fn file_entry_hash_with_mtime_stable() {
+        assert_eq!(
+            file_entry_hash_with_mtime(
+                &file_entry_hash(Path::new("scratch/foo"), &"bar".crypto_hash(), false),
+                1_234_567_890,
+            ),
+            "cc9969236e0cd5358015aabab911901d735744cfa135b6e13389fb07b564a094",
+        );
+    }
+
+    #[test]
+    fn file_entry_hash_with_mtime_mtime() {
+        let entry_hash = file_entry_hash(Path::new("scratch/foo"), &"bar".crypto_hash(), false);
+
+        assert_ne!(
+            file_entry_hash_with_mtime(&entry_hash, 1),
+            file_entry_hash_with_mtime(&entry_hash, 2),
+        );
+    }
+
+    #[test]
+    fn symlink_entry_hash_stable() {
+        assert_eq!(
+            symlink_entry_hash(Path::new("scratch/foo"), Path::new("bar")),
+            "35068b647127cfe6afa353cf6d067316347ff9d44f4dbd6a96512544661b090a",
+        );
+    }
+
+    #[test]
+    fn directory_entry_hash_stable() {
+        assert_eq!(
+            directory_entry_hash(Path::new("scratch/foo")),
+            "809a2457c2bf52cbb5c678fadc9791ee3e7ebae0856f386e3c0056734edcdd62",
+        );
+    }
+
+    #[test]
+    fn directory_entry_hash_with_mode_stable() {
+        assert_eq!(
+            directory_entry_hash_with_mode(Path::new("scratch/foo"), 0o700),
+            "fa04686b69f8e7fef2e445628125fbfd9f594a59693d4e1b26c5968f420c4b0a",
+        );
+    }
+
+    #[test]
+    fn directory_entry_hash_with_mode_mode() {
+        assert_ne!(
+            directory_entry_hash_with_mode(Path::new("scratch/foo"), 0o700),
+            directory_entry_hash_with_mode(Path::new("scratch/foo"), 0o755),
+        );
+    }
+
+    #[test]
+    fn hmac_rfc_4231() {
+        assert_eq!(
+            hmac(&[0x0b; 20], b"Hi There"),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+        );
+        assert_eq!(
+            hmac(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
+    }
+
+    #[test]
+    fn hmac_long_key() {
+        assert_eq!(
+            hmac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+            ),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+        );
+    }
+
+    #[test]
+    fn base64url_rfc_4648() {
+        assert_eq!(base64url(b""), "");
+        assert_eq!(base64url(b"f"), "Zg");
+        assert_eq!(base64url(b"fo"), "Zm8");
+        assert_eq!(base64url(b"foo"), "Zm9v");
+        assert_eq!(base64url(b"foob"), "Zm9vYg");
+        assert_eq!(base64url(b"fooba"), "Zm9vYmE");
+        assert_eq!(base64url(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn base64url_url_safe() {
+        assert_eq!(base64url(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn image_key_toast_image() {
+        assert_eq!(image_key("localhost:5000/toast:toast-foo"), Some("foo"));
+    }
+
+    #[test]
+    fn image_key_other_image() {
+        assert_eq!(image_key("encom:os-12"), None);
+        assert_eq!(image_key("encom"), None);
+    }
+
     #[test]
     fn image_name_noop() {
         let previous_image = "corge";
@@ -209,19 +739,39 @@ mod tests {
 
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment,
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -234,6 +784,7 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task,
                 input_files_hash,
                 &full_environment,
@@ -241,6 +792,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn image_name_base64url() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let environment: HashMap<String, Option<String>> = HashMap::new();
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let input_files_hash = "grault";
+
+        let full_environment = HashMap::new();
+
+        let hex_image = image_name(
+            previous_image,
+            docker_repo,
+            KeyEncoding::Hex,
+            &task,
+            input_files_hash,
+            &full_environment,
+        );
+        let base64url_image = image_name(
+            previous_image,
+            docker_repo,
+            KeyEncoding::Base64Url,
+            &task,
+            input_files_hash,
+            &full_environment,
+        );
+
+        let hex_key = image_key(&hex_image).unwrap();
+        let base64url_key = image_key(&base64url_image).unwrap();
+        assert_eq!(base64url_key.len(), 43);
+        assert_eq!(base64url_key, base64url(&hex::decode(hex_key).unwrap()));
+    }
+
     #[test]
     fn image_name_pure() {
         let previous_image = "corge";
@@ -251,39 +874,798 @@ mod tests {
 
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment,
+            env_file: None,
             input_paths: vec![Path::new("flob").to_owned()],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![Path::new("thud").to_owned()],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let input_files_hash = "grault";
+
+        let mut full_environment = HashMap::new();
+        full_environment.insert("foo".to_owned(), "qux".to_owned());
+
+        assert_eq!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task,
+                input_files_hash,
+                &full_environment,
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
+    }
+
+    #[test]
+    fn image_name_and_inputs_consistent() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let mut environment: HashMap<String, Option<String>> = HashMap::new();
+        environment.insert("foo".to_owned(), None);
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment,
+            env_file: None,
+            input_paths: vec![Path::new("flob").to_owned()],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![Path::new("thud").to_owned()],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let input_files_hash = "grault";
+
+        let mut full_environment = HashMap::new();
+        full_environment.insert("foo".to_owned(), "qux".to_owned());
+
+        let (image, inputs) = image_name_and_inputs(
+            previous_image,
+            docker_repo,
+            KeyEncoding::Hex,
+            &task,
+            input_files_hash,
+            &full_environment,
+        );
+
+        assert_eq!(
+            image,
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
+        assert_eq!(
+            inputs
+                .iter()
+                .map(|(description, _)| description.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "cache version 0",
+                "previous image corge",
+                "environment variable foo",
+                "input files",
+                "location /scratch",
+                "user root",
+                "command",
+            ],
+        );
+        assert_eq!(inputs[2].1, None);
+        assert_eq!(inputs[3].1.as_deref(), Some(input_files_hash));
+    }
+
+    #[test]
+    fn image_name_previous_image() {
+        let previous_image1 = "foo";
+        let previous_image2 = "bar";
+        let docker_repo = "toast";
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let input_files_hash = "grault";
+
+        let full_environment = HashMap::new();
+
+        assert_ne!(
+            image_name(
+                previous_image1,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task,
+                input_files_hash,
+                &full_environment,
+            ),
+            image_name(
+                previous_image2,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
+    }
+
+    #[test]
+    fn image_name_environment_order() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let mut environment1: HashMap<String, Option<String>> = HashMap::new();
+        environment1.insert("foo".to_owned(), None);
+        environment1.insert("bar".to_owned(), None);
+
+        let mut environment2: HashMap<String, Option<String>> = HashMap::new();
+        environment2.insert("bar".to_owned(), None);
+        environment2.insert("foo".to_owned(), None);
+
+        let task1 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: environment1,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let task2 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: environment2,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let input_files_hash = "grault";
+
+        let mut full_environment = HashMap::new();
+        full_environment.insert("foo".to_owned(), "qux".to_owned());
+        full_environment.insert("bar".to_owned(), "fum".to_owned());
+
+        assert_eq!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task1,
+                input_files_hash,
+                &full_environment,
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task2,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
+    }
+
+    #[test]
+    fn image_name_environment_keys() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let mut environment1: HashMap<String, Option<String>> = HashMap::new();
+        environment1.insert("foo".to_owned(), None);
+
+        let mut environment2: HashMap<String, Option<String>> = HashMap::new();
+        environment2.insert("bar".to_owned(), None);
+
+        let task1 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: environment1,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let task2 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: environment2,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let input_files_hash = "grault";
+
+        let mut full_environment = HashMap::new();
+        full_environment.insert("foo".to_owned(), "qux".to_owned());
+        full_environment.insert("bar".to_owned(), "fum".to_owned());
+
+        assert_ne!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task1,
+                input_files_hash,
+                &full_environment,
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task2,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
+    }
+
+    #[test]
+    fn image_name_environment_values() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let mut environment: HashMap<String, Option<String>> = HashMap::new();
+        environment.insert("foo".to_owned(), None);
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let input_files_hash = "grault";
+
+        let mut full_environment1 = HashMap::new();
+        full_environment1.insert("foo".to_owned(), "bar".to_owned());
+        let mut full_environment2 = HashMap::new();
+        full_environment2.insert("foo".to_owned(), "baz".to_owned());
+
+        assert_ne!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task,
+                input_files_hash,
+                &full_environment1,
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task,
+                input_files_hash,
+                &full_environment2,
+            ),
+        );
+    }
+
+    #[test]
+    fn image_name_input_files_hash() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![Path::new("flob").to_owned()],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![Path::new("thud").to_owned()],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let input_files_hash1 = "foo";
+        let input_files_hash2 = "bar";
+
+        let full_environment = HashMap::new();
+
+        assert_ne!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task,
+                input_files_hash1,
+                &full_environment,
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task,
+                input_files_hash2,
+                &full_environment,
+            ),
+        );
+    }
+
+    #[test]
+    fn image_name_location() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let task1 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new("/foo").to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let task2 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new("/bar").to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let input_files_hash = "grault";
+
+        let full_environment = HashMap::new();
+
+        assert_ne!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task1,
+                input_files_hash,
+                &full_environment,
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task2,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
+    }
+
+    #[test]
+    fn image_name_user() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let task1 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: "foo".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let task2 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            user: "bar".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let input_files_hash = "grault";
 
-        let mut full_environment = HashMap::new();
-        full_environment.insert("foo".to_owned(), "qux".to_owned());
+        let full_environment = HashMap::new();
 
-        assert_eq!(
+        assert_ne!(
             image_name(
                 previous_image,
                 docker_repo,
-                &task,
+                KeyEncoding::Hex,
+                &task1,
                 input_files_hash,
                 &full_environment,
             ),
             image_name(
                 previous_image,
                 docker_repo,
-                &task,
+                KeyEncoding::Hex,
+                &task2,
                 input_files_hash,
                 &full_environment,
             ),
@@ -291,26 +1673,83 @@ mod tests {
     }
 
     #[test]
-    fn image_name_previous_image() {
-        let previous_image1 = "foo";
-        let previous_image2 = "bar";
+    fn image_name_shell() {
+        let previous_image = "corge";
         let docker_repo = "toast";
 
-        let task = Task {
+        let task1 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let task2 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            shell: Some("/bin/bash".to_owned()),
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -320,16 +1759,18 @@ mod tests {
 
         assert_ne!(
             image_name(
-                previous_image1,
+                previous_image,
                 docker_repo,
-                &task,
+                KeyEncoding::Hex,
+                &task1,
                 input_files_hash,
                 &full_environment,
             ),
             image_name(
-                previous_image2,
+                previous_image,
                 docker_repo,
-                &task,
+                KeyEncoding::Hex,
+                &task2,
                 input_files_hash,
                 &full_environment,
             ),
@@ -337,64 +1778,95 @@ mod tests {
     }
 
     #[test]
-    fn image_name_environment_order() {
+    fn image_name_use_entrypoint() {
         let previous_image = "corge";
         let docker_repo = "toast";
 
-        let mut environment1: HashMap<String, Option<String>> = HashMap::new();
-        environment1.insert("foo".to_owned(), None);
-        environment1.insert("bar".to_owned(), None);
-
-        let mut environment2: HashMap<String, Option<String>> = HashMap::new();
-        environment2.insert("bar".to_owned(), None);
-        environment2.insert("foo".to_owned(), None);
-
         let task1 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
-            environment: environment1,
+            environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let task2 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
-            environment: environment2,
+            environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            shell: None,
+            use_entrypoint: true,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let input_files_hash = "grault";
 
-        let mut full_environment = HashMap::new();
-        full_environment.insert("foo".to_owned(), "qux".to_owned());
-        full_environment.insert("bar".to_owned(), "fum".to_owned());
+        let full_environment = HashMap::new();
 
-        assert_eq!(
+        assert_ne!(
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task1,
                 input_files_hash,
                 &full_environment,
@@ -402,6 +1874,7 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task2,
                 input_files_hash,
                 &full_environment,
@@ -410,62 +1883,96 @@ mod tests {
     }
 
     #[test]
-    fn image_name_environment_keys() {
+    fn image_name_command_exec() {
         let previous_image = "corge";
         let docker_repo = "toast";
 
-        let mut environment1: HashMap<String, Option<String>> = HashMap::new();
-        environment1.insert("foo".to_owned(), None);
-
-        let mut environment2: HashMap<String, Option<String>> = HashMap::new();
-        environment2.insert("bar".to_owned(), None);
-
         let task1 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
-            environment: environment1,
+            environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let task2 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
-            environment: environment2,
+            environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Exec(vec!["echo".to_owned(), "wibble".to_owned()]),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let input_files_hash = "grault";
 
-        let mut full_environment = HashMap::new();
-        full_environment.insert("foo".to_owned(), "qux".to_owned());
-        full_environment.insert("bar".to_owned(), "fum".to_owned());
+        let full_environment = HashMap::new();
 
+        // A list of arguments isn't confused with the shell command it resembles.
         assert_ne!(
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task1,
                 input_files_hash,
                 &full_environment,
@@ -473,6 +1980,7 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task2,
                 input_files_hash,
                 &full_environment,
@@ -481,81 +1989,125 @@ mod tests {
     }
 
     #[test]
-    fn image_name_environment_values() {
+    fn image_name_allowed_exit_codes() {
         let previous_image = "corge";
         let docker_repo = "toast";
 
-        let mut environment: HashMap<String, Option<String>> = HashMap::new();
-        environment.insert("foo".to_owned(), None);
-
-        let task = Task {
+        let task1 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
-            environment,
+            environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
-        let input_files_hash = "grault";
-
-        let mut full_environment1 = HashMap::new();
-        full_environment1.insert("foo".to_owned(), "bar".to_owned());
-        let mut full_environment2 = HashMap::new();
-        full_environment2.insert("foo".to_owned(), "baz".to_owned());
-
-        assert_ne!(
-            image_name(
-                previous_image,
-                docker_repo,
-                &task,
-                input_files_hash,
-                &full_environment1,
-            ),
-            image_name(
-                previous_image,
-                docker_repo,
-                &task,
-                input_files_hash,
-                &full_environment2,
-            ),
-        );
-    }
-
-    #[test]
-    fn image_name_input_files_hash() {
-        let previous_image = "corge";
-        let docker_repo = "toast";
+        let task2 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![1, 2],
+            extra_docker_arguments: vec![],
+        };
 
-        let task = Task {
+        let task3 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
-            input_paths: vec![Path::new("flob").to_owned()],
-            excluded_input_paths: vec![Path::new("thud").to_owned()],
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![2, 1, 2],
             extra_docker_arguments: vec![],
         };
 
-        let input_files_hash1 = "foo";
-        let input_files_hash2 = "bar";
+        let input_files_hash = "grault";
 
         let full_environment = HashMap::new();
 
@@ -563,58 +2115,162 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
-                &task,
-                input_files_hash1,
+                KeyEncoding::Hex,
+                &task1,
+                input_files_hash,
                 &full_environment,
             ),
             image_name(
                 previous_image,
                 docker_repo,
-                &task,
-                input_files_hash2,
+                KeyEncoding::Hex,
+                &task2,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
+
+        // The order of the exit codes doesn't matter.
+        assert_eq!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task2,
+                input_files_hash,
+                &full_environment,
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task3,
+                input_files_hash,
                 &full_environment,
             ),
         );
     }
 
     #[test]
-    fn image_name_location() {
+    fn image_name_expect_outputs() {
         let previous_image = "corge";
         let docker_repo = "toast";
 
         let task1 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
-            location: Path::new("/foo").to_owned(),
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let task2 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![Path::new("foo").to_owned(), Path::new("bar").to_owned()],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
-            location: Path::new("/bar").to_owned(),
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let task3 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![
+                Path::new("bar").to_owned(),
+                Path::new("foo").to_owned(),
+                Path::new("bar").to_owned(),
+            ],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo wibble".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -626,6 +2282,7 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task1,
                 input_files_hash,
                 &full_environment,
@@ -633,51 +2290,150 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
+                &task2,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
+
+        // The order of the expected outputs doesn't matter.
+        assert_eq!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
                 &task2,
                 input_files_hash,
                 &full_environment,
             ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task3,
+                input_files_hash,
+                &full_environment,
+            ),
         );
     }
 
     #[test]
-    fn image_name_user() {
+    fn image_name_exclusion_limits() {
         let previous_image = "corge";
         let docker_repo = "toast";
 
         let task1 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
-            user: "foo".to_owned(),
-            command: "echo wibble".to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let task2 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: Some(100),
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
-            user: "bar".to_owned(),
-            command: "echo wibble".to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let task3 = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: Some(100),
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo wibble".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -689,6 +2445,7 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task1,
                 input_files_hash,
                 &full_environment,
@@ -696,11 +2453,50 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task2,
                 input_files_hash,
                 &full_environment,
             ),
         );
+
+        assert_ne!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task1,
+                input_files_hash,
+                &full_environment,
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task3,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
+
+        assert_ne!(
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task2,
+                input_files_hash,
+                &full_environment,
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                KeyEncoding::Hex,
+                &task3,
+                input_files_hash,
+                &full_environment,
+            ),
+        );
     }
 
     #[test]
@@ -710,37 +2506,77 @@ mod tests {
 
         let task1 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo foo".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo foo".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let task2 = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: "echo bar".to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Shell("echo bar".to_owned()),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -752,6 +2588,7 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task1,
                 input_files_hash,
                 &full_environment,
@@ -759,6 +2596,7 @@ mod tests {
             image_name(
                 previous_image,
                 docker_repo,
+                KeyEncoding::Hex,
                 &task2,
                 input_files_hash,
                 &full_environment,
@@ -1,4 +1,4 @@
-use crate::{failure, failure::Failure};
+use crate::{cache::KeyEncoding, failure, failure::Failure};
 use serde::{Deserialize, Serialize};
 
 pub const REPO_DEFAULT: &str = "toast";
@@ -12,6 +12,9 @@ pub struct Config {
     #[serde(default = "default_docker_repo")]
     pub docker_repo: String,
 
+    #[serde(default = "default_cache_key_encoding")]
+    pub cache_key_encoding: KeyEncoding,
+
     #[serde(default = "default_read_local_cache")]
     pub read_local_cache: bool,
 
@@ -23,12 +26,49 @@ pub struct Config {
 
     #[serde(default = "default_write_remote_cache")]
     pub write_remote_cache: bool,
+
+    #[serde(default = "default_probe_remote_cache")]
+    pub probe_remote_cache: bool,
+
+    #[serde(default = "default_remote_cache_probe_timeout")]
+    pub remote_cache_probe_timeout: u64,
+
+    #[serde(default = "default_prefetch_remote_cache")]
+    pub prefetch_remote_cache: bool,
+
+    #[serde(default = "default_cache_concurrency")]
+    pub cache_concurrency: u32,
+
+    #[serde(default = "default_write_manifests")]
+    pub write_manifests: bool,
+
+    #[serde(default = "default_io_retries")]
+    pub io_retries: u32,
+
+    #[serde(default = "default_case_insensitive_check")]
+    pub case_insensitive_check: bool,
+
+    #[serde(default = "default_large_archive_size")]
+    pub large_archive_size: u64,
+
+    #[serde(default = "default_large_archive_entries")]
+    pub large_archive_entries: u64,
+
+    #[serde(default)]
+    pub archive_timeout: Option<u64>,
+
+    #[serde(default = "default_scan_cache")]
+    pub scan_cache: bool,
 }
 
 fn default_docker_repo() -> String {
     REPO_DEFAULT.to_owned()
 }
 
+fn default_cache_key_encoding() -> KeyEncoding {
+    KeyEncoding::Hex
+}
+
 fn default_read_local_cache() -> bool {
     true
 }
@@ -45,6 +85,46 @@ fn default_write_remote_cache() -> bool {
     false
 }
 
+fn default_probe_remote_cache() -> bool {
+    false
+}
+
+fn default_remote_cache_probe_timeout() -> u64 {
+    30
+}
+
+fn default_prefetch_remote_cache() -> bool {
+    false
+}
+
+fn default_cache_concurrency() -> u32 {
+    4
+}
+
+fn default_write_manifests() -> bool {
+    false
+}
+
+fn default_io_retries() -> u32 {
+    3
+}
+
+fn default_case_insensitive_check() -> bool {
+    false
+}
+
+fn default_large_archive_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_large_archive_entries() -> u64 {
+    100_000
+}
+
+fn default_scan_cache() -> bool {
+    false
+}
+
 // Parse a program configuration.
 pub fn parse(config: &str) -> Result<Config, Failure> {
     serde_yaml::from_str(config).map_err(failure::user("Syntax error."))
@@ -52,16 +132,31 @@ pub fn parse(config: &str) -> Result<Config, Failure> {
 
 #[cfg(test)]
 mod tests {
-    use crate::config::{parse, Config, EMPTY_CONFIG};
+    use crate::{
+        cache::KeyEncoding,
+        config::{parse, Config, EMPTY_CONFIG},
+    };
 
     #[test]
     fn parse_empty() {
         let result = Config {
             docker_repo: "toast".to_owned(),
+            cache_key_encoding: KeyEncoding::Hex,
             read_local_cache: true,
             write_local_cache: true,
             read_remote_cache: false,
             write_remote_cache: false,
+            probe_remote_cache: false,
+            remote_cache_probe_timeout: 30,
+            prefetch_remote_cache: false,
+            cache_concurrency: 4,
+            write_manifests: false,
+            io_retries: 3,
+            case_insensitive_check: false,
+            large_archive_size: 1024 * 1024 * 1024,
+            large_archive_entries: 100_000,
+            archive_timeout: None,
+            scan_cache: false,
         };
 
         assert_eq!(parse(EMPTY_CONFIG).unwrap(), result);
@@ -71,19 +166,43 @@ mod tests {
     fn parse_nonempty() {
         let config = r#"
 docker_repo: foo
+cache_key_encoding: base64url
 read_local_cache: false
 write_local_cache: false
 read_remote_cache: true
 write_remote_cache: true
+probe_remote_cache: true
+remote_cache_probe_timeout: 10
+prefetch_remote_cache: true
+cache_concurrency: 8
+write_manifests: true
+io_retries: 5
+case_insensitive_check: true
+large_archive_size: 1000
+large_archive_entries: 10
+archive_timeout: 60
+scan_cache: true
     "#
         .trim();
 
         let result = Config {
             docker_repo: "foo".to_owned(),
+            cache_key_encoding: KeyEncoding::Base64Url,
             read_local_cache: false,
             write_local_cache: false,
             read_remote_cache: true,
             write_remote_cache: true,
+            probe_remote_cache: true,
+            remote_cache_probe_timeout: 10,
+            prefetch_remote_cache: true,
+            cache_concurrency: 8,
+            write_manifests: true,
+            io_retries: 5,
+            case_insensitive_check: true,
+            large_archive_size: 1000,
+            large_archive_entries: 10,
+            archive_timeout: Some(60),
+            scan_cache: true,
         };
 
         assert_eq!(parse(config).unwrap(), result);
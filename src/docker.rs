@@ -1,11 +1,11 @@
-use crate::{failure, failure::Failure, format::CodeStr, spinner::spin};
+use crate::{failure, failure::Failure, format::CodeStr, glob, spinner::spin};
 
 #[cfg(unix)]
 use std::{
     collections::HashMap,
     fs::{copy, create_dir_all, read_link, rename, symlink_metadata, Metadata},
     io,
-    io::Read,
+    io::{Read, Write},
     path::{Path, PathBuf},
     process::{ChildStdin, Command, Stdio},
     string::ToString,
@@ -13,6 +13,8 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    thread::sleep,
+    time::{Duration, Instant},
 };
 
 #[cfg(windows)]
@@ -20,7 +22,7 @@ use std::{
     collections::HashMap,
     fs::{copy, create_dir_all, rename, symlink_metadata, Metadata},
     io,
-    io::Read,
+    io::{Read, Write},
     path::{Path, PathBuf},
     process::{ChildStdin, Command, Stdio},
     string::ToString,
@@ -28,12 +30,39 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    thread::sleep,
+    time::{Duration, Instant},
 };
 
-use crate::toastfile::MappingPath;
-use tempfile::tempdir;
+use crate::{toastfile, toastfile::MappingPath};
+use tempfile::{tempdir, TempDir};
 use walkdir::WalkDir;
 
+// Determine the version of the Docker client. This fails if Docker isn't installed.
+pub fn client_version(interrupted: &Arc<AtomicBool>) -> Result<String, Failure> {
+    run_quiet(
+        "Checking the Docker client\u{2026}",
+        "Unable to run Docker.",
+        &["--version".to_owned()],
+        interrupted,
+    )
+    .map(|output| output.trim().to_owned())
+}
+
+// Determine the version of the Docker daemon. This fails if the daemon can't be reached.
+pub fn server_version(interrupted: &Arc<AtomicBool>) -> Result<String, Failure> {
+    run_quiet(
+        "Checking the Docker daemon\u{2026}",
+        "Unable to reach the Docker daemon.",
+        &vec!["info", "--format", "{{.ServerVersion}}"]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        interrupted,
+    )
+    .map(|output| output.trim().to_owned())
+}
+
 // Query whether an image exists locally.
 pub fn image_exists(image: &str, interrupted: &Arc<AtomicBool>) -> Result<bool, Failure> {
     debug!("Checking existence of image {}\u{2026}", image.code_str());
@@ -53,6 +82,122 @@ pub fn image_exists(image: &str, interrupted: &Arc<AtomicBool>) -> Result<bool,
     }
 }
 
+// Look up the ID of a local image. The ID is a digest of the image's configuration, so it changes
+// whenever the contents of the image do.
+pub fn image_id(image: &str, interrupted: &Arc<AtomicBool>) -> Result<String, Failure> {
+    debug!("Looking up the ID of image {}\u{2026}", image.code_str());
+
+    run_quiet(
+        "Looking up the ID of image\u{2026}",
+        "Unable to look up the ID of the image.",
+        &vec!["image", "inspect", "--format", "{{.Id}}", image]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        interrupted,
+    )
+    .map(|output| output.trim().to_owned())
+}
+
+// Look up the digests under which a local image is known to the registries it was pulled from
+// (e.g., `ubuntu@sha256:…`). An image which was only built or tagged locally has none. Returns
+// `None` if the image doesn't exist locally.
+pub fn image_repo_digests(
+    image: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Option<Vec<String>>, Failure> {
+    debug!(
+        "Looking up the repository digests of image {}\u{2026}",
+        image.code_str(),
+    );
+
+    match run_quiet(
+        "Looking up the digests of image\u{2026}",
+        "The image doesn't exist.",
+        &vec![
+            "image",
+            "inspect",
+            "--format",
+            "{{range .RepoDigests}}{{println .}}{{end}}",
+            image,
+        ]
+        .into_iter()
+        .map(std::borrow::ToOwned::to_owned)
+        .collect::<Vec<_>>(),
+        interrupted,
+    ) {
+        Ok(output) => Ok(Some(output.lines().map(ToOwned::to_owned).collect())),
+        Err(Failure::Interrupted) => Err(Failure::Interrupted),
+        Err(Failure::System(_, _) | Failure::User(_, _)) => Ok(None),
+    }
+}
+
+// Query whether an image exists in the remote registry without pulling it. If the registry doesn't
+// respond within the timeout, the image is assumed not to exist.
+pub fn remote_image_exists(
+    image: &str,
+    timeout: Duration,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<bool, Failure> {
+    debug!(
+        "Checking existence of remote image {}\u{2026}",
+        image.code_str()
+    );
+
+    match run_quiet_timeout(
+        "Checking existence of remote image\u{2026}",
+        "The remote image doesn't exist.",
+        &vec!["manifest", "inspect", image]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        timeout,
+        interrupted,
+    ) {
+        Ok(()) => Ok(true),
+        Err(Failure::Interrupted) => Err(Failure::Interrupted),
+        Err(e @ (Failure::System(_, _) | Failure::User(_, _))) => {
+            debug!("{}", e);
+            Ok(false)
+        }
+    }
+}
+
+// Look up the value of a label on a local image, if it has that label.
+pub fn image_label(
+    image: &str,
+    key: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Option<String>, Failure> {
+    debug!(
+        "Looking up label {} of image {}\u{2026}",
+        key.code_str(),
+        image.code_str(),
+    );
+
+    run_quiet(
+        "Looking up image label\u{2026}",
+        "Unable to look up the label of the image.",
+        &[
+            "image".to_owned(),
+            "inspect".to_owned(),
+            "--format".to_owned(),
+            format!("{{{{index .Config.Labels {:?}}}}}", key),
+            image.to_owned(),
+        ],
+        interrupted,
+    )
+    .map(|output| {
+        // Docker prints `<no value>` if the image has no labels at all.
+        let value = output.trim();
+        if value.is_empty() || value == "<no value>" {
+            None
+        } else {
+            Some(value.to_owned())
+        }
+    })
+}
+
 // Push an image.
 pub fn push_image(image: &str, interrupted: &Arc<AtomicBool>) -> Result<(), Failure> {
     debug!("Pushing image {}\u{2026}", image.code_str());
@@ -101,6 +246,35 @@ pub fn delete_image(image: &str, interrupted: &Arc<AtomicBool>) -> Result<(), Fa
     .map(|_| ())
 }
 
+// List the local images in a repository which have the given label (`key=value`).
+pub fn list_images(
+    repo: &str,
+    label: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Vec<String>, Failure> {
+    debug!(
+        "Listing the images in repository {} with label {}\u{2026}",
+        repo.code_str(),
+        label.code_str(),
+    );
+
+    run_quiet(
+        "Listing images\u{2026}",
+        "Unable to list images.",
+        &[
+            "image".to_owned(),
+            "ls".to_owned(),
+            "--filter".to_owned(),
+            format!("label={}", label),
+            "--format".to_owned(),
+            "{{.Repository}}:{{.Tag}}".to_owned(),
+            repo.to_owned(),
+        ],
+        interrupted,
+    )
+    .map(|output| output.lines().map(ToString::to_string).collect())
+}
+
 // Create a container and return its ID.
 #[allow(clippy::too_many_arguments)]
 pub fn create_container(
@@ -110,9 +284,13 @@ pub fn create_container(
     mount_paths: &[MappingPath],
     mount_readonly: bool,
     ports: &[String],
+    read_only: bool,
+    writable_paths: &[PathBuf],
     location: &Path,
     user: &str,
-    command: &str,
+    shell: Option<&str>,
+    use_entrypoint: bool,
+    command: &toastfile::Command,
     extra_args: &[String],
     interrupted: &Arc<AtomicBool>,
 ) -> Result<String, Failure> {
@@ -133,11 +311,65 @@ pub fn create_container(
         extra_args,
     ));
 
+    // With a read-only root filesystem, the writable paths are anonymous volumes. Unlike tmpfs
+    // mounts, those exist before the container starts and after it stops, so files can be copied
+    // into and out of them.
+    if read_only {
+        args.push("--read-only".to_owned());
+        args.extend(writable_paths.iter().flat_map(|path| {
+            // [ref:writable_paths_no_commas]
+            vec![
+                "--mount".to_owned(),
+                format!(
+                    "type=volume,target={}",
+                    location.join(path).to_string_lossy()
+                ),
+            ]
+        }));
+    }
+
+    // By default, the command is run by `su`, which uses the login shell of the user. Otherwise,
+    // Docker switches to the user, and the command is run by the given shell or passed to the
+    // image's entrypoint. Those don't require the image to have `su`. A list of arguments is passed
+    // to the entrypoint too, or else its first element replaces the entrypoint, so the program is
+    // run directly without a shell.
     args.extend(
-        vec![image, "/bin/su", "-c", command, user]
-            .into_iter()
-            .map(std::borrow::ToOwned::to_owned)
-            .collect::<Vec<_>>(),
+        match (command, use_entrypoint) {
+            (toastfile::Command::Exec(command_args), true) => {
+                let mut args = vec!["--user", user, image];
+                args.extend(command_args.iter().map(String::as_str));
+                args
+            }
+            (toastfile::Command::Exec(command_args), false) => {
+                // The list isn't empty [ref:command_nonempty], so there's always a program.
+                let mut args = vec!["--user", user];
+                match command_args.split_first() {
+                    Some((program, program_args)) => {
+                        args.extend(["--entrypoint", program, image]);
+                        args.extend(program_args.iter().map(String::as_str));
+                    }
+                    None => args.push(image),
+                }
+                args
+            }
+            (toastfile::Command::Shell(command), true) => {
+                let mut args = vec!["--user", user, image];
+                if !command.is_empty() {
+                    args.push(command);
+                }
+                args
+            }
+            (toastfile::Command::Shell(command), false) => {
+                if let Some(shell) = shell {
+                    vec!["--user", user, image, shell, "-c", command]
+                } else {
+                    vec![image, "/bin/su", "-c", command, user]
+                }
+            }
+        }
+        .into_iter()
+        .map(std::borrow::ToOwned::to_owned)
+        .collect::<Vec<_>>(),
     );
 
     Ok(run_quiet(
@@ -150,10 +382,11 @@ pub fn create_container(
     .to_owned())
 }
 
-// Copy files into a container.
+// Copy files into a container. The paths in the archive are relative to the destination directory.
 pub fn copy_into_container<R: Read>(
     container: &str,
     mut tar: R,
+    destination_dir: &Path,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
     debug!(
@@ -168,7 +401,7 @@ pub fn copy_into_container<R: Read>(
             "container".to_owned(),
             "cp".to_owned(),
             "-".to_owned(),
-            format!("{}:/", container),
+            format!("{}:{}", container, destination_dir.to_string_lossy()),
         ],
         |mut stdin| {
             io::copy(&mut tar, &mut stdin)
@@ -235,7 +468,8 @@ fn rename_or_copy_file_or_symlink(
     Ok(())
 }
 
-// Copy files from a container.
+// Copy files from a container. Paths which contain wildcards are patterns, and the paths they match
+// in the container are copied. A pattern must match something.
 pub fn copy_from_container(
     container: &str,
     paths: &[PathBuf],
@@ -243,6 +477,39 @@ pub fn copy_from_container(
     destination_dir: &Path,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
+    fetch_from_container(container, paths, source_dir, interrupted)?.move_to(destination_dir)
+}
+
+// Files which were copied out of a container, but not yet moved to their destination on the host
+pub struct FetchedFiles {
+    _temp_dirs: Vec<TempDir>, // Deleted, with anything left in them, when this is dropped
+    moves: Vec<(PathBuf, PathBuf)>, // From a path in `_temp_dirs` to one in the destination
+}
+
+impl FetchedFiles {
+    // Move the files to their destination on the host.
+    pub fn move_to(&self, destination_dir: &Path) -> Result<(), Failure> {
+        for (intermediate, path) in &self.moves {
+            move_from_intermediate(intermediate, &destination_dir.join(path))?;
+        }
+
+        Ok(())
+    }
+}
+
+// This is the first half of `copy_from_container`. Failures here have to do with the container,
+// e.g., a path that doesn't exist in it, whereas moving the files afterward only involves the host.
+pub fn fetch_from_container(
+    container: &str,
+    paths: &[PathBuf],
+    source_dir: &Path,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<FetchedFiles, Failure> {
+    let mut fetched = FetchedFiles {
+        _temp_dirs: vec![],
+        moves: vec![],
+    };
+
     // Copy each path from the container to the host.
     for path in paths {
         debug!(
@@ -251,6 +518,10 @@ pub fn copy_from_container(
             container.code_str(),
         );
 
+        // Only the files in the container can be matched against a pattern, so we copy the part of
+        // the path before the first wildcard and match the rest of the pattern on the host.
+        let (prefix, pattern) = glob::split_literal_prefix(path);
+
         // `docker container cp` is not idempotent. For example, suppose there is a directory called
         // `/foo` in the container and `/bar` does not exist on the host. Consider the command
         // `docker cp container:/foo /bar`. The first time that command is run, Docker will create
@@ -263,9 +534,8 @@ pub fn copy_from_container(
             tempdir().map_err(failure::system("Unable to create temporary directory."))?;
 
         // Figure out what needs to go where.
-        let source = source_dir.join(path);
+        let source = source_dir.join(&prefix);
         let intermediate = temp_dir.path().join("data");
-        let destination = destination_dir.join(path);
 
         // Get the path from the container.
         run_quiet(
@@ -281,83 +551,206 @@ pub fn copy_from_container(
         )
         .map(|_| ())?;
 
-        // Fetch filesystem metadata for `input_path`.
-        let intermediate_metadata =
-            symlink_metadata(&intermediate).map_err(failure::system(format!(
-                "Unable to fetch filesystem metadata for {}.",
+        // If the path is a pattern, each match will be moved into place. Since matches are sorted,
+        // a directory comes before the paths inside it, which get moved along with it.
+        if let Some(pattern) = pattern {
+            let mut matches = glob::expand(&pattern, &intermediate)?;
+            if matches.is_empty() {
+                return Err(Failure::User(
+                    format!(
+                        "The pattern {} doesn't match anything in the container.",
+                        path.to_string_lossy().code_str(),
+                    ),
+                    None,
+                ));
+            }
+            matches.dedup_by(|path, ancestor| path.starts_with(ancestor));
+
+            for path in matches {
+                fetched
+                    .moves
+                    .push((intermediate.join(&path), prefix.join(&path)));
+            }
+        } else {
+            fetched.moves.push((intermediate, prefix));
+        }
+
+        fetched._temp_dirs.push(temp_dir);
+    }
+
+    Ok(fetched)
+}
+
+// This is a helper function for the `copy_from_container` function. It moves a file, symlink, or
+// directory which was copied out of a container to its final destination on the host.
+fn move_from_intermediate(intermediate: &Path, destination: &Path) -> Result<(), Failure> {
+    // Fetch filesystem metadata for `intermediate`.
+    let intermediate_metadata =
+        symlink_metadata(intermediate).map_err(failure::system(format!(
+            "Unable to fetch filesystem metadata for {}.",
+            intermediate.to_string_lossy().code_str(),
+        )))?;
+
+    // Determine what we got from the container.
+    if intermediate_metadata.is_dir() {
+        // It's a directory. Traverse it.
+        for entry in WalkDir::new(intermediate) {
+            // If we run into an error traversing the filesystem, report it.
+            let entry = entry.map_err(failure::system(format!(
+                "Unable to traverse directory {}.",
                 intermediate.to_string_lossy().code_str(),
             )))?;
 
-        // Determine what we got from the container.
-        if intermediate_metadata.is_dir() {
-            // It's a directory. Traverse it.
-            for entry in WalkDir::new(&intermediate) {
-                // If we run into an error traversing the filesystem, report it.
-                let entry = entry.map_err(failure::system(format!(
-                    "Unable to traverse directory {}.",
-                    intermediate.to_string_lossy().code_str(),
-                )))?;
+            // Fetch the metadata for this entry.
+            let entry_metadata = entry.metadata().map_err(failure::system(format!(
+                "Unable to fetch filesystem metadata for {}.",
+                entry.path().to_string_lossy().code_str(),
+            )))?;
 
-                // Fetch the metadata for this entry.
-                let entry_metadata = entry.metadata().map_err(failure::system(format!(
-                    "Unable to fetch filesystem metadata for {}.",
-                    entry.path().to_string_lossy().code_str(),
+            // Figure out what needs to go where. The `unwrap` is safe because `entry` is
+            // guaranteed to be inside `intermediate` (or equal to it).
+            let entry_source_path = entry.path();
+            let entry_destination_path =
+                destination.join(entry_source_path.strip_prefix(intermediate).unwrap());
+
+            // Check if the entry is a file or a directory.
+            if entry.file_type().is_dir() {
+                // It's a directory. Create a directory at the destination.
+                create_dir_all(&entry_destination_path).map_err(failure::system(format!(
+                    "Unable to create directory {}.",
+                    entry_destination_path.to_string_lossy().code_str(),
                 )))?;
-
-                // Figure out what needs to go where. The `unwrap` is safe because `entry` is
-                // guaranteed to be inside `intermediate` (or equal to it).
-                let entry_source_path = entry.path();
-                let entry_destination_path =
-                    destination.join(entry_source_path.strip_prefix(&intermediate).unwrap());
-
-                // Check if the entry is a file or a directory.
-                if entry.file_type().is_dir() {
-                    // It's a directory. Create a directory at the destination.
-                    create_dir_all(&entry_destination_path).map_err(failure::system(format!(
-                        "Unable to create directory {}.",
-                        entry_destination_path.to_string_lossy().code_str(),
-                    )))?;
-                } else {
-                    // It's a file or symlink. Move or copy it to the destination.
-                    rename_or_copy_file_or_symlink(
-                        entry_source_path,
-                        &entry_destination_path,
-                        &entry_metadata,
-                    )?;
-                }
+            } else {
+                // It's a file or symlink. Move or copy it to the destination.
+                rename_or_copy_file_or_symlink(
+                    entry_source_path,
+                    &entry_destination_path,
+                    &entry_metadata,
+                )?;
             }
-        } else {
-            // It's a file or symlink. Determine the destination directory. The `unwrap` is safe
-            // because the root of the filesystem cannot be a file or symlink.
-            let destination_parent = destination.parent().unwrap().to_owned();
-
-            // Make sure the destination directory exists.
-            create_dir_all(&destination_parent).map_err(failure::system(format!(
-                "Unable to create directory {}.",
-                destination_parent.to_string_lossy().code_str(),
-            )))?;
-
-            // Move or copy it to the destination.
-            rename_or_copy_file_or_symlink(&intermediate, &destination, &intermediate_metadata)?;
         }
+    } else {
+        // It's a file or symlink. Determine the destination directory. The `unwrap` is safe
+        // because the root of the filesystem cannot be a file or symlink.
+        let destination_parent = destination.parent().unwrap().to_owned();
+
+        // Make sure the destination directory exists.
+        create_dir_all(&destination_parent).map_err(failure::system(format!(
+            "Unable to create directory {}.",
+            destination_parent.to_string_lossy().code_str(),
+        )))?;
+
+        // Move or copy it to the destination.
+        rename_or_copy_file_or_symlink(intermediate, destination, &intermediate_metadata)?;
     }
 
     Ok(())
 }
 
-// Start a container.
-pub fn start_container(container: &str, interrupted: &Arc<AtomicBool>) -> Result<(), Failure> {
-    debug!("Starting container {}\u{2026}", container.code_str());
+// Determine whether a path exists in a container. Docker sends the path as a tar archive, so we
+// stop it as soon as any of the archive arrives, rather than waiting for all of it.
+pub fn path_exists(
+    container: &str,
+    path: &Path,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<bool, Failure> {
+    debug!(
+        "Checking for {} in container {}\u{2026}",
+        path.to_string_lossy().code_str(),
+        container.code_str(),
+    );
 
-    run_loud(
-        "Unable to start container.",
-        &vec!["container", "start", "--attach", container]
+    // Render a spinner animation and clear it when we're done.
+    let guard = spin("Checking the outputs\u{2026}");
+
+    // This is used to determine whether the user interrupted the program during the execution of
+    // the child process.
+    let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Run the child process.
+    let error = "Unable to check for a path in the container.";
+    let mut child = command(&[
+        "container".to_owned(),
+        "cp".to_owned(),
+        format!("{}:{}", container, path.to_string_lossy()),
+        "-".to_owned(),
+    ])
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(failure::system(format!(
+        "{} Perhaps you don't have Docker installed [9].",
+        error,
+    )))?;
+
+    // If any of the archive arrives, the path exists. The child might have terminated in the
+    // meantime, so errors from stopping it are ignored.
+    let mut byte = [0_u8];
+    if child.stdout.as_mut().unwrap().read(&mut byte).unwrap_or(0) > 0 {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Ok(true);
+    }
+
+    // Otherwise, find out why not.
+    let output = child.wait_with_output().map_err(failure::system(format!(
+        "{} Perhaps you don't have Docker installed [10].",
+        error,
+    )))?;
+    if output.status.code().is_none() || (!was_interrupted && interrupted.load(Ordering::SeqCst)) {
+        interrupted.store(true, Ordering::SeqCst);
+        return Err(Failure::Interrupted);
+    }
+
+    // The wording of the error varies between Docker versions (and other container runtimes), so
+    // rather than parsing it, check that the container itself is still there. If so, the path is
+    // what's missing. Only one spinner can be shown at a time, so ours is cleared first.
+    drop(guard);
+    match run_quiet(
+        "Checking the outputs\u{2026}",
+        error,
+        &vec!["container", "inspect", "--format", "{{.Id}}", container]
             .into_iter()
             .map(std::borrow::ToOwned::to_owned)
             .collect::<Vec<_>>(),
         interrupted,
+    ) {
+        Ok(_) => Ok(false),
+        Err(Failure::Interrupted) => Err(Failure::Interrupted),
+        Err(Failure::System(_, _) | Failure::User(_, _)) => Err(Failure::System(
+            format!("{}\n{}", error, String::from_utf8_lossy(&output.stderr)),
+            None,
+        )),
+    }
+}
+
+// Start a container. If `stdout_to_stderr` is set, the standard output of the container's command
+// is sent to standard error, e.g., to keep standard output for a document that's printed afterward.
+pub fn start_container(
+    container: &str,
+    stdout_to_stderr: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<i32, Failure> {
+    debug!("Starting container {}\u{2026}", container.code_str());
+
+    // With `--attach`, Docker exits with the exit code of the container's command.
+    let mut stderr = io::stderr();
+    run_loud(
+        "Unable to start container.",
+        command(
+            &vec!["container", "start", "--attach", container]
+                .into_iter()
+                .map(std::borrow::ToOwned::to_owned)
+                .collect::<Vec<_>>(),
+        ),
+        if stdout_to_stderr {
+            Some(&mut stderr)
+        } else {
+            None
+        },
+        interrupted,
     )
-    .map(|_| ())
 }
 
 // Stop a container.
@@ -380,6 +773,7 @@ pub fn stop_container(container: &str, interrupted: &Arc<AtomicBool>) -> Result<
 pub fn commit_container(
     container: &str,
     image: &str,
+    labels: &[String],
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
     debug!(
@@ -391,23 +785,81 @@ pub fn commit_container(
     run_quiet(
         "Committing container\u{2026}",
         "Unable to commit container.",
-        &vec!["container", "commit", container, image]
-            .into_iter()
-            .map(std::borrow::ToOwned::to_owned)
-            .collect::<Vec<_>>(),
+        &[
+            "container".to_owned(),
+            "commit".to_owned(),
+            "--change".to_owned(),
+            format!("LABEL {}", labels.join(" ")),
+            container.to_owned(),
+            image.to_owned(),
+        ],
         interrupted,
     )
     .map(|_| ())
 }
 
-// Delete a container.
+// Fetch the last lines of what the command of a container wrote to its standard output and
+// standard error, in that order. This relies on the logging driver keeping the output, which the
+// default one does.
+pub fn container_logs(
+    container: &str,
+    lines: usize,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(String, String), Failure> {
+    debug!(
+        "Fetching the output of container {}\u{2026}",
+        container.code_str(),
+    );
+
+    // Render a spinner animation and clear it when we're done.
+    let _guard = spin("Fetching the output of the command\u{2026}");
+
+    // This is used to determine whether the user interrupted the program during the execution of
+    // the child process.
+    let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Run the child process.
+    let error = "Unable to fetch the output of the command.";
+    let output = command(&[
+        "container".to_owned(),
+        "logs".to_owned(),
+        "--tail".to_owned(),
+        lines.to_string(),
+        container.to_owned(),
+    ])
+    .output()
+    .map_err(failure::system(format!(
+        "{} Perhaps you don't have Docker installed [11].",
+        error,
+    )))?;
+
+    // Handle the result.
+    if output.status.success() {
+        Ok((
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ))
+    } else if output.status.code().is_none()
+        || (!was_interrupted && interrupted.load(Ordering::SeqCst))
+    {
+        interrupted.store(true, Ordering::SeqCst);
+        Err(Failure::Interrupted)
+    } else {
+        Err(Failure::System(
+            format!("{}\n{}", error, String::from_utf8_lossy(&output.stderr)),
+            None,
+        ))
+    }
+}
+
+// Delete a container along with its anonymous volumes (e.g., for `writable_paths`).
 pub fn delete_container(container: &str, interrupted: &Arc<AtomicBool>) -> Result<(), Failure> {
     debug!("Deleting container {}\u{2026}", container.code_str());
 
     run_quiet(
         "Deleting container\u{2026}",
         "Unable to delete container.",
-        &vec!["container", "rm", "--force", container]
+        &vec!["container", "rm", "--force", "--volumes", container]
             .into_iter()
             .map(std::borrow::ToOwned::to_owned)
             .collect::<Vec<_>>(),
@@ -427,6 +879,7 @@ pub fn spawn_shell(
     mount_readonly: bool,
     ports: &[String],
     user: &str,
+    shell: Option<&str>,
     extra_args: &[String],
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
@@ -451,10 +904,14 @@ pub fn spawn_shell(
     ));
 
     args.extend(
-        vec![image, "/bin/su", user]
-            .into_iter()
-            .map(std::borrow::ToOwned::to_owned)
-            .collect::<Vec<_>>(),
+        if let Some(shell) = shell {
+            vec!["--user", user, image, shell]
+        } else {
+            vec![image, "/bin/su", user]
+        }
+        .into_iter()
+        .map(std::borrow::ToOwned::to_owned)
+        .collect::<Vec<_>>(),
     );
 
     run_attach("The shell exited with a failure.", &args, interrupted)
@@ -572,6 +1029,74 @@ fn run_quiet(
     }
 }
 
+// How often to check whether a child process with a timeout has terminated
+const TIMEOUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Run a command and discard its output. If it doesn't terminate within the timeout, kill it.
+fn run_quiet_timeout(
+    spinner_message: &str,
+    error: &str,
+    args: &[String],
+    timeout: Duration,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // Render a spinner animation and clear it when we're done.
+    let _guard = spin(spinner_message);
+
+    // This is used to determine whether the user interrupted the program during the execution of
+    // the child process.
+    let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Run the child process. Its output is discarded so it can't fill up a pipe while we wait.
+    let mut child = command(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(failure::system(format!(
+            "{} Perhaps you don't have Docker installed [7].",
+            error,
+        )))?;
+
+    // Wait for the child to terminate, or give up when the time runs out.
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(failure::system(format!(
+            "{} Perhaps you don't have Docker installed [8].",
+            error,
+        )))? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            // The child might have terminated in the meantime, so errors are ignored here.
+            let _ = child.kill();
+            let _ = child.wait();
+
+            return Err(Failure::System(
+                format!("{} The operation timed out.", error),
+                None,
+            ));
+        }
+
+        sleep(TIMEOUT_POLL_INTERVAL);
+    };
+
+    // Handle the result.
+    if status.success() {
+        Ok(())
+    } else {
+        Err(
+            if status.code().is_none() || (!was_interrupted && interrupted.load(Ordering::SeqCst)) {
+                interrupted.store(true, Ordering::SeqCst);
+                Failure::Interrupted
+            } else {
+                Failure::System(error.to_owned(), None)
+            },
+        )
+    }
+}
+
 // Run a command and return its standard output. Accepts a closure which receives a pipe to the
 // standard input stream of the child process.
 fn run_quiet_stdin<W: FnOnce(&mut ChildStdin) -> Result<(), Failure>>(
@@ -628,20 +1153,37 @@ fn run_quiet_stdin<W: FnOnce(&mut ChildStdin) -> Result<(), Failure>>(
     }
 }
 
-// Run a command and inherit standard output and error streams.
-fn run_loud(error: &str, args: &[String], interrupted: &Arc<AtomicBool>) -> Result<(), Failure> {
+// Run a command and inherit standard output and error streams, unless a destination is given for
+// standard output. Return its exit code, even if it's nonzero.
+fn run_loud(
+    error: &str,
+    mut command: Command,
+    stdout: Option<&mut dyn Write>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<i32, Failure> {
     // This is used to determine whether the user interrupted the program during the execution of
     // the child process.
     let was_interrupted = interrupted.load(Ordering::SeqCst);
 
     // Run the child process.
-    let mut child = command(args)
-        .stdin(Stdio::null())
-        .spawn()
-        .map_err(failure::system(format!(
-            "{} Perhaps you don't have Docker installed [4].",
+    command.stdin(Stdio::null());
+    if stdout.is_some() {
+        command.stdout(Stdio::piped());
+    }
+    let mut child = command.spawn().map_err(failure::system(format!(
+        "{} Perhaps you don't have Docker installed [4].",
+        error,
+    )))?;
+
+    // Forward standard output to its destination until the child closes it. Standard error is
+    // inherited, so the child can't block on it in the meantime. The `unwrap` is safe since
+    // standard output was piped above.
+    if let Some(stdout) = stdout {
+        io::copy(child.stdout.as_mut().unwrap(), stdout).map_err(failure::system(format!(
+            "{} Unable to forward the output of the command.",
             error,
         )))?;
+    }
 
     // Wait for the child to terminate.
     let status = child.wait().map_err(failure::system(format!(
@@ -651,16 +1193,15 @@ fn run_loud(error: &str, args: &[String], interrupted: &Arc<AtomicBool>) -> Resu
 
     // Handle the result.
     if status.success() {
-        Ok(())
+        Ok(0)
     } else {
-        Err(
-            if status.code().is_none() || (!was_interrupted && interrupted.load(Ordering::SeqCst)) {
+        match status.code() {
+            Some(code) if was_interrupted || !interrupted.load(Ordering::SeqCst) => Ok(code),
+            _ => {
                 interrupted.store(true, Ordering::SeqCst);
-                Failure::Interrupted
-            } else {
-                Failure::System(error.to_owned(), None)
-            },
-        )
+                Err(Failure::Interrupted)
+            }
+        }
     }
 }
 
@@ -699,3 +1240,31 @@ fn command(args: &[String]) -> Command {
     }
     command
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::docker::run_loud;
+    use std::{
+        process::Command,
+        sync::{atomic::AtomicBool, Arc},
+    };
+
+    #[cfg(unix)]
+    #[test]
+    fn run_loud_redirects_stdout() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo foo; exit 3"]);
+        let mut stdout = vec![];
+
+        let exit_code = run_loud(
+            "Unable to run the command.",
+            command,
+            Some(&mut stdout),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(exit_code, 3);
+        assert_eq!(stdout, b"foo\n");
+    }
+}
@@ -29,6 +29,25 @@ pub fn number(n: usize, noun: &str) -> String {
     }
 }
 
+// This function takes a number of bytes and returns a human-readable size with a binary unit, e.g.,
+// 1536 becomes "1.5 KiB".
+pub fn bytes(n: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+    if n < 1024 {
+        return number(n as usize, "byte");
+    }
+
+    let mut size = n as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
 // This function takes an array of strings and returns a comma-separated list with the word "and"
 // (and an Oxford comma, if applicable) between the last two items.
 pub fn series(items: &[String]) -> String {
@@ -46,7 +65,7 @@ pub fn series(items: &[String]) -> String {
 
 #[cfg(test)]
 mod tests {
-    use crate::format::{number, series, CodeStr};
+    use crate::format::{bytes, number, series, CodeStr};
 
     #[test]
     fn code_str_display() {
@@ -69,6 +88,19 @@ mod tests {
         assert_eq!(number(2, "cow"), "2 cows");
     }
 
+    #[test]
+    fn bytes_small() {
+        assert_eq!(bytes(1), "1 byte");
+        assert_eq!(bytes(1023), "1023 bytes");
+    }
+
+    #[test]
+    fn bytes_large() {
+        assert_eq!(bytes(1536), "1.5 KiB");
+        assert_eq!(bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+        assert_eq!(bytes(u64::MAX), "16.0 EiB");
+    }
+
     #[test]
     fn series_empty() {
         assert_eq!(series(&[]), "");
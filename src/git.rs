@@ -0,0 +1,180 @@
+use crate::{failure, failure::Failure, format::CodeStr};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+
+// Run a Git command in a directory and return its standard output.
+fn run(dir: &Path, error: &str, args: &[&str]) -> Result<Vec<u8>, Failure> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(failure::system(format!(
+            "{} Perhaps you don't have Git installed.",
+            error,
+        )))?;
+
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(Failure::User(
+            format!(
+                "{}\n{}",
+                error,
+                String::from_utf8_lossy(&output.stderr).trim_end(),
+            ),
+            None,
+        ))
+    }
+}
+
+// Convert raw bytes printed by Git into a path.
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+// Convert raw bytes printed by Git into a path.
+#[cfg(windows)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::new(String::from_utf8_lossy(bytes).as_ref()))
+}
+
+// Determine which files in the repository containing `dir` have changed since the given Git ref.
+// This includes uncommitted changes and untracked files (but not ignored files) anywhere in the
+// repository. The returned paths are absolute.
+pub fn changed_paths(dir: &Path, since: &str) -> Result<Vec<PathBuf>, Failure> {
+    // Git would parse a ref which starts with a hyphen as an option (e.g., `--output=<file>`),
+    // and no valid ref starts with one anyway.
+    if since.starts_with('-') {
+        return Err(Failure::User(
+            format!("{} is not a valid Git ref.", since.code_str()),
+            None,
+        ));
+    }
+
+    debug!(
+        "Determining which files changed since {}\u{2026}",
+        since.code_str()
+    );
+
+    // Git reports paths relative to the root of the repository.
+    let top_level_output = run(
+        dir,
+        "Unable to determine the root of the Git repository.",
+        &["rev-parse", "--show-toplevel"],
+    )?;
+    let top_level = path_from_bytes(
+        top_level_output
+            .strip_suffix(b"\n")
+            .unwrap_or(&top_level_output),
+    );
+
+    // Ask Git for the files that changed since `since` and the files it's not tracking yet,
+    // anywhere in the repository. Rename detection is disabled, so a renamed file is reported under
+    // both its old and new paths.
+    let error = format!(
+        "Unable to determine which files changed since {}.",
+        since.code_str()
+    );
+    let mut paths = run(
+        dir,
+        &error,
+        &["diff", "--name-only", "--no-renames", "-z", since, "--"],
+    )?;
+    paths.extend(run(
+        &top_level,
+        &error,
+        &[
+            "ls-files",
+            "--others",
+            "--exclude-standard",
+            "-z",
+            "--full-name",
+        ],
+    )?);
+
+    // The paths are separated by NUL bytes.
+    Ok(paths
+        .split(|byte| *byte == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| top_level.join(path_from_bytes(path)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::git::changed_paths;
+    use std::{
+        fs::{canonicalize, create_dir, rename, write},
+        path::Path,
+        process::Command,
+    };
+    use tempfile::tempdir;
+
+    fn git(dir: &Path, args: &[&str]) {
+        assert!(Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args([
+                "-c",
+                "user.name=Toast",
+                "-c",
+                "user.email=toast@example.com"
+            ])
+            .args(args)
+            .output()
+            .unwrap()
+            .status
+            .success());
+    }
+
+    #[test]
+    fn changed_paths_option_like_ref() {
+        assert!(changed_paths(Path::new("."), "--output=foo")
+            .unwrap_err()
+            .to_string()
+            .contains("not a valid Git ref"));
+    }
+
+    #[test]
+    fn changed_paths_rename() {
+        let repo = tempdir().unwrap();
+        let repo_path = canonicalize(repo.path()).unwrap();
+        git(&repo_path, &["init", "--quiet"]);
+        write(repo_path.join("foo.txt"), "foo").unwrap();
+        git(&repo_path, &["add", "foo.txt"]);
+        git(&repo_path, &["commit", "--quiet", "--message", "Add foo"]);
+        rename(repo_path.join("foo.txt"), repo_path.join("bar.txt")).unwrap();
+        git(&repo_path, &["add", "--all"]);
+        git(
+            &repo_path,
+            &["commit", "--quiet", "--message", "Rename foo"],
+        );
+
+        let paths = changed_paths(&repo_path, "HEAD~1").unwrap();
+        assert!(paths.contains(&repo_path.join("foo.txt")));
+        assert!(paths.contains(&repo_path.join("bar.txt")));
+    }
+
+    #[test]
+    fn changed_paths_untracked_outside_dir() {
+        let repo = tempdir().unwrap();
+        let repo_path = canonicalize(repo.path()).unwrap();
+        git(&repo_path, &["init", "--quiet"]);
+        create_dir(repo_path.join("sub")).unwrap();
+        write(repo_path.join("sub").join("foo.txt"), "foo").unwrap();
+        git(&repo_path, &["add", "--all"]);
+        git(&repo_path, &["commit", "--quiet", "--message", "Add foo"]);
+        write(repo_path.join("bar.txt"), "bar").unwrap();
+
+        let paths = changed_paths(&repo_path.join("sub"), "HEAD").unwrap();
+        assert_eq!(paths, vec![repo_path.join("bar.txt")]);
+    }
+}
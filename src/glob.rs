@@ -0,0 +1,292 @@
+use crate::{failure, failure::Failure, format::CodeStr};
+use std::{
+    ffi::OsStr,
+    fs::{read_dir, symlink_metadata},
+    path::{Component, Path, PathBuf},
+};
+
+// A pattern component consisting of just this matches any number of directories (including none).
+const RECURSIVE_WILDCARD: &str = "**";
+
+// Determine whether a path contains any wildcards. Paths without wildcards are taken literally.
+pub fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(|c| c == '*' || c == '?')
+}
+
+// Match a single path component against a pattern component, where `*` matches any sequence of
+// characters and `?` matches any single character.
+fn matches_component(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            matches_component(&pattern[1..], name)
+                || (!name.is_empty() && matches_component(pattern, &name[1..]))
+        }
+        (Some('?'), Some(_)) => matches_component(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) => p == n && matches_component(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+// Convert a path component into characters for matching.
+fn chars(component: &OsStr) -> Vec<char> {
+    component.to_string_lossy().chars().collect()
+}
+
+// Determine whether a path matches a pattern, or is inside a directory which matches the pattern.
+// Both are compared component-wise, so they should be normalized in the same way.
+pub fn matches_prefix(pattern: &Path, path: &Path) -> bool {
+    let pattern_components = pattern
+        .components()
+        .map(Component::as_os_str)
+        .collect::<Vec<_>>();
+    let path_components = path
+        .components()
+        .map(Component::as_os_str)
+        .collect::<Vec<_>>();
+
+    matches_components_prefix(&pattern_components, &path_components)
+}
+
+fn matches_components_prefix(pattern: &[&OsStr], path: &[&OsStr]) -> bool {
+    match pattern.first() {
+        // The whole pattern matched, so anything left over is inside the matched path.
+        None => true,
+        Some(first) if *first == RECURSIVE_WILDCARD => {
+            matches_components_prefix(&pattern[1..], path)
+                || (!path.is_empty() && matches_components_prefix(pattern, &path[1..]))
+        }
+        Some(first) => path.first().map_or(false, |component| {
+            matches_component(&chars(first), &chars(component))
+                && matches_components_prefix(&pattern[1..], &path[1..])
+        }),
+    }
+}
+
+// Split a path into the components before the first one with a wildcard, which are taken
+// literally, and the rest of the path starting with that component. The rest is `None` if the path
+// isn't a pattern.
+pub fn split_literal_prefix(path: &Path) -> (PathBuf, Option<PathBuf>) {
+    let mut prefix = PathBuf::new();
+    let mut components = path.components();
+
+    while let Some(component) = components.next() {
+        let component = Path::new(component.as_os_str());
+        if is_pattern(component) {
+            return (prefix, Some(component.join(components.as_path())));
+        }
+        prefix.push(component);
+    }
+
+    (prefix, None)
+}
+
+// Find the paths (relative to `source_dir`) which match a pattern. Symbolic links are not followed.
+// The result is sorted.
+pub fn expand(pattern: &Path, source_dir: &Path) -> Result<Vec<PathBuf>, Failure> {
+    let components = pattern
+        .components()
+        .map(Component::as_os_str)
+        .collect::<Vec<_>>();
+
+    let mut matches = vec![];
+    expand_components(&components, source_dir, Path::new(""), &mut matches)?;
+    matches.sort();
+    matches.dedup();
+
+    Ok(matches)
+}
+
+fn expand_components(
+    pattern: &[&OsStr],
+    source_dir: &Path,
+    path: &Path,
+    matches: &mut Vec<PathBuf>,
+) -> Result<(), Failure> {
+    let first = if let Some(first) = pattern.first() {
+        first
+    } else {
+        // The whole pattern matched.
+        matches.push(path.to_owned());
+        return Ok(());
+    };
+
+    if *first == RECURSIVE_WILDCARD {
+        // Match no directories here, or descend into each subdirectory and try again.
+        expand_components(&pattern[1..], source_dir, path, matches)?;
+        for child in children(source_dir, path)? {
+            if symlink_metadata(source_dir.join(&child)).map_or(false, |metadata| metadata.is_dir())
+            {
+                expand_components(pattern, source_dir, &child, matches)?;
+            }
+        }
+    } else if is_pattern(Path::new(first)) {
+        // Match the entries in this directory against the pattern component.
+        let pattern_chars = chars(first);
+        for child in children(source_dir, path)? {
+            // The `unwrap` is safe since `child` was constructed by joining a file name.
+            if matches_component(&pattern_chars, &chars(child.file_name().unwrap())) {
+                expand_components(&pattern[1..], source_dir, &child, matches)?;
+            }
+        }
+    } else {
+        // The component is taken literally.
+        let child = path.join(first);
+        if symlink_metadata(source_dir.join(&child)).is_ok() {
+            expand_components(&pattern[1..], source_dir, &child, matches)?;
+        }
+    }
+
+    Ok(())
+}
+
+// List the entries of a directory (relative to `source_dir`). If the path isn't a directory, there
+// are no entries.
+fn children(source_dir: &Path, path: &Path) -> Result<Vec<PathBuf>, Failure> {
+    let dir = source_dir.join(path);
+    if !symlink_metadata(&dir).map_or(false, |metadata| metadata.is_dir()) {
+        return Ok(vec![]);
+    }
+
+    read_dir(&dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| path.join(entry.file_name())))
+                .collect()
+        })
+        .map_err(failure::system(format!(
+            "Unable to read directory {}.",
+            dir.to_string_lossy().code_str(),
+        )))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::glob::{expand, is_pattern, matches_prefix, split_literal_prefix};
+    use std::{
+        fs::{create_dir_all, write},
+        path::{Path, PathBuf},
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn is_pattern_literal() {
+        assert!(!is_pattern(Path::new("foo/bar.rs")));
+    }
+
+    #[test]
+    fn split_literal_prefix_literal() {
+        assert_eq!(
+            split_literal_prefix(Path::new("foo/bar.rs")),
+            (Path::new("foo/bar.rs").to_owned(), None),
+        );
+    }
+
+    #[test]
+    fn split_literal_prefix_pattern() {
+        assert_eq!(
+            split_literal_prefix(Path::new("foo/bar/*.rs")),
+            (
+                Path::new("foo/bar").to_owned(),
+                Some(Path::new("*.rs").to_owned()),
+            ),
+        );
+        assert_eq!(
+            split_literal_prefix(Path::new("foo/**/bar/*.rs")),
+            (
+                Path::new("foo").to_owned(),
+                Some(Path::new("**/bar/*.rs").to_owned()),
+            ),
+        );
+        assert_eq!(
+            split_literal_prefix(Path::new("*/bar.rs")),
+            (PathBuf::new(), Some(Path::new("*/bar.rs").to_owned())),
+        );
+    }
+
+    #[test]
+    fn is_pattern_wildcards() {
+        assert!(is_pattern(Path::new("foo/*.rs")));
+        assert!(is_pattern(Path::new("foo/ba?.rs")));
+        assert!(is_pattern(Path::new("foo/**/bar.rs")));
+    }
+
+    #[test]
+    fn matches_prefix_star() {
+        assert!(matches_prefix(
+            Path::new("foo/*.rs"),
+            Path::new("foo/bar.rs")
+        ));
+        assert!(!matches_prefix(
+            Path::new("foo/*.rs"),
+            Path::new("foo/bar.txt")
+        ));
+        assert!(!matches_prefix(
+            Path::new("foo/*.rs"),
+            Path::new("foo/bar/baz.rs")
+        ));
+    }
+
+    #[test]
+    fn matches_prefix_question_mark() {
+        assert!(matches_prefix(Path::new("foo/ba?"), Path::new("foo/bar")));
+        assert!(!matches_prefix(Path::new("foo/ba?"), Path::new("foo/ba")));
+    }
+
+    #[test]
+    fn matches_prefix_recursive() {
+        assert!(matches_prefix(
+            Path::new("foo/**/*.rs"),
+            Path::new("foo/bar.rs")
+        ));
+        assert!(matches_prefix(
+            Path::new("foo/**/*.rs"),
+            Path::new("foo/bar/baz/qux.rs")
+        ));
+        assert!(!matches_prefix(
+            Path::new("foo/**/*.rs"),
+            Path::new("bar/baz.rs")
+        ));
+    }
+
+    #[test]
+    fn matches_prefix_inside_match() {
+        assert!(matches_prefix(
+            Path::new("foo/b*"),
+            Path::new("foo/bar/baz.txt")
+        ));
+    }
+
+    #[test]
+    fn expand_patterns() {
+        let source_dir = tempdir().unwrap();
+        create_dir_all(source_dir.path().join("src/foo/bar")).unwrap();
+        write(source_dir.path().join("src/main.rs"), "").unwrap();
+        write(source_dir.path().join("src/foo/mod.rs"), "").unwrap();
+        write(source_dir.path().join("src/foo/bar/mod.rs"), "").unwrap();
+        write(source_dir.path().join("src/foo/README.md"), "").unwrap();
+
+        assert_eq!(
+            expand(Path::new("src/**/*.rs"), source_dir.path()).unwrap(),
+            vec![
+                Path::new("src/foo/bar/mod.rs").to_owned(),
+                Path::new("src/foo/mod.rs").to_owned(),
+                Path::new("src/main.rs").to_owned(),
+            ],
+        );
+
+        assert_eq!(
+            expand(Path::new("src/*/README.*"), source_dir.path()).unwrap(),
+            vec![Path::new("src/foo/README.md").to_owned()],
+        );
+    }
+
+    #[test]
+    fn expand_no_matches() {
+        let source_dir = tempdir().unwrap();
+
+        assert!(expand(Path::new("src/*.rs"), source_dir.path())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::{self, Display, Formatter, Write};
+
+// A JSON value. Toast only needs to produce JSON documents (reading them back can be done with
+// `serde_yaml`, since JSON is a subset of YAML), so this is just enough structure to render them.
+// Use `{}` to render a value on a single line and `{:#}` to render it with indentation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Number(u64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>), // The order of the fields is preserved.
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write_value(f, self, if f.alternate() { Some(0) } else { None })
+    }
+}
+
+// Write a value. If `indent` is `Some`, the value is rendered across multiple lines with the given
+// level of indentation. Otherwise, it's rendered on a single line.
+fn write_value(f: &mut Formatter, value: &Value, indent: Option<usize>) -> fmt::Result {
+    match value {
+        Value::Bool(b) => write!(f, "{}", b),
+        Value::Number(n) => write!(f, "{}", n),
+        Value::String(s) => write_string(f, s),
+        Value::Array(elements) => {
+            if elements.is_empty() {
+                return f.write_str("[]");
+            }
+
+            f.write_char('[')?;
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    f.write_char(',')?;
+                }
+                write_newline(f, indent.map(|level| level + 1))?;
+                write_value(f, element, indent.map(|level| level + 1))?;
+            }
+            write_newline(f, indent)?;
+            f.write_char(']')
+        }
+        Value::Object(fields) => {
+            if fields.is_empty() {
+                return f.write_str("{}");
+            }
+
+            f.write_char('{')?;
+            for (i, (key, field)) in fields.iter().enumerate() {
+                if i > 0 {
+                    f.write_char(',')?;
+                }
+                write_newline(f, indent.map(|level| level + 1))?;
+                write_string(f, key)?;
+                f.write_str(if indent.is_some() { ": " } else { ":" })?;
+                write_value(f, field, indent.map(|level| level + 1))?;
+            }
+            write_newline(f, indent)?;
+            f.write_char('}')
+        }
+    }
+}
+
+// Start a new line at the given level of indentation, if applicable.
+fn write_newline(f: &mut Formatter, indent: Option<usize>) -> fmt::Result {
+    if let Some(level) = indent {
+        f.write_char('\n')?;
+        for _ in 0..level {
+            f.write_str("  ")?;
+        }
+    }
+
+    Ok(())
+}
+
+// Write a string literal with the necessary escaping.
+fn write_string(f: &mut Formatter, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json::Value;
+
+    #[test]
+    fn display_string_escaping() {
+        assert_eq!(
+            format!("{}", Value::String("a\"b\\c\nd\u{1}".to_owned())),
+            "\"a\\\"b\\\\c\\nd\\u0001\"",
+        );
+    }
+
+    #[test]
+    fn display_scalars() {
+        assert_eq!(format!("{}", Value::Bool(true)), "true");
+        assert_eq!(format!("{}", Value::Number(42)), "42");
+    }
+
+    #[test]
+    fn display_compact() {
+        let value = Value::Object(vec![
+            ("foo".to_owned(), Value::String("bar".to_owned())),
+            ("baz".to_owned(), Value::Object(vec![])),
+            (
+                "qux".to_owned(),
+                Value::Array(vec![Value::Number(1), Value::Array(vec![])]),
+            ),
+        ]);
+
+        assert_eq!(
+            format!("{}", value),
+            "{\"foo\":\"bar\",\"baz\":{},\"qux\":[1,[]]}",
+        );
+    }
+
+    #[test]
+    fn display_pretty() {
+        let value = Value::Object(vec![
+            ("foo".to_owned(), Value::String("bar".to_owned())),
+            (
+                "baz".to_owned(),
+                Value::Object(vec![("qux".to_owned(), Value::String("quux".to_owned()))]),
+            ),
+            (
+                "corge".to_owned(),
+                Value::Array(vec![Value::Bool(true), Value::Bool(false)]),
+            ),
+        ]);
+
+        assert_eq!(
+            format!("{:#}", value),
+            "{\n  \"foo\": \"bar\",\n  \"baz\": {\n    \"qux\": \"quux\"\n  },\n  \
+             \"corge\": [\n    true,\n    false\n  ]\n}",
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::{fmt::Write, time::Duration};
+
+// A JUnit report has one test suite for the whole run, with one test case per task. Most CI systems
+// (e.g., Jenkins, GitLab, and CircleCI) can show such a report in their test panels.
+
+// How a test case turned out
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+// A test case in a report
+pub struct TestCase {
+    pub name: String,
+    pub duration: Duration,
+    pub outcome: Outcome,
+    pub stdout: String, // Omitted if empty
+    pub stderr: String, // Omitted if empty
+}
+
+// Escape a string for use in XML text or in an attribute value.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Other control characters aren't allowed in XML 1.0 at all.
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// Render a report with a test suite of the given name.
+pub fn render(suite_name: &str, test_cases: &[TestCase]) -> String {
+    let tests = test_cases.len();
+    let failures = test_cases
+        .iter()
+        .filter(|test_case| matches!(test_case.outcome, Outcome::Failed(_)))
+        .count();
+    let skipped = test_cases
+        .iter()
+        .filter(|test_case| matches!(test_case.outcome, Outcome::Skipped(_)))
+        .count();
+    let time = test_cases
+        .iter()
+        .map(|test_case| test_case.duration)
+        .sum::<Duration>()
+        .as_secs_f64();
+
+    // Writing to a `String` can't fail, so the results of `writeln!` are ignored.
+    let mut report = String::new();
+    let _ = writeln!(report, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        report,
+        r#"<testsuites tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+        tests, failures, skipped, time,
+    );
+    let _ = writeln!(
+        report,
+        r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+        escape(suite_name),
+        tests,
+        failures,
+        skipped,
+        time,
+    );
+
+    for test_case in test_cases {
+        let attributes = format!(
+            r#"name="{}" classname="{}" time="{:.3}""#,
+            escape(&test_case.name),
+            escape(suite_name),
+            test_case.duration.as_secs_f64(),
+        );
+
+        let mut body = String::new();
+        match &test_case.outcome {
+            Outcome::Passed => {}
+            Outcome::Failed(message) => {
+                let _ = writeln!(
+                    body,
+                    r#"      <failure message="{}">{}</failure>"#,
+                    escape(message),
+                    escape(message),
+                );
+            }
+            Outcome::Skipped(message) => {
+                let _ = writeln!(body, r#"      <skipped message="{}"/>"#, escape(message));
+            }
+        }
+        for (element, output) in &[
+            ("system-out", &test_case.stdout),
+            ("system-err", &test_case.stderr),
+        ] {
+            if !output.is_empty() {
+                let _ = writeln!(body, "      <{}>{}</{}>", element, escape(output), element,);
+            }
+        }
+
+        if body.is_empty() {
+            let _ = writeln!(report, "    <testcase {}/>", attributes);
+        } else {
+            let _ = writeln!(report, "    <testcase {}>", attributes);
+            report.push_str(&body);
+            let _ = writeln!(report, "    </testcase>");
+        }
+    }
+
+    let _ = writeln!(report, "  </testsuite>");
+    let _ = writeln!(report, "</testsuites>");
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::junit::{escape, render, Outcome, TestCase};
+    use std::time::Duration;
+
+    #[test]
+    fn escape_special_characters() {
+        assert_eq!(
+            escape("<a href=\"x\">Tom & Jerry's</a>\u{1b}[0m"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;[0m",
+        );
+    }
+
+    #[test]
+    fn render_empty() {
+        assert_eq!(
+            render("toast", &[]),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites tests="0" failures="0" skipped="0" time="0.000">
+  <testsuite name="toast" tests="0" failures="0" skipped="0" time="0.000">
+  </testsuite>
+</testsuites>
+"#,
+        );
+    }
+
+    #[test]
+    fn render_outcomes() {
+        let test_cases = vec![
+            TestCase {
+                name: "foo".to_owned(),
+                duration: Duration::from_millis(1500),
+                outcome: Outcome::Passed,
+                stdout: String::new(),
+                stderr: String::new(),
+            },
+            TestCase {
+                name: "bar".to_owned(),
+                duration: Duration::from_millis(250),
+                outcome: Outcome::Failed("Command failed.".to_owned()),
+                stdout: "Compiling\u{2026}\n".to_owned(),
+                stderr: "error: expected `;`\n".to_owned(),
+            },
+            TestCase {
+                name: "baz".to_owned(),
+                duration: Duration::default(),
+                outcome: Outcome::Skipped("Cached.".to_owned()),
+                stdout: String::new(),
+                stderr: String::new(),
+            },
+        ];
+
+        assert_eq!(
+            render("toast", &test_cases),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites tests="3" failures="1" skipped="1" time="1.750">
+  <testsuite name="toast" tests="3" failures="1" skipped="1" time="1.750">
+    <testcase name="foo" classname="toast" time="1.500"/>
+    <testcase name="bar" classname="toast" time="0.250">
+      <failure message="Command failed.">Command failed.</failure>
+      <system-out>Compiling…
+</system-out>
+      <system-err>error: expected `;`
+</system-err>
+    </testcase>
+    <testcase name="baz" classname="toast" time="0.000">
+      <skipped message="Cached."/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#,
+        );
+    }
+}
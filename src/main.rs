@@ -5,20 +5,30 @@ mod config;
 mod docker;
 mod failure;
 mod format;
+mod git;
+mod glob;
+mod json;
+mod junit;
+mod manifest;
+mod ports;
+mod progress;
 mod runner;
+mod scan_cache;
 mod schedule;
 mod spinner;
+mod tags;
 mod tar;
+mod telemetry;
 mod toastfile;
 
 use crate::{failure::Failure, format::CodeStr};
 use atty::Stream;
-use clap::{App, AppSettings, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
 use env_logger::{fmt::Color, Builder};
 use log::{Level, LevelFilter};
 use std::{
-    collections::{HashMap, HashSet},
-    convert::AsRef,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::{AsRef, TryFrom},
     default::Default,
     env,
     env::current_dir,
@@ -33,6 +43,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex,
     },
+    time::{Duration, Instant},
 };
 use toastfile::{default_task_mount_readonly, DEFAULT_USER};
 
@@ -51,19 +62,58 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 // Defaults
 const TOASTFILE_DEFAULT_NAME: &str = "toast.yml";
 const CONFIG_FILE_XDG_PATH: &str = "toast/toast.yml";
+const CACHE_DIR_XDG_PATH: &str = "toast";
 const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
 
+// Exit codes. These are a documented interface for scripts, so they must not change.
+// [tag:exit_codes]
+const EXIT_TASK_FAILURE: i32 = 1; // A task failed (e.g., its command returned a nonzero code).
+const EXIT_USER_ERROR: i32 = 2; // The arguments, config file, or toastfile are invalid.
+const EXIT_SYSTEM_ERROR: i32 = 3; // E.g., an image couldn't be pulled, or a cache operation failed.
+const EXIT_RUNTIME_UNAVAILABLE: i32 = 4; // Docker isn't installed, or its daemon can't be reached.
+const EXIT_INTERRUPTED: i32 = 130; // E.g., by SIGINT or SIGTERM
+
 // Command-line argument and option names
 const TOASTFILE_OPTION: &str = "file";
 const CONFIG_FILE_OPTION: &str = "config-file";
+const CONFIG_FILE_ENV_VAR: &str = "TOAST_CONFIG";
 const READ_LOCAL_CACHE_OPTION: &str = "read-local-cache";
 const WRITE_LOCAL_CACHE_OPTION: &str = "write-local-cache";
 const READ_REMOTE_CACHE_OPTION: &str = "read-remote-cache";
 const WRITE_REMOTE_CACHE_OPTION: &str = "write-remote-cache";
+const CACHE_CONCURRENCY_OPTION: &str = "cache-concurrency";
+const WRITE_MANIFESTS_OPTION: &str = "write-manifests";
+const SCAN_CACHE_OPTION: &str = "scan-cache";
+const SHOW_MANIFEST_OPTION: &str = "show-manifest";
+const REPAIR_MANIFESTS_OPTION: &str = "repair-manifests";
+const MANIFEST_KEY_FILE_OPTION: &str = "manifest-key-file";
+const ANNOTATION_OPTION: &str = "annotation";
 const REPO_OPTION: &str = "repo";
 const LIST_OPTION: &str = "list";
+const DOCTOR_OPTION: &str = "doctor";
 const SHELL_OPTION: &str = "shell";
+const INTERACTIVE_OPTION: &str = "interactive";
+const SINCE_OPTION: &str = "since";
+const TAGS_OPTION: &str = "tags";
+const TRACE_FILE_OPTION: &str = "trace-file";
+const PROFILE_OPTION: &str = "profile";
+const JUNIT_FILE_OPTION: &str = "junit-file";
+const PROGRESS_SOCKET_OPTION: &str = "progress-socket";
+const PRUNE_DANGLING_OPTION: &str = "prune-dangling";
+const DRY_RUN_OPTION: &str = "dry-run";
+const EXPLAIN_OPTION: &str = "explain";
+const FINGERPRINT_OPTION: &str = "fingerprint";
+const TRACE_HASHES_OPTION: &str = "trace-hashes";
+const OUTPUT_FORMAT_OPTION: &str = "output-format";
+const SUMMARY_ONLY_OPTION: &str = "summary-only";
+const STRICT_SIZE_OPTION: &str = "strict-size";
+const INCLUDE_CACHE_DIR_OPTION: &str = "include-cache-dir";
+const REQUIRE_INPUT_FILES_OPTION: &str = "require-input-files";
+const REQUIRE_PINNED_IMAGE_OPTION: &str = "require-pinned-image";
+const KEEP_GOING_ON_CACHE_ERROR_OPTION: &str = "keep-going-on-cache-error";
+const MAX_RETRIES_TOTAL_OPTION: &str = "max-retries-total";
 const TASKS_OPTION: &str = "tasks";
+const TASK_ARGUMENTS_OPTION: &str = "task-arguments";
 const FORCE_OPTION: &str = "force";
 
 // Set up the logger.
@@ -98,7 +148,7 @@ fn set_up_logging() {
                 buf,
                 "{} {}",
                 style.value(format!("[{}]", record.level())),
-                record.args().to_string(),
+                record.args(),
             )
         })
         .init();
@@ -119,7 +169,7 @@ fn set_up_signal_handlers(
         if interrupted.swap(true, Ordering::SeqCst) {
             // Stop any active containers. The `unwrap` will only fail if a panic already occurred.
             for container in &*active_containers.lock().unwrap() {
-                if let Err(e) = docker::stop_container(&container, &interrupted) {
+                if let Err(e) = docker::stop_container(container, &interrupted) {
                     error!("{}", e);
                 }
             }
@@ -145,25 +195,114 @@ fn parse_bool(s: &str) -> Result<bool, Failure> {
     }
 }
 
+// Convert a string (from a command-line argument) into a count.
+fn parse_count(s: &str) -> Result<u32, Failure> {
+    s.trim().parse().map_err(|_| {
+        Failure::User(
+            format!("{} is not a nonnegative integer.", s.code_str()),
+            None,
+        )
+    })
+}
+
+// Convert a string (from a command-line argument) of the form `NAME=value` into an annotation.
+fn parse_annotation(s: &str) -> Result<(String, String), Failure> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_owned(), value.to_owned()))
+        }
+        _ => Err(Failure::User(
+            format!(
+                "{} isn\u{2019}t of the form {}.",
+                s.code_str(),
+                "NAME=value".code_str(),
+            ),
+            None,
+        )),
+    }
+}
+
+// The ways Toast can report the outcome of a run
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum OutputFormat {
+    Human, // Just the log messages
+    Json,  // The log messages, plus a summary of the run as a JSON document on standard output
+}
+
+// Convert a string (from a command-line argument) into an output format.
+fn parse_output_format(s: &str) -> Result<OutputFormat, Failure> {
+    match s.trim().to_lowercase().as_ref() {
+        "human" => Ok(OutputFormat::Human),
+        "json" => Ok(OutputFormat::Json),
+        _ => Err(Failure::User(
+            format!(
+                "{} is not an output format. The supported formats are {} and {}.",
+                s.code_str(),
+                "human".code_str(),
+                "json".code_str(),
+            ),
+            None,
+        )),
+    }
+}
+
 // This struct represents the command-line arguments.
 #[allow(clippy::struct_excessive_bools)]
 pub struct Settings {
     toastfile_path: PathBuf,
     docker_repo: String,
+    cache_key_encoding: cache::KeyEncoding,
     read_local_cache: bool,
     write_local_cache: bool,
     read_remote_cache: bool,
     write_remote_cache: bool,
+    probe_remote_cache: bool,
+    remote_cache_probe_timeout: Duration,
+    prefetch_remote_cache: bool,
+    cache_concurrency: u32,
+    write_manifests: bool,
+    io_retries: u32,
+    case_insensitive_check: bool,
+    large_archive_size: u64,
+    large_archive_entries: u64,
+    archive_timeout: Option<Duration>,
+    scan_cache: Option<Arc<scan_cache::ScanCache>>,
+    strict_size: bool,
+    include_cache_dir: bool,
+    require_input_files: bool,
+    require_pinned_image: bool,
+    keep_going_on_cache_error: bool,
+    max_retries_total: u32,
+    cache_dir: Option<PathBuf>,
     list: bool,
+    show_manifest: Option<String>,
+    repair_manifests: bool,
+    manifest_key: Option<Vec<u8>>,
+    annotations: BTreeMap<String, String>,
     spawn_shell: bool,
+    interactive: Option<String>,
+    since: Option<String>,
+    tags: Option<tags::Expression>,
+    trace_file: Option<PathBuf>,
+    profile_file: Option<PathBuf>,
+    junit_file: Option<PathBuf>,
+    progress_socket: Option<PathBuf>,
+    prune_dangling: bool,
+    dry_run: bool,
+    explain: bool,
+    fingerprint: bool,
+    trace_hashes: bool,
+    output_format: OutputFormat,
+    summary_only: bool,
     tasks: Option<Vec<String>>,
     forced_tasks: Vec<String>,
+    task_arguments: Vec<String>,
 }
 
 // Parse the command-line arguments.
 #[allow(clippy::too_many_lines)]
-fn settings() -> Result<Settings, Failure> {
-    let matches = App::new("Toast")
+fn matches() -> ArgMatches<'static> {
+    App::new("Toast")
         .version(VERSION)
         .version_short("v")
         .author("Stephan Boyer <stephan@stephanboyer.com>")
@@ -183,6 +322,8 @@ fn settings() -> Result<Settings, Failure> {
                 .value_name("PATH")
                 .short("c")
                 .long(CONFIG_FILE_OPTION)
+                .visible_alias("config")
+                .env(CONFIG_FILE_ENV_VAR)
                 .help("Sets the path of the config file"),
         )
         .arg(
@@ -209,6 +350,24 @@ fn settings() -> Result<Settings, Failure> {
                 .long(WRITE_REMOTE_CACHE_OPTION)
                 .help("Sets whether remote cache writing is enabled"),
         )
+        .arg(
+            Arg::with_name(CACHE_CONCURRENCY_OPTION)
+                .value_name("COUNT")
+                .long(CACHE_CONCURRENCY_OPTION)
+                .help("Sets how many images are pulled at once when prefetching from remote cache"),
+        )
+        .arg(
+            Arg::with_name(WRITE_MANIFESTS_OPTION)
+                .value_name("BOOL")
+                .long(WRITE_MANIFESTS_OPTION)
+                .help("Sets whether manifests are written for cache entries"),
+        )
+        .arg(
+            Arg::with_name(SCAN_CACHE_OPTION)
+                .value_name("BOOL")
+                .long(SCAN_CACHE_OPTION)
+                .help("Sets whether unchanged input files reuse their hashes from earlier runs"),
+        )
         .arg(
             Arg::with_name(REPO_OPTION)
                 .value_name("REPO")
@@ -222,12 +381,155 @@ fn settings() -> Result<Settings, Failure> {
                 .long(LIST_OPTION)
                 .help("Lists the tasks in the toastfile"),
         )
+        .arg(
+            Arg::with_name(DOCTOR_OPTION)
+                .long(DOCTOR_OPTION)
+                .help("Checks whether Toast is set up correctly"),
+        )
+        .arg(
+            Arg::with_name(SHOW_MANIFEST_OPTION)
+                .value_name("KEY")
+                .long(SHOW_MANIFEST_OPTION)
+                .help("Shows the manifest for a cache key"),
+        )
+        .arg(
+            Arg::with_name(REPAIR_MANIFESTS_OPTION)
+                .long(REPAIR_MANIFESTS_OPTION)
+                .help("Deletes leftover temporary files and corrupt manifests from the cache"),
+        )
+        .arg(
+            Arg::with_name(MANIFEST_KEY_FILE_OPTION)
+                .value_name("PATH")
+                .long(MANIFEST_KEY_FILE_OPTION)
+                .help("Signs manifests (or verifies them when shown) with the key in a file"),
+        )
+        .arg(
+            Arg::with_name(ANNOTATION_OPTION)
+                .value_name("NAME=VALUE")
+                .long(ANNOTATION_OPTION)
+                .help("Records an annotation in the manifests of new cache entries")
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             Arg::with_name(SHELL_OPTION)
                 .short("s")
                 .long(SHELL_OPTION)
                 .help("Drops you into a shell after the tasks are finished"),
         )
+        .arg(
+            Arg::with_name(INTERACTIVE_OPTION)
+                .value_name("TASK")
+                .long(INTERACTIVE_OPTION)
+                .conflicts_with(TASKS_OPTION)
+                .help("Drops you into a shell where the task would run its command"),
+        )
+        .arg(
+            Arg::with_name(SINCE_OPTION)
+                .value_name("REF")
+                .long(SINCE_OPTION)
+                .help("Only runs tasks affected by files changed since a Git ref"),
+        )
+        .arg(
+            Arg::with_name(TAGS_OPTION)
+                .value_name("EXPRESSION")
+                .long(TAGS_OPTION)
+                .help("Only runs tasks whose tags match an expression"),
+        )
+        .arg(
+            Arg::with_name(TRACE_FILE_OPTION)
+                .value_name("PATH")
+                .long(TRACE_FILE_OPTION)
+                .help("Writes tracing spans to a file"),
+        )
+        .arg(
+            Arg::with_name(PROFILE_OPTION)
+                .value_name("PATH")
+                .long(PROFILE_OPTION)
+                .help("Writes a profile of reading the input files to a file, as folded stacks"),
+        )
+        .arg(
+            Arg::with_name(JUNIT_FILE_OPTION)
+                .value_name("PATH")
+                .long(JUNIT_FILE_OPTION)
+                .help("Writes a JUnit XML report of the tasks to a file"),
+        )
+        .arg(
+            Arg::with_name(PROGRESS_SOCKET_OPTION)
+                .value_name("PATH")
+                .long(PROGRESS_SOCKET_OPTION)
+                .help("Sends progress events to a Unix domain socket (or named pipe on Windows)"),
+        )
+        .arg(
+            Arg::with_name(PRUNE_DANGLING_OPTION)
+                .long(PRUNE_DANGLING_OPTION)
+                .help("Deletes local cache images which the toastfile can no longer produce"),
+        )
+        .arg(
+            Arg::with_name(DRY_RUN_OPTION)
+                .long(DRY_RUN_OPTION)
+                .requires(PRUNE_DANGLING_OPTION)
+                .help("Lists the images that would be deleted instead of deleting them"),
+        )
+        .arg(
+            Arg::with_name(EXPLAIN_OPTION)
+                .long(EXPLAIN_OPTION)
+                .help("Shows what went into the cache keys of the tasks instead of running them"),
+        )
+        .arg(
+            Arg::with_name(FINGERPRINT_OPTION)
+                .long(FINGERPRINT_OPTION)
+                .help("Prints one hash of the cache keys of the tasks instead of running them"),
+        )
+        .arg(
+            Arg::with_name(TRACE_HASHES_OPTION)
+                .long(TRACE_HASHES_OPTION)
+                .requires(EXPLAIN_OPTION)
+                .help("Logs each step of hashing the input files (requires --explain)"),
+        )
+        .arg(
+            Arg::with_name(STRICT_SIZE_OPTION)
+                .long(STRICT_SIZE_OPTION)
+                .help("Fails instead of warning when the input files for a task are too large"),
+        )
+        .arg(
+            Arg::with_name(INCLUDE_CACHE_DIR_OPTION)
+                .long(INCLUDE_CACHE_DIR_OPTION)
+                .help("Copies the Toast cache directory if it's among the input files"),
+        )
+        .arg(
+            Arg::with_name(REQUIRE_INPUT_FILES_OPTION)
+                .long(REQUIRE_INPUT_FILES_OPTION)
+                .help("Fails if the input paths for a task don't contain any files"),
+        )
+        .arg(
+            Arg::with_name(REQUIRE_PINNED_IMAGE_OPTION)
+                .long(REQUIRE_PINNED_IMAGE_OPTION)
+                .help("Fails unless the base image is pinned by digest"),
+        )
+        .arg(
+            Arg::with_name(KEEP_GOING_ON_CACHE_ERROR_OPTION)
+                .long(KEEP_GOING_ON_CACHE_ERROR_OPTION)
+                .help("Treats errors from the cache as misses instead of failing"),
+        )
+        .arg(
+            Arg::with_name(MAX_RETRIES_TOTAL_OPTION)
+                .value_name("COUNT")
+                .long(MAX_RETRIES_TOTAL_OPTION)
+                .help("Sets how many times failed tasks can be retried in total (default: 0)"),
+        )
+        .arg(
+            Arg::with_name(OUTPUT_FORMAT_OPTION)
+                .value_name("FORMAT")
+                .long(OUTPUT_FORMAT_OPTION)
+                .help("Sets the output format (human or json)"),
+        )
+        .arg(
+            Arg::with_name(SUMMARY_ONLY_OPTION)
+                .long(SUMMARY_ONLY_OPTION)
+                .visible_alias("quiet")
+                .help("Only prints warnings, errors, and a summary of the run"),
+        )
         .arg(
             Arg::with_name(FORCE_OPTION)
                 .value_name("TASK")
@@ -241,10 +543,28 @@ fn settings() -> Result<Settings, Failure> {
                 .help("Sets the tasks to run")
                 .multiple(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name(TASK_ARGUMENTS_OPTION)
+                .value_name("ARGS")
+                .help("Passes extra arguments (after `--`) to the command of the task")
+                .multiple(true)
+                .last(true),
+        )
+        .get_matches_safe()
+        .unwrap_or_else(|error| {
+            // Invalid arguments are user errors [ref:exit_codes], but `clap` would exit with
+            // `EXIT_TASK_FAILURE`. It exits successfully after printing the help or the version.
+            if error.use_stderr() {
+                eprintln!("{}", error.message);
+                exit(EXIT_USER_ERROR);
+            }
+            error.exit()
+        })
+}
 
-    // Find the toastfile.
-    let toastfile_path = matches.value_of(TOASTFILE_OPTION).map_or_else(
+// Find the toastfile.
+fn find_toastfile(matches: &ArgMatches) -> Result<PathBuf, Failure> {
+    matches.value_of(TOASTFILE_OPTION).map_or_else(
         || {
             let mut candidate_dir =
                 current_dir().map_err(failure::system("Unable to determine working directory."))?;
@@ -266,60 +586,123 @@ fn settings() -> Result<Settings, Failure> {
                 }
             }
         },
-        |x| Ok(Path::new(x).to_owned()),
-    )?;
+        |x| {
+            // All relative paths in the toastfile are relative to where the toastfile lives, so we
+            // make its path absolute. Otherwise, paths derived from it (e.g., the source paths of
+            // `mount_paths`) would be relative to the working directory instead.
+            let path = Path::new(x);
+            if path.is_absolute() {
+                Ok(path.to_owned())
+            } else {
+                Ok(current_dir()
+                    .map_err(failure::system("Unable to determine working directory."))?
+                    .join(path))
+            }
+        },
+    )
+}
 
+// Read the config file, or use the default configuration if there isn't one.
+fn read_config(matches: &ArgMatches) -> Result<config::Config, Failure> {
     // Read the config file path.
     let default_config_file_path = dirs::config_dir().map(|path| path.join(CONFIG_FILE_XDG_PATH));
-    let config_file_path = matches.value_of(CONFIG_FILE_OPTION).map_or_else(
-        || default_config_file_path,
-        |path| Some(PathBuf::from(path)),
-    );
-
-    // Parse the config file.
-    let config_data = config_file_path
-        .as_ref()
-        .and_then(|path| {
-            debug!(
-                "Attempting to load configuration file {}\u{2026}",
-                path.to_string_lossy().code_str(),
-            );
-            fs::read_to_string(path).ok()
-        })
-        .map_or_else(
-            || {
-                debug!("Configuration file not found. Using the default configuration.");
-                config::EMPTY_CONFIG.to_owned()
-            },
-            |data| {
-                debug!("Found it.");
-                data
-            },
-        );
-    let config = config::parse(&config_data).map_err(failure::user(format!(
+    let explicit_config_file_path = matches.value_of(CONFIG_FILE_OPTION).map(PathBuf::from);
+    let config_file_path = explicit_config_file_path
+        .clone()
+        .or(default_config_file_path);
+
+    // Parse the config file. A config file that was chosen explicitly (with `--config-file` or
+    // `TOAST_CONFIG`) must exist, whereas the one in the default location is optional.
+    let config_data = if let Some(path) = &explicit_config_file_path {
+        fs::read_to_string(path).map_err(failure::user(format!(
+            "Unable to read config file {}.",
+            path.to_string_lossy().code_str(),
+        )))?
+    } else {
+        config_file_path
+            .as_ref()
+            .and_then(|path| {
+                debug!(
+                    "Attempting to load configuration file {}\u{2026}",
+                    path.to_string_lossy().code_str(),
+                );
+                fs::read_to_string(path).ok()
+            })
+            .map_or_else(
+                || {
+                    debug!("Configuration file not found. Using the default configuration.");
+                    config::EMPTY_CONFIG.to_owned()
+                },
+                |data| {
+                    debug!("Found it.");
+                    data
+                },
+            )
+    };
+    config::parse(&config_data).map_err(failure::user(format!(
         "Unable to parse file {}.",
         config_file_path
             .as_ref()
             .unwrap() // Manually verified safe
             .to_string_lossy()
             .code_str(),
-    )))?;
+    )))
+}
+
+// Determine where the cache directory is.
+fn default_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|path| path.join(CACHE_DIR_XDG_PATH))
+}
+
+// Interpret the command-line arguments and the config file.
+#[allow(clippy::too_many_lines)]
+fn settings(matches: &ArgMatches) -> Result<Settings, Failure> {
+    // Find the toastfile.
+    let toastfile_path = find_toastfile(matches)?;
+
+    // Read the config file.
+    let config = read_config(matches)?;
 
     // Read the local caching switches.
     let read_local_cache = matches
         .value_of(READ_LOCAL_CACHE_OPTION)
-        .map_or(Ok(config.read_local_cache), |s| parse_bool(s))?;
+        .map_or(Ok(config.read_local_cache), parse_bool)?;
     let write_local_cache = matches
         .value_of(WRITE_LOCAL_CACHE_OPTION)
-        .map_or(Ok(config.write_local_cache), |s| parse_bool(s))?;
+        .map_or(Ok(config.write_local_cache), parse_bool)?;
 
     // Read the remote caching switches.
     let read_remote_cache = matches
         .value_of(READ_REMOTE_CACHE_OPTION)
-        .map_or(Ok(config.read_remote_cache), |s| parse_bool(s))?;
+        .map_or(Ok(config.read_remote_cache), parse_bool)?;
     let write_remote_cache = matches
         .value_of(WRITE_REMOTE_CACHE_OPTION)
-        .map_or(Ok(config.write_remote_cache), |s| parse_bool(s))?;
+        .map_or(Ok(config.write_remote_cache), parse_bool)?;
+
+    // Read how many images can be pulled at once when prefetching.
+    let cache_concurrency = matches
+        .value_of(CACHE_CONCURRENCY_OPTION)
+        .map_or(Ok(config.cache_concurrency), parse_count)?;
+
+    // Read the manifest switch.
+    let write_manifests = matches
+        .value_of(WRITE_MANIFESTS_OPTION)
+        .map_or(Ok(config.write_manifests), parse_bool)?;
+
+    // Determine where the cache directory is.
+    let cache_dir = default_cache_dir();
+
+    // Read the scan cache switch, and load the scan cache if it's enabled.
+    let scan_cache = if matches
+        .value_of(SCAN_CACHE_OPTION)
+        .map_or(Ok(config.scan_cache), parse_bool)?
+    {
+        cache_dir
+            .as_deref()
+            .map(|cache_dir| Arc::new(scan_cache::ScanCache::load(cache_dir)))
+    } else {
+        None
+    };
 
     // Read the Docker repo.
     let docker_repo = matches
@@ -330,15 +713,142 @@ fn settings() -> Result<Settings, Failure> {
     // Read the list switch.
     let list = matches.is_present(LIST_OPTION);
 
+    // Read the cache key for which to show the manifest, if any.
+    let show_manifest = matches
+        .value_of(SHOW_MANIFEST_OPTION)
+        .map(std::borrow::ToOwned::to_owned);
+
+    // Read the switch for repairing the manifests.
+    let repair_manifests = matches.is_present(REPAIR_MANIFESTS_OPTION);
+
+    // Read the key for signing manifests, if any. Trailing whitespace (e.g., a final newline) isn't
+    // part of the key.
+    let manifest_key = matches
+        .value_of(MANIFEST_KEY_FILE_OPTION)
+        .map(|path| {
+            let mut key = fs::read(path).map_err(failure::user(format!(
+                "Unable to read file {}.",
+                path.code_str(),
+            )))?;
+            while key.last().map_or(false, u8::is_ascii_whitespace) {
+                key.pop();
+            }
+            if key.is_empty() {
+                return Err(Failure::User(
+                    format!("The key in file {} is empty.", path.code_str()),
+                    None,
+                ));
+            }
+            Ok(key)
+        })
+        .transpose()?;
+
+    // Read the annotations for new manifests. If a name is given more than once, the last value
+    // wins.
+    let annotations = matches
+        .values_of(ANNOTATION_OPTION)
+        .into_iter()
+        .flatten()
+        .map(parse_annotation)
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+    // Annotations are only recorded in manifests, so without those they'd be silently dropped.
+    if !annotations.is_empty() && !write_manifests {
+        return Err(Failure::User(
+            format!(
+                "{} has no effect unless manifests are written. Set {} in the configuration \
+                 file or pass {}.",
+                format!("--{}", ANNOTATION_OPTION).code_str(),
+                "write_manifests: true".code_str(),
+                format!("--{} true", WRITE_MANIFESTS_OPTION).code_str(),
+            ),
+            None,
+        ));
+    }
+
+    // Read the task to debug interactively, if any. It's run instead of any other tasks, and the
+    // shell replaces its command.
+    let interactive = matches.value_of(INTERACTIVE_OPTION).map(ToOwned::to_owned);
+
     // Read the shell switch.
-    let spawn_shell = matches.is_present(SHELL_OPTION);
+    let spawn_shell = matches.is_present(SHELL_OPTION) || interactive.is_some();
+
+    // Read the Git ref to compare against, if any.
+    let since = matches
+        .value_of(SINCE_OPTION)
+        .map(std::borrow::ToOwned::to_owned);
+
+    // Parse the tag expression, if any.
+    let tags = matches.value_of(TAGS_OPTION).map(tags::parse).transpose()?;
+
+    // Read the path of the file for tracing spans, if any.
+    let trace_file = matches.value_of(TRACE_FILE_OPTION).map(PathBuf::from);
+
+    // Read the path of the file for the profile of reading input files, if any.
+    let profile_file = matches.value_of(PROFILE_OPTION).map(PathBuf::from);
+
+    // Read the path to the JUnit report, if any.
+    let junit_file = matches.value_of(JUNIT_FILE_OPTION).map(PathBuf::from);
+
+    // Read the path of the socket for progress events, if any.
+    let progress_socket = matches.value_of(PROGRESS_SOCKET_OPTION).map(PathBuf::from);
+
+    // Read the pruning switches.
+    let prune_dangling = matches.is_present(PRUNE_DANGLING_OPTION);
+    let dry_run = matches.is_present(DRY_RUN_OPTION);
+
+    // Read the explain switch.
+    let explain = matches.is_present(EXPLAIN_OPTION);
+    let fingerprint = matches.is_present(FINGERPRINT_OPTION);
+    let trace_hashes = matches.is_present(TRACE_HASHES_OPTION);
+
+    // Read the switch for oversized input files.
+    let strict_size = matches.is_present(STRICT_SIZE_OPTION);
+
+    // Read the switch for the cache directory among the input files.
+    let include_cache_dir = matches.is_present(INCLUDE_CACHE_DIR_OPTION);
+
+    // Read the switch for input paths without any files.
+    let require_input_files = matches.is_present(REQUIRE_INPUT_FILES_OPTION);
+
+    // Read the switch for unpinned base images.
+    let require_pinned_image = matches.is_present(REQUIRE_PINNED_IMAGE_OPTION);
+
+    // Read the switch for cache errors.
+    let keep_going_on_cache_error = matches.is_present(KEEP_GOING_ON_CACHE_ERROR_OPTION);
+
+    // Read the retry budget.
+    let max_retries_total = matches
+        .value_of(MAX_RETRIES_TOTAL_OPTION)
+        .map_or(Ok(0), parse_count)?;
+
+    // Read the output format.
+    let output_format = matches
+        .value_of(OUTPUT_FORMAT_OPTION)
+        .map_or(Ok(OutputFormat::Human), parse_output_format)?;
+
+    // Read the switch for quiet output.
+    let summary_only = matches.is_present(SUMMARY_ONLY_OPTION);
 
     // Read the list of tasks.
-    let tasks = matches.values_of(TASKS_OPTION).map(|tasks| {
-        tasks
-            .map(std::borrow::ToOwned::to_owned)
-            .collect::<Vec<_>>()
-    });
+    let tasks = matches
+        .values_of(TASKS_OPTION)
+        .map(|tasks| {
+            tasks
+                .map(std::borrow::ToOwned::to_owned)
+                .collect::<Vec<_>>()
+        })
+        .or_else(|| interactive.clone().map(|task| vec![task]));
+
+    // Read the extra arguments for the command of the task, if any.
+    let task_arguments =
+        matches
+            .values_of(TASK_ARGUMENTS_OPTION)
+            .map_or_else(Vec::new, |arguments| {
+                arguments
+                    .map(std::borrow::ToOwned::to_owned)
+                    .collect::<Vec<_>>()
+            });
 
     // Read the list of forced tasks.
     let forced_tasks = matches
@@ -352,14 +862,52 @@ fn settings() -> Result<Settings, Failure> {
     Ok(Settings {
         toastfile_path,
         docker_repo,
+        cache_key_encoding: config.cache_key_encoding,
         read_local_cache,
         write_local_cache,
         read_remote_cache,
         write_remote_cache,
+        probe_remote_cache: config.probe_remote_cache,
+        remote_cache_probe_timeout: Duration::from_secs(config.remote_cache_probe_timeout),
+        prefetch_remote_cache: config.prefetch_remote_cache,
+        cache_concurrency,
+        write_manifests,
+        io_retries: config.io_retries,
+        case_insensitive_check: config.case_insensitive_check,
+        large_archive_size: config.large_archive_size,
+        large_archive_entries: config.large_archive_entries,
+        archive_timeout: config.archive_timeout.map(Duration::from_secs),
+        scan_cache,
+        strict_size,
+        include_cache_dir,
+        require_input_files,
+        require_pinned_image,
+        keep_going_on_cache_error,
+        max_retries_total,
+        cache_dir,
         list,
+        show_manifest,
+        repair_manifests,
+        manifest_key,
+        annotations,
         spawn_shell,
+        interactive,
+        since,
+        tags,
+        trace_file,
+        profile_file,
+        junit_file,
+        progress_socket,
+        prune_dangling,
+        dry_run,
+        explain,
+        fingerprint,
+        trace_hashes,
+        output_format,
+        summary_only,
         tasks,
         forced_tasks,
+        task_arguments,
     })
 }
 
@@ -372,92 +920,518 @@ fn parse_toastfile(toastfile_path: &Path) -> Result<toastfile::Toastfile, Failur
     )))?;
 
     // Parse it.
-    toastfile::parse(&toastfile_data).map_err(failure::user(format!(
+    let mut toastfile = toastfile::parse(&toastfile_data).map_err(failure::user(format!(
         "Unable to parse file {}.",
         toastfile_path.to_string_lossy().code_str(),
-    )))
+    )))?;
+
+    // Add the variables from each `env_file` to the `environment` of its task. The defaults in
+    // `environment` take precedence, and the environment of the host takes precedence over both.
+    let toastfile_dir = toastfile_path.parent().unwrap_or_else(|| Path::new(""));
+    for task in toastfile.tasks.values_mut() {
+        if let Some(env_file) = &task.env_file {
+            let env_file_path = toastfile_dir.join(env_file);
+            let env_file_data =
+                fs::read_to_string(&env_file_path).map_err(failure::user(format!(
+                    "Unable to read file {}.",
+                    env_file_path.to_string_lossy().code_str(),
+                )))?;
+
+            for (name, value) in
+                toastfile::parse_env_file(&env_file_data).map_err(failure::user(format!(
+                    "Unable to parse file {}.",
+                    env_file_path.to_string_lossy().code_str(),
+                )))?
+            {
+                task.environment
+                    .entry(name)
+                    .or_default()
+                    .get_or_insert(value);
+            }
+        }
+    }
+
+    Ok(toastfile)
 }
 
-// Determine which tasks the user wants to run.
-fn get_roots<'a>(
-    settings: &'a Settings,
-    toastfile: &'a toastfile::Toastfile,
-) -> Result<Vec<&'a str>, Failure> {
-    // Start with the tasks provided via positional arguments.
-    let mut roots: Vec<&'a str> = settings
-        .tasks
-        .as_ref()
-        .map_or_else(Vec::new, |tasks| tasks.iter().map(AsRef::as_ref).collect());
+// Determine where the cache directory is, or fail if it can't be determined.
+fn cache_dir(settings: &Settings) -> Result<&Path, Failure> {
+    settings.cache_dir.as_deref().ok_or_else(|| {
+        Failure::System(
+            "Unable to determine the location of the cache directory.".to_owned(),
+            None,
+        )
+    })
+}
 
-    // Add the tasks that were provided via the `--force` flag.
-    roots.extend(
-        settings
-            .forced_tasks
-            .iter()
-            .map(AsRef::as_ref)
-            .collect::<Vec<&'a str>>(),
+// Delete the files in the cache directory which were left behind or corrupted by an unclean
+// shutdown.
+fn repair_manifests(settings: &Settings) -> Result<(), Failure> {
+    let deleted = manifest::repair(cache_dir(settings)?, settings.manifest_key.as_deref())?;
+
+    for path in &deleted {
+        println!("* {}", path.to_string_lossy().code_str());
+    }
+
+    info!("Deleted {}.", format::number(deleted.len(), "corrupt file"));
+
+    Ok(())
+}
+
+// Print the manifest for a cache key.
+fn show_manifest(settings: &Settings, key: &str) -> Result<(), Failure> {
+    let manifest = manifest::read(cache_dir(settings)?, key)?;
+
+    // If there's a key, make sure the manifest was signed with it before showing anything.
+    if let Some(manifest_key) = &settings.manifest_key {
+        manifest::verify(&manifest, manifest_key)?;
+        info!("The signature of the manifest is valid.");
+    }
+
+    info!(
+        "Here are the paths in image {} and their hashes:",
+        manifest.image.code_str(),
     );
 
-    // For convenience, there is some special behavior for the empty case.
-    if roots.is_empty() {
-        // The user didn't provide any tasks. Check if there is a default task.
-        if let Some(default) = &toastfile.default {
-            // There is a default. Use it.
-            Ok(vec![default.as_ref()])
-        } else {
-            // There is no default. Run all the tasks.
-            Ok(toastfile
-                .tasks
-                .keys()
-                .map(AsRef::as_ref)
-                .collect::<Vec<_>>())
-        }
-    } else {
-        // The user provided some tasks. Check that they exist.
-        for task in &roots {
-            if !toastfile.tasks.contains_key(*task) {
-                // [tag:tasks_valid]
-                return Err(Failure::User(
-                    format!(
-                        "No task named {} in {}.",
-                        task.code_str(),
-                        settings.toastfile_path.to_string_lossy().code_str(),
-                    ),
-                    None,
-                ));
-            }
+    for (path, entry) in &manifest.paths {
+        match entry.size() {
+            Some(size) => println!(
+                "* {} \u{2014} {} ({})",
+                path.code_str(),
+                entry.hash(),
+                format::bytes(size),
+            ),
+            None => println!("* {} \u{2014} {}", path.code_str(), entry.hash()),
         }
+    }
 
-        // Run the tasks that the user provided.
-        Ok(roots)
+    if !manifest.annotations.is_empty() {
+        info!("Here are the annotations:");
+
+        for (name, value) in &manifest.annotations {
+            println!("* {} \u{2014} {}", name.code_str(), value);
+        }
     }
+
+    Ok(())
 }
 
-// Fetch all the environment variables used by the tasks in the schedule.
-fn fetch_environment(
-    schedule: &[&str],
-    tasks: &HashMap<String, toastfile::Task>,
-) -> Result<HashMap<String, String>, Failure> {
-    let mut env = HashMap::new();
-    let mut violations = HashMap::new();
+// Determine how many bytes are available to unprivileged users on the filesystem containing a path.
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)] // The types of the `statvfs` fields vary by platform.
+fn free_space(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
 
-    for task in schedule {
-        match toastfile::environment(&tasks[*task]) {
-            // [ref:tasks_valid]
-            Ok(env_for_task) => {
-                env.extend(env_for_task);
-            }
-            Err(vars) => {
-                violations.insert((*task).to_owned(), vars);
-            }
-        }
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats = MaybeUninit::<libc::statvfs>::uninit();
+
+    // The `unsafe` is needed to call into libc. `statvfs` initializes `stats` if it succeeds, and
+    // `stats` isn't read otherwise.
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } == 0 {
+        let stats = unsafe { stats.assume_init() };
+        Some(u64::from(stats.f_bavail) * u64::from(stats.f_frsize))
+    } else {
+        None
     }
+}
 
-    if !violations.is_empty() {
-        // [tag:environment_valid]
-        return Err(Failure::User(
-            format!(
-                "The following tasks use variables which are missing from the environment: {}.",
+#[cfg(windows)]
+fn free_space(_path: &Path) -> Option<u64> {
+    // This isn't supported on Windows yet.
+    None
+}
+
+// Check that the cache directory exists (creating it if necessary, as Toast would) and that it's
+// writable. Return a description of the free space, if it's known.
+fn check_cache_dir(cache_dir: &Path) -> Result<String, Failure> {
+    fs::create_dir_all(cache_dir).map_err(failure::user(format!(
+        "Unable to create directory {}.",
+        cache_dir.to_string_lossy().code_str(),
+    )))?;
+    tempfile::tempfile_in(cache_dir).map_err(failure::user(format!(
+        "Unable to write to directory {}.",
+        cache_dir.to_string_lossy().code_str(),
+    )))?;
+
+    Ok(free_space(cache_dir).map_or_else(
+        || "the free space is unknown".to_owned(),
+        |bytes| format!("{} free", format::bytes(bytes)),
+    ))
+}
+
+// Check the things Toast needs in order to work, and report what's wrong with each one along with a
+// suggestion for fixing it. Each check runs even if the ones before it failed.
+fn doctor(matches: &ArgMatches, interrupted: &Arc<AtomicBool>) -> Result<(), Failure> {
+    let mut failed_checks = 0_usize;
+    let mut report = |result: Result<String, (Failure, String)>| match result {
+        Ok(message) => info!("{}", message),
+        Err((Failure::Interrupted, _)) => {}
+        Err((e, hint)) => {
+            failed_checks += 1;
+            error!("{} {}", e.to_string().trim_end(), hint);
+        }
+    };
+
+    // Check that Docker is installed and the daemon is running.
+    report(
+        docker::client_version(interrupted)
+            .map(|version| format!("Docker is installed ({}).", version.code_str()))
+            .map_err(|e| {
+                (
+                    e,
+                    format!(
+                        "Make sure Docker is installed and {} is on your {}.",
+                        "docker".code_str(),
+                        "PATH".code_str(),
+                    ),
+                )
+            }),
+    );
+    report(
+        docker::server_version(interrupted)
+            .map(|version| {
+                format!(
+                    "The Docker daemon is running (version {}).",
+                    version.code_str()
+                )
+            })
+            .map_err(|e| {
+                (
+                    e,
+                    "Make sure the Docker daemon is running and that you have permission to use it."
+                        .to_owned(),
+                )
+            }),
+    );
+
+    // Check that the cache directory is usable.
+    report(
+        default_cache_dir()
+            .ok_or_else(|| {
+                Failure::System(
+                    "Unable to determine the location of the cache directory.".to_owned(),
+                    None,
+                )
+            })
+            .and_then(|cache_dir| {
+                check_cache_dir(&cache_dir).map(|free_space| {
+                    format!(
+                        "The cache directory {} is writable ({}).",
+                        cache_dir.to_string_lossy().code_str(),
+                        free_space,
+                    )
+                })
+            })
+            .map_err(|e| {
+                (
+                    e,
+                    "Make sure you have permission to write to it.".to_owned(),
+                )
+            }),
+    );
+
+    // Check that the config file and toastfile can be read.
+    report(
+        read_config(matches)
+            .map(|_| "The configuration is valid.".to_owned())
+            .map_err(|e| {
+                (
+                    e,
+                    "See the README for the configuration file format.".to_owned(),
+                )
+            }),
+    );
+    report(
+        find_toastfile(matches)
+            .and_then(|toastfile_path| {
+                parse_toastfile(&toastfile_path)?;
+                Ok(format!(
+                    "The toastfile {} is valid.",
+                    toastfile_path.to_string_lossy().code_str(),
+                ))
+            })
+            .map_err(|e| {
+                (
+                    e,
+                    format!(
+                        "Run Toast in a directory containing a valid {}, or pass {}.",
+                        TOASTFILE_DEFAULT_NAME.code_str(),
+                        format!("--{}", TOASTFILE_OPTION).code_str(),
+                    ),
+                )
+            }),
+    );
+
+    if interrupted.load(Ordering::SeqCst) {
+        return Err(Failure::Interrupted);
+    }
+
+    if failed_checks == 0 {
+        info!("Everything looks good.");
+        Ok(())
+    } else {
+        Err(Failure::User(
+            format!("{} failed.", format::number(failed_checks, "check")),
+            None,
+        ))
+    }
+}
+
+// Remove the root tasks which aren't affected by the files that changed since the given Git ref.
+// Forced tasks are always kept. The dependencies of the remaining tasks will still be scheduled,
+// but they're likely to be cached.
+fn filter_unaffected_roots(
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    since: &str,
+    roots: &mut Vec<&str>,
+) -> Result<(), Failure> {
+    // Canonicalize the toastfile path, since Git reports canonical paths. The `unwrap` is safe
+    // because the toastfile path refers to a file, so it has a parent.
+    let toastfile_path =
+        fs::canonicalize(&settings.toastfile_path).map_err(failure::system(format!(
+            "Unable to resolve path {}.",
+            settings.toastfile_path.to_string_lossy().code_str(),
+        )))?;
+    let toastfile_dir = toastfile_path.parent().unwrap();
+
+    // Ask Git what changed.
+    let changed_paths = git::changed_paths(toastfile_dir, since)?;
+
+    // If the toastfile itself changed, any task could be affected.
+    if changed_paths.contains(&toastfile_path) {
+        debug!("The toastfile changed, so all tasks are considered affected.");
+        return Ok(());
+    }
+
+    // Keep only the affected tasks.
+    let affected = schedule::affected(toastfile, toastfile_dir, &changed_paths);
+    roots.retain(|task| {
+        affected.contains(task) || settings.forced_tasks.iter().any(|forced| forced == task)
+    });
+
+    if roots.is_empty() {
+        info!(
+            "No tasks are affected by the changes since {}.",
+            since.code_str(),
+        );
+    }
+
+    Ok(())
+}
+
+// Remove the root tasks whose tags don't match the given expression. Forced tasks are always kept.
+// The dependencies of the remaining tasks will still be scheduled, regardless of their tags.
+fn filter_untagged_roots(
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    expression: &tags::Expression,
+    roots: &mut Vec<&str>,
+) {
+    // The tasks are known to exist. [ref:tasks_valid]
+    roots.retain(|task| {
+        expression.matches(&toastfile.tasks[*task].tags)
+            || settings.forced_tasks.iter().any(|forced| forced == task)
+    });
+
+    if roots.is_empty() {
+        info!("No tasks match the tag expression.");
+    }
+}
+
+// Pass the extra arguments from the command line to the command of the task they're for, which is
+// the only task given on the command line or else the default task. Since they change the command,
+// they're part of the cache key.
+fn apply_task_arguments(
+    settings: &Settings,
+    toastfile: &mut toastfile::Toastfile,
+) -> Result<(), Failure> {
+    if settings.task_arguments.is_empty() {
+        return Ok(());
+    }
+
+    // With `--interactive`, the command doesn't run, so the arguments would be silently dropped.
+    if settings.interactive.is_some() {
+        return Err(Failure::User(
+            format!(
+                "Extra arguments after {} can\u{2019}t be used with {}, since the command of the \
+                 task doesn\u{2019}t run.",
+                "--".code_str(),
+                format!("--{}", INTERACTIVE_OPTION).code_str(),
+            ),
+            None,
+        ));
+    }
+
+    let mut named_tasks = settings
+        .tasks
+        .iter()
+        .flatten()
+        .chain(&settings.forced_tasks)
+        .collect::<Vec<_>>();
+    named_tasks.sort();
+    named_tasks.dedup();
+
+    // Everything after `--` is an argument for the task, so `toast -- foo` doesn't run a task named
+    // `foo`. If no task is named and there's no default, that's probably what the user meant.
+    let task_name = match named_tasks.as_slice() {
+        [task_name] => (*task_name).clone(),
+        [] if settings.tags.is_none() => toastfile.default.clone().ok_or_else(|| {
+            Failure::User(
+                format!(
+                    "Extra arguments after {} are for the task named before them (or the default \
+                     task), but no task is named and there\u{2019}s no default task. Note that \
+                     everything after {} is an argument, not a task, e.g., {}.",
+                    "--".code_str(),
+                    "--".code_str(),
+                    "toast build -- --release".code_str(),
+                ),
+                None,
+            )
+        })?,
+        _ => {
+            return Err(Failure::User(
+                format!(
+                    "Extra arguments after {} can only be passed to a single task.",
+                    "--".code_str(),
+                ),
+                None,
+            ));
+        }
+    };
+
+    // If the task doesn't exist, `get_roots` will report it [ref:tasks_valid].
+    if let Some(task) = toastfile.tasks.get_mut(&task_name) {
+        task.command = task
+            .command
+            .with_arguments(&settings.task_arguments, task.use_entrypoint);
+    }
+
+    Ok(())
+}
+
+// With `--interactive`, the task runs without its command, so the shell that follows starts in the
+// same container the command would have run in: the same base image and input files, plus the
+// environment, location, user, mounts, and ports the shell always gets from the last task. Nothing
+// the task would produce is expected, and the result isn't cached, since it isn't what the task
+// would produce. Tasks with a read-only root filesystem are rejected, since their input files are
+// copied into anonymous volumes for the `writable_paths`, which the shell wouldn't get.
+fn prepare_interactive(
+    settings: &Settings,
+    toastfile: &mut toastfile::Toastfile,
+) -> Result<(), Failure> {
+    // If the task doesn't exist, `get_roots` will report it [ref:tasks_valid].
+    if let Some((task_name, task)) = settings.interactive.as_ref().and_then(|task_name| {
+        toastfile
+            .tasks
+            .get_mut(task_name)
+            .map(|task| (task_name, task))
+    }) {
+        if task.read_only {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has a read-only root filesystem, so it can\u{2019}t be debugged with \
+                     {}. Its input files would be missing from the shell.",
+                    task_name.code_str(),
+                    format!("--{}", INTERACTIVE_OPTION).code_str(),
+                ),
+                None,
+            ));
+        }
+
+        task.cache = false;
+        task.command = toastfile::Command::default();
+        task.use_entrypoint = false;
+        task.allowed_exit_codes.clear();
+        task.expect_outputs.clear();
+        task.output_paths.clear();
+        task.output_paths_on_failure.clear();
+    }
+
+    Ok(())
+}
+
+// Determine which tasks the user wants to run.
+fn get_roots<'a>(
+    settings: &'a Settings,
+    toastfile: &'a toastfile::Toastfile,
+) -> Result<Vec<&'a str>, Failure> {
+    // Start with the tasks provided via positional arguments.
+    let mut roots: Vec<&'a str> = settings
+        .tasks
+        .as_ref()
+        .map_or_else(Vec::new, |tasks| tasks.iter().map(AsRef::as_ref).collect());
+
+    // Add the tasks that were provided via the `--force` flag.
+    roots.extend(
+        settings
+            .forced_tasks
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<&'a str>>(),
+    );
+
+    // For convenience, there is some special behavior for the empty case.
+    if roots.is_empty() {
+        // The user didn't provide any tasks. Check if there is a default task. If the tasks are
+        // being selected by tag, the default doesn't apply.
+        if let (Some(default), None) = (&toastfile.default, &settings.tags) {
+            // There is a default. Use it.
+            Ok(vec![default.as_ref()])
+        } else {
+            // There is no default. Run all the tasks.
+            Ok(toastfile
+                .tasks
+                .keys()
+                .map(AsRef::as_ref)
+                .collect::<Vec<_>>())
+        }
+    } else {
+        // The user provided some tasks. Check that they exist.
+        for task in &roots {
+            if !toastfile.tasks.contains_key(*task) {
+                // [tag:tasks_valid]
+                return Err(Failure::User(
+                    format!(
+                        "No task named {} in {}.",
+                        task.code_str(),
+                        settings.toastfile_path.to_string_lossy().code_str(),
+                    ),
+                    None,
+                ));
+            }
+        }
+
+        // Run the tasks that the user provided.
+        Ok(roots)
+    }
+}
+
+// Fetch all the environment variables used by the tasks in the schedule.
+fn fetch_environment(
+    schedule: &[&str],
+    tasks: &HashMap<String, toastfile::Task>,
+) -> Result<HashMap<String, String>, Failure> {
+    let mut env = HashMap::new();
+    let mut violations = HashMap::new();
+
+    for task in schedule {
+        match toastfile::environment(&tasks[*task]) {
+            // [ref:tasks_valid]
+            Ok(env_for_task) => {
+                env.extend(env_for_task);
+            }
+            Err(vars) => {
+                violations.insert((*task).to_owned(), vars);
+            }
+        }
+    }
+
+    if !violations.is_empty() {
+        // [tag:environment_valid]
+        return Err(Failure::User(
+            format!(
+                "The following tasks use variables which are missing from the environment: {}.",
                 format::series(
                     violations
                         .iter()
@@ -484,6 +1458,341 @@ fn fetch_environment(
     Ok(env)
 }
 
+// Pull the latest version of an image and return its ID, which can be used in place of the image
+// name. If that fails for any reason other than an interruption (e.g., because there's no network
+// connection), fall back to the ID of the local copy of the image if it was pulled from a registry
+// before, or to the image name otherwise.
+fn resolve_image_digest(image: &str, interrupted: &Arc<AtomicBool>) -> Result<String, Failure> {
+    // An image pinned by digest can't change, so its name is as good as its ID.
+    if toastfile::image_digest(image).is_some() {
+        return Ok(image.to_owned());
+    }
+
+    // Check for a local copy first, since it's what to fall back to if the pull fails. A copy
+    // without repository digests was built or tagged locally, so it may not be the image from the
+    // registry at all.
+    let local_copy = docker::image_repo_digests(image, interrupted)?
+        .map_or(false, |repo_digests| !repo_digests.is_empty());
+
+    // Pulling an image which is already up to date only fetches its manifest.
+    match docker::pull_image(image, interrupted).and_then(|()| docker::image_id(image, interrupted))
+    {
+        Ok(id) => {
+            debug!("Image {} has ID {}.", image.code_str(), id.code_str());
+            Ok(id)
+        }
+        Err(Failure::Interrupted) => Err(Failure::Interrupted),
+        Err(e) => {
+            if local_copy {
+                if let Ok(id) = docker::image_id(image, interrupted) {
+                    warn!(
+                        "Unable to pull image {}, so the cache keys will be based on the local \
+                         copy, which may be out of date. Reason: {}",
+                        image.code_str(),
+                        e,
+                    );
+                    return Ok(id);
+                }
+            }
+
+            warn!(
+                "Unable to resolve the digest of image {}, so the cache keys will be based on the \
+                 image name instead. Reason: {}",
+                image.code_str(),
+                e,
+            );
+            Ok(image.to_owned())
+        }
+    }
+}
+
+// Check that the base image is pinned by digest if the user requires it. Otherwise, just warn if it
+// refers to the `latest` tag, which is the most likely to change, unless the cache keys are based
+// on the image digest anyway.
+fn check_image_pinned(
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+) -> Result<(), Failure> {
+    if toastfile::image_digest(&toastfile.image).is_some() {
+        return Ok(());
+    }
+
+    if settings.require_pinned_image {
+        return Err(Failure::User(
+            format!(
+                "Image {} isn't pinned by digest (e.g., {}).",
+                toastfile.image.code_str(),
+                format!("{}@sha256:\u{2026}", toastfile.image).code_str(),
+            ),
+            None,
+        ));
+    }
+
+    if toastfile::image_tag(&toastfile.image) == "latest" && !toastfile.resolve_image_digest {
+        warn!(
+            "Image {} refers to the {} tag, which can change without invalidating the cache. \
+             Consider pinning it by digest (e.g., {}) or enabling {}.",
+            toastfile.image.code_str(),
+            "latest".code_str(),
+            format!("{}@sha256:\u{2026}", toastfile.image).code_str(),
+            "resolve_image_digest".code_str(),
+        );
+    }
+
+    Ok(())
+}
+
+// Delete the local cache images for the toastfile which it can no longer produce. Cache keys depend
+// on which tasks were scheduled before, so we compute the keys for the requested tasks as well as
+// for each task on its own. Images from other combinations of tasks will be deleted too, but they
+// can always be rebuilt. Only images with the label for this toastfile are considered, so the
+// caches of other projects which use the same repository are left alone.
+fn prune_dangling(
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // Determine which schedules to consider.
+    let roots = get_roots(settings, toastfile)?;
+    let mut task_names = toastfile
+        .tasks
+        .keys()
+        .map(AsRef::as_ref)
+        .collect::<Vec<&str>>();
+    task_names.sort_unstable();
+    let mut schedules = vec![schedule::compute(toastfile, &roots)];
+    schedules.extend(
+        task_names
+            .iter()
+            .map(|task| schedule::compute(toastfile, &[task])),
+    );
+
+    // Start with the base image, just like `run_tasks` does.
+    let base_image = if toastfile.resolve_image_digest {
+        resolve_image_digest(&toastfile.image, interrupted)?
+    } else {
+        toastfile.image.clone()
+    };
+
+    // Compute the images that each schedule would produce. No tasks are run, so the files on the
+    // host don't change and all the archives of input files can be shared.
+    let mut archives = runner::Archives::default();
+    let mut reachable_images = HashSet::new();
+    for schedule in &schedules {
+        let environment = fetch_environment(schedule, &toastfile.tasks)?;
+        let mut image = base_image.clone();
+
+        for task in schedule {
+            image = runner::image_name(
+                settings,
+                &environment,
+                interrupted,
+                &mut archives,
+                &toastfile.tasks[*task], // [ref:tasks_valid]
+                &image,
+            )?
+            .0;
+            reachable_images.insert(image.clone());
+        }
+    }
+
+    // Find the images which can't be produced anymore.
+    let dangling_images = docker::list_images(
+        &settings.docker_repo,
+        &runner::toastfile_label(settings),
+        interrupted,
+    )?
+    .into_iter()
+    .filter(|image| cache::image_key(image).is_some() && !reachable_images.contains(image))
+    .collect::<Vec<_>>();
+
+    if dangling_images.is_empty() {
+        info!("There are no dangling images.");
+        return Ok(());
+    }
+
+    // If this is a dry run, just list the images.
+    if settings.dry_run {
+        info!(
+            "Here are the {} that would be deleted:",
+            format::number(dangling_images.len(), "dangling image"),
+        );
+
+        for image in &dangling_images {
+            println!("* {}", image.code_str());
+        }
+
+        return Ok(());
+    }
+
+    // Delete the images along with their manifests.
+    for image in &dangling_images {
+        info!("Deleting image {}\u{2026}", image.code_str());
+        docker::delete_image(image, interrupted)?;
+
+        if let (Some(cache_dir), Some(key)) = (&settings.cache_dir, cache::image_key(image)) {
+            manifest::delete(cache_dir, key)?;
+        }
+    }
+
+    info!(
+        "Deleted {}.",
+        format::number(dangling_images.len(), "dangling image"),
+    );
+
+    Ok(())
+}
+
+// Print everything that went into the cache key of each task in the schedule, without running
+// anything. Comparing the output of two runs reveals why a task wasn't cached.
+fn explain(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // Start with the base image, just like `run_tasks` does.
+    let mut image = if toastfile.resolve_image_digest && !schedule.is_empty() {
+        resolve_image_digest(&toastfile.image, interrupted)?
+    } else {
+        toastfile.image.clone()
+    };
+
+    // No tasks are run, so the archives of input files can be shared.
+    let mut archives = runner::Archives::default();
+
+    for task in schedule {
+        let previous_image = image;
+        let (new_image, inputs, manifest) = runner::image_name(
+            settings,
+            environment,
+            interrupted,
+            &mut archives,
+            &toastfile.tasks[*task], // [ref:tasks_valid]
+            &previous_image,
+        )?;
+        image = new_image;
+
+        if inputs.is_empty() {
+            info!(
+                "Task {} has no environment variables, input files, or command, so it reuses \
+                 image {}.",
+                task.code_str(),
+                image.code_str(),
+            );
+            continue;
+        }
+
+        info!(
+            "Task {} produces image {}. Here is what went into its cache key and the hash of each \
+             (the values of environment variables aren't shown):",
+            task.code_str(),
+            image.code_str(),
+        );
+
+        for (description, hash) in &inputs {
+            match hash {
+                Some(hash) => println!("* {} \u{2014} {}", description.code_str(), hash),
+                None => println!("* {}", description.code_str()),
+            }
+        }
+
+        if !manifest.is_empty() {
+            info!("Here are the input files and their hashes:");
+
+            for (path, entry) in &manifest {
+                println!(
+                    "* {} \u{2014} {}",
+                    Path::new("/").join(path).to_string_lossy().code_str(),
+                    entry.hash,
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Check that the cache keys of the tasks in a schedule capture everything that affects them. Tasks
+// with `cache: false` presumably depend on something else, e.g., the files in `mount_paths`, which
+// aren't part of the cache key [ref:mount_paths_nand_cache].
+fn check_fingerprintable(
+    schedule: &[&str],
+    toastfile: &toastfile::Toastfile,
+) -> Result<(), Failure> {
+    let violations = schedule
+        .iter()
+        .filter_map(|task_name| {
+            let task = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+            let reason = if !task.mount_paths.is_empty() {
+                "mount_paths"
+            } else if !task.cache {
+                "cache: false"
+            } else {
+                return None;
+            };
+            Some(format!("{} ({})", task_name.code_str(), reason.code_str()))
+        })
+        .collect::<Vec<_>>();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Failure::User(
+            format!(
+                "Unable to compute a fingerprint, since the cache keys of the following tasks \
+                 don\u{2019}t capture everything that affects them: {}.",
+                format::series(&violations),
+            ),
+            None,
+        ))
+    }
+}
+
+// Print a hash of the cache keys of all the tasks in a schedule, without running any of them. It
+// changes whenever any of the cache keys do, so it answers whether anything changed since an
+// earlier run. Schedules with tasks that the cache keys don't fully describe are refused.
+fn fingerprint(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    check_fingerprintable(schedule, toastfile)?;
+
+    // Start with the base image, just like `run_tasks` does.
+    let mut image = if toastfile.resolve_image_digest && !schedule.is_empty() {
+        resolve_image_digest(&toastfile.image, interrupted)?
+    } else {
+        toastfile.image.clone()
+    };
+
+    // No tasks are run, so the archives of input files can be shared.
+    let mut archives = runner::Archives::default();
+
+    // Fold the name of each image into the fingerprint, starting with the base image.
+    let mut fingerprint = cache::combine("", &image);
+    for task in schedule {
+        let previous_image = image;
+        let (new_image, _, _) = runner::image_name(
+            settings,
+            environment,
+            interrupted,
+            &mut archives,
+            &toastfile.tasks[*task], // [ref:tasks_valid]
+            &previous_image,
+        )?;
+        image = new_image;
+        fingerprint = cache::combine(&fingerprint, &image);
+    }
+
+    println!("{}", fingerprint);
+
+    Ok(())
+}
+
 // Run some tasks and return the final context and the last attempted task. The returned context
 // should not be `None` if `need_context` is `true`.
 #[allow(clippy::too_many_arguments)]
@@ -493,17 +1802,65 @@ fn run_tasks(
     toastfile: &toastfile::Toastfile,
     environment: &HashMap<String, String>,
     need_context: bool,
+    retries_left: &mut u32,
     interrupted: &Arc<AtomicBool>,
     active_containers: &Arc<Mutex<HashSet<String>>>,
-) -> (Result<(), Failure>, Option<runner::Context>, Option<String>) {
+    span: &telemetry::Span,
+    progress: &progress::Progress,
+) -> (
+    Result<(), Failure>,
+    Option<runner::Context>,
+    Option<String>,
+    Vec<TaskSummary>,
+) {
+    // This will contain a summary of each task in the schedule.
+    let mut summaries = vec![];
+
     // This variable will be `true` as long as we're executing tasks that have `cache: true`. As
     // soon as we encounter a task with `cache: false`, this variable will be permanently set to
     // `false`.
     let mut caching_enabled = true;
 
-    // We start with the base image.
+    // Tasks with identical inputs can share the same archive of input files.
+    let mut archives = runner::Archives::default();
+
+    // We start with the base image. If requested, we refer to it by its ID so that the cache keys
+    // change if the image does.
+    let base_image = if toastfile.resolve_image_digest && (need_context || !schedule.is_empty()) {
+        match resolve_image_digest(&toastfile.image, interrupted) {
+            Ok(image) => image,
+            Err(e) => return (Err(e), None, None, skipped(schedule).collect()),
+        }
+    } else {
+        toastfile.image.clone()
+    };
+    // If requested, pull the images for the cacheable tasks from the remote cache before running
+    // any of them.
+    if settings.prefetch_remote_cache && settings.read_remote_cache {
+        let cacheable_tasks = schedule
+            .iter()
+            .take_while(|task| {
+                toastfile.tasks[**task].cache // [ref:tasks_valid]
+                    && !settings
+                        .forced_tasks
+                        .iter()
+                        .any(|forced_task| *task == forced_task)
+            })
+            .map(|task| &toastfile.tasks[*task]) // [ref:tasks_valid]
+            .collect::<Vec<_>>();
+
+        runner::prefetch(
+            settings,
+            environment,
+            interrupted,
+            &archives,
+            &cacheable_tasks,
+            &base_image,
+        );
+    }
+
     let mut context = Some(runner::Context {
-        image: toastfile.image.clone(),
+        image: base_image,
         persist: true,
         interrupted: interrupted.clone(),
     });
@@ -524,20 +1881,114 @@ fn run_tasks(
 
         // If the user wants to stop the schedule, quit now.
         if interrupted.load(Ordering::SeqCst) {
-            return (Err(Failure::Interrupted), context, Some((*task).to_owned()));
+            summaries.extend(skipped(&schedule[i..]));
+            return (
+                Err(Failure::Interrupted),
+                context,
+                Some((*task).to_owned()),
+                summaries,
+            );
         }
 
-        // Run the task.
+        // Run the task. If it fails and there are retries left, run it again from the same context.
         info!("Running task {}\u{2026}", task.code_str());
-        let (result, new_context) = runner::run(
-            settings,
-            &environment,
-            &interrupted,
-            &active_containers,
-            task_data,
-            caching_enabled,
-            context.unwrap(), // Safe due to [ref:context_needed_if_not_final_task].
-            need_context || i != schedule.len() - 1, // [tag:context_needed_if_not_final_task]
+        progress.emit(
+            "task_started",
+            vec![
+                ("name".to_owned(), json::Value::String((*task).to_owned())),
+                ("index".to_owned(), json::Value::Number(i as u64)),
+                (
+                    "total".to_owned(),
+                    json::Value::Number(schedule.len() as u64),
+                ),
+            ],
+        );
+        let start_time = Instant::now();
+        let mut report = runner::Report::default();
+        let mut original_context = context.take();
+        let (result, new_context) = loop {
+            // As long as the task might be retried, it's given a copy of the context which never
+            // deletes the image, so the original is still around for the next attempt.
+            let can_retry = *retries_left > 0;
+            let attempt_context = if can_retry {
+                original_context.as_ref().map(|context| runner::Context {
+                    image: context.image.clone(),
+                    persist: true,
+                    interrupted: context.interrupted.clone(),
+                })
+            } else {
+                original_context.take()
+            };
+
+            let mut task_span = span.child("toast.task");
+            task_span.set_attribute("toast.task.name", json::Value::String((*task).to_owned()));
+            let (result, new_context) = runner::run(
+                settings,
+                environment,
+                interrupted,
+                active_containers,
+                &mut archives,
+                task_data,
+                caching_enabled,
+                attempt_context.unwrap(), // Safe due to [ref:context_needed_if_not_final_task].
+                need_context || i != schedule.len() - 1, // [tag:context_needed_if_not_final_task]
+                &task_span,
+                progress,
+                &mut report,
+            );
+            drop(task_span);
+
+            // If the task could have changed files on the host, the archives of input files
+            // created so far may be out of date.
+            if runner::modifies_host(task_data) {
+                archives.invalidate();
+            }
+
+            // Retry the task if its command failed and the budget allows it. Other failures, e.g.,
+            // a missing input file or output file, would just happen again.
+            if can_retry && report.command_failed && !interrupted.load(Ordering::SeqCst) {
+                report.command_failed = false;
+                report.exit_code = None;
+                report.output = None;
+                *retries_left -= 1;
+                warn!(
+                    "Task {} failed. Retrying it with {} left in the retry budget\u{2026}",
+                    task.code_str(),
+                    retries_left,
+                );
+                continue;
+            }
+
+            // If the task produced the same image it was given, keep the original context, since
+            // the copy doesn't own the image. Otherwise, the original is no longer needed.
+            break match (original_context.take(), new_context) {
+                (Some(original_context), Some(new_context))
+                    if new_context.image == original_context.image =>
+                {
+                    (result, Some(original_context))
+                }
+                (_, new_context) => (result, new_context),
+            };
+        };
+
+        // Summarize what happened.
+        summaries.push(TaskSummary {
+            name: (*task).to_owned(),
+            status: match (&result, report.cached) {
+                (Ok(()), true) => TaskStatus::Cached,
+                (Ok(()), false) => TaskStatus::Success,
+                (Err(_), _) => TaskStatus::Failure,
+            },
+            duration: start_time.elapsed(),
+            image: report.image,
+            exit_code: report.exit_code,
+            error: result.as_ref().err().map(ToString::to_string),
+            output: report.output,
+            archive_timings: report.archive_timings,
+        });
+        progress.emit(
+            "task_finished",
+            task_summary_fields(summaries.last().unwrap()),
         );
 
         // Remember the context for the next task, if there is one.
@@ -545,7 +1996,8 @@ fn run_tasks(
 
         // Return an error if the task failed.
         if let Err(e) = result {
-            return (Err(e), context, Some((*task).to_owned()));
+            summaries.extend(skipped(&schedule[i + 1..]));
+            return (Err(e), context, Some((*task).to_owned()), summaries);
         }
     }
 
@@ -554,12 +2006,262 @@ fn run_tasks(
         Ok(()),
         context,
         schedule.last().map(|task| (*task).to_owned()),
+        summaries,
     )
 }
 
-// Program entrypoint
+// Compute a schedule for the failure handlers of the tasks in a schedule, along with their
+// dependencies. The tasks in the given schedule are left out, so a handler which depends on the
+// task that failed doesn't run it again.
+fn failure_handler_schedule<'a>(
+    toastfile: &'a toastfile::Toastfile,
+    schedule: &[&'a str],
+) -> Vec<&'a str> {
+    let handlers = schedule
+        .iter()
+        .flat_map(|task| &toastfile.tasks[*task].on_failure) // [ref:tasks_valid]
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    schedule::compute(toastfile, &handlers)
+        .into_iter()
+        .filter(|task| !schedule.contains(task))
+        .collect()
+}
+
+// Run the failure handlers for a task that failed, and return a summary of each task that ran. The
+// handlers are never cached, since they're run for their side effects. If one of them fails, the
+// error is logged, but it doesn't replace the error from the original task.
+#[allow(clippy::too_many_arguments)]
+fn run_failure_handlers(
+    failed_task: &str,
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    retries_left: &mut u32,
+    interrupted: &Arc<AtomicBool>,
+    active_containers: &Arc<Mutex<HashSet<String>>>,
+    span: &telemetry::Span,
+    progress: &progress::Progress,
+) -> Vec<TaskSummary> {
+    let handlers = &toastfile.tasks[failed_task].on_failure; // [ref:tasks_valid]
+    if handlers.is_empty() {
+        return vec![];
+    }
+
+    // Disable caching for the handlers. [ref:on_failure_exist]
+    let mut handler_toastfile = toastfile.clone();
+    for handler in handlers {
+        handler_toastfile.tasks.get_mut(handler).unwrap().cache = false;
+    }
+
+    let schedule = failure_handler_schedule(toastfile, &[failed_task]);
+    info!(
+        "Running the failure handlers for task {}\u{2026}",
+        failed_task.code_str(),
+    );
+
+    let (result, _, _, summaries) = run_tasks(
+        &schedule,
+        settings,
+        &handler_toastfile,
+        environment,
+        false,
+        retries_left,
+        interrupted,
+        active_containers,
+        span,
+        progress,
+    );
+
+    if let Err(e) = result {
+        error!("{}", e);
+    }
+
+    summaries
+}
+
+// What happened to a task during a run
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum TaskStatus {
+    Success,
+    Cached,
+    Failure,
+    Skipped, // The task didn't run, because an earlier one failed or the user interrupted Toast.
+}
+
+impl TaskStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Cached => "cached",
+            Self::Failure => "failure",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+// A summary of a task in a run, for `--output-format json`
+struct TaskSummary {
+    name: String,
+    status: TaskStatus,
+    duration: Duration,
+    image: Option<String>,
+    exit_code: Option<i32>,
+    error: Option<String>,
+    output: Option<(String, String)>,
+    archive_timings: Option<Arc<tar::Timings>>,
+}
+
+// Summarize tasks which didn't run.
+fn skipped<'a>(tasks: &'a [&str]) -> impl Iterator<Item = TaskSummary> + 'a {
+    tasks.iter().map(|task| TaskSummary {
+        name: (*task).to_owned(),
+        status: TaskStatus::Skipped,
+        duration: Duration::default(),
+        image: None,
+        exit_code: None,
+        error: None,
+        output: None,
+        archive_timings: None,
+    })
+}
+
+// Render the time spent reading input files in the folded stack format, i.e., one line per stack of
+// the form `frame;frame;... count`, which tools like `inferno` can turn into a flame graph. The
+// counts are in microseconds.
+fn folded_profile(summaries: &[TaskSummary]) -> String {
+    let mut profile = String::new();
+
+    for summary in summaries {
+        if let Some(timings) = &summary.archive_timings {
+            for phase in tar::Phase::ALL {
+                let micros = timings.total(phase).as_micros();
+                if micros > 0 {
+                    profile.push_str(&format!(
+                        "toast;{};archive;{} {}\n",
+                        summary.name,
+                        phase.name(),
+                        micros,
+                    ));
+                }
+            }
+        }
+    }
+
+    profile
+}
+
+// Render a JUnit report with a test case for each task. Tasks which were cached or didn't run are
+// reported as skipped. Failed tasks include the end of their output, if it was captured.
+fn junit_report(summaries: &[TaskSummary]) -> String {
+    junit::render(
+        "toast",
+        &summaries
+            .iter()
+            .map(|summary| junit::TestCase {
+                name: summary.name.clone(),
+                duration: summary.duration,
+                outcome: match summary.status {
+                    TaskStatus::Success => junit::Outcome::Passed,
+                    TaskStatus::Cached => junit::Outcome::Skipped("Cached.".to_owned()),
+                    TaskStatus::Failure => junit::Outcome::Failed(
+                        summary
+                            .error
+                            .clone()
+                            .unwrap_or_else(|| "Failed.".to_owned()),
+                    ),
+                    TaskStatus::Skipped => junit::Outcome::Skipped("Not run.".to_owned()),
+                },
+                stdout: summary
+                    .output
+                    .as_ref()
+                    .map_or_else(String::new, |(stdout, _)| stdout.clone()),
+                stderr: summary
+                    .output
+                    .as_ref()
+                    .map_or_else(String::new, |(_, stderr)| stderr.clone()),
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+// Describe a task in a run, for the summary and progress events.
+fn task_summary_fields(summary: &TaskSummary) -> Vec<(String, json::Value)> {
+    let mut fields = vec![
+        ("name".to_owned(), json::Value::String(summary.name.clone())),
+        (
+            "status".to_owned(),
+            json::Value::String(summary.status.as_str().to_owned()),
+        ),
+        (
+            "duration_ms".to_owned(),
+            json::Value::Number(summary.duration.as_millis() as u64),
+        ),
+    ];
+    if let Some(key) = summary.image.as_deref().and_then(cache::image_key) {
+        fields.push(("cache_key".to_owned(), json::Value::String(key.to_owned())));
+    }
+    if let Some(exit_code) = summary.exit_code.and_then(|code| u64::try_from(code).ok()) {
+        fields.push(("exit_code".to_owned(), json::Value::Number(exit_code)));
+    }
+    if let Some(error) = &summary.error {
+        fields.push(("error".to_owned(), json::Value::String(error.clone())));
+    }
+    fields
+}
+
+// Describe the outcome of a run, for the summary and progress events.
+fn run_summary_fields(
+    result: &Result<(), Failure>,
+    summaries: &[TaskSummary],
+) -> Vec<(String, json::Value)> {
+    let mut fields = vec![("success".to_owned(), json::Value::Bool(result.is_ok()))];
+    if let Err(e) = result {
+        fields.push(("error".to_owned(), json::Value::String(e.to_string())));
+    }
+    fields.push((
+        "tasks".to_owned(),
+        json::Value::Array(
+            summaries
+                .iter()
+                .map(|summary| json::Value::Object(task_summary_fields(summary)))
+                .collect(),
+        ),
+    ));
+    fields
+}
+
+// Describe the outcome of a run with one line per task and a line for the overall result, for
+// `--summary-only`. The details of any failure are logged separately.
+fn human_summary(result: &Result<(), Failure>, summaries: &[TaskSummary]) -> String {
+    let mut summary = String::new();
+
+    for task in summaries {
+        summary.push_str(&match task.status {
+            TaskStatus::Skipped => format!("* {}: skipped\n", task.name.code_str()),
+            status => format!(
+                "* {}: {} in {:.1}s\n",
+                task.name.code_str(),
+                status.as_str(),
+                task.duration.as_secs_f64(),
+            ),
+        });
+    }
+
+    summary.push_str(if result.is_ok() {
+        "The run succeeded.\n"
+    } else {
+        "The run failed.\n"
+    });
+
+    summary
+}
+
+// Program entrypoint. Before returning the result of the run, this sets `run_finished`, so that a
+// user failure can be reported as a task failure rather than as invalid input [ref:exit_codes].
 #[allow(clippy::too_many_lines)]
-fn entry() -> Result<(), Failure> {
+fn entry(run_finished: &mut bool) -> Result<(), Failure> {
     // Determine whether to print colored output.
     colored::control::set_override(atty::is(Stream::Stderr));
 
@@ -573,11 +2275,35 @@ fn entry() -> Result<(), Failure> {
     // Set up the signal handlers.
     set_up_signal_handlers(interrupted.clone(), active_containers.clone())?;
 
-    // Parse the command-line arguments;
-    let settings = settings()?;
+    // Parse the command-line arguments.
+    let matches = matches();
+
+    // If the user wants to diagnose their environment, do that and quit. This comes before reading
+    // the toastfile and config file, since problems with those are among the things it reports.
+    if matches.is_present(DOCTOR_OPTION) {
+        return doctor(&matches, &interrupted);
+    }
+
+    let settings = settings(&matches)?;
+
+    // In quiet mode, only warnings and errors are logged, and there are no spinners.
+    if settings.summary_only {
+        log::set_max_level(log::max_level().min(LevelFilter::Warn));
+        spinner::disable();
+    }
+
+    // If the user just wants to see a manifest, do that and quit.
+    if let Some(key) = &settings.show_manifest {
+        return show_manifest(&settings, key);
+    }
+
+    // If the user wants to repair the manifests, do that and quit.
+    if settings.repair_manifests {
+        return repair_manifests(&settings);
+    }
 
     // Parse the toastfile.
-    let toastfile = parse_toastfile(&settings.toastfile_path)?;
+    let mut toastfile = parse_toastfile(&settings.toastfile_path)?;
 
     // If the user just wants to list all the tasks, do that and quit.
     if settings.list {
@@ -613,8 +2339,29 @@ fn entry() -> Result<(), Failure> {
         return Ok(());
     }
 
+    // If the user wants to prune the cache, do that and quit.
+    if settings.prune_dangling {
+        return prune_dangling(&settings, &toastfile, &interrupted);
+    }
+
+    // Pass the extra arguments from the command line, if any, to the task.
+    apply_task_arguments(&settings, &mut toastfile)?;
+
+    // Replace the command of the task to debug interactively, if any, with a shell.
+    prepare_interactive(&settings, &mut toastfile)?;
+
     // Determine which tasks the user wants to run.
-    let root_tasks = get_roots(&settings, &toastfile)?;
+    let mut root_tasks = get_roots(&settings, &toastfile)?;
+
+    // If requested, skip the tasks whose tags don't match.
+    if let Some(expression) = &settings.tags {
+        filter_untagged_roots(&settings, &toastfile, expression, &mut root_tasks);
+    }
+
+    // If requested, skip the tasks that aren't affected by recent changes.
+    if let Some(since) = &settings.since {
+        filter_unaffected_roots(&settings, &toastfile, since, &mut root_tasks)?;
+    }
 
     // Compute a schedule of tasks to run.
     let schedule = schedule::compute(&toastfile, &root_tasks);
@@ -632,20 +2379,125 @@ fn entry() -> Result<(), Failure> {
         );
     }
 
-    // Fetch all the environment variables used by the tasks in the schedule.
-    let environment = fetch_environment(&schedule, &toastfile.tasks)?;
+    // Fetch all the environment variables used by the tasks in the schedule, including the ones
+    // that only run if a task fails.
+    let environment = fetch_environment(
+        &[
+            schedule.clone(),
+            failure_handler_schedule(&toastfile, &schedule),
+        ]
+        .concat(),
+        &toastfile.tasks,
+    )?;
+
+    // All relative paths are relative to where the toastfile lives.
+    let mut toastfile_dir = PathBuf::from(&settings.toastfile_path);
+    toastfile_dir.pop();
+
+    // Make sure the input files exist before running anything.
+    runner::check_input_paths(&toastfile_dir, &toastfile, &schedule)?;
+
+    // Check whether the base image is pinned, if it'll be used.
+    if !schedule.is_empty() || settings.spawn_shell {
+        check_image_pinned(&settings, &toastfile)?;
+    }
 
-    // Execute the schedule.
-    let (result, context, last_task) = run_tasks(
+    // If the user just wants to know what goes into the cache keys, show that and quit.
+    if settings.explain {
+        return explain(&schedule, &settings, &toastfile, &environment, &interrupted);
+    }
+
+    // If the user just wants a fingerprint of the whole run, print that and quit.
+    if settings.fingerprint {
+        return fingerprint(&schedule, &settings, &toastfile, &environment, &interrupted);
+    }
+
+    // Set up tracing and progress reporting, if requested.
+    let tracer = telemetry::Tracer::new(settings.trace_file.as_deref())?;
+    let progress = progress::Progress::new(settings.progress_socket.as_deref())?;
+    let mut run_span = tracer.span("toast.run");
+    run_span.set_attribute(
+        "toast.run.tasks",
+        json::Value::Number(schedule.len() as u64),
+    );
+
+    // Execute the schedule. All the tasks, including failure handlers, share one retry budget.
+    let mut retries_left = settings.max_retries_total;
+    let (result, context, last_task, mut summaries) = run_tasks(
         &schedule,
         &settings,
         &toastfile,
         &environment,
         settings.spawn_shell, // [tag:spawn_shell_requires_context]
+        &mut retries_left,
         &interrupted,
         &active_containers,
+        &run_span,
+        &progress,
     );
 
+    // If a task failed, run its failure handlers.
+    if let (Err(Failure::User(_, _) | Failure::System(_, _)), Some(failed_task)) =
+        (&result, &last_task)
+    {
+        summaries.extend(run_failure_handlers(
+            failed_task,
+            &settings,
+            &toastfile,
+            &environment,
+            &mut retries_left,
+            &interrupted,
+            &active_containers,
+            &run_span,
+            &progress,
+        ));
+    }
+    drop(run_span);
+
+    // Write the profile, if requested.
+    if let Some(path) = &settings.profile_file {
+        fs::write(path, folded_profile(&summaries)).map_err(failure::user(format!(
+            "Unable to write file {}.",
+            path.to_string_lossy().code_str(),
+        )))?;
+    }
+
+    // Write the JUnit report, if requested.
+    if let Some(path) = &settings.junit_file {
+        fs::write(path, junit_report(&summaries)).map_err(failure::user(format!(
+            "Unable to write file {}.",
+            path.to_string_lossy().code_str(),
+        )))?;
+    }
+
+    // Save what was learned about the input files for the next run. The scan cache is only an
+    // optimization, so failing to save it isn't fatal.
+    if let Some(scan_cache) = &settings.scan_cache {
+        if let Err(e) = scan_cache.save() {
+            warn!("{}", e);
+        }
+    }
+
+    // Report the end of the run, and print the summary if requested.
+    progress.emit("run_finished", run_summary_fields(&result, &summaries));
+
+    match settings.output_format {
+        OutputFormat::Human => {
+            if settings.summary_only {
+                print!("{}", human_summary(&result, &summaries));
+            }
+        }
+        OutputFormat::Json => {
+            println!(
+                "{:#}",
+                json::Value::Object(run_summary_fields(&result, &summaries)),
+            );
+        }
+    }
+
+    // From here on, the result is that of the run.
+    *run_finished = true;
+
     // Return early if needed.
     match result {
         Ok(_) | Err(Failure::User(_, _)) => {
@@ -667,6 +2519,11 @@ fn entry() -> Result<(), Failure> {
         // Inform the user of what's about to happen.
         info!("Preparing a shell\u{2026}");
 
+        // Use the shell of the last task, if it overrides the default. [ref:tasks_valid]
+        let shell = last_task
+            .as_ref()
+            .and_then(|last_task| toastfile.tasks[last_task].shell.clone());
+
         // Determine the environment, location, mount settings, ports, and user for the shell.
         let (task_environment, location, mount_paths, mount_readonly, ports, user, extra_args) =
             if let Some(last_task) = last_task {
@@ -704,10 +2561,6 @@ fn entry() -> Result<(), Failure> {
                 )
             };
 
-        // All relative paths are relative to where the toastfile lives.
-        let mut toastfile_dir = PathBuf::from(&settings.toastfile_path);
-        toastfile_dir.pop();
-
         // Spawn the shell.
         docker::spawn_shell(
             &context.unwrap().image, // Safe due to [ref:spawn_shell_requires_context].
@@ -718,6 +2571,7 @@ fn entry() -> Result<(), Failure> {
             mount_readonly,
             &ports,
             &user,
+            shell.as_deref(),
             &extra_args,
             &interrupted,
         )?;
@@ -727,11 +2581,147 @@ fn entry() -> Result<(), Failure> {
     result
 }
 
+// Determine the exit code for a failure. [ref:exit_codes]
+fn exit_code(failure: &Failure, run_finished: bool, runtime_available: bool) -> i32 {
+    match failure {
+        Failure::Interrupted => EXIT_INTERRUPTED,
+        Failure::System(_, _) => {
+            if runtime_available {
+                EXIT_SYSTEM_ERROR
+            } else {
+                EXIT_RUNTIME_UNAVAILABLE
+            }
+        }
+        Failure::User(_, _) => {
+            if run_finished {
+                EXIT_TASK_FAILURE
+            } else {
+                EXIT_USER_ERROR
+            }
+        }
+    }
+}
+
 // Let the fun begin!
 fn main() {
     // Jump to the entrypoint and handle any resulting errors.
-    if let Err(e) = entry() {
+    let mut run_finished = false;
+    if let Err(e) = entry(&mut run_finished) {
         error!("{}", e);
-        exit(1);
+
+        // A system failure may be because Docker isn't installed or its daemon can't be reached.
+        // Rather than guessing from the error message, ask the daemon directly.
+        let runtime_available = !matches!(e, Failure::System(_, _))
+            || docker::server_version(&Arc::new(AtomicBool::new(false))).is_ok();
+
+        exit(exit_code(&e, run_finished, runtime_available));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        check_fingerprintable, exit_code, failure::Failure, toastfile, EXIT_INTERRUPTED,
+        EXIT_RUNTIME_UNAVAILABLE, EXIT_SYSTEM_ERROR, EXIT_TASK_FAILURE, EXIT_USER_ERROR,
+    };
+
+    #[test]
+    fn exit_code_interrupted() {
+        assert_eq!(
+            exit_code(&Failure::Interrupted, false, true),
+            EXIT_INTERRUPTED
+        );
+        assert_eq!(
+            exit_code(&Failure::Interrupted, true, false),
+            EXIT_INTERRUPTED
+        );
+    }
+
+    #[test]
+    fn exit_code_system() {
+        let failure = Failure::System("foo".to_owned(), None);
+
+        assert_eq!(exit_code(&failure, false, true), EXIT_SYSTEM_ERROR);
+        assert_eq!(exit_code(&failure, true, true), EXIT_SYSTEM_ERROR);
+    }
+
+    #[test]
+    fn exit_code_runtime_unavailable() {
+        let failure = Failure::System("foo".to_owned(), None);
+
+        assert_eq!(exit_code(&failure, false, false), EXIT_RUNTIME_UNAVAILABLE);
+        assert_eq!(exit_code(&failure, true, false), EXIT_RUNTIME_UNAVAILABLE);
+    }
+
+    #[test]
+    fn exit_code_user() {
+        let failure = Failure::User("foo".to_owned(), None);
+
+        assert_eq!(exit_code(&failure, false, true), EXIT_USER_ERROR);
+        assert_eq!(exit_code(&failure, true, true), EXIT_TASK_FAILURE);
+        assert_eq!(exit_code(&failure, true, false), EXIT_TASK_FAILURE);
+    }
+
+    #[test]
+    fn exit_codes_distinct() {
+        let mut codes = vec![
+            EXIT_TASK_FAILURE,
+            EXIT_USER_ERROR,
+            EXIT_SYSTEM_ERROR,
+            EXIT_RUNTIME_UNAVAILABLE,
+            EXIT_INTERRUPTED,
+        ];
+        codes.sort_unstable();
+        codes.dedup();
+
+        assert_eq!(codes.len(), 5);
+    }
+
+    #[test]
+    fn check_fingerprintable_cached() {
+        let toastfile = toastfile::parse(
+            r#"
+image: encom:os-12
+tasks:
+  foo: {}
+  bar:
+    dependencies:
+      - foo
+            "#
+            .trim(),
+        )
+        .unwrap();
+
+        assert!(check_fingerprintable(&["foo", "bar"], &toastfile).is_ok());
+    }
+
+    #[test]
+    fn check_fingerprintable_uncached() {
+        let toastfile = toastfile::parse(
+            r#"
+image: encom:os-12
+tasks:
+  foo: {}
+  bar:
+    cache: false
+    dependencies:
+      - foo
+  baz:
+    cache: false
+    mount_paths:
+      - qux
+            "#
+            .trim(),
+        )
+        .unwrap();
+
+        assert!(check_fingerprintable(&["foo"], &toastfile).is_ok());
+
+        let result = check_fingerprintable(&["foo", "bar", "baz"], &toastfile);
+        assert!(result.is_err());
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("bar"));
+        assert!(message.contains("baz"));
+        assert!(!message.contains("foo"));
     }
 }
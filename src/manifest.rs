@@ -0,0 +1,649 @@
+use crate::{cache, failure, failure::Failure, format::CodeStr, json::Value, tar::Manifest};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs::{create_dir_all, read_dir, read_to_string, remove_file},
+    io,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+
+// The name of the directory (within the cache directory) where manifests are stored
+const MANIFEST_DIR_NAME: &str = "manifests";
+
+// The file extension for manifests
+const MANIFEST_EXTENSION: &str = "manifest";
+
+// This struct represents a manifest as it's stored on disk. The file is JSON, which `serde_yaml`
+// can read since JSON is a subset of YAML.
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestFile {
+    pub image: String,
+    pub paths: BTreeMap<String, PathEntry>,
+
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+// What a manifest records about a path. Manifests written by older versions of Toast only have the
+// hash, and they're rendered (and signed) the same way they were written.
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum PathEntry {
+    Hash(String),
+    HashAndSize { hash: String, size: u64 },
+}
+
+impl PathEntry {
+    pub fn hash(&self) -> &str {
+        match self {
+            Self::Hash(hash) | Self::HashAndSize { hash, .. } => hash,
+        }
+    }
+
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            Self::Hash(_) => None,
+            Self::HashAndSize { size, .. } => Some(*size),
+        }
+    }
+
+    fn render(&self) -> Value {
+        match self {
+            Self::Hash(hash) => Value::String(hash.clone()),
+            Self::HashAndSize { hash, size } => Value::Object(vec![
+                ("hash".to_owned(), Value::String(hash.clone())),
+                ("size".to_owned(), Value::Number(*size)),
+            ]),
+        }
+    }
+}
+
+// Determine where the manifest for a cache key is stored.
+pub fn path(cache_dir: &Path, key: &str) -> PathBuf {
+    cache_dir
+        .join(MANIFEST_DIR_NAME)
+        .join(format!("{}.{}", key, MANIFEST_EXTENSION))
+}
+
+// Convert the paths in a manifest into the absolute paths that are written to disk.
+fn absolute_paths(manifest: &Manifest) -> BTreeMap<String, PathEntry> {
+    manifest
+        .iter()
+        .map(|(path_rcr, entry)| {
+            (
+                Path::new("/").join(path_rcr).to_string_lossy().into_owned(),
+                PathEntry::HashAndSize {
+                    hash: entry.hash.clone(),
+                    size: entry.size,
+                },
+            )
+        })
+        .collect()
+}
+
+// Render a manifest as a JSON document, with a signature if there is one. Annotations are only
+// included if there are any, so manifests without them render (and are signed) as they always were.
+fn render(
+    image: &str,
+    paths: &BTreeMap<String, PathEntry>,
+    annotations: &BTreeMap<String, String>,
+    signature: Option<&str>,
+) -> Value {
+    let mut fields = vec![
+        ("image".to_owned(), Value::String(image.to_owned())),
+        (
+            "paths".to_owned(),
+            Value::Object(
+                paths
+                    .iter()
+                    .map(|(path, entry)| (path.clone(), entry.render()))
+                    .collect(),
+            ),
+        ),
+    ];
+
+    if !annotations.is_empty() {
+        fields.push((
+            "annotations".to_owned(),
+            Value::Object(
+                annotations
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+                    .collect(),
+            ),
+        ));
+    }
+
+    if let Some(signature) = signature {
+        fields.push(("signature".to_owned(), Value::String(signature.to_owned())));
+    }
+
+    Value::Object(fields)
+}
+
+// Compute the signature of a manifest. It covers the image, every path with its hash and size, and
+// the annotations, in the compact rendering of the unsigned manifest, which is the same no matter
+// how the file was formatted.
+fn sign(
+    signing_key: &[u8],
+    image: &str,
+    paths: &BTreeMap<String, PathEntry>,
+    annotations: &BTreeMap<String, String>,
+) -> String {
+    cache::hmac(
+        signing_key,
+        render(image, paths, annotations, None)
+            .to_string()
+            .as_bytes(),
+    )
+}
+
+// Write the manifest for a cache entry. The file is written atomically, so readers never observe a
+// partially written manifest. The annotations are arbitrary metadata about the cache entry (e.g.,
+// the commit it was built from), which aren't part of the cache key. If a signing key is given, the
+// manifest is signed with it.
+pub fn write(
+    cache_dir: &Path,
+    key: &str,
+    image: &str,
+    manifest: &Manifest,
+    annotations: &BTreeMap<String, String>,
+    signing_key: Option<&[u8]>,
+) -> Result<(), Failure> {
+    let manifest_path = path(cache_dir, key);
+    let paths = absolute_paths(manifest);
+    let signature = signing_key.map(|signing_key| sign(signing_key, image, &paths, annotations));
+    debug!(
+        "Writing manifest {}\u{2026}",
+        manifest_path.to_string_lossy().code_str(),
+    );
+
+    // Make sure the manifest directory exists. The `unwrap` is safe since `manifest_path` was
+    // constructed by joining a file name onto a directory.
+    let manifest_dir = manifest_path.parent().unwrap();
+    create_dir_all(manifest_dir).map_err(failure::system(format!(
+        "Unable to create directory {}.",
+        manifest_dir.to_string_lossy().code_str(),
+    )))?;
+
+    // Write the manifest to a temporary file in the same directory, then move it into place.
+    let mut temp_file = NamedTempFile::new_in(manifest_dir).map_err(failure::system(format!(
+        "Unable to create temporary file in {}.",
+        manifest_dir.to_string_lossy().code_str(),
+    )))?;
+    temp_file
+        .write_all(
+            format!(
+                "{:#}\n",
+                render(image, &paths, annotations, signature.as_deref())
+            )
+            .as_bytes(),
+        )
+        .map_err(failure::system(format!(
+            "Unable to write manifest {}.",
+            manifest_path.to_string_lossy().code_str(),
+        )))?;
+    temp_file
+        .persist(&manifest_path)
+        .map_err(failure::system(format!(
+            "Unable to write manifest {}.",
+            manifest_path.to_string_lossy().code_str(),
+        )))?;
+
+    Ok(())
+}
+
+// Read the manifest for a cache entry.
+pub fn read(cache_dir: &Path, key: &str) -> Result<ManifestFile, Failure> {
+    let manifest_path = path(cache_dir, key);
+
+    let data = read_to_string(&manifest_path).map_err(failure::user(format!(
+        "Unable to read manifest {}.",
+        manifest_path.to_string_lossy().code_str(),
+    )))?;
+
+    serde_yaml::from_str(&data).map_err(failure::user(format!(
+        "Unable to parse manifest {}.",
+        manifest_path.to_string_lossy().code_str(),
+    )))
+}
+
+// Check that a manifest was signed with the given key and hasn't been changed since.
+pub fn verify(manifest: &ManifestFile, signing_key: &[u8]) -> Result<(), Failure> {
+    let signature = manifest.signature.as_deref().ok_or_else(|| {
+        Failure::User(
+            format!(
+                "The manifest for image {} isn't signed.",
+                manifest.image.code_str(),
+            ),
+            None,
+        )
+    })?;
+
+    // Compare every byte, so the time this takes doesn't reveal how much of the signature matched.
+    let expected = sign(
+        signing_key,
+        &manifest.image,
+        &manifest.paths,
+        &manifest.annotations,
+    );
+    if expected.len() != signature.len()
+        || expected
+            .bytes()
+            .zip(signature.bytes())
+            .fold(0, |difference, (x, y)| difference | (x ^ y))
+            != 0
+    {
+        return Err(Failure::User(
+            format!(
+                "The signature of the manifest for image {} is invalid.",
+                manifest.image.code_str(),
+            ),
+            None,
+        ));
+    }
+
+    Ok(())
+}
+
+// Delete the manifest for a cache entry, if there is one.
+pub fn delete(cache_dir: &Path, key: &str) -> Result<(), Failure> {
+    let manifest_path = path(cache_dir, key);
+
+    match remove_file(&manifest_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(failure::system(format!(
+            "Unable to delete manifest {}.",
+            manifest_path.to_string_lossy().code_str(),
+        ))(e)),
+        _ => Ok(()),
+    }
+}
+
+// Delete the files in the manifest directory which can't be trusted, e.g., after an unclean
+// shutdown, and return their paths. These are leftover temporary files from interrupted writes,
+// manifests which can't be parsed (e.g., because they're empty), manifests for a different cache
+// key than their name says, and, if a signing key is given, manifests which weren't signed with it.
+pub fn repair(cache_dir: &Path, signing_key: Option<&[u8]>) -> Result<Vec<PathBuf>, Failure> {
+    let manifest_dir = cache_dir.join(MANIFEST_DIR_NAME);
+
+    // If there's no manifest directory, there's nothing to repair.
+    let entries = match read_dir(&manifest_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            return Err(failure::system(format!(
+                "Unable to read directory {}.",
+                manifest_dir.to_string_lossy().code_str(),
+            ))(e));
+        }
+    };
+
+    let mut deleted = vec![];
+    for entry in entries {
+        let entry = entry.map_err(failure::system(format!(
+            "Unable to read directory {}.",
+            manifest_dir.to_string_lossy().code_str(),
+        )))?;
+        let entry_path = entry.path();
+        if !entry_path.is_file() {
+            continue;
+        }
+
+        // Temporary files are created by `write` with the default prefix of `NamedTempFile`.
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let corrupt = if let Some(key) = file_name.strip_suffix(&format!(".{}", MANIFEST_EXTENSION))
+        {
+            read(cache_dir, key).map_or(true, |manifest| {
+                cache::image_key(&manifest.image) != Some(key)
+                    || signing_key
+                        .map_or(false, |signing_key| verify(&manifest, signing_key).is_err())
+            })
+        } else {
+            file_name.starts_with(".tmp")
+        };
+
+        if corrupt {
+            remove_file(&entry_path).map_err(failure::system(format!(
+                "Unable to delete {}.",
+                entry_path.to_string_lossy().code_str(),
+            )))?;
+            deleted.push(entry_path);
+        }
+    }
+
+    deleted.sort();
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manifest::{delete, path, read, repair, sign, verify, write, ManifestFile, PathEntry},
+        tar::{Manifest, ManifestEntry},
+    };
+    use std::{
+        collections::BTreeMap,
+        fs::{create_dir_all, write as write_file},
+        path::{Path, PathBuf},
+    };
+    use tempfile::tempdir;
+
+    fn entry(hash: &str, size: u64) -> ManifestEntry {
+        ManifestEntry {
+            hash: hash.to_owned(),
+            size,
+        }
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let cache_dir = tempdir().unwrap();
+
+        let mut manifest = Manifest::new();
+        manifest.insert(Path::new("scratch/foo").to_owned(), entry("bar", 3));
+        manifest.insert(Path::new("scratch/\"baz\"").to_owned(), entry("qux", 0));
+
+        write(
+            cache_dir.path(),
+            "corge",
+            "toast:toast-corge",
+            &manifest,
+            &BTreeMap::new(),
+            None,
+        )
+        .unwrap();
+
+        let mut paths = BTreeMap::new();
+        paths.insert(
+            "/scratch/foo".to_owned(),
+            PathEntry::HashAndSize {
+                hash: "bar".to_owned(),
+                size: 3,
+            },
+        );
+        paths.insert(
+            "/scratch/\"baz\"".to_owned(),
+            PathEntry::HashAndSize {
+                hash: "qux".to_owned(),
+                size: 0,
+            },
+        );
+
+        assert_eq!(
+            read(cache_dir.path(), "corge").unwrap(),
+            ManifestFile {
+                image: "toast:toast-corge".to_owned(),
+                paths,
+                annotations: BTreeMap::new(),
+                signature: None,
+            },
+        );
+    }
+
+    #[test]
+    fn verify_signed() {
+        let cache_dir = tempdir().unwrap();
+
+        let mut manifest = Manifest::new();
+        manifest.insert(Path::new("scratch/foo").to_owned(), entry("bar", 3));
+
+        write(
+            cache_dir.path(),
+            "corge",
+            "toast:toast-corge",
+            &manifest,
+            &BTreeMap::new(),
+            Some(b"grault"),
+        )
+        .unwrap();
+
+        let manifest_file = read(cache_dir.path(), "corge").unwrap();
+        assert!(verify(&manifest_file, b"grault").is_ok());
+        assert!(verify(&manifest_file, b"garply").is_err());
+    }
+
+    #[test]
+    fn verify_tampered() {
+        let cache_dir = tempdir().unwrap();
+
+        let mut manifest = Manifest::new();
+        manifest.insert(Path::new("scratch/foo").to_owned(), entry("bar", 3));
+
+        write(
+            cache_dir.path(),
+            "corge",
+            "toast:toast-corge",
+            &manifest,
+            &BTreeMap::new(),
+            Some(b"grault"),
+        )
+        .unwrap();
+
+        let mut manifest_file = read(cache_dir.path(), "corge").unwrap();
+        manifest_file.paths.insert(
+            "/scratch/foo".to_owned(),
+            PathEntry::HashAndSize {
+                hash: "baz".to_owned(),
+                size: 3,
+            },
+        );
+        assert!(verify(&manifest_file, b"grault").is_err());
+    }
+
+    #[test]
+    fn verify_tampered_size() {
+        let cache_dir = tempdir().unwrap();
+
+        let mut manifest = Manifest::new();
+        manifest.insert(Path::new("scratch/foo").to_owned(), entry("bar", 3));
+
+        write(
+            cache_dir.path(),
+            "corge",
+            "toast:toast-corge",
+            &manifest,
+            &BTreeMap::new(),
+            Some(b"grault"),
+        )
+        .unwrap();
+
+        let mut manifest_file = read(cache_dir.path(), "corge").unwrap();
+        manifest_file.paths.insert(
+            "/scratch/foo".to_owned(),
+            PathEntry::HashAndSize {
+                hash: "bar".to_owned(),
+                size: 4,
+            },
+        );
+        assert!(verify(&manifest_file, b"grault").is_err());
+    }
+
+    #[test]
+    fn read_verify_without_sizes() {
+        let cache_dir = tempdir().unwrap();
+        create_dir_all(cache_dir.path().join("manifests")).unwrap();
+
+        let mut paths = BTreeMap::new();
+        paths.insert("/scratch/foo".to_owned(), PathEntry::Hash("bar".to_owned()));
+        let signature = sign(b"grault", "toast:toast-corge", &paths, &BTreeMap::new());
+        write_file(
+            path(cache_dir.path(), "corge"),
+            format!(
+                "{{\"image\": \"toast:toast-corge\", \"paths\": {{\"/scratch/foo\": \"bar\"}}, \
+                 \"signature\": \"{}\"}}",
+                signature,
+            ),
+        )
+        .unwrap();
+
+        let manifest_file = read(cache_dir.path(), "corge").unwrap();
+        assert_eq!(manifest_file.paths, paths);
+        assert_eq!(manifest_file.paths["/scratch/foo"].size(), None);
+        assert!(verify(&manifest_file, b"grault").is_ok());
+    }
+
+    #[test]
+    fn write_read_annotations() {
+        let cache_dir = tempdir().unwrap();
+
+        let mut annotations = BTreeMap::new();
+        annotations.insert("commit".to_owned(), "0123abc".to_owned());
+        annotations.insert(
+            "build_url".to_owned(),
+            "https://ci.example.com/42".to_owned(),
+        );
+
+        write(
+            cache_dir.path(),
+            "corge",
+            "toast:toast-corge",
+            &Manifest::new(),
+            &annotations,
+            Some(b"grault"),
+        )
+        .unwrap();
+
+        let manifest_file = read(cache_dir.path(), "corge").unwrap();
+        assert_eq!(manifest_file.annotations, annotations);
+        assert!(verify(&manifest_file, b"grault").is_ok());
+    }
+
+    #[test]
+    fn verify_tampered_annotations() {
+        let cache_dir = tempdir().unwrap();
+
+        let mut annotations = BTreeMap::new();
+        annotations.insert("commit".to_owned(), "0123abc".to_owned());
+
+        write(
+            cache_dir.path(),
+            "corge",
+            "toast:toast-corge",
+            &Manifest::new(),
+            &annotations,
+            Some(b"grault"),
+        )
+        .unwrap();
+
+        let mut manifest_file = read(cache_dir.path(), "corge").unwrap();
+        manifest_file
+            .annotations
+            .insert("commit".to_owned(), "4567def".to_owned());
+        assert!(verify(&manifest_file, b"grault").is_err());
+    }
+
+    #[test]
+    fn verify_unsigned() {
+        let cache_dir = tempdir().unwrap();
+
+        write(
+            cache_dir.path(),
+            "corge",
+            "toast:toast-corge",
+            &Manifest::new(),
+            &BTreeMap::new(),
+            None,
+        )
+        .unwrap();
+
+        let manifest_file = read(cache_dir.path(), "corge").unwrap();
+        assert!(verify(&manifest_file, b"grault").is_err());
+    }
+
+    #[test]
+    fn read_missing() {
+        let cache_dir = tempdir().unwrap();
+
+        assert!(read(cache_dir.path(), "corge").is_err());
+    }
+
+    #[test]
+    fn delete_existing() {
+        let cache_dir = tempdir().unwrap();
+
+        write(
+            cache_dir.path(),
+            "corge",
+            "toast:toast-corge",
+            &Manifest::new(),
+            &BTreeMap::new(),
+            None,
+        )
+        .unwrap();
+        delete(cache_dir.path(), "corge").unwrap();
+
+        assert!(read(cache_dir.path(), "corge").is_err());
+    }
+
+    #[test]
+    fn delete_missing() {
+        let cache_dir = tempdir().unwrap();
+
+        assert!(delete(cache_dir.path(), "corge").is_ok());
+    }
+
+    #[test]
+    fn repair_corrupt() {
+        let cache_dir = tempdir().unwrap();
+        let manifest_dir = cache_dir.path().join("manifests");
+        create_dir_all(&manifest_dir).unwrap();
+
+        for (key, signing_key) in [("corge", Some(&b"grault"[..])), ("garply", None)] {
+            write(
+                cache_dir.path(),
+                key,
+                &format!("toast:toast-{}", key),
+                &Manifest::new(),
+                &BTreeMap::new(),
+                signing_key,
+            )
+            .unwrap();
+        }
+        write(
+            cache_dir.path(),
+            "waldo",
+            "toast:toast-fred",
+            &Manifest::new(),
+            &BTreeMap::new(),
+            Some(b"grault"),
+        )
+        .unwrap();
+        write_file(path(cache_dir.path(), "plugh"), "").unwrap();
+        write_file(manifest_dir.join(".tmpxyzzy"), "{").unwrap();
+
+        // Without a signing key, signatures aren't checked, so both well-formed manifests survive.
+        assert_eq!(
+            repair(cache_dir.path(), None).unwrap(),
+            vec![
+                manifest_dir.join(".tmpxyzzy"),
+                path(cache_dir.path(), "plugh"),
+                path(cache_dir.path(), "waldo"),
+            ],
+        );
+        assert!(read(cache_dir.path(), "corge").is_ok());
+        assert!(read(cache_dir.path(), "garply").is_ok());
+
+        // With a signing key, the unsigned manifest is deleted too.
+        assert_eq!(
+            repair(cache_dir.path(), Some(b"grault")).unwrap(),
+            vec![path(cache_dir.path(), "garply")],
+        );
+        assert!(read(cache_dir.path(), "corge").is_ok());
+    }
+
+    #[test]
+    fn repair_missing() {
+        let cache_dir = tempdir().unwrap();
+
+        assert_eq!(
+            repair(cache_dir.path(), None).unwrap(),
+            Vec::<PathBuf>::new()
+        );
+    }
+}
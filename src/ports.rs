@@ -0,0 +1,157 @@
+use crate::{failure::Failure, format::CodeStr};
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket},
+};
+
+// A port on the host which a port mapping publishes to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HostPort {
+    pub address: IpAddr,
+    pub port: u16,
+    pub udp: bool,
+}
+
+// Determine which host ports a port mapping (in the format accepted by `docker container create
+// --publish`, e.g., `127.0.0.1:8080:80/tcp`) publishes to. Mappings without a host port (e.g.,
+// `80`) are published to a port that Docker chooses, so they don't contribute any. Mappings that
+// can't be parsed don't either, so Docker can report the problem with them.
+pub fn host_ports(mapping: &str) -> Vec<HostPort> {
+    // Split off the protocol, if any.
+    let (mapping, udp) = match mapping.rsplit_once('/') {
+        Some((mapping, protocol)) => (mapping, protocol == "udp"),
+        None => (mapping, false),
+    };
+
+    // Split off the container port, and then the host address. IPv6 addresses contain colons, so
+    // we split from the right.
+    let (address, ports) = match mapping.rsplit_once(':') {
+        Some((host, _)) => match host.rsplit_once(':') {
+            Some((address, ports)) => {
+                (address.trim_start_matches('[').trim_end_matches(']'), ports)
+            }
+            None => ("", host),
+        },
+        None => return vec![],
+    };
+
+    let address = if address.is_empty() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else if let Ok(address) = address.parse() {
+        address
+    } else {
+        return vec![];
+    };
+
+    // The host port may be a range, e.g., `8000-8010`.
+    let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+    match (first.parse::<u16>(), last.parse::<u16>()) {
+        (Ok(first), Ok(last)) => (first..=last)
+            .map(|port| HostPort { address, port, udp })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// Check that the host ports a task publishes aren't already in use. Docker would fail to start the
+// container in that case, but its error doesn't make it clear which port is the problem. This
+// check can race with other programs binding the same ports, so it can't rule out that failure.
+pub fn check_available(ports: &[String]) -> Result<(), Failure> {
+    for mapping in ports {
+        for host_port in host_ports(mapping) {
+            let result = if host_port.udp {
+                UdpSocket::bind((host_port.address, host_port.port)).map(drop)
+            } else {
+                TcpListener::bind((host_port.address, host_port.port)).map(drop)
+            };
+
+            // Only report ports that are in use. Other errors (e.g., not being allowed to bind
+            // privileged ports) don't necessarily apply to Docker, so we leave those to it.
+            if let Err(error) = result {
+                if error.kind() == ErrorKind::AddrInUse {
+                    return Err(Failure::User(
+                        format!(
+                            "Port {} (from {}) is already in use on the host.",
+                            format!("{}:{}", host_port.address, host_port.port).code_str(),
+                            mapping.code_str(),
+                        ),
+                        None,
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ports::{check_available, host_ports, HostPort};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener};
+
+    #[test]
+    fn host_ports_container_only() {
+        assert_eq!(host_ports("80"), vec![]);
+        assert_eq!(host_ports("80/udp"), vec![]);
+    }
+
+    #[test]
+    fn host_ports_host_and_container() {
+        assert_eq!(
+            host_ports("8080:80"),
+            vec![HostPort {
+                address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                port: 8080,
+                udp: false,
+            }],
+        );
+    }
+
+    #[test]
+    fn host_ports_address() {
+        assert_eq!(
+            host_ports("127.0.0.1:8080:80/udp"),
+            vec![HostPort {
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 8080,
+                udp: true,
+            }],
+        );
+        assert_eq!(
+            host_ports("[::1]:8080:80"),
+            vec![HostPort {
+                address: IpAddr::V6(Ipv6Addr::LOCALHOST),
+                port: 8080,
+                udp: false,
+            }],
+        );
+        assert_eq!(host_ports("127.0.0.1::80"), vec![]);
+    }
+
+    #[test]
+    fn host_ports_range() {
+        assert_eq!(
+            host_ports("8000-8002:8000-8002")
+                .into_iter()
+                .map(|host_port| host_port.port)
+                .collect::<Vec<_>>(),
+            vec![8000, 8001, 8002],
+        );
+    }
+
+    #[test]
+    fn host_ports_invalid() {
+        assert_eq!(host_ports("foo:80"), vec![]);
+        assert_eq!(host_ports("localhost:8080:80"), vec![]);
+    }
+
+    #[test]
+    fn check_available_in_use() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(check_available(&[format!("127.0.0.1:{}:80", port)]).is_err());
+        assert!(check_available(&["80".to_owned()]).is_ok());
+    }
+}
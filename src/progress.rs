@@ -0,0 +1,247 @@
+use crate::{failure, failure::Failure, format::CodeStr, json::Value, tar::ProgressHook};
+use std::{
+    io,
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use {
+    crossbeam::channel::bounded,
+    std::{os::unix::net::UnixStream, thread},
+};
+
+#[cfg(windows)]
+use std::fs::OpenOptions;
+
+// Progress events are written as JSON lines to a Unix domain socket (or a named pipe on Windows)
+// that another program, such as an editor, is listening on. Each event is an object with an
+// `event` field naming it, so consumers can ignore events they don't know about.
+
+// How long to wait for the listener to accept the connection or an event before giving up on it
+#[cfg(unix)]
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+// The minimum time between `archive_progress` events
+const ARCHIVE_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// A reporter sends progress events to a socket. If there is no socket, or if sending an event
+// failed, events are discarded.
+#[derive(Clone)]
+pub struct Progress {
+    stream: Option<Arc<Mutex<Option<Stream>>>>, // `None` inside once sending an event failed
+}
+
+type Stream = Box<dyn Write + Send>;
+
+impl Progress {
+    // Create a reporter which connects to the given socket, if any. The socket must already exist.
+    pub fn new(path: Option<&Path>) -> Result<Self, Failure> {
+        let stream = path
+            .map(|path| {
+                connect(path)
+                    .map(|stream| Arc::new(Mutex::new(Some(stream))))
+                    .map_err(failure::user(format!(
+                        "Unable to connect to socket {}.",
+                        path.to_string_lossy().code_str(),
+                    )))
+            })
+            .transpose()?;
+
+        Ok(Self { stream })
+    }
+
+    // Send an event with the given fields.
+    pub fn emit(&self, event: &str, fields: Vec<(String, Value)>) {
+        if let Some(stream) = &self.stream {
+            // The `unwrap` will only fail if a panic already occurred.
+            let mut stream = stream.lock().unwrap();
+            if let Some(writer) = stream.as_mut() {
+                let mut event_fields = vec![("event".to_owned(), Value::String(event.to_owned()))];
+                event_fields.extend(fields);
+
+                // Progress reporting is best-effort, so a failure to send an event (e.g., because
+                // the listener went away or stopped reading) shouldn't fail the build. A listener
+                // that missed an event can't make sense of the ones after it, so stop sending them,
+                // which also means a stuck listener only holds up the build once.
+                if let Err(e) =
+                    writeln!(writer, "{}", Value::Object(event_fields)).and_then(|_| writer.flush())
+                {
+                    debug!(
+                        "Unable to send progress event. No more will be sent. Reason: {}",
+                        e,
+                    );
+                    *stream = None;
+                }
+            }
+        }
+    }
+
+    // Create a hook which reports the bytes of input files hashed so far for the current archive
+    // as `archive_progress` events, at most one per `ARCHIVE_PROGRESS_INTERVAL`. If there is no
+    // socket, there's no hook.
+    pub fn archive_hook(&self) -> Option<ProgressHook> {
+        self.stream.as_ref()?;
+
+        let progress = self.clone();
+        let state = Mutex::new((0_u64, None::<Instant>));
+        Some(ProgressHook(Arc::new(move |bytes| {
+            // The `unwrap` will only fail if a panic already occurred.
+            let mut state = state.lock().unwrap();
+            let (total, last_event) = &mut *state;
+            *total += bytes;
+            if last_event.map_or(true, |last_event| {
+                last_event.elapsed() >= ARCHIVE_PROGRESS_INTERVAL
+            }) {
+                *last_event = Some(Instant::now());
+                progress.emit(
+                    "archive_progress",
+                    vec![("bytes".to_owned(), Value::Number(*total))],
+                );
+            }
+        })))
+    }
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> io::Result<Stream> {
+    // Connecting only blocks if the listener has fallen behind on accepting connections, but then
+    // it might never return, so it happens on another thread which is abandoned after a while.
+    let (sender, receiver) = bounded(1);
+    let path = path.to_owned();
+    thread::spawn(move || {
+        // The receiver may have given up already, in which case there's nothing left to do.
+        let _ = sender.send(UnixStream::connect(path));
+    });
+    let stream = receiver.recv_timeout(TIMEOUT).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            "The listener didn't accept the connection.",
+        )
+    })??;
+
+    // A listener which doesn't read its events would otherwise block the build once the socket's
+    // buffer is full.
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    Ok(Box::new(stream))
+}
+
+#[cfg(windows)]
+fn connect(path: &Path) -> io::Result<Stream> {
+    // Named pipes (e.g., `\\.\pipe\toast`) can be opened like files.
+    Ok(Box::new(OpenOptions::new().write(true).open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{json::Value, progress::Progress};
+
+    #[test]
+    fn emit_without_socket() {
+        Progress::new(None)
+            .unwrap()
+            .emit("foo", vec![("bar".to_owned(), Value::Bool(true))]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn emit_to_socket() {
+        use std::{
+            io::{BufRead, BufReader},
+            os::unix::net::UnixListener,
+        };
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("progress.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let progress = Progress::new(Some(&path)).unwrap();
+        progress.emit("foo", vec![("bar".to_owned(), Value::Number(42))]);
+        progress.emit("baz", vec![]);
+        drop(progress);
+
+        let (stream, _) = listener.accept().unwrap();
+        let lines = BufReader::new(stream)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"event":"foo","bar":42}"#.to_owned(),
+                r#"{"event":"baz"}"#.to_owned(),
+            ],
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn emit_stops_after_failure() {
+        use std::os::unix::net::UnixListener;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("progress.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let progress = Progress::new(Some(&path)).unwrap();
+        drop(listener.accept().unwrap());
+        drop(listener);
+        progress.emit("foo", vec![]);
+
+        assert!(progress.stream.unwrap().lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn archive_hook_without_socket() {
+        assert!(Progress::new(None).unwrap().archive_hook().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn archive_hook_to_socket() {
+        use std::{
+            io::{BufRead, BufReader},
+            os::unix::net::UnixListener,
+        };
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("progress.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // The second call comes too soon after the first to be reported.
+        let progress = Progress::new(Some(&path)).unwrap();
+        let hook = progress.archive_hook().unwrap();
+        (hook.0)(5);
+        (hook.0)(7);
+        drop(hook);
+        drop(progress);
+
+        let (stream, _) = listener.accept().unwrap();
+        let lines = BufReader::new(stream)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            lines,
+            vec![r#"{"event":"archive_progress","bytes":5}"#.to_owned()],
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_missing_socket() {
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+
+        assert!(Progress::new(Some(&dir.path().join("progress.sock"))).is_err());
+    }
+}
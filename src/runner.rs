@@ -1,12 +1,35 @@
-use crate::{cache, docker, failure, failure::Failure, tar, toastfile::Task};
+use crate::{
+    cache,
+    cache::CryptoHash,
+    docker, failure,
+    failure::Failure,
+    format,
+    format::CodeStr,
+    glob,
+    json::Value,
+    manifest, ports,
+    progress::Progress,
+    schedule, spinner,
+    spinner::spin,
+    tar,
+    telemetry::Span,
+    toastfile,
+    toastfile::{FileMode, Task, Toastfile},
+};
+use ::tar::Builder;
+use crossbeam::channel::unbounded;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
+    fs::{canonicalize, read_to_string, symlink_metadata, File},
+    io,
     io::{Seek, SeekFrom},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex,
     },
+    time::{Duration, Instant},
 };
 use tempfile::tempfile;
 
@@ -29,6 +52,996 @@ impl Drop for Context {
     }
 }
 
+// An archive of input files is determined by the expanded input paths, the excluded input paths,
+// the generated input files, the overridden file modes, whether modification times and permissions
+// are preserved, which files have their line endings normalized, whether version control metadata
+// is included, whether the inputs are required to contain files, and where the files are placed in
+// the container.
+#[derive(Clone, Eq, Hash, PartialEq)]
+struct ArchiveKey {
+    input_paths: Vec<PathBuf>,
+    excluded_input_paths: Vec<PathBuf>,
+    generated_input_files: BTreeMap<PathBuf, String>,
+    input_file_modes: BTreeMap<PathBuf, FileMode>,
+    preserve_mtime: bool,
+    preserve_permissions: bool,
+    normalize_line_endings: Vec<PathBuf>,
+    include_vcs: bool,
+    excluded_larger_than: Option<u64>,
+    excluded_mtime_before: Option<u64>,
+    require_files: bool,
+    location: PathBuf,
+}
+
+impl ArchiveKey {
+    // Determine whether an archive for this key can be made by appending paths to an archive for
+    // the given key. The generated input files are added last, so the other archive can't have any.
+    fn extends(&self, base: &Self) -> bool {
+        self.input_paths.starts_with(&base.input_paths)
+            && self.excluded_input_paths == base.excluded_input_paths
+            && base.generated_input_files.is_empty()
+            && self.input_file_modes == base.input_file_modes
+            && self.preserve_mtime == base.preserve_mtime
+            && self.preserve_permissions == base.preserve_permissions
+            && self.normalize_line_endings == base.normalize_line_endings
+            && self.include_vcs == base.include_vcs
+            && self.excluded_larger_than == base.excluded_larger_than
+            && self.excluded_mtime_before == base.excluded_mtime_before
+            && self.require_files == base.require_files
+            && self.location == base.location
+    }
+}
+
+// An archive of input files together with its hash and manifest
+type Archive = (File, String, tar::Manifest);
+
+// This struct remembers the archives of input files that were created during a run, so that tasks
+// with identical inputs only need to read and hash those files once. Archives without generated
+// input files also come with the state needed to extend them, so a task whose input paths start
+// with those of an earlier task only needs to read and hash the additional paths.
+#[derive(Default)]
+pub struct Archives {
+    archives: HashMap<ArchiveKey, (Archive, Option<tar::State>)>,
+}
+
+impl Archives {
+    // Return the archive of the input files for a task along with its hash and manifest, creating
+    // it if no earlier task had the same inputs.
+    fn get_or_create(
+        &mut self,
+        task: &Task,
+        source_dir: &Path,
+        options: &tar::Options,
+        interrupted: &Arc<AtomicBool>,
+    ) -> Result<Archive, Failure> {
+        let key = archive_key(task, source_dir, options)?;
+
+        // Reuse the archive from an earlier task, if there is one.
+        if let Some(((tar_file, input_files_hash, input_files_manifest), _)) =
+            self.archives.get(&key)
+        {
+            debug!("Reusing the archive of input files from an earlier task\u{2026}");
+
+            return Ok((
+                tar_file
+                    .try_clone()
+                    .map_err(failure::system("Unable to reuse temporary file."))?,
+                input_files_hash.clone(),
+                input_files_manifest.clone(),
+            ));
+        }
+
+        // Create a temporary archive for the input file contents.
+        let mut tar_file =
+            tempfile().map_err(failure::system("Unable to create temporary file."))?;
+
+        // Archives with generated input files can't be extended, so there's no need to keep their
+        // state. If there's also no archive to extend, just create the archive from scratch.
+        if !key.generated_input_files.is_empty()
+            && !self.archives.keys().any(|base_key| key.extends(base_key))
+        {
+            let (tar_file, input_files_hash, input_files_manifest) = tar::create(
+                "Reading files\u{2026}",
+                tar_file,
+                &key.input_paths,
+                &task.excluded_input_paths,
+                source_dir,
+                toastfile::input_location(task),
+                options,
+                interrupted,
+            )?;
+
+            return self.remember(key, tar_file, input_files_hash, input_files_manifest, None);
+        }
+
+        // Find the earlier archive with the longest list of input paths that this one extends, if
+        // any, and start with a copy of it.
+        let base = self
+            .archives
+            .iter()
+            .filter_map(|(base_key, ((base_file, _, _), base_state))| {
+                base_state
+                    .as_ref()
+                    .filter(|_| key.extends(base_key))
+                    .map(|base_state| (base_key.input_paths.len(), base_file, base_state))
+            })
+            .max_by_key(|(base_len, _, _)| *base_len);
+        let (base_len, mut state) = if let Some((base_len, base_file, base_state)) = base {
+            debug!("Extending the archive of input files from an earlier task\u{2026}");
+
+            let mut base_file = base_file
+                .try_clone()
+                .map_err(failure::system("Unable to reuse temporary file."))?;
+            base_file
+                .seek(SeekFrom::Start(0))
+                .and_then(|_| base_file.metadata())
+                .and_then(|metadata| {
+                    tar::copy_unfinished(&mut base_file, metadata.len(), &mut tar_file)
+                })
+                .map_err(failure::system("Unable to copy temporary file."))?;
+
+            (base_len, base_state.clone())
+        } else {
+            (0, tar::State::default())
+        };
+
+        // Write the remaining input paths to the archive.
+        let (tar_file, input_files_hash, input_files_manifest, state) = {
+            let _guard = spin("Reading files\u{2026}");
+
+            let mut builder = Builder::new(tar_file);
+            tar::append(
+                &mut builder,
+                &mut state,
+                &key.input_paths[base_len..],
+                &task.excluded_input_paths,
+                source_dir,
+                toastfile::input_location(task),
+                options,
+                interrupted,
+            )?;
+
+            // Only keep the state if it can be used to extend this archive later.
+            let extensible_state = if key.generated_input_files.is_empty() {
+                Some(state.clone())
+            } else {
+                None
+            };
+
+            let (tar_file, input_files_hash, input_files_manifest) = tar::finish(
+                builder,
+                state,
+                &task.excluded_input_paths,
+                toastfile::input_location(task),
+                options,
+            )?;
+
+            (
+                tar_file,
+                input_files_hash,
+                input_files_manifest,
+                extensible_state,
+            )
+        };
+
+        self.remember(key, tar_file, input_files_hash, input_files_manifest, state)
+    }
+
+    // Compute the hash of the input files for a task without keeping an archive, unless an earlier
+    // task already archived the same inputs. The files are read just as they would be for the
+    // archive, so the hash is the same as the one `get_or_create` would return.
+    fn hash(
+        &self,
+        task: &Task,
+        source_dir: &Path,
+        options: &tar::Options,
+        interrupted: &Arc<AtomicBool>,
+    ) -> Result<String, Failure> {
+        let key = archive_key(task, source_dir, options)?;
+
+        if let Some(((_, input_files_hash, _), _)) = self.archives.get(&key) {
+            return Ok(input_files_hash.clone());
+        }
+
+        tar::create(
+            "Hashing files\u{2026}",
+            io::sink(),
+            &key.input_paths,
+            &task.excluded_input_paths,
+            source_dir,
+            toastfile::input_location(task),
+            options,
+            interrupted,
+        )
+        .map(|(_, input_files_hash, _)| input_files_hash)
+    }
+
+    // Remember an archive for subsequent tasks and return it.
+    fn remember(
+        &mut self,
+        key: ArchiveKey,
+        tar_file: File,
+        input_files_hash: String,
+        input_files_manifest: tar::Manifest,
+        state: Option<tar::State>,
+    ) -> Result<Archive, Failure> {
+        self.archives.insert(
+            key,
+            (
+                (
+                    tar_file
+                        .try_clone()
+                        .map_err(failure::system("Unable to reuse temporary file."))?,
+                    input_files_hash.clone(),
+                    input_files_manifest.clone(),
+                ),
+                state,
+            ),
+        );
+
+        Ok((tar_file, input_files_hash, input_files_manifest))
+    }
+
+    // Forget all the archives. This must be called whenever the files on the host might have
+    // changed, e.g., when a task copies its output files to the host.
+    pub fn invalidate(&mut self) {
+        self.archives.clear();
+    }
+}
+
+// Determine which archive of input files a task needs. Any patterns are expanded first, so the key
+// only depends on the files which actually match.
+fn archive_key(
+    task: &Task,
+    source_dir: &Path,
+    options: &tar::Options,
+) -> Result<ArchiveKey, Failure> {
+    Ok(ArchiveKey {
+        input_paths: expand_input_paths(task, source_dir)?,
+        excluded_input_paths: task.excluded_input_paths.clone(),
+        generated_input_files: task.generated_input_files.clone(),
+        input_file_modes: task.input_file_modes.clone(),
+        preserve_mtime: task.preserve_mtime,
+        preserve_permissions: task.preserve_permissions,
+        normalize_line_endings: task.normalize_line_endings.clone(),
+        include_vcs: task.include_vcs,
+        excluded_larger_than: task.excluded_larger_than,
+        excluded_mtime_before: task.excluded_mtime_before,
+        require_files: options.require_files,
+        location: toastfile::input_location(task).to_owned(),
+    })
+}
+
+// Replace the patterns among the input paths of a task with the paths they match. A pattern in
+// `input_paths` must match something, since otherwise it's probably a typo. The entries of
+// `optional_input_paths` are included only if they exist or match something. The paths listed in
+// the file named by `input_paths_from` are added too, along with that file itself.
+fn expand_input_paths(task: &Task, source_dir: &Path) -> Result<Vec<PathBuf>, Failure> {
+    let mut input_paths = vec![];
+
+    for path in &task.input_paths {
+        if glob::is_pattern(path) {
+            let matches = glob::expand(path, source_dir)?;
+
+            if matches.is_empty() {
+                return Err(Failure::User(
+                    format!(
+                        "The pattern {} in {} doesn't match anything. If that's expected, move \
+                            it to {}.",
+                        path.to_string_lossy().code_str(),
+                        "input_paths".code_str(),
+                        "optional_input_paths".code_str(),
+                    ),
+                    None,
+                ));
+            }
+
+            input_paths.extend(matches);
+        } else {
+            input_paths.push(path.clone());
+        }
+    }
+
+    for path in &task.optional_input_paths {
+        if glob::is_pattern(path) {
+            input_paths.extend(glob::expand(path, source_dir)?);
+        } else if symlink_metadata(source_dir.join(path)).is_ok() {
+            input_paths.push(path.clone());
+        }
+    }
+
+    if let Some(list_path) = &task.input_paths_from {
+        input_paths.extend(read_input_paths_from(list_path, source_dir)?);
+        input_paths.push(list_path.clone());
+    }
+
+    Ok(input_paths)
+}
+
+// Read the paths listed in a file named by `input_paths_from`. They're taken literally rather than
+// as patterns, and each one must be relative and exist.
+fn read_input_paths_from(list_path: &Path, source_dir: &Path) -> Result<Vec<PathBuf>, Failure> {
+    let contents = read_to_string(source_dir.join(list_path)).map_err(failure::user(format!(
+        "Unable to read file {} (from {}).",
+        list_path.to_string_lossy().code_str(),
+        "input_paths_from".code_str(),
+    )))?;
+
+    toastfile::parse_input_paths_from(&contents)
+        .into_iter()
+        .map(|(line, path)| {
+            let problem = if !toastfile::is_relative_linux_path(&path) {
+                "which is absolute"
+            } else if symlink_metadata(source_dir.join(&path)).is_err() {
+                "which doesn't exist"
+            } else {
+                return Ok(path);
+            };
+
+            Err(Failure::User(
+                format!(
+                    "Line {} of {} lists {}, {}.",
+                    line,
+                    list_path.to_string_lossy().code_str(),
+                    path.to_string_lossy().code_str(),
+                    problem,
+                ),
+                None,
+            ))
+        })
+        .collect()
+}
+
+// Make sure the `input_paths` of the tasks in a schedule exist (or, for patterns, match something)
+// before anything runs, so they can all be reported at once rather than one at a time as the tasks
+// get to them. A path is exempt if a dependency of the task lists it in `output_paths`, or lists a
+// path inside of it or containing it (or a pattern which could match either), since it might not
+// exist until the dependency runs.
+pub fn check_input_paths(
+    toastfile_dir: &Path,
+    toastfile: &Toastfile,
+    schedule: &[&str],
+) -> Result<(), Failure> {
+    let mut violations = vec![];
+
+    for task_name in schedule {
+        let task = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+
+        // Gather the paths that the dependencies of the task will produce.
+        let output_paths = schedule::compute(toastfile, &[task_name])
+            .into_iter()
+            .filter(|dependency| dependency != task_name)
+            .flat_map(|dependency| &toastfile.tasks[dependency].output_paths)
+            .collect::<Vec<_>>();
+        let produced = |path: &Path| {
+            output_paths.iter().any(|output_path| {
+                path.starts_with(output_path)
+                    || output_path.starts_with(path)
+                    || glob::matches_prefix(path, output_path)
+                    || glob::matches_prefix(output_path, path)
+            })
+        };
+
+        let mut missing = vec![];
+        for path in task.input_paths.iter().chain(task.input_paths_from.iter()) {
+            let exists = if glob::is_pattern(path) {
+                !glob::expand(path, toastfile_dir)?.is_empty()
+            } else {
+                symlink_metadata(toastfile_dir.join(path)).is_ok()
+            };
+
+            if !exists && !produced(path) {
+                missing.push(path);
+            }
+        }
+
+        if !missing.is_empty() {
+            violations.push((task_name, missing));
+        }
+    }
+
+    if !violations.is_empty() {
+        return Err(Failure::User(
+            format!(
+                "The following tasks have input paths which don't exist: {}.",
+                format::series(
+                    violations
+                        .iter()
+                        .map(|(task, paths)| {
+                            format!(
+                                "{} ({})",
+                                task.code_str(),
+                                format::series(
+                                    paths
+                                        .iter()
+                                        .map(|path| format!(
+                                            "{}",
+                                            path.to_string_lossy().code_str()
+                                        ))
+                                        .collect::<Vec<_>>()
+                                        .as_ref(),
+                                ),
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .as_ref(),
+                ),
+            ),
+            None,
+        ));
+    }
+
+    Ok(())
+}
+
+// Images are labeled with a hash of the path of the toastfile that produced them, so Toast can
+// tell which images in a shared repository belong to which project.
+const TOASTFILE_LABEL_KEY: &str = "toast.toastfile";
+
+// Compute the label (`key=value`) for the images produced by the current toastfile.
+pub fn toastfile_label(settings: &super::Settings) -> String {
+    format!(
+        "{}={}",
+        TOASTFILE_LABEL_KEY,
+        settings.toastfile_path.to_string_lossy().crypto_hash(),
+    )
+}
+
+// Images are also labeled with their cache key. A Docker registry verifies the contents of an image
+// against its digest, but not that the tag it was pulled by is the one it was pushed with, so the
+// label is checked when pulling from the remote cache. This only catches mistakes, e.g., an image
+// retagged by hand. Anyone who can push to the repository can also set the label, so it's no
+// protection against tampering.
+const CACHE_KEY_LABEL_KEY: &str = "toast.cache_key";
+
+// If a task's command exited with a nonzero code that the task allows, its image is labeled with
+// the code, so it can be reported when the image is used from the cache.
+const EXIT_CODE_LABEL_KEY: &str = "toast.exit_code";
+
+// Compute the labels (`key=value`) for an image that a task produces.
+fn image_labels(settings: &super::Settings, image: &str, exit_code: i32) -> Vec<String> {
+    let mut labels = vec![toastfile_label(settings)];
+    if let Some(key) = cache::image_key(image) {
+        labels.push(format!("{}={}", CACHE_KEY_LABEL_KEY, key));
+    }
+    if exit_code != 0 {
+        labels.push(format!("{}={}", EXIT_CODE_LABEL_KEY, exit_code));
+    }
+    labels
+}
+
+// Determine the exit code of the command which produced a cached image. Only tasks which allow
+// nonzero exit codes can produce cached images from commands that didn't exit with zero. If the
+// label is missing or can't be read, the exit code is unknown.
+fn cached_exit_code(image: &str, task: &Task, interrupted: &Arc<AtomicBool>) -> Option<i32> {
+    if task.allowed_exit_codes.is_empty() {
+        return Some(0);
+    }
+
+    let exit_code = match docker::image_label(image, EXIT_CODE_LABEL_KEY, interrupted) {
+        Ok(label) => label.and_then(|label| label.parse().ok()),
+        Err(e) => {
+            debug!("{}", e);
+            None
+        }
+    };
+    if exit_code.is_none() {
+        warn!(
+            "Unable to determine the exit code of the command which produced image {}, since \
+             its {} label is missing or invalid.",
+            image.code_str(),
+            EXIT_CODE_LABEL_KEY.code_str(),
+        );
+    }
+
+    exit_code
+}
+
+// Determine whether a command's exit code means it succeeded.
+fn exit_code_allowed(task: &Task, exit_code: i32) -> bool {
+    exit_code == 0
+        || u8::try_from(exit_code).map_or(false, |exit_code| {
+            task.allowed_exit_codes.contains(&exit_code)
+        })
+}
+
+// Pull an image from the remote cache and check that its cache key label matches its tag. If it's
+// labeled with a different key, delete it and report a miss. Images without the label were
+// written by older versions of Toast, so they're used as before.
+fn pull_cached_image(image: &str, interrupted: &Arc<AtomicBool>) -> Result<bool, Failure> {
+    docker::pull_image(image, interrupted)?;
+
+    let label = match docker::image_label(image, CACHE_KEY_LABEL_KEY, interrupted)? {
+        Some(label) => label,
+        None => return Ok(true),
+    };
+    if Some(label.as_str()) == cache::image_key(image) {
+        return Ok(true);
+    }
+
+    warn!(
+        "Ignoring image {} from the remote cache, since it's labeled with cache key {} instead of \
+         the expected one.",
+        image.code_str(),
+        label.code_str(),
+    );
+    docker::delete_image(image, interrupted)?;
+
+    Ok(false)
+}
+
+// With `--keep-going-on-cache-error`, an error from the cache is downgraded to a warning so the
+// run can carry on as if the cache had missed. Interruptions are never downgraded.
+fn tolerate_cache_error(
+    settings: &super::Settings,
+    interrupted: &Arc<AtomicBool>,
+    error: Failure,
+) -> Result<(), Failure> {
+    if settings.keep_going_on_cache_error
+        && !matches!(error, Failure::Interrupted)
+        && !interrupted.load(Ordering::SeqCst)
+    {
+        warn!("{} Continuing without the cache.", error);
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+// Copy the output files for a task out of its cached image. They still need to be moved into place.
+fn fetch_cached_output_files(
+    image: &str,
+    toastfile_dir: &Path,
+    task_environment: &HashMap<String, String>,
+    task: &Task,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<docker::FetchedFiles, Failure> {
+    // We need to create a container from which we can extract the output files.
+    let container = docker::create_container(
+        image,
+        toastfile_dir,
+        task_environment,
+        &task.mount_paths,
+        task.mount_readonly,
+        &task.ports,
+        task.read_only,
+        &task.writable_paths,
+        &task.location,
+        &task.user,
+        task.shell.as_deref(),
+        task.use_entrypoint,
+        &task.command,
+        &task.extra_docker_arguments,
+        interrupted,
+    )?;
+
+    // Delete the container when we're done.
+    defer! {{
+      if let Err(e) = docker::delete_container(&container, interrupted) {
+        error!("{}", e);
+      }
+    }}
+
+    // Extract the output files from the container.
+    docker::fetch_from_container(&container, &task.output_paths, &task.location, interrupted)
+}
+
+// Leave out the input files that are too large or too old for a task, if it has such limits. Both
+// only depend on the metadata of the files, so the archive is still deterministic.
+fn exclusion_filter(task: &Task) -> Option<tar::ExclusionFilter> {
+    if task.excluded_larger_than.is_none() && task.excluded_mtime_before.is_none() {
+        return None;
+    }
+
+    let larger_than = task.excluded_larger_than;
+    let modified_before = task.excluded_mtime_before;
+    Some(tar::ExclusionFilter(Arc::new(move |_, metadata| {
+        larger_than.map_or(false, |size| metadata.len() > size)
+            || modified_before.map_or(false, |cutoff| tar::file_mtime(metadata) < cutoff)
+    })))
+}
+
+// Determine how to archive the input files for a task.
+fn archive_options(
+    settings: &super::Settings,
+    task: &Task,
+    timings: Option<&Arc<tar::Timings>>,
+    progress: Option<&Progress>,
+) -> tar::Options {
+    tar::Options {
+        io_retries: settings.io_retries,
+        case_insensitive_check: settings.case_insensitive_check,
+        synthetic_files: task
+            .generated_input_files
+            .iter()
+            .map(|(path_rsd, contents)| tar::SyntheticFile {
+                path_rsd: path_rsd.clone(),
+                contents: contents.clone().into_bytes(),
+                executable: false,
+            })
+            .collect(),
+        file_modes: task
+            .input_file_modes
+            .iter()
+            .map(|(path_rsd, mode)| (path_rsd.clone(), mode.0))
+            .collect(),
+        preserve_mtime: task.preserve_mtime,
+        preserve_permissions: task.preserve_permissions,
+        content_transform: if task.normalize_line_endings.is_empty() {
+            None
+        } else {
+            Some(tar::ContentTransform::normalize_line_endings(
+                toastfile::input_location(task),
+                &task.normalize_line_endings,
+            ))
+        },
+        exclusion_filter: exclusion_filter(task),
+        exclude_vcs_directories: !task.include_vcs,
+        trace_hashes: settings.trace_hashes,
+        timings: timings.cloned(),
+        deadline: settings
+            .archive_timeout
+            .map(|timeout| Instant::now() + timeout),
+        scan_cache: settings.scan_cache.clone(),
+        skipped_dir: if settings.include_cache_dir {
+            None
+        } else {
+            settings
+                .cache_dir
+                .as_ref()
+                .and_then(|cache_dir| canonicalize(cache_dir).ok())
+        },
+        // Only `optional_input_paths` are allowed to match nothing at all.
+        require_files: settings.require_input_files
+            && (!task.input_paths.is_empty() || task.input_paths_from.is_some()),
+        progress_hook: progress.and_then(Progress::archive_hook),
+    }
+}
+
+// Compute the name of the image that a task would produce, without running it. This requires
+// reading the input files. Also return everything that went into the cache key (see
+// `cache::image_name_and_inputs`) and the manifest of the input files.
+pub fn image_name(
+    settings: &super::Settings,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+    archives: &mut Archives,
+    task: &Task,
+    previous_image: &str,
+) -> Result<(String, cache::KeyInputs, tar::Manifest), Failure> {
+    // All relative paths are relative to where the toastfile lives.
+    let mut toastfile_dir = PathBuf::from(&settings.toastfile_path);
+    toastfile_dir.pop();
+
+    let (_, input_files_hash, input_files_manifest) = archives.get_or_create(
+        task,
+        &toastfile_dir,
+        &archive_options(settings, task, None, None),
+        interrupted,
+    )?;
+
+    let (image, inputs) = cache::image_name_and_inputs(
+        previous_image,
+        &settings.docker_repo,
+        settings.cache_key_encoding,
+        task,
+        &input_files_hash,
+        environment,
+    );
+
+    Ok((image, inputs, input_files_manifest))
+}
+
+// Pull the images that some cacheable tasks would produce from the remote cache before running any
+// of them, so they're already local by the time the tasks are run. The input files are hashed one
+// task at a time, and each image is handed to one of `cache_concurrency` workers as soon as its
+// name is known, so the pulls overlap with the hashing and with each other. Every task still checks
+// the caches as usual, so anything that goes wrong here just means the task finds less.
+pub fn prefetch(
+    settings: &super::Settings,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+    archives: &Archives,
+    tasks: &[&Task],
+    base_image: &str,
+) {
+    // All relative paths are relative to where the toastfile lives.
+    let mut toastfile_dir = PathBuf::from(&settings.toastfile_path);
+    toastfile_dir.pop();
+
+    // The workers only need to know whether to probe the registry before pulling.
+    let probe_timeout = if settings.probe_remote_cache {
+        Some(settings.remote_cache_probe_timeout)
+    } else {
+        None
+    };
+
+    // The `unwrap` will only fail if a panic already occurred.
+    let pulled = crossbeam::scope(|scope| {
+        let (sender, receiver) = unbounded::<String>();
+
+        // Start the workers. Only one spinner can be shown at a time, so they don't show any.
+        let workers = (0..settings.cache_concurrency.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                scope.spawn(move |_| {
+                    spinner::suppress_on_current_thread();
+
+                    let mut pulled = 0_usize;
+                    for image in receiver {
+                        if interrupted.load(Ordering::SeqCst) {
+                            break;
+                        }
+
+                        match prefetch_image(&image, probe_timeout, interrupted) {
+                            Ok(true) => pulled += 1,
+                            Ok(false) => {}
+                            Err(e) => debug!("{}", e),
+                        }
+                    }
+
+                    pulled
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(receiver);
+
+        // Compute the name of the image that each task produces, just like `run` does.
+        let mut image = base_image.to_owned();
+        for task in tasks {
+            if interrupted.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let input_files_hash = match archives.hash(
+                task,
+                &toastfile_dir,
+                &archive_options(settings, task, None, None),
+                interrupted,
+            ) {
+                Ok(input_files_hash) => input_files_hash,
+                Err(e) => {
+                    // The task will report the error when it's run.
+                    debug!("{}", e);
+                    break;
+                }
+            };
+
+            let previous_image = image;
+            image = cache::image_name(
+                &previous_image,
+                &settings.docker_repo,
+                settings.cache_key_encoding,
+                task,
+                &input_files_hash,
+                environment,
+            );
+
+            // A task which doesn't change the image has nothing to pull. The workers only hang up
+            // if they were interrupted.
+            if image != previous_image && sender.send(image.clone()).is_err() {
+                break;
+            }
+
+            // Running a task which could change files on the host may change the input files of
+            // the tasks after it, so their images can't be known yet.
+            if modifies_host(task) {
+                break;
+            }
+        }
+        drop(sender);
+
+        // Wait for the workers to finish. The `unwrap` will only fail if a panic already occurred.
+        let _guard = spin("Pulling images from the remote cache\u{2026}");
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .sum::<usize>()
+    })
+    .unwrap();
+
+    if pulled > 0 {
+        info!(
+            "Prefetched {} from the remote cache.",
+            format::number(pulled, "image"),
+        );
+    }
+}
+
+// Pull an image from the remote cache for `prefetch`, unless it's already local. If a probe
+// timeout is given, ask the registry whether the image exists first. Return whether the image was
+// pulled.
+fn prefetch_image(
+    image: &str,
+    probe_timeout: Option<Duration>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<bool, Failure> {
+    if docker::image_exists(image, interrupted)? {
+        return Ok(false);
+    }
+
+    if let Some(timeout) = probe_timeout {
+        if !docker::remote_image_exists(image, timeout, interrupted)? {
+            return Ok(false);
+        }
+    }
+
+    pull_cached_image(image, interrupted)
+}
+
+// Make sure the command of a task created all of its `expect_outputs` in the container, and name
+// the ones it didn't.
+fn check_expected_outputs(
+    task: &Task,
+    container: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    let mut missing = vec![];
+    for path in &task.expect_outputs {
+        if !docker::path_exists(container, &task.location.join(path), interrupted)? {
+            missing.push(path.to_string_lossy().code_str().to_string());
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Failure::User(
+            format!(
+                "The command succeeded, but it didn't create {} from {}: {}.",
+                if missing.len() == 1 {
+                    "this path"
+                } else {
+                    "these paths"
+                },
+                "expect_outputs".code_str(),
+                format::series(&missing),
+            ),
+            None,
+        ))
+    }
+}
+
+// Determine whether running a task could change files on the host, which would invalidate any
+// archives of input files created before. Extra Docker arguments are treated conservatively, since
+// they can be used to mount host paths into the container.
+pub fn modifies_host(task: &Task) -> bool {
+    !task.output_paths.is_empty()
+        || !task.output_paths_on_failure.is_empty()
+        || (!task.mount_paths.is_empty() && !task.mount_readonly)
+        || !task.extra_docker_arguments.is_empty()
+}
+
+// Archive the input files for a task (see `Archives::get_or_create`) and prepare the archive for
+// copying into a container.
+#[allow(clippy::too_many_arguments)]
+fn archive_input_files(
+    settings: &super::Settings,
+    interrupted: &Arc<AtomicBool>,
+    archives: &mut Archives,
+    task: &Task,
+    toastfile_dir: &Path,
+    timings: Option<&Arc<tar::Timings>>,
+    span: &Span,
+    progress: &Progress,
+) -> Result<Archive, Failure> {
+    let mut archive_span = span.child("toast.archive");
+    progress.emit("archive_started", vec![]);
+    let (mut tar_file, input_files_hash, input_files_manifest) = archives.get_or_create(
+        task,
+        toastfile_dir,
+        &archive_options(settings, task, timings, Some(progress)),
+        interrupted,
+    )?;
+    let entries = Value::Number(input_files_manifest.len() as u64);
+    let bytes = tar_file.metadata().ok().map(|metadata| metadata.len());
+    archive_span.set_attribute("toast.archive.entries", entries.clone());
+    let mut fields = vec![("entries".to_owned(), entries)];
+    if let Some(bytes) = bytes {
+        archive_span.set_attribute("toast.archive.bytes", Value::Number(bytes));
+        fields.push(("bytes".to_owned(), Value::Number(bytes)));
+    }
+    drop(archive_span);
+    progress.emit("archive_finished", fields);
+
+    // Make sure nothing huge was included by accident.
+    check_archive_size(settings, task, &mut tar_file, input_files_manifest.len())?;
+
+    // Seek back to the beginning of the archive to prepare for copying it into the container.
+    tar_file
+        .seek(SeekFrom::Start(0))
+        .map_err(failure::system("Unable to seek temporary file."))?;
+
+    Ok((tar_file, input_files_hash, input_files_manifest))
+}
+
+// Rewrite the archive of input files for a task with a read-only root filesystem so it can be
+// copied into the writable path which contains the location of the input files (see
+// `toastfile::input_location`).
+fn reroot_archive(tar_file: &mut File, writable_path: &Path) -> Result<File, Failure> {
+    let rerooted_file = tempfile().map_err(failure::system("Unable to create temporary file."))?;
+    let mut rerooted_file = tar::reroot(&mut *tar_file, rerooted_file, writable_path)?;
+
+    // Seek back to the beginning of the archive to prepare for copying it into the container.
+    rerooted_file
+        .seek(SeekFrom::Start(0))
+        .map_err(failure::system("Unable to seek temporary file."))?;
+
+    Ok(rerooted_file)
+}
+
+// How many of the largest input paths to name when the input files for a task are too large
+const LARGEST_PATHS_SHOWN: usize = 5;
+
+// How many lines of the output of a failed task to include in the JUnit report
+const JUNIT_OUTPUT_LINES: usize = 100;
+
+// Warn if the archive of input files for a task exceeds the configured size or number of entries,
+// and name the paths which contribute the most. With `--strict-size`, fail instead. Large inputs
+// are usually a mistake, like including `target` or `.git`.
+fn check_archive_size(
+    settings: &super::Settings,
+    task: &Task,
+    tar_file: &mut File,
+    entries: usize,
+) -> Result<(), Failure> {
+    let size = tar_file
+        .metadata()
+        .map_err(failure::system("Unable to read temporary file metadata."))?
+        .len();
+    if size <= settings.large_archive_size && entries as u64 <= settings.large_archive_entries {
+        return Ok(());
+    }
+
+    // Find the culprits.
+    tar_file
+        .seek(SeekFrom::Start(0))
+        .map_err(failure::system("Unable to seek temporary file."))?;
+    let usage = tar::usage_by_top_level_path(&mut *tar_file, toastfile::input_location(task))?;
+    let largest_paths = usage
+        .iter()
+        .take(LARGEST_PATHS_SHOWN)
+        .map(|path_usage| {
+            format!(
+                "{} ({}, {})",
+                path_usage.path_rsd.to_string_lossy().code_str(),
+                format::bytes(path_usage.bytes),
+                format::number(path_usage.entries, "path"),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let message = format!(
+        "The input files for this task take up {} in {}, which is more than {} or {} (see {} and \
+         {} in the configuration file). The largest paths are {}.",
+        format::bytes(size),
+        format::number(entries, "path"),
+        format::bytes(settings.large_archive_size),
+        format::number(settings.large_archive_entries as usize, "path"),
+        "large_archive_size".code_str(),
+        "large_archive_entries".code_str(),
+        format::series(&largest_paths),
+    );
+
+    if settings.strict_size {
+        Err(Failure::User(message, None))
+    } else {
+        warn!("{}", message);
+        Ok(())
+    }
+}
+
+// What happened when a task ran, for the run summary
+#[derive(Default)]
+pub struct Report {
+    pub image: Option<String>,
+    pub cached: bool,
+    pub exit_code: Option<i32>, // Only when the command ran (even if it failed) or was cached
+    pub command_failed: bool,   // The command exited with a code that the task doesn't allow
+    pub output: Option<(String, String)>, // The end of the output of a failed task, for JUnit
+    pub archive_timings: Option<Arc<tar::Timings>>, // Only when profiling
+}
+
 // Run a task in a given context and return a new context. The returned context should not be `None`
 // if `need_context` is `true`.
 #[allow(clippy::too_many_arguments)]
@@ -38,56 +1051,67 @@ pub fn run(
     environment: &HashMap<String, String>,
     interrupted: &Arc<AtomicBool>,
     active_containers: &Arc<Mutex<HashSet<String>>>,
+    archives: &mut Archives,
     task: &Task,
     caching_enabled: bool,
     context: Context,
     need_context: bool,
+    span: &Span,
+    progress: &Progress,
+    report: &mut Report,
 ) -> (Result<(), Failure>, Option<Context>) {
     // All relative paths are relative to where the toastfile lives.
     let mut toastfile_dir = PathBuf::from(&settings.toastfile_path);
     toastfile_dir.pop();
 
-    // Create a temporary archive for the input file contents.
-    let tar_file = match tempfile() {
-        Ok(tar_file) => tar_file,
-        Err(e) => {
-            return (
-                Err(failure::system("Unable to create temporary file.")(e)),
-                Some(context),
-            )
-        }
-    };
-
-    // Write to the archive.
-    let (mut tar_file, input_files_hash) = match tar::create(
-        "Reading files\u{2026}",
-        tar_file,
-        &task.input_paths,
-        &task.excluded_input_paths,
-        &toastfile_dir,
-        &task.location,
-        &interrupted,
-    ) {
-        Ok((tar_file, input_files_hash)) => (tar_file, input_files_hash),
-        Err(e) => return (Err(e), Some(context)),
-    };
+    // If requested, keep track of where the time goes while reading the input files.
+    if settings.profile_file.is_some() {
+        report.archive_timings.get_or_insert_with(Arc::default);
+    }
+    let timings = report.archive_timings.clone();
 
-    // Seek back to the beginning of the archive to prepare for copying it into the container.
-    if let Err(e) = tar_file.seek(SeekFrom::Start(0)) {
-        return (
-            Err(failure::system("Unable to seek temporary file.")(e)),
-            Some(context),
-        );
+    // Archive the input files, or reuse the archive from an earlier task with the same inputs. When
+    // probing the cache, only hash them for now, since the archive isn't needed if there's a hit.
+    let probe_cache = caching_enabled && settings.probe_remote_cache;
+    let (input_files_hash, archive) = if probe_cache {
+        let _hash_span = span.child("toast.hash");
+        match archives.hash(
+            task,
+            &toastfile_dir,
+            &archive_options(settings, task, timings.as_ref(), Some(progress)),
+            interrupted,
+        ) {
+            Ok(input_files_hash) => (input_files_hash, None),
+            Err(e) => return (Err(e), Some(context)),
+        }
+    } else {
+        match archive_input_files(
+            settings,
+            interrupted,
+            archives,
+            task,
+            &toastfile_dir,
+            timings.as_ref(),
+            span,
+            progress,
+        ) {
+            Ok((tar_file, input_files_hash, input_files_manifest)) => {
+                (input_files_hash, Some((tar_file, input_files_manifest)))
+            }
+            Err(e) => return (Err(e), Some(context)),
+        }
     };
 
     // Compute the name of the image that this task produces.
     let image = cache::image_name(
         &context.image,
         &settings.docker_repo,
-        &task,
+        settings.cache_key_encoding,
+        task,
         &input_files_hash,
-        &environment,
+        environment,
     );
+    report.image = Some(image.clone());
 
     // If we need to return the same image as the one that was passed in, we should be careful to
     // reuse the given context rather than constructing a new one. Otherwise, the given context will
@@ -105,67 +1129,102 @@ pub fn run(
     // Check the cache, if applicable.
     let mut cached = false;
     if caching_enabled {
+        let mut cache_span = span.child("toast.cache_lookup");
+
         // Check the local cache.
         cached = settings.read_local_cache
             && match docker::image_exists(&image, interrupted) {
                 Ok(exists) => exists,
-                Err(e) => return (Err(e), Some(context)),
+                Err(e) => {
+                    if let Err(e) = tolerate_cache_error(settings, interrupted, e) {
+                        return (Err(e), Some(context));
+                    }
+                    false
+                }
             };
 
-        // Check the remote cache.
-        if !cached && settings.read_remote_cache {
-            if let Err(e) = docker::pull_image(&image, interrupted) {
-                // If the pull failed, it could be because the user killed the child process (e.g.,
-                // by hitting CTRL+C).
-                if interrupted.load(Ordering::SeqCst) {
-                    return (Err(e), Some(context));
+        // Check the remote cache. When probing, a registry which is slow to respond is treated
+        // like a miss.
+        if !cached
+            && settings.read_remote_cache
+            && (!probe_cache
+                || match docker::remote_image_exists(
+                    &image,
+                    settings.remote_cache_probe_timeout,
+                    interrupted,
+                ) {
+                    Ok(exists) => exists,
+                    Err(e) => {
+                        if let Err(e) = tolerate_cache_error(settings, interrupted, e) {
+                            return (Err(e), Some(context));
+                        }
+                        false
+                    }
+                })
+        {
+            match pull_cached_image(&image, interrupted) {
+                Ok(consistent) => cached = consistent,
+                Err(e) => {
+                    // If the pull failed, it could be because the user killed the child process
+                    // (e.g., by hitting CTRL+C).
+                    if interrupted.load(Ordering::SeqCst) {
+                        return (Err(e), Some(context));
+                    }
                 }
-            } else {
-                cached = true;
             }
         }
+
+        cache_span.set_attribute("toast.cache.hit", Value::Bool(cached));
     }
 
     // If the task is cached, extract the output files if applicable.
-    if cached {
-        // The task is cached. Check if there are any output files.
-        if !task.output_paths.is_empty() {
-            // We need to create a container from which we can extract the output files.
-            let container = match docker::create_container(
-                &image,
-                &toastfile_dir,
-                &task_environment,
-                &task.mount_paths,
-                task.mount_readonly,
-                &task.ports,
-                &task.location,
-                &task.user,
-                &task.command,
-                &task.extra_docker_arguments,
-                interrupted,
-            ) {
-                Ok(container) => container,
-                Err(e) => return (Err(e), Some(context)),
-            };
-
-            // Delete the container when we're done.
-            defer! {{
-              if let Err(e) = docker::delete_container(&container, interrupted) {
-                error!("{}", e);
-              }
-            }}
+    if cached && !task.output_paths.is_empty() {
+        match fetch_cached_output_files(
+            &image,
+            &toastfile_dir,
+            &task_environment,
+            task,
+            interrupted,
+        ) {
+            // Moving the files into place only involves the host, so if that fails, the cache entry
+            // isn't to blame.
+            Ok(fetched) => {
+                if let Err(e) = fetched.move_to(&toastfile_dir) {
+                    return (Err(e), Some(context));
+                }
+            }
+            Err(e) => {
+                if let Err(e) = tolerate_cache_error(settings, interrupted, e) {
+                    return (Err(e), Some(context));
+                }
 
-            // Extract the output files from the container.
-            if let Err(e) = docker::copy_from_container(
-                &container,
-                &task.output_paths,
-                &task.location,
-                &toastfile_dir,
-                interrupted,
-            ) {
-                return (Err(e), Some(context));
+                // Delete the broken cache entry so it doesn't fail again next time, and run the
+                // task instead.
+                if !context_unchanged {
+                    if let Err(e) = docker::delete_image(&image, interrupted) {
+                        error!("{}", e);
+                    }
+                }
+                cached = false;
             }
         }
+    }
+    report.cached = cached;
+
+    if cached {
+        // Report the exit code of the command, if it was allowed to fail.
+        let exit_code = if context_unchanged {
+            Some(0)
+        } else {
+            cached_exit_code(&image, task, interrupted)
+        };
+        if let Some(exit_code) = exit_code.filter(|exit_code| *exit_code != 0) {
+            info!(
+                "The command exited with code {} when it ran, which the task allows.",
+                exit_code,
+            );
+        }
+        report.exit_code = exit_code;
 
         // The cached image becomes the new context.
         (
@@ -181,6 +1240,41 @@ pub fn run(
             }),
         )
     } else {
+        // Archive the input files, unless that was already done.
+        let (mut tar_file, input_files_manifest) = if let Some(archive) = archive {
+            archive
+        } else {
+            match archive_input_files(
+                settings,
+                interrupted,
+                archives,
+                task,
+                &toastfile_dir,
+                timings.as_ref(),
+                span,
+                progress,
+            ) {
+                Ok((tar_file, archived_files_hash, input_files_manifest)) => {
+                    // The input files were read again to build the archive. If they changed since
+                    // they were hashed, the task would be cached under the key for other inputs.
+                    if archived_files_hash != input_files_hash {
+                        return (
+                            Err(Failure::User(
+                                "The input files for this task changed while Toast was reading \
+                                 them. Please try again."
+                                    .to_owned(),
+                                None,
+                            )),
+                            Some(context),
+                        );
+                    }
+
+                    (tar_file, input_files_manifest)
+                }
+                Err(e) => return (Err(e), Some(context)),
+            }
+        };
+
         // Pull the image if necessary. Note that this is not considered reading from the remote
         // cache.
         if !match docker::image_exists(&context.image, interrupted) {
@@ -192,6 +1286,12 @@ pub fn run(
             }
         }
 
+        // Make sure the ports the task publishes are free, so a conflict isn't mistaken for a
+        // failure of the command.
+        if let Err(e) = ports::check_available(&task.ports) {
+            return (Err(e), Some(context));
+        }
+
         // Create a container from the image.
         let container = match docker::create_container(
             &context.image,
@@ -200,8 +1300,12 @@ pub fn run(
             &task.mount_paths,
             task.mount_readonly,
             &task.ports,
+            task.read_only,
+            &task.writable_paths,
             &task.location,
             &task.user,
+            task.shell.as_deref(),
+            task.use_entrypoint,
             &task.command,
             &task.extra_docker_arguments,
             interrupted,
@@ -231,18 +1335,84 @@ pub fn run(
         }}
 
         // Copy files into the container. If `task.input_paths` is empty, then this will just create
-        // a directory for `task.location`.
-        if let Err(e) = docker::copy_into_container(&container, &mut tar_file, interrupted) {
+        // a directory for the input files. With a read-only root filesystem, the files have to be
+        // copied into the writable path which contains that directory [ref:location_writable].
+        let copy_result = match toastfile::location_writable_path(task).filter(|_| task.read_only) {
+            Some(writable_path) => {
+                reroot_archive(&mut tar_file, &writable_path).and_then(|rerooted_file| {
+                    docker::copy_into_container(
+                        &container,
+                        rerooted_file,
+                        &writable_path,
+                        interrupted,
+                    )
+                })
+            }
+            None => {
+                docker::copy_into_container(&container, &mut tar_file, Path::new("/"), interrupted)
+            }
+        };
+        if let Err(e) = copy_result {
             return (Err(e), Some(context));
         }
 
+        // Writing outside the writable paths is the most likely reason for the command of a task
+        // with a read-only root filesystem to fail, and Docker's error for it isn't very clear.
+        let failure_message = if task.read_only {
+            format!(
+                "Command failed. If the command tried to write outside the {} of the task, \
+                 that's why: the root filesystem is read-only.",
+                "writable_paths".code_str(),
+            )
+        } else {
+            "Command failed.".to_owned()
+        };
+
         // Start the container to run the command.
-        let result = docker::start_container(&container, interrupted).map_err(|e| match e {
+        let mut execute_span = span.child("toast.execute");
+        // In JSON mode, standard output is reserved for the summary of the run.
+        let result = docker::start_container(
+            &container,
+            settings.output_format == super::OutputFormat::Json,
+            interrupted,
+        )
+        .map_err(|e| match e {
             Failure::Interrupted => e,
             Failure::System(_, _) | Failure::User(_, _) => {
-                Failure::User("Command failed.".to_owned(), None)
+                Failure::User(failure_message.clone(), None)
+            }
+        })
+        .and_then(|exit_code| {
+            report.exit_code = Some(exit_code);
+            if exit_code_allowed(task, exit_code) {
+                Ok(exit_code)
+            } else {
+                report.command_failed = true;
+                Err(Failure::User(failure_message.clone(), None))
             }
         });
+        let result = result.and_then(|exit_code| {
+            check_expected_outputs(task, &container, interrupted).map(|()| exit_code)
+        });
+
+        // The container is about to be deleted, so save what the command printed for the JUnit
+        // report while it's still around. That's just a nicety, so it's fine if it's unavailable.
+        if settings.junit_file.is_some() && matches!(result, Err(Failure::User(_, _))) {
+            match docker::container_logs(&container, JUNIT_OUTPUT_LINES, interrupted) {
+                Ok(output) => report.output = Some(output),
+                Err(e) => debug!("{}", e),
+            }
+        }
+        if let Ok(exit_code) = result {
+            if exit_code != 0 {
+                info!(
+                    "The command exited with code {}, which the task allows.",
+                    exit_code,
+                );
+            }
+        }
+        execute_span.set_attribute("toast.execute.success", Value::Bool(result.is_ok()));
+        drop(execute_span);
 
         // Copy files from the container, if applicable.
         match result {
@@ -279,7 +1449,12 @@ pub fn run(
         // Only commit the container if we actually need to return a context.
         if need_context || persist_locally || persist_remotely {
             // Commit the container.
-            if let Err(e) = docker::commit_container(&container, &image, interrupted) {
+            if let Err(e) = docker::commit_container(
+                &container,
+                &image,
+                &image_labels(settings, &image, *result.as_ref().unwrap_or(&0)),
+                interrupted,
+            ) {
                 return (Err(e), Some(context));
             }
 
@@ -294,10 +1469,32 @@ pub fn run(
                 }
             };
 
+            // Write the manifest for the new cache entry, if applicable.
+            if settings.write_manifests && (persist_locally || persist_remotely) {
+                if let Some(key) = cache::image_key(&new_context.image) {
+                    if let Err(e) = super::cache_dir(settings).and_then(|cache_dir| {
+                        manifest::write(
+                            cache_dir,
+                            key,
+                            &new_context.image,
+                            &input_files_manifest,
+                            &settings.annotations,
+                            settings.manifest_key.as_deref(),
+                        )
+                    }) {
+                        if let Err(e) = tolerate_cache_error(settings, interrupted, e) {
+                            return (Err(e), Some(new_context));
+                        }
+                    }
+                }
+            }
+
             // Write to remote cache, if applicable.
             if persist_remotely {
                 if let Err(e) = docker::push_image(&new_context.image, interrupted) {
-                    return (Err(e), Some(new_context));
+                    if let Err(e) = tolerate_cache_error(settings, interrupted, e) {
+                        return (Err(e), Some(new_context));
+                    }
                 }
             }
 
@@ -309,3 +1506,164 @@ pub fn run(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        runner::{check_input_paths, exclusion_filter},
+        toastfile,
+    };
+    use filetime::{set_file_mtime, FileTime};
+    use std::{
+        fs::{create_dir_all, metadata, write},
+        path::Path,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn check_input_paths_existing() {
+        let toastfile_dir = tempdir().unwrap();
+        create_dir_all(toastfile_dir.path().join("src")).unwrap();
+        write(toastfile_dir.path().join("src/main.rs"), "").unwrap();
+        write(toastfile_dir.path().join("Cargo.toml"), "").unwrap();
+
+        let toastfile = toastfile::parse(
+            r#"
+image: encom:os-12
+tasks:
+  foo:
+    input_paths:
+      - Cargo.toml
+      - src
+      - src/*.rs
+        "#,
+        )
+        .unwrap();
+
+        assert!(check_input_paths(toastfile_dir.path(), &toastfile, &["foo"]).is_ok());
+    }
+
+    #[test]
+    fn check_input_paths_missing() {
+        let toastfile_dir = tempdir().unwrap();
+        write(toastfile_dir.path().join("Cargo.toml"), "").unwrap();
+
+        let toastfile = toastfile::parse(
+            r#"
+image: encom:os-12
+tasks:
+  foo:
+    input_paths:
+      - Cargo.toml
+      - Cargo.lock
+  bar:
+    input_paths:
+      - src/*.rs
+        "#,
+        )
+        .unwrap();
+
+        let result = check_input_paths(toastfile_dir.path(), &toastfile, &["foo", "bar"]);
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("`foo` (`Cargo.lock`)"));
+        assert!(message.contains("`bar` (`src/*.rs`)"));
+        assert!(!message.contains("Cargo.toml"));
+    }
+
+    #[test]
+    fn check_input_paths_produced_by_dependency() {
+        let toastfile_dir = tempdir().unwrap();
+
+        let toastfile = toastfile::parse(
+            r#"
+image: encom:os-12
+tasks:
+  foo:
+    output_paths:
+      - target
+      - dist/*.tar.gz
+  bar:
+    dependencies:
+      - foo
+    input_paths:
+      - target/release/bar
+      - dist/bar.tar.gz
+      - dist
+        "#,
+        )
+        .unwrap();
+
+        assert!(check_input_paths(toastfile_dir.path(), &toastfile, &["foo", "bar"]).is_ok());
+    }
+
+    #[test]
+    fn check_input_paths_produced_by_non_dependency() {
+        let toastfile_dir = tempdir().unwrap();
+
+        let toastfile = toastfile::parse(
+            r#"
+image: encom:os-12
+tasks:
+  foo:
+    output_paths:
+      - target
+  bar:
+    input_paths:
+      - target
+        "#,
+        )
+        .unwrap();
+
+        let result = check_input_paths(toastfile_dir.path(), &toastfile, &["foo", "bar"]);
+        assert!(result.unwrap_err().to_string().contains("`bar` (`target`)"));
+    }
+
+    #[test]
+    fn exclusion_filter_none() {
+        let toastfile = toastfile::parse(
+            r#"
+image: encom:os-12
+tasks:
+  foo: {}
+        "#,
+        )
+        .unwrap();
+
+        assert!(exclusion_filter(&toastfile.tasks["foo"]).is_none());
+    }
+
+    #[test]
+    fn exclusion_filter_limits() {
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("small.txt"), "foo").unwrap();
+        write(source_dir.path().join("large.bin"), [0; 100]).unwrap();
+        write(source_dir.path().join("old.txt"), "foo").unwrap();
+        set_file_mtime(
+            source_dir.path().join("old.txt"),
+            FileTime::from_unix_time(1_000_000_000, 0),
+        )
+        .unwrap();
+
+        let toastfile = toastfile::parse(
+            r#"
+image: encom:os-12
+tasks:
+  foo:
+    excluded_larger_than: 10
+    excluded_mtime_before: 1500000000
+        "#,
+        )
+        .unwrap();
+
+        let filter = exclusion_filter(&toastfile.tasks["foo"]).unwrap();
+        let excludes = |name: &str| {
+            (filter.0)(
+                Path::new(name),
+                &metadata(source_dir.path().join(name)).unwrap(),
+            )
+        };
+        assert!(!excludes("small.txt"));
+        assert!(excludes("large.bin"));
+        assert!(excludes("old.txt"));
+    }
+}
@@ -0,0 +1,384 @@
+use crate::{failure, failure::Failure, format::CodeStr, json::Value};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::TryFrom,
+    env::current_dir,
+    fs::{create_dir_all, read_to_string, symlink_metadata, Metadata},
+    io,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tempfile::NamedTempFile;
+
+// The name of the file (within the cache directory) where the scan cache is stored
+const SCAN_CACHE_FILE_NAME: &str = "scan-cache.json";
+
+// Files modified this recently aren't recorded. On filesystems with coarse timestamps, such a file
+// could still be changed without its modification time changing.
+const MIN_AGE: Duration = Duration::from_secs(2);
+
+// While files are being hashed, the scan cache is written out this often, so a run which crashes
+// partway through reading a large tree doesn't have to hash everything it got through again
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+// What the scan cache remembers about a file: its modification time (in nanoseconds since the Unix
+// epoch), its size, and the hash of its contents
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    mtime: u64,
+    size: u64,
+    hash: String,
+}
+
+// This struct represents the scan cache as it's stored on disk. The file is JSON, which
+// `serde_yaml` can read since JSON is a subset of YAML. The keys are absolute paths on the host.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScanCacheFile {
+    files: BTreeMap<String, Entry>,
+}
+
+// The mutable part of the scan cache
+#[derive(Debug)]
+struct State {
+    entries: BTreeMap<String, Entry>,
+    used: HashSet<String>,
+    changed: bool,
+    last_written: Instant,
+}
+
+// The scan cache remembers the hashes of the contents of files from earlier runs, so a file whose
+// modification time and size haven't changed doesn't need to be hashed again. It can be shared by
+// the tasks of a run, since entries are looked up and recorded through a shared reference.
+#[derive(Debug)]
+pub struct ScanCache {
+    path: PathBuf,
+    base_dir: PathBuf,
+    state: Mutex<State>,
+}
+
+// Determine the modification time of a file in nanoseconds since the Unix epoch, if possible.
+fn mtime_nanos(metadata: &Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .and_then(|duration| u64::try_from(duration.as_nanos()).ok())
+}
+
+impl ScanCache {
+    // Load the scan cache from the cache directory. It's only a cache, so if the file is missing or
+    // can't be parsed, the scan cache starts out empty.
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(SCAN_CACHE_FILE_NAME);
+
+        let entries = match read_to_string(&path) {
+            Ok(data) => match serde_yaml::from_str::<ScanCacheFile>(&data) {
+                Ok(file) => file.files,
+                Err(e) => {
+                    warn!(
+                        "Ignoring scan cache {}, since it can't be parsed. Reason: {}",
+                        path.to_string_lossy().code_str(),
+                        e,
+                    );
+                    BTreeMap::new()
+                }
+            },
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!(
+                        "Ignoring scan cache {}, since it can't be read. Reason: {}",
+                        path.to_string_lossy().code_str(),
+                        e,
+                    );
+                }
+                BTreeMap::new()
+            }
+        };
+
+        Self {
+            path,
+            base_dir: current_dir().unwrap_or_default(),
+            state: Mutex::new(State {
+                entries,
+                used: HashSet::new(),
+                changed: false,
+                last_written: Instant::now(),
+            }),
+        }
+    }
+
+    // Determine the key for a file, which is its absolute path.
+    fn key(&self, path_cd: &Path) -> String {
+        self.base_dir.join(path_cd).to_string_lossy().into_owned()
+    }
+
+    // Return the hash of the contents of a file from an earlier run, if its modification time and
+    // size are the same as they were then.
+    pub fn lookup(&self, path_cd: &Path, metadata: &Metadata) -> Option<String> {
+        let mtime = mtime_nanos(metadata)?;
+        let key = self.key(path_cd);
+
+        // The `unwrap` will only fail if a panic already occurred.
+        let mut state = self.state.lock().unwrap();
+        let hash = state
+            .entries
+            .get(&key)
+            .filter(|entry| entry.mtime == mtime && entry.size == metadata.len())
+            .map(|entry| entry.hash.clone())?;
+        state.used.insert(key);
+
+        Some(hash)
+    }
+
+    // Remember the hash of the contents of a file for later runs. Every so often, this also writes
+    // the scan cache out as a checkpoint (see `CHECKPOINT_INTERVAL`).
+    pub fn record(&self, path_cd: &Path, metadata: &Metadata, hash: &str) {
+        let mtime = match mtime_nanos(metadata) {
+            Some(mtime) => mtime,
+            None => return,
+        };
+
+        // Don't record files which were modified too recently to be trusted.
+        if metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .map_or(true, |age| age < MIN_AGE)
+        {
+            return;
+        }
+
+        let key = self.key(path_cd);
+        let entry = Entry {
+            mtime,
+            size: metadata.len(),
+            hash: hash.to_owned(),
+        };
+
+        // The `unwrap` will only fail if a panic already occurred.
+        let mut state = self.state.lock().unwrap();
+        if state.entries.get(&key) != Some(&entry) {
+            state.entries.insert(key.clone(), entry);
+            state.changed = true;
+        }
+        state.used.insert(key);
+
+        // A checkpoint is only a head start for the next run, so a failure to write it isn't fatal.
+        if state.changed && state.last_written.elapsed() >= CHECKPOINT_INTERVAL {
+            if let Err(e) = self.write(&mut state) {
+                debug!("Unable to checkpoint the scan cache. Reason: {}", e);
+            }
+        }
+    }
+
+    // Write the scan cache back to the cache directory if anything was recorded since it was last
+    // written. Entries for files which weren't used in this run and no longer exist are dropped, so
+    // the file doesn't grow forever.
+    pub fn save(&self) -> Result<(), Failure> {
+        // The `unwrap` will only fail if a panic already occurred.
+        let mut state = self.state.lock().unwrap();
+        let State { entries, used, .. } = &mut *state;
+        let count = entries.len();
+        entries.retain(|key, _| used.contains(key) || symlink_metadata(key).is_ok());
+        if entries.len() != count {
+            state.changed = true;
+        }
+
+        if state.changed {
+            self.write(&mut state)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Write the scan cache to its file atomically, so concurrent runs (or a run which crashes while
+    // writing a checkpoint) never observe a partially written scan cache.
+    fn write(&self, state: &mut State) -> Result<(), Failure> {
+        debug!(
+            "Writing scan cache {}\u{2026}",
+            self.path.to_string_lossy().code_str(),
+        );
+
+        let document = Value::Object(vec![(
+            "files".to_owned(),
+            Value::Object(
+                state
+                    .entries
+                    .iter()
+                    .map(|(key, entry)| {
+                        (
+                            key.clone(),
+                            Value::Object(vec![
+                                ("mtime".to_owned(), Value::Number(entry.mtime)),
+                                ("size".to_owned(), Value::Number(entry.size)),
+                                ("hash".to_owned(), Value::String(entry.hash.clone())),
+                            ]),
+                        )
+                    })
+                    .collect(),
+            ),
+        )]);
+
+        // Make sure the cache directory exists. The `unwrap` is safe since `self.path` was
+        // constructed by joining a file name onto a directory.
+        let cache_dir = self.path.parent().unwrap();
+        create_dir_all(cache_dir).map_err(failure::system(format!(
+            "Unable to create directory {}.",
+            cache_dir.to_string_lossy().code_str(),
+        )))?;
+
+        // Write the scan cache to a temporary file in the same directory, then move it into place.
+        let mut temp_file = NamedTempFile::new_in(cache_dir).map_err(failure::system(format!(
+            "Unable to create temporary file in {}.",
+            cache_dir.to_string_lossy().code_str(),
+        )))?;
+        temp_file
+            .write_all(format!("{}\n", document).as_bytes())
+            .map_err(failure::system(format!(
+                "Unable to write scan cache {}.",
+                self.path.to_string_lossy().code_str(),
+            )))?;
+        temp_file
+            .persist(&self.path)
+            .map_err(failure::system(format!(
+                "Unable to write scan cache {}.",
+                self.path.to_string_lossy().code_str(),
+            )))?;
+
+        state.changed = false;
+        state.last_written = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scan_cache::{ScanCache, CHECKPOINT_INTERVAL};
+    use filetime::{set_file_mtime, FileTime};
+    use std::{
+        fs::{metadata, remove_file, write},
+        path::Path,
+        time::Instant,
+    };
+    use tempfile::tempdir;
+
+    fn set_mtime(path: &Path, mtime: i64) {
+        set_file_mtime(path, FileTime::from_unix_time(mtime, 0)).unwrap();
+    }
+
+    #[test]
+    fn record_lookup_round_trip() {
+        let cache_dir = tempdir().unwrap();
+        let source_dir = tempdir().unwrap();
+        let path = source_dir.path().join("foo.txt");
+        write(&path, "foo").unwrap();
+        set_mtime(&path, 1_000_000_000);
+
+        let scan_cache = ScanCache::load(cache_dir.path());
+        assert_eq!(scan_cache.lookup(&path, &metadata(&path).unwrap()), None);
+        scan_cache.record(&path, &metadata(&path).unwrap(), "bar");
+        scan_cache.save().unwrap();
+
+        let scan_cache = ScanCache::load(cache_dir.path());
+        assert_eq!(
+            scan_cache.lookup(&path, &metadata(&path).unwrap()),
+            Some("bar".to_owned()),
+        );
+    }
+
+    #[test]
+    fn lookup_changed() {
+        let cache_dir = tempdir().unwrap();
+        let source_dir = tempdir().unwrap();
+        let path = source_dir.path().join("foo.txt");
+        write(&path, "foo").unwrap();
+        set_mtime(&path, 1_000_000_000);
+
+        let scan_cache = ScanCache::load(cache_dir.path());
+        scan_cache.record(&path, &metadata(&path).unwrap(), "bar");
+
+        // A different modification time is a miss.
+        set_mtime(&path, 1_000_000_001);
+        assert_eq!(scan_cache.lookup(&path, &metadata(&path).unwrap()), None);
+
+        // So is a different size.
+        write(&path, "fooo").unwrap();
+        set_mtime(&path, 1_000_000_000);
+        assert_eq!(scan_cache.lookup(&path, &metadata(&path).unwrap()), None);
+    }
+
+    #[test]
+    fn record_recently_modified() {
+        let cache_dir = tempdir().unwrap();
+        let source_dir = tempdir().unwrap();
+        let path = source_dir.path().join("foo.txt");
+        write(&path, "foo").unwrap();
+
+        let scan_cache = ScanCache::load(cache_dir.path());
+        scan_cache.record(&path, &metadata(&path).unwrap(), "bar");
+        assert_eq!(scan_cache.lookup(&path, &metadata(&path).unwrap()), None);
+    }
+
+    #[test]
+    fn record_checkpoint() {
+        let cache_dir = tempdir().unwrap();
+        let source_dir = tempdir().unwrap();
+        let path = source_dir.path().join("foo.txt");
+        write(&path, "foo").unwrap();
+        set_mtime(&path, 1_000_000_000);
+
+        // Pretend the scan cache was last written a while ago.
+        let scan_cache = ScanCache::load(cache_dir.path());
+        scan_cache.state.lock().unwrap().last_written =
+            Instant::now().checked_sub(CHECKPOINT_INTERVAL).unwrap();
+        scan_cache.record(&path, &metadata(&path).unwrap(), "bar");
+
+        // The entry was written out even though the scan cache wasn't saved.
+        assert_eq!(
+            ScanCache::load(cache_dir.path()).lookup(&path, &metadata(&path).unwrap()),
+            Some("bar".to_owned()),
+        );
+    }
+
+    #[test]
+    fn save_drops_deleted_files() {
+        let cache_dir = tempdir().unwrap();
+        let source_dir = tempdir().unwrap();
+        let path = source_dir.path().join("foo.txt");
+        write(&path, "foo").unwrap();
+        set_mtime(&path, 1_000_000_000);
+        let file_metadata = metadata(&path).unwrap();
+
+        let scan_cache = ScanCache::load(cache_dir.path());
+        scan_cache.record(&path, &file_metadata, "bar");
+        scan_cache.save().unwrap();
+        remove_file(&path).unwrap();
+
+        // The entry wasn't used in this run, and the file is gone.
+        ScanCache::load(cache_dir.path()).save().unwrap();
+        assert_eq!(
+            ScanCache::load(cache_dir.path()).lookup(&path, &file_metadata),
+            None,
+        );
+    }
+
+    #[test]
+    fn load_corrupt() {
+        let cache_dir = tempdir().unwrap();
+        write(cache_dir.path().join("scan-cache.json"), "{").unwrap();
+
+        let source_dir = tempdir().unwrap();
+        let path = source_dir.path().join("foo.txt");
+        write(&path, "foo").unwrap();
+
+        assert_eq!(
+            ScanCache::load(cache_dir.path()).lookup(&path, &metadata(&path).unwrap()),
+            None,
+        );
+    }
+}
@@ -1,5 +1,13 @@
-use crate::toastfile::Toastfile;
-use std::{collections::HashSet, convert::AsRef};
+use crate::{
+    glob, toastfile,
+    toastfile::{Task, Toastfile},
+};
+use std::{
+    collections::HashSet,
+    convert::AsRef,
+    fs::read_to_string,
+    path::{Component, Path, PathBuf},
+};
 
 // Compute a topological sort of the transitive reflexive closure of a set of tasks. The resulting
 // schedule does not depend on the order of the inputs or dependencies. We assume the tasks form a
@@ -25,11 +33,7 @@ pub fn compute<'a>(toastfile: &'a Toastfile, tasks: &[&'a str]) -> Vec<&'a str>
         let mut topological_sort: Vec<&'a str> = vec![];
 
         // Keep processing nodes on the frontier until there aren't any more left.
-        // [tag:schedule_frontier_nonempty]
-        while !frontier.is_empty() {
-            // Pop a task from the frontier. [ref:schedule_frontier_nonempty]
-            let (task, new) = frontier.pop().unwrap();
-
+        while let Some((task, new)) = frontier.pop() {
             // Check if this is a new task or one that we are coming back to because we finished
             // processing its dependencies.
             if new {
@@ -75,28 +79,161 @@ pub fn compute<'a>(toastfile: &'a Toastfile, tasks: &[&'a str]) -> Vec<&'a str>
     schedule
 }
 
+// Determine which tasks are affected by changes to the given files. A task is directly affected if
+// a changed file is among its inputs: its `input_paths`, its `optional_input_paths`, and the file
+// named by its `input_paths_from` along with the paths listed there (minus its
+// `excluded_input_paths`), its `env_file`, or the host side of its `mount_paths`. Patterns are
+// matched against the changed paths rather than expanded, so deleted files are taken into account
+// too. A task is also affected if it depends on an affected task. Relative paths in the toastfile
+// are resolved against `source_dir`, and `changed_paths` should be absolute.
+pub fn affected<'a>(
+    toastfile: &'a Toastfile,
+    source_dir: &Path,
+    changed_paths: &[PathBuf],
+) -> HashSet<&'a str> {
+    // Normalize the changed paths so they can be compared with the input paths component-wise.
+    let changed_paths: Vec<PathBuf> = changed_paths.iter().map(|path| normalize(path)).collect();
+
+    // Find the tasks whose inputs include a changed file.
+    let mut affected: HashSet<&'a str> = toastfile
+        .tasks
+        .iter()
+        .filter(|(_, task)| {
+            let resolve = |path: &Path| normalize(&source_dir.join(path));
+            let inputs: Vec<PathBuf> = task
+                .input_paths
+                .iter()
+                .chain(task.optional_input_paths.iter())
+                .chain(task.input_paths_from.iter())
+                .chain(task.env_file.iter())
+                .map(|path| resolve(path))
+                .chain(
+                    listed_input_paths(task, source_dir)
+                        .iter()
+                        .map(|path| resolve(path)),
+                )
+                .chain(task.mount_paths.iter().map(|path| resolve(&path.host_path)))
+                .collect();
+            let excluded_inputs: Vec<PathBuf> = task
+                .excluded_input_paths
+                .iter()
+                .map(|path| resolve(path))
+                .collect();
+
+            changed_paths.iter().any(|changed_path| {
+                inputs
+                    .iter()
+                    .any(|input| glob::matches_prefix(input, changed_path))
+                    && !excluded_inputs
+                        .iter()
+                        .any(|excluded_input| changed_path.starts_with(excluded_input))
+            })
+        })
+        .map(|(name, _)| name.as_ref())
+        .collect();
+
+    // Add the tasks which depend on affected tasks until there are no more to add.
+    loop {
+        let dependents: Vec<&'a str> = toastfile
+            .tasks
+            .iter()
+            .filter(|(name, task)| {
+                !affected.contains(name.as_str())
+                    && task
+                        .dependencies
+                        .iter()
+                        .any(|dependency| affected.contains(dependency.as_str()))
+            })
+            .map(|(name, _)| name.as_ref())
+            .collect();
+
+        if dependents.is_empty() {
+            break;
+        }
+
+        affected.extend(dependents);
+    }
+
+    affected
+}
+
+// Read the paths listed in the file named by a task's `input_paths_from`, if any. If the file can't
+// be read, it lists nothing here, and the problem is reported when the task runs.
+fn listed_input_paths(task: &Task, source_dir: &Path) -> Vec<PathBuf> {
+    task.input_paths_from
+        .iter()
+        .filter_map(|path| read_to_string(source_dir.join(path)).ok())
+        .flat_map(|contents| toastfile::parse_input_paths_from(&contents))
+        .map(|(_, path)| path)
+        .collect()
+}
+
+// Lexically remove `.` and `..` components from a path without consulting the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::schedule::compute;
-    use crate::toastfile::{Task, Toastfile, DEFAULT_LOCATION, DEFAULT_USER};
-    use std::{collections::HashMap, path::Path};
+    use crate::schedule::{affected, compute};
+    use crate::toastfile::{Command, MappingPath, Task, Toastfile, DEFAULT_LOCATION, DEFAULT_USER};
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        fs::write,
+        path::Path,
+    };
+    use tempfile::tempdir;
 
     fn task_with_dependencies(dependencies: Vec<String>) -> Task {
         Task {
             description: None,
+            tags: vec![],
             dependencies,
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         }
     }
@@ -109,6 +246,7 @@ mod tests {
     fn schedule_empty() {
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks: HashMap::new(),
         };
@@ -126,6 +264,7 @@ mod tests {
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -151,6 +290,7 @@ mod tests {
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -180,6 +320,7 @@ mod tests {
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -199,6 +340,7 @@ mod tests {
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -229,12 +371,14 @@ mod tests {
 
         let toastfile1 = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks: tasks1,
         };
 
         let toastfile2 = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks: tasks2,
         };
@@ -254,6 +398,7 @@ mod tests {
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -286,12 +431,14 @@ mod tests {
 
         let toastfile1 = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks: tasks1,
         };
 
         let toastfile2 = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks: tasks2,
         };
@@ -311,6 +458,7 @@ mod tests {
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -320,4 +468,243 @@ mod tests {
 
         assert_eq!(first, second);
     }
+
+    #[test]
+    fn affected_none() {
+        let mut foo = empty_task();
+        foo.input_paths = vec![Path::new("foo").to_owned()];
+
+        let mut tasks = HashMap::new();
+        tasks.insert("foo".to_owned(), foo);
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
+            default: None,
+            tasks,
+        };
+
+        let actual = affected(
+            &toastfile,
+            Path::new("/repo"),
+            &[Path::new("/repo/bar").to_owned()],
+        );
+        let expected: HashSet<&str> = HashSet::new();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn affected_input_paths() {
+        let mut foo = empty_task();
+        foo.input_paths = vec![Path::new("foo").to_owned()];
+
+        let mut bar = empty_task();
+        bar.input_paths = vec![Path::new("bar/baz.txt").to_owned()];
+
+        let mut tasks = HashMap::new();
+        tasks.insert("foo".to_owned(), foo);
+        tasks.insert("bar".to_owned(), bar);
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
+            default: None,
+            tasks,
+        };
+
+        let actual = affected(
+            &toastfile,
+            Path::new("/repo"),
+            &[Path::new("/repo/foo/qux.txt").to_owned()],
+        );
+        let expected: HashSet<&str> = vec!["foo"].into_iter().collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn affected_input_path_patterns() {
+        let mut foo = empty_task();
+        foo.input_paths = vec![Path::new("src/**/*.rs").to_owned()];
+
+        let mut bar = empty_task();
+        bar.optional_input_paths = vec![Path::new("config/*.yml").to_owned()];
+
+        let mut tasks = HashMap::new();
+        tasks.insert("foo".to_owned(), foo);
+        tasks.insert("bar".to_owned(), bar);
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
+            default: None,
+            tasks,
+        };
+
+        let actual = affected(
+            &toastfile,
+            Path::new("/repo"),
+            &[
+                Path::new("/repo/src/foo/bar.rs").to_owned(),
+                Path::new("/repo/config/baz.json").to_owned(),
+            ],
+        );
+        let expected: HashSet<&str> = vec!["foo"].into_iter().collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn affected_input_paths_from() {
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("paths.txt"), "src/foo.rs\n").unwrap();
+
+        let mut foo = empty_task();
+        foo.input_paths_from = Some(Path::new("paths.txt").to_owned());
+
+        let mut tasks = HashMap::new();
+        tasks.insert("foo".to_owned(), foo);
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
+            default: None,
+            tasks,
+        };
+
+        // Both the list and the paths in it are inputs.
+        for changed_path in ["paths.txt", "src/foo.rs"] {
+            let actual = affected(
+                &toastfile,
+                source_dir.path(),
+                &[source_dir.path().join(changed_path)],
+            );
+            let expected: HashSet<&str> = vec!["foo"].into_iter().collect();
+
+            assert_eq!(actual, expected);
+        }
+
+        let actual = affected(
+            &toastfile,
+            source_dir.path(),
+            &[source_dir.path().join("src/bar.rs")],
+        );
+
+        assert_eq!(actual, HashSet::new());
+    }
+
+    #[test]
+    fn affected_env_file() {
+        let mut foo = empty_task();
+        foo.env_file = Some(Path::new(".env").to_owned());
+
+        let mut tasks = HashMap::new();
+        tasks.insert("foo".to_owned(), foo);
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
+            default: None,
+            tasks,
+        };
+
+        let actual = affected(
+            &toastfile,
+            Path::new("/repo"),
+            &[Path::new("/repo/.env").to_owned()],
+        );
+        let expected: HashSet<&str> = vec!["foo"].into_iter().collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn affected_excluded_input_paths() {
+        let mut foo = empty_task();
+        foo.input_paths = vec![Path::new("foo").to_owned()];
+        foo.excluded_input_paths = vec![Path::new("foo/target").to_owned()];
+
+        let mut tasks = HashMap::new();
+        tasks.insert("foo".to_owned(), foo);
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
+            default: None,
+            tasks,
+        };
+
+        let actual = affected(
+            &toastfile,
+            Path::new("/repo"),
+            &[Path::new("/repo/foo/target/bar").to_owned()],
+        );
+        let expected: HashSet<&str> = HashSet::new();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn affected_mount_paths() {
+        let mut foo = empty_task();
+        foo.cache = false;
+        foo.mount_paths = vec![MappingPath {
+            host_path: Path::new("../shared").to_owned(),
+            container_path: Path::new("/shared").to_owned(),
+        }];
+
+        let mut tasks = HashMap::new();
+        tasks.insert("foo".to_owned(), foo);
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
+            default: None,
+            tasks,
+        };
+
+        let actual = affected(
+            &toastfile,
+            Path::new("/repo/project"),
+            &[Path::new("/repo/shared/bar").to_owned()],
+        );
+        let expected: HashSet<&str> = vec!["foo"].into_iter().collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn affected_dependents() {
+        let mut foo = empty_task();
+        foo.input_paths = vec![Path::new("foo").to_owned()];
+
+        let mut tasks = HashMap::new();
+        tasks.insert("foo".to_owned(), foo);
+        tasks.insert(
+            "bar".to_owned(),
+            task_with_dependencies(vec!["foo".to_owned()]),
+        );
+        tasks.insert(
+            "baz".to_owned(),
+            task_with_dependencies(vec!["bar".to_owned()]),
+        );
+        tasks.insert("qux".to_owned(), empty_task());
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
+            default: None,
+            tasks,
+        };
+
+        let actual = affected(
+            &toastfile,
+            Path::new("/repo"),
+            &[Path::new("/repo/foo").to_owned()],
+        );
+        let expected: HashSet<&str> = vec!["foo", "bar", "baz"].into_iter().collect();
+
+        assert_eq!(actual, expected);
+    }
 }
@@ -1,10 +1,12 @@
+use crate::format;
 use atty::Stream;
 use crossbeam::channel::{bounded, Sender};
 use indicatif::{ProgressBar, ProgressStyle};
 use scopeguard::guard;
 use std::{
+    cell::Cell,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
     thread,
@@ -12,6 +14,38 @@ use std::{
     time::{Duration, Instant},
 };
 
+// Whether spinners are shown. This is a global setting, since spinners are started from all over.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+// The number of bytes processed since the current spinner was started, as reported by
+// `add_progress`. The spinner shows this next to its message.
+static PROGRESS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // Whether spinners are suppressed on the current thread
+    static SUPPRESSED: Cell<bool> = Cell::new(false);
+}
+
+// Stop showing spinners (and the messages printed in their place when STDERR isn't a TTY) for the
+// rest of the program.
+pub fn disable() {
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+// Stop showing spinners on the current thread. Only one spinner can be shown at a time, so threads
+// which do work alongside each other would otherwise take turns.
+pub fn suppress_on_current_thread() {
+    SUPPRESSED.with(|suppressed| suppressed.set(true));
+}
+
+// Report that some bytes were processed for the current spinner. This is for long-running work,
+// such as hashing a huge file, which would otherwise look like it's stuck.
+pub fn add_progress(bytes: u64) {
+    if !SUPPRESSED.with(Cell::get) {
+        PROGRESS.fetch_add(bytes, Ordering::SeqCst);
+    }
+}
+
 // Render a spinner in the terminal. When the returned value is dropped, the spinner is stopped.
 pub fn spin(message: &str) -> impl Drop {
     // Start a thread for our spinner-as-a-service. This thread will only be created once and will
@@ -30,18 +64,31 @@ pub fn spin(message: &str) -> impl Drop {
 
           // If STDERR is not a TTY, the spinner will be hidden. In that case, just print
           // the message to STDERR.
-          if !atty::is(Stream::Stderr) {
+          let enabled = ENABLED.load(Ordering::SeqCst);
+          if enabled && !atty::is(Stream::Stderr) {
             info!("{}", message);
           }
 
-          // Create the spinner!
-          let spinner = ProgressBar::new(1);
+          // Create the spinner! If spinners are disabled, it's hidden.
+          let spinner = if enabled {
+            ProgressBar::new(1)
+          } else {
+            ProgressBar::hidden()
+          };
           spinner.set_style(ProgressStyle::default_spinner());
           spinner.set_message(message.clone());
 
           // Animate the spinner for as long as necessary.
           let now = Instant::now();
+          let mut shown_progress = 0;
           while spinning.load(Ordering::SeqCst) {
+            // Show how many bytes have been processed so far, if any have been reported.
+            let progress = PROGRESS.load(Ordering::SeqCst);
+            if progress != shown_progress {
+              spinner.set_message(format!("{} ({})", message, format::bytes(progress)));
+              shown_progress = progress;
+            }
+
             // Render the next frame of the spinner.
             spinner.tick();
 
@@ -68,23 +115,36 @@ pub fn spin(message: &str) -> impl Drop {
       };
     }
 
-    // Create a channel for waiting on the spinner.
-    let (response_sender, response_receiver) = bounded::<()>(0);
+    // If spinners are suppressed on this thread, there's nothing to stop later.
+    let spinner = if SUPPRESSED.with(Cell::get) {
+        None
+    } else {
+        // Create a channel for waiting on the spinner.
+        let (response_sender, response_receiver) = bounded::<()>(0);
+
+        // Progress reported for an earlier spinner doesn't count toward this one.
+        PROGRESS.store(0, Ordering::SeqCst);
+
+        // This will be set to `false` when it's time to stop the spinner.
+        let spinning = Arc::new(AtomicBool::new(true));
 
-    // This will be set to `false` when it's time to stop the spinner.
-    let spinning = Arc::new(AtomicBool::new(true));
+        // Create and animate the spinner. The `unwrap` is safe since we never hang up the channel.
+        SPINNER_SERVICE
+            .send((message.to_owned(), spinning.clone(), response_sender))
+            .unwrap();
 
-    // Create and animate the spinner. The `unwrap` is safe since we never hang up the channel.
-    SPINNER_SERVICE
-        .send((message.to_owned(), spinning.clone(), response_sender))
-        .unwrap();
+        Some((spinning, response_receiver))
+    };
 
     // Return a guard that stops the spinner via its destructor.
-    guard((), move |_| {
-        // Tell the spinner service to stop the spinner.
-        spinning.store(false, Ordering::SeqCst);
+    guard(spinner, move |spinner| {
+        if let Some((spinning, response_receiver)) = spinner {
+            // Tell the spinner service to stop the spinner.
+            spinning.store(false, Ordering::SeqCst);
 
-        // Wait for the spinner to stop. The `unwrap` is safe since we never hang up the channel.
-        response_receiver.recv().unwrap();
+            // Wait for the spinner to stop. The `unwrap` is safe since we never hang up the
+            // channel.
+            response_receiver.recv().unwrap();
+        }
     })
 }
@@ -0,0 +1,256 @@
+use crate::{failure::Failure, format::CodeStr};
+
+// The keywords of tag expressions, which can't be used as tags
+const AND: &str = "and";
+const OR: &str = "or";
+const NOT: &str = "not";
+
+// A tag expression selects tasks by their tags, e.g., `test and not slow`. `not` binds more tightly
+// than `and`, which binds more tightly than `or`. Parentheses can be used for grouping.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expression {
+    Tag(String),
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    // Determine whether a task with the given tags is selected by the expression.
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            Self::Tag(tag) => tags.contains(tag),
+            Self::Not(operand) => !operand.matches(tags),
+            Self::And(left, right) => left.matches(tags) && right.matches(tags),
+            Self::Or(left, right) => left.matches(tags) || right.matches(tags),
+        }
+    }
+}
+
+// Determine whether a string can be used as a tag.
+pub fn is_valid(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && ![AND, OR, NOT].contains(&tag)
+}
+
+// A lexical unit of a tag expression
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    OpenParen,
+    CloseParen,
+}
+
+// Split a tag expression into words and parentheses.
+fn tokenize(expression: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut word_start = None;
+
+    for (i, c) in expression.char_indices() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if let Some(start) = word_start.take() {
+                tokens.push(Token::Word(&expression[start..i]));
+            }
+
+            if c == '(' {
+                tokens.push(Token::OpenParen);
+            } else if c == ')' {
+                tokens.push(Token::CloseParen);
+            }
+        } else if word_start.is_none() {
+            word_start = Some(i);
+        }
+    }
+
+    if let Some(start) = word_start {
+        tokens.push(Token::Word(&expression[start..]));
+    }
+
+    tokens
+}
+
+// Parse a tag expression.
+pub fn parse(expression: &str) -> Result<Expression, Failure> {
+    let tokens = tokenize(expression);
+    let mut parser = Parser {
+        expression,
+        tokens: &tokens,
+        position: 0,
+    };
+
+    let result = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        return Err(parser.unexpected(token));
+    }
+
+    Ok(result)
+}
+
+// A recursive descent parser for tag expressions, with one function per level of precedence
+struct Parser<'a> {
+    expression: &'a str,
+    tokens: &'a [Token<'a>],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn advance(&mut self) -> Option<Token<'a>> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, Failure> {
+        let mut result = self.parse_and()?;
+        while self.peek() == Some(Token::Word(OR)) {
+            self.advance();
+            result = Expression::Or(Box::new(result), Box::new(self.parse_and()?));
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, Failure> {
+        let mut result = self.parse_not()?;
+        while self.peek() == Some(Token::Word(AND)) {
+            self.advance();
+            result = Expression::And(Box::new(result), Box::new(self.parse_not()?));
+        }
+        Ok(result)
+    }
+
+    fn parse_not(&mut self) -> Result<Expression, Failure> {
+        match self.advance() {
+            Some(Token::Word(NOT)) => Ok(Expression::Not(Box::new(self.parse_not()?))),
+            Some(Token::OpenParen) => {
+                let result = self.parse_or()?;
+                match self.advance() {
+                    Some(Token::CloseParen) => Ok(result),
+                    Some(token) => Err(self.unexpected(token)),
+                    None => Err(self.unexpected_end()),
+                }
+            }
+            Some(Token::Word(tag)) if is_valid(tag) => Ok(Expression::Tag(tag.to_owned())),
+            Some(token) => Err(self.unexpected(token)),
+            None => Err(self.unexpected_end()),
+        }
+    }
+
+    fn unexpected(&self, token: Token) -> Failure {
+        Failure::User(
+            format!(
+                "Unexpected {} in tag expression {}.",
+                match token {
+                    Token::Word(word) => word,
+                    Token::OpenParen => "(",
+                    Token::CloseParen => ")",
+                }
+                .code_str(),
+                self.expression.code_str(),
+            ),
+            None,
+        )
+    }
+
+    fn unexpected_end(&self) -> Failure {
+        Failure::User(
+            format!(
+                "Unexpected end of tag expression {}.",
+                self.expression.code_str(),
+            ),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tags::{is_valid, parse, Expression};
+
+    fn tag(tag: &str) -> Box<Expression> {
+        Box::new(Expression::Tag(tag.to_owned()))
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| (*tag).to_owned()).collect()
+    }
+
+    #[test]
+    fn is_valid_ok() {
+        assert!(is_valid("test"));
+        assert!(is_valid("ci-lane_2.x"));
+    }
+
+    #[test]
+    fn is_valid_invalid() {
+        assert!(!is_valid(""));
+        assert!(!is_valid("foo bar"));
+        assert!(!is_valid("foo!"));
+        assert!(!is_valid("and"));
+        assert!(!is_valid("or"));
+        assert!(!is_valid("not"));
+    }
+
+    #[test]
+    fn parse_tag() {
+        assert_eq!(parse("test").unwrap(), *tag("test"));
+    }
+
+    #[test]
+    fn parse_precedence() {
+        assert_eq!(
+            parse("lint or test and not slow").unwrap(),
+            Expression::Or(
+                tag("lint"),
+                Box::new(Expression::And(
+                    tag("test"),
+                    Box::new(Expression::Not(tag("slow"))),
+                )),
+            ),
+        );
+    }
+
+    #[test]
+    fn parse_parentheses() {
+        assert_eq!(
+            parse("(lint or test)and not(slow)").unwrap(),
+            Expression::And(
+                Box::new(Expression::Or(tag("lint"), tag("test"))),
+                Box::new(Expression::Not(tag("slow"))),
+            ),
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        for expression in &[
+            "",
+            "test and",
+            "not",
+            "(test",
+            "test)",
+            "test slow",
+            "te$t",
+            "or",
+        ] {
+            assert!(parse(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn matches() {
+        let expression = parse("test and not slow").unwrap();
+
+        assert!(expression.matches(&tags(&["test"])));
+        assert!(expression.matches(&tags(&["build", "test"])));
+        assert!(!expression.matches(&tags(&["test", "slow"])));
+        assert!(!expression.matches(&tags(&["lint"])));
+        assert!(!expression.matches(&[]));
+    }
+}
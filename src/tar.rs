@@ -1,15 +1,29 @@
-use crate::{cache, cache::CryptoHash, failure, failure::Failure, format::CodeStr, spinner::spin};
+use crate::{
+    cache, failure,
+    failure::Failure,
+    format,
+    format::CodeStr,
+    glob,
+    scan_cache::ScanCache,
+    spinner::{add_progress, spin},
+};
 use std::{
-    collections::HashSet,
-    fs::{read_link, symlink_metadata, File, Metadata},
-    io::{empty, Read, Seek, SeekFrom, Write},
-    path::{Path, PathBuf},
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
+    ffi::OsStr,
+    fmt::{self, Debug, Formatter},
+    fs::{canonicalize, read_link, symlink_metadata, File, Metadata},
+    io::{self, empty, Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
+    thread::sleep,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
-use tar::{Builder, EntryType, Header};
+use tar::{Archive, Builder, EntryType, Header};
 use walkdir::WalkDir;
 
 // To help keep track of the various types of paths, we will adopt the following variable suffixes:
@@ -22,7 +36,310 @@ use walkdir::WalkDir;
 //   - `_rsd`: Relative to the source directory (e.g., `foo.txt`)
 
 #[cfg(unix)]
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+// Archives are written in blocks of this size. Each entry is padded with zeros to a whole number
+// of blocks, and there's no further padding to a larger record size (i.e., the blocking factor is
+// 1), so the layout of an archive only depends on its entries.
+const BLOCK_SIZE: u64 = 512;
+
+// The size of the end-of-archive marker, which consists of two zero-filled blocks
+const END_OF_ARCHIVE_SIZE: u64 = 2 * BLOCK_SIZE;
+
+// The mode of a directory in an archive when it isn't preserved from the filesystem
+const DEFAULT_DIRECTORY_MODE: u32 = 0o777;
+
+// The delay before retrying a filesystem operation that failed with a transient error. It doubles
+// after every attempt.
+const IO_RETRY_INITIAL_DELAY: Duration = Duration::from_millis(100);
+
+// The names of the metadata directories of version control systems, which are left out of
+// directories in an archive when `Options::exclude_vcs_directories` is set
+const VCS_DIRECTORIES: &[&str] = &[".git", ".hg", ".svn"];
+
+// Operating system error codes which indicate a transient failure: `EIO` and `ESTALE`
+#[cfg(target_os = "linux")]
+const TRANSIENT_OS_ERRORS: &[i32] = &[5, 116];
+#[cfg(all(unix, not(target_os = "linux")))]
+const TRANSIENT_OS_ERRORS: &[i32] = &[5, 70];
+#[cfg(windows)]
+const TRANSIENT_OS_ERRORS: &[i32] = &[];
+
+// Options which control how an archive is created
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    // How many times to retry reading a file which failed with a transient error
+    pub io_retries: u32,
+
+    // Whether to reject archives containing paths which differ only by case, since they would
+    // collide on case-insensitive filesystems
+    pub case_insensitive_check: bool,
+
+    // Files to add to the archive which don't exist on the host
+    pub synthetic_files: Vec<SyntheticFile>,
+
+    // Modes which supersede the ones on the filesystem for the given files, which are specified
+    // relative to the source directory
+    pub file_modes: HashMap<PathBuf, u32>,
+
+    // Whether to record the modification times of files rather than setting them all to the Unix
+    // epoch
+    pub preserve_mtime: bool,
+
+    // Whether to record the modes of files and directories from the filesystem rather than only
+    // whether files are executable. Modes in `file_modes` still take precedence.
+    pub preserve_permissions: bool,
+
+    // A function which rewrites the contents of files before they're hashed and archived
+    pub content_transform: Option<ContentTransform>,
+
+    // A function which decides which files to leave out based on their metadata, in addition to
+    // `excluded_input_paths`
+    pub exclusion_filter: Option<ExclusionFilter>,
+
+    // Whether to leave out version control metadata (e.g., `.git`) found inside input directories.
+    // An input path which is itself such a directory is still added.
+    pub exclude_vcs_directories: bool,
+
+    // Whether to log how the hash of each entry is computed and how the entry hashes are combined
+    // into the hash of the archive, for debugging cache keys which differ between machines
+    pub trace_hashes: bool,
+
+    // Where to add up the time spent in each phase of creating the archive, if anywhere
+    pub timings: Option<Arc<Timings>>,
+
+    // When to give up on creating the archive, if ever
+    pub deadline: Option<Instant>,
+
+    // Where to look up and record the hashes of the contents of files, so files which haven't
+    // changed since an earlier run don't need to be hashed again, if anywhere. It isn't used for
+    // files with a `content_transform`, since those need to be read anyway.
+    pub scan_cache: Option<Arc<ScanCache>>,
+
+    // A directory to leave out if it's ever reached, along with everything in it, with a warning.
+    // This is for the Toast cache directory, which would otherwise end up in the archive if an
+    // input path contains it. It must be canonical, so it can be compared with canonical paths.
+    pub skipped_dir: Option<PathBuf>,
+
+    // Whether to fail if no files or symbolic links were found on the host and there are no
+    // synthetic files, which suggests that the input paths are wrong
+    pub require_files: bool,
+
+    // A function to tell about the bytes of file contents as they're hashed, if any
+    pub progress_hook: Option<ProgressHook>,
+}
+
+// The phases of creating an archive, for profiling
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    Traverse, // Listing directories and fetching metadata
+    Open,     // Opening files
+    Hash,     // Reading and hashing the contents of files
+    Append,   // Writing entries to the archive
+}
+
+impl Phase {
+    pub const ALL: [Self; 4] = [Self::Traverse, Self::Open, Self::Hash, Self::Append];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Traverse => "traverse",
+            Self::Open => "open",
+            Self::Hash => "hash",
+            Self::Append => "append",
+        }
+    }
+}
+
+// The total time spent in each phase of creating one or more archives. The totals can be updated
+// through a shared reference, since `Options` are only ever borrowed.
+#[derive(Debug, Default)]
+pub struct Timings {
+    nanos: [AtomicU64; Phase::ALL.len()],
+}
+
+impl Timings {
+    // Determine the total time spent in a phase.
+    pub fn total(&self, phase: Phase) -> Duration {
+        Duration::from_nanos(self.nanos[phase as usize].load(Ordering::SeqCst))
+    }
+
+    fn add(&self, phase: Phase, duration: Duration) {
+        self.nanos[phase as usize].fetch_add(
+            u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::SeqCst,
+        );
+    }
+}
+
+// Run a function, counting the time it takes towards a phase if profiling was requested.
+fn timed<T, F: FnOnce() -> T>(options: &Options, phase: Phase, f: F) -> T {
+    if let Some(timings) = &options.timings {
+        let start = Instant::now();
+        let result = f();
+        timings.add(phase, start.elapsed());
+        result
+    } else {
+        f()
+    }
+}
+
+// Fail if the deadline for creating an archive has passed. Unlike an interruption, this is an
+// error, and it reports how far the archive got.
+fn check_deadline(options: &Options, entries: usize) -> Result<(), Failure> {
+    if options
+        .deadline
+        .map_or(false, |deadline| Instant::now() >= deadline)
+    {
+        return Err(Failure::User(
+            format!(
+                "Reading the input files took longer than {} allows, so it was stopped after {}.",
+                "archive_timeout".code_str(),
+                format::number(entries, "path"),
+            ),
+            None,
+        ));
+    }
+
+    Ok(())
+}
+
+// A function which is given the path of each file (relative to the container filesystem root) and
+// its contents, and returns the contents to archive instead. Since the result is what gets hashed,
+// the function must be deterministic, or the cache will be ineffective. Returning the contents
+// unchanged (i.e., `Cow::Borrowed`) leaves the file as it is.
+#[derive(Clone)]
+pub struct ContentTransform(pub Arc<ContentTransformFn>);
+
+type ContentTransformFn = dyn for<'a> Fn(&Path, &'a [u8]) -> Cow<'a, [u8]> + Send + Sync;
+
+impl ContentTransform {
+    // Construct a transform which converts CRLF line endings to LF in the files which match any of
+    // the given patterns (relative to the destination directory), so those files have the same
+    // hash regardless of which platform they were checked out on. Files containing a null byte are
+    // presumed to be binary and left unchanged.
+    pub fn normalize_line_endings(destination_dir: &Path, patterns_rsd: &[PathBuf]) -> Self {
+        let destination_dir_acr = normalize_destination_dir_acr(destination_dir);
+        let patterns_rcr = patterns_rsd
+            .iter()
+            .map(|pattern_rsd| strip_root_rcr(&destination_dir_acr).join(pattern_rsd))
+            .collect::<Vec<_>>();
+
+        Self(Arc::new(move |path_rcr, contents| {
+            if patterns_rcr
+                .iter()
+                .any(|pattern_rcr| glob::matches_prefix(pattern_rcr, path_rcr))
+            {
+                normalize_line_endings(contents)
+            } else {
+                Cow::Borrowed(contents)
+            }
+        }))
+    }
+
+    fn apply<'a>(&self, path_rcr: &Path, contents: &'a [u8]) -> Cow<'a, [u8]> {
+        (self.0)(path_rcr, contents)
+    }
+}
+
+// Convert CRLF line endings to LF, unless the contents look binary (i.e., contain a null byte).
+// Lone carriage returns are left alone.
+fn normalize_line_endings(contents: &[u8]) -> Cow<'_, [u8]> {
+    if contents.contains(&0) || !contents.windows(2).any(|pair| pair == b"\r\n") {
+        return Cow::Borrowed(contents);
+    }
+
+    let mut normalized = Vec::with_capacity(contents.len());
+    for (i, byte) in contents.iter().enumerate() {
+        if !(*byte == b'\r' && contents.get(i + 1) == Some(&b'\n')) {
+            normalized.push(*byte);
+        }
+    }
+
+    Cow::Owned(normalized)
+}
+
+impl Debug for ContentTransform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("ContentTransform")
+    }
+}
+
+// A function which is given the path of each file (relative to the container filesystem root) and
+// its metadata, and returns whether to leave the file out of the archive (e.g., because it's too
+// large). Only regular files are filtered. The result must only depend on the path and metadata,
+// so that the same files and filter always produce the same archive and hash.
+#[derive(Clone)]
+pub struct ExclusionFilter(pub Arc<ExclusionFilterFn>);
+
+type ExclusionFilterFn = dyn Fn(&Path, &Metadata) -> bool + Send + Sync;
+
+impl ExclusionFilter {
+    // Determine whether a path is denied by an exclusion filter, if there is one.
+    fn excludes(filter: Option<&Self>, path_rcr: &Path, metadata: &Metadata) -> bool {
+        filter.map_or(false, |filter| {
+            metadata.is_file() && (filter.0)(path_rcr, metadata)
+        })
+    }
+}
+
+impl Debug for ExclusionFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("ExclusionFilter")
+    }
+}
+
+// A function which is given the number of bytes of file contents that were just hashed. It's called
+// many times for a large file, so it should be cheap.
+#[derive(Clone)]
+pub struct ProgressHook(pub Arc<dyn Fn(u64) + Send + Sync>);
+
+impl Debug for ProgressHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressHook")
+    }
+}
+
+// A file which is added to an archive from memory rather than from the host filesystem
+#[derive(Clone, Debug)]
+pub struct SyntheticFile {
+    pub path_rsd: PathBuf,
+    pub contents: Vec<u8>,
+    pub executable: bool,
+}
+
+// What an archive records about one of its paths
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ManifestEntry {
+    pub hash: String, // The hash of the contents and metadata
+    pub size: u64,    // The size of the contents in bytes, which is zero for non-files
+}
+
+// A map from each path in an archive (relative to the container filesystem root) to the hash of its
+// contents and metadata, along with its size
+pub type Manifest = BTreeMap<PathBuf, ManifestEntry>;
+
+// What `append` needs to know about the entries already in an unfinished archive
+#[derive(Clone, Debug, Default)]
+pub struct State {
+    // The hashes of the contents and metadata of the entries (and their sizes), along with the
+    // paths they belong to. In the end, we will sort the hashes and then take the hash of the whole
+    // thing.
+    content_hashes: Vec<(PathBuf, ManifestEntry)>,
+
+    // This set is used to avoid adding the same path to the archive multiple times, which could
+    // otherwise easily happen since we explicitly add all ancestor directories for every entry
+    // added to the archive.
+    visited_paths: HashSet<PathBuf>,
+
+    // The source directory that each path (other than implicitly added ancestor directories) came
+    // from, and whether it's a directory. This is used to detect collisions between paths from
+    // different source directories.
+    origins: HashMap<PathBuf, (PathBuf, bool)>,
+
+    // How many files and symbolic links from the host have been added
+    files: usize,
+}
 
 #[cfg(unix)]
 fn is_file_executable(metadata: &Metadata) -> bool {
@@ -37,6 +354,111 @@ fn is_file_executable(_metadata: &Metadata) -> bool {
     true
 }
 
+// Determine the mode of a file or directory on the filesystem, if permissions are to be preserved.
+#[cfg(unix)]
+fn preserved_mode(options: &Options, metadata: &Metadata) -> Option<u32> {
+    if options.preserve_permissions {
+        Some(metadata.permissions().mode() & 0o7777)
+    } else {
+        None
+    }
+}
+
+#[cfg(windows)]
+fn preserved_mode(_options: &Options, _metadata: &Metadata) -> Option<u32> {
+    // Windows doesn't have Unix-style modes to preserve.
+    None
+}
+
+// Paths on Unix are hashed as raw bytes, so any path is fine.
+#[cfg(unix)]
+fn check_path_unicode(_path_cd: &Path) -> Result<(), Failure> {
+    Ok(())
+}
+
+// Paths on Windows are hashed as UTF-8, so a path which isn't valid Unicode can't be hashed
+// faithfully. [tag:windows_paths_unicode]
+#[cfg(windows)]
+fn check_path_unicode(path_cd: &Path) -> Result<(), Failure> {
+    if path_cd.to_str().is_none() {
+        return Err(Failure::User(
+            format!(
+                "Path {} isn't valid Unicode, so it can't be part of the cache key.",
+                path_cd.to_string_lossy().code_str(),
+            ),
+            None,
+        ));
+    }
+
+    Ok(())
+}
+
+// Explain why a file couldn't be opened when permission is denied. On a shared machine (e.g., in
+// CI), this usually means the file belongs to another user, so we report who owns it.
+#[cfg(unix)]
+fn permission_denied_reason(metadata: &Metadata) -> String {
+    // The `unsafe` is needed to call into libc, but `geteuid` can't fail or cause any side effects.
+    ownership_reason(metadata.mode(), metadata.uid(), unsafe { libc::geteuid() })
+}
+
+#[cfg(windows)]
+fn permission_denied_reason(_metadata: &Metadata) -> String {
+    // Windows doesn't have Unix-style modes or user IDs to report.
+    "The file may be locked or owned by another user.".to_owned()
+}
+
+#[cfg(unix)]
+fn ownership_reason(mode: u32, owner_uid: u32, current_uid: u32) -> String {
+    if owner_uid == current_uid {
+        format!(
+            "The file has mode {} and is owned by the current user (UID {}).",
+            format!("{:04o}", mode & 0o7777).code_str(),
+            current_uid,
+        )
+    } else {
+        format!(
+            "The file has mode {} and is owned by UID {}, but Toast is running as UID {}.",
+            format!("{:04o}", mode & 0o7777).code_str(),
+            owner_uid,
+            current_uid,
+        )
+    }
+}
+
+// Open a file for reading. If permission is denied, the error explains the ownership of the file.
+fn open_file(path_cd: &Path, metadata: &Metadata) -> io::Result<File> {
+    File::open(path_cd).map_err(|error| {
+        if error.kind() == ErrorKind::PermissionDenied {
+            io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} {}", error, permission_denied_reason(metadata)),
+            )
+        } else {
+            error
+        }
+    })
+}
+
+// Determine the mode of a file in an archive when it isn't overridden. Only the executable bit is
+// taken from the filesystem.
+fn default_file_mode(executable: bool) -> u32 {
+    if executable {
+        0o777
+    } else {
+        0o666
+    }
+}
+
+// Determine the modification time of a file in seconds since the Unix epoch, which is the precision
+// of tar headers. Times before the epoch are clamped to it.
+pub fn file_mtime(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
 // Tar archives must contain only relative paths. For our purposes, the paths will be relative to
 // the filesystem root, so we need to strip the leading `/` before adding paths to the archive.
 fn strip_root_rcr(path_acr: &Path) -> &Path {
@@ -44,6 +466,100 @@ fn strip_root_rcr(path_acr: &Path) -> &Path {
     path_acr.strip_prefix("/").unwrap()
 }
 
+// Determine whether an I/O error might go away if the operation is retried. Errors like `ENOENT`
+// are permanent.
+fn is_transient(error: &io::Error) -> bool {
+    match error.kind() {
+        ErrorKind::Interrupted | ErrorKind::TimedOut | ErrorKind::WouldBlock => true,
+        _ => error
+            .raw_os_error()
+            .map_or(false, |code| TRANSIENT_OS_ERRORS.contains(&code)),
+    }
+}
+
+// Run a filesystem operation, retrying it with exponential backoff if it fails with a transient
+// error. Permanent errors fail immediately, and so does the operation if the user interrupts it.
+// The error message is only constructed if it's needed.
+fn retry_io<T, F: FnMut() -> io::Result<T>, M: Fn() -> String>(
+    retries: u32,
+    interrupted: &Arc<AtomicBool>,
+    error_message: M,
+    mut operation: F,
+) -> Result<T, Failure> {
+    let mut delay = IO_RETRY_INITIAL_DELAY;
+    let mut attempt = 0;
+
+    loop {
+        match operation() {
+            Ok(result) => return Ok(result),
+            Err(error) if attempt < retries && is_transient(&error) => {
+                // If the user wants to stop the operation, quit now rather than waiting.
+                if interrupted.load(Ordering::SeqCst) {
+                    return Err(Failure::Interrupted);
+                }
+
+                attempt += 1;
+                debug!(
+                    "{} Reason: {} Retrying in {} ms (attempt {} of {})\u{2026}",
+                    error_message(),
+                    error,
+                    delay.as_millis(),
+                    attempt,
+                    retries,
+                );
+                sleep(delay);
+                delay *= 2;
+
+                if interrupted.load(Ordering::SeqCst) {
+                    return Err(Failure::Interrupted);
+                }
+            }
+            Err(error) => return Err(failure::system(error_message())(error)),
+        }
+    }
+}
+
+// Find two paths which differ only by case, if there are any. The result doesn't depend on the
+// order of the paths.
+fn case_collision<'a, I: IntoIterator<Item = &'a Path>>(
+    paths_rcr: I,
+) -> Option<(&'a Path, &'a Path)> {
+    let mut sorted_paths_rcr = paths_rcr.into_iter().collect::<Vec<_>>();
+    sorted_paths_rcr.sort();
+
+    let mut folded_paths_rcr = HashMap::new();
+    for path_rcr in sorted_paths_rcr {
+        if let Some(other_path_rcr) =
+            folded_paths_rcr.insert(path_rcr.to_string_lossy().to_lowercase(), path_rcr)
+        {
+            if other_path_rcr != path_rcr {
+                return Some((other_path_rcr, path_rcr));
+            }
+        }
+    }
+
+    None
+}
+
+// Normalize a destination directory into an absolute path without any `.` components or redundant
+// separators. An empty or relative destination is interpreted relative to the root, and `..`
+// components are resolved lexically.
+fn normalize_destination_dir_acr(destination_dir: &Path) -> PathBuf {
+    let mut destination_dir_acr = PathBuf::from("/");
+
+    for component in destination_dir.components() {
+        match component {
+            Component::Normal(name) => destination_dir_acr.push(name),
+            Component::ParentDir => {
+                destination_dir_acr.pop();
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+
+    destination_dir_acr
+}
+
 // Check if a file is denied by `excluded_input_paths`.
 fn path_excluded(excluded_input_paths_rcr: &[PathBuf], path_rcr: &Path) -> bool {
     // Don't add this path if it's denied by `excluded_input_paths`.
@@ -76,6 +592,7 @@ fn can_add_path(
 }
 
 // Add a file to a tar archive.
+#[allow(clippy::too_many_arguments)]
 fn add_file<R: Read, W: Write>(
     builder: &mut Builder<W>,
     visited_paths_rcr: &mut HashSet<PathBuf>,
@@ -83,7 +600,8 @@ fn add_file<R: Read, W: Write>(
     path_rcr: &Path,
     data: R,
     size: u64,
-    executable: bool,
+    mode: u32,
+    mtime: u64,
 ) -> Result<(), Failure> {
     // Check if this path should be added.
     if !can_add_path(visited_paths_rcr, excluded_input_paths_rcr, path_rcr) {
@@ -93,8 +611,9 @@ fn add_file<R: Read, W: Write>(
     // Construct a tar header for this entry.
     let mut header = Header::new_gnu();
     header.set_entry_type(EntryType::Regular);
-    header.set_mode(if executable { 0o777 } else { 0o666 });
+    header.set_mode(mode);
     header.set_size(size);
+    header.set_mtime(mtime);
 
     // Add the entry to the archive.
     builder
@@ -142,6 +661,7 @@ fn add_directory<W: Write>(
     visited_paths_rcr: &mut HashSet<PathBuf>,
     excluded_input_paths_rcr: &[PathBuf],
     path_rcr: &Path,
+    mode: u32,
 ) -> Result<(), Failure> {
     // Check if this path should be added.
     if !can_add_path(visited_paths_rcr, excluded_input_paths_rcr, path_rcr) {
@@ -158,7 +678,7 @@ fn add_directory<W: Write>(
     // Construct a tar header for this entry.
     let mut header = Header::new_gnu();
     header.set_entry_type(EntryType::Directory);
-    header.set_mode(0o777);
+    header.set_mode(mode);
     header.set_size(0);
 
     // Add the entry to the archive.
@@ -170,63 +690,304 @@ fn add_directory<W: Write>(
     Ok(())
 }
 
-// Add a file, symlink, or directory to a tar archive.
-fn add_path<W: Write>(
+// Add the ancestor directories of a path to a tar archive. They would be created automatically, but
+// we add them explicitly to ensure they have the right permissions.
+fn add_ancestors<W: Write>(
     builder: &mut Builder<W>,
-    content_hashes: &mut Vec<String>,
     visited_paths_rcr: &mut HashSet<PathBuf>,
     excluded_input_paths_rcr: &[PathBuf],
-    path_cd: &Path,
     path_rcr: &Path,
-    metadata: &Metadata,
+    trace_hashes: bool,
 ) -> Result<(), Failure> {
-    // Add the ancestor directories. They would be created automatically, but we add them explicitly
-    // here to ensure they have the right permissions.
     if let Some(parent) = path_rcr.parent() {
         for ancestor in parent.ancestors() {
+            let added = !visited_paths_rcr.contains(ancestor)
+                && ancestor.components().next().is_some()
+                && !path_excluded(excluded_input_paths_rcr, ancestor);
             add_directory(
                 builder,
                 visited_paths_rcr,
                 excluded_input_paths_rcr,
                 ancestor,
+                DEFAULT_DIRECTORY_MODE,
             )?;
+
+            // Ancestors aren't part of the hash, but they're traced so every entry is listed.
+            if trace_hashes && added {
+                info!(
+                    "Directory {}: mode {:o}, ancestor (not part of the hash)",
+                    Path::new("/").join(ancestor).to_string_lossy().code_str(),
+                    DEFAULT_DIRECTORY_MODE,
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Add a file from memory to a tar archive. Its contents are hashed exactly as if it had been read
+// from the host filesystem. It's an error if the path was already added to the archive.
+#[allow(clippy::too_many_arguments)]
+fn add_bytes<W: Write>(
+    builder: &mut Builder<W>,
+    content_hashes: &mut Vec<(PathBuf, ManifestEntry)>,
+    visited_paths_rcr: &mut HashSet<PathBuf>,
+    excluded_input_paths_rcr: &[PathBuf],
+    path_rcr: &Path,
+    contents: &[u8],
+    executable: bool,
+    content_transform: Option<&ContentTransform>,
+    trace_hashes: bool,
+) -> Result<(), Failure> {
+    // Make sure this file doesn't conflict with one from the host. Paths denied by
+    // `excluded_input_paths` are recorded as visited, but they weren't actually added.
+    // [tag:generated_input_files_unique]
+    if visited_paths_rcr.contains(path_rcr) && !path_excluded(excluded_input_paths_rcr, path_rcr) {
+        return Err(Failure::User(
+            format!(
+                "Generated file {} conflicts with a path copied from the host.",
+                Path::new("/").join(path_rcr).to_string_lossy().code_str(),
+            ),
+            None,
+        ));
+    }
+
+    // Add the ancestor directories.
+    add_ancestors(
+        builder,
+        visited_paths_rcr,
+        excluded_input_paths_rcr,
+        path_rcr,
+        trace_hashes,
+    )?;
+
+    // Rewrite the contents, if requested.
+    let contents = content_transform.map_or(Cow::Borrowed(contents), |transform| {
+        transform.apply(path_rcr, contents)
+    });
+
+    // Compute the hash of the file contents and metadata.
+    let contents_hash =
+        cache::hash_read(&mut &contents[..]).map_err(failure::system("Unable to compute hash."))?;
+    let hash = cache::file_entry_hash(path_rcr, &contents_hash, executable);
+    if trace_hashes {
+        info!(
+            "File {}: contents {}, mode {:o}, entry {}",
+            Path::new("/").join(path_rcr).to_string_lossy().code_str(),
+            contents_hash,
+            default_file_mode(executable),
+            hash,
+        );
+    }
+    content_hashes.push((
+        path_rcr.to_owned(),
+        ManifestEntry {
+            hash,
+            size: contents.len() as u64,
+        },
+    ));
+
+    // Add the file to the archive. Generated files are never denied by `excluded_input_paths`.
+    add_file(
+        builder,
+        visited_paths_rcr,
+        &[],
+        path_rcr,
+        &contents[..],
+        contents.len() as u64,
+        default_file_mode(executable),
+        0,
+    )
+}
+
+// Record which source directory a path came from, and return whether it should be added. It's an
+// error if the path was already added from a different source directory, unless both are
+// directories, which are merged by only adding the first one. Paths denied by
+// `excluded_input_paths` are ignored, since they won't be added anyway.
+fn check_origin(
+    origins: &mut HashMap<PathBuf, (PathBuf, bool)>,
+    excluded_input_paths_rcr: &[PathBuf],
+    source_dir_cd: &Path,
+    path_rcr: &Path,
+    is_dir: bool,
+) -> Result<bool, Failure> {
+    if path_excluded(excluded_input_paths_rcr, path_rcr) {
+        return Ok(true);
+    }
+
+    if let Some((other_source_dir_cd, other_is_dir)) = origins.get(path_rcr) {
+        if other_source_dir_cd != source_dir_cd {
+            if is_dir && *other_is_dir {
+                return Ok(false);
+            }
+
+            return Err(Failure::User(
+                format!(
+                    "Path {} is provided by both {} and {}.",
+                    Path::new("/").join(path_rcr).to_string_lossy().code_str(),
+                    other_source_dir_cd.to_string_lossy().code_str(),
+                    source_dir_cd.to_string_lossy().code_str(),
+                ),
+                None,
+            ));
         }
+    } else {
+        origins.insert(path_rcr.to_owned(), (source_dir_cd.to_owned(), is_dir));
     }
 
+    Ok(true)
+}
+
+// Add a file, symlink, or directory to a tar archive.
+#[allow(clippy::too_many_arguments)]
+fn add_path<W: Write>(
+    builder: &mut Builder<W>,
+    content_hashes: &mut Vec<(PathBuf, ManifestEntry)>,
+    visited_paths_rcr: &mut HashSet<PathBuf>,
+    excluded_input_paths_rcr: &[PathBuf],
+    path_cd: &Path,
+    path_rcr: &Path,
+    metadata: &Metadata,
+    file_modes_rcr: &HashMap<PathBuf, u32>,
+    options: &Options,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // Make sure the path can be hashed.
+    check_path_unicode(path_cd)?;
+
+    // Add the ancestor directories.
+    add_ancestors(
+        builder,
+        visited_paths_rcr,
+        excluded_input_paths_rcr,
+        path_rcr,
+        options.trace_hashes,
+    )?;
+
     // Check the type of the entry.
     if metadata.file_type().is_file() {
+        // It's a file.
         let executable = is_file_executable(metadata);
+        let error_message = || {
+            format!(
+                "Unable to read file {}.",
+                path_cd.to_string_lossy().code_str(),
+            )
+        };
+        let open = || open_file(path_cd, metadata);
 
-        // It's a file. Open it so we can compute the hash of its contents and add it to the
-        // archive.
-        let mut file = File::open(path_cd).map_err(failure::system(format!(
-            "Unable to open file {}.",
-            path_cd.to_string_lossy().code_str(),
-        )))?;
+        let (data, size, contents_hash): (Box<dyn Read>, u64, String) =
+            if let Some(transform) = &options.content_transform {
+                // The contents need to be rewritten, so read the whole file into memory. If this
+                // fails with a transient error (e.g., on a network filesystem), it's retried.
+                let contents = retry_io(options.io_retries, interrupted, error_message, || {
+                    let mut file = timed(options, Phase::Open, open)?;
+                    let mut contents = vec![];
+                    timed(options, Phase::Hash, || file.read_to_end(&mut contents))?;
+                    Ok(contents)
+                })?;
 
-        // Compute the hash of the file contents and metadata.
-        content_hashes.push(cache::combine(
-            &cache::combine(&path_rcr.crypto_hash(), &cache::hash_read(&mut file)?),
-            if executable { "+x" } else { "-x" },
-        ));
+                // Hash and archive the rewritten contents rather than the original ones.
+                let contents = transform.apply(path_rcr, &contents).into_owned();
+                let contents_hash = timed(options, Phase::Hash, || {
+                    cache::hash_read(&mut &contents[..])
+                })
+                .map_err(failure::system("Unable to compute hash."))?;
+                let size = contents.len() as u64;
+                (Box::new(Cursor::new(contents)), size, contents_hash)
+            } else if let Some(contents_hash) = options
+                .scan_cache
+                .as_ref()
+                .and_then(|scan_cache| scan_cache.lookup(path_cd, metadata))
+            {
+                // The file has the same modification time and size as when it was hashed in an
+                // earlier run, so trust that its contents are the same too.
+                let file = retry_io(options.io_retries, interrupted, error_message, || {
+                    timed(options, Phase::Open, open)
+                })?;
+                (Box::new(file), metadata.len(), contents_hash)
+            } else {
+                // Open the file and compute the hash of its contents, then jump back to the
+                // beginning of the file so the tar builder can read it. If this fails with a
+                // transient error, the whole sequence is retried. The file is hashed in chunks,
+                // so a huge one shows progress and can be interrupted partway through.
+                let (file, contents_hash) =
+                    retry_io(options.io_retries, interrupted, error_message, || {
+                        let mut file = timed(options, Phase::Open, open)?;
+                        let contents_hash = timed(options, Phase::Hash, || {
+                            cache::hash_read_incremental(&mut file, |bytes| {
+                                add_progress(bytes);
+                                if let Some(progress_hook) = &options.progress_hook {
+                                    (progress_hook.0)(bytes);
+                                }
+                                !interrupted.load(Ordering::SeqCst)
+                            })
+                        })?;
+                        file.seek(SeekFrom::Start(0))?;
+                        Ok((file, contents_hash))
+                    })?;
+                let contents_hash = contents_hash.ok_or(Failure::Interrupted)?;
+                if let Some(scan_cache) = &options.scan_cache {
+                    scan_cache.record(path_cd, metadata, &contents_hash);
+                }
+                (Box::new(file), metadata.len(), contents_hash)
+            };
 
-        // Jump back to the beginning of the file so the tar builder can read it.
-        file.seek(SeekFrom::Start(0))
-            .map_err(failure::system(format!(
-                "Unable to seek file {}.",
-                path_cd.to_string_lossy().code_str(),
-            )))?;
+        // Record the hash of the file contents and metadata. If the mode is overridden or
+        // preserved, the whole mode is part of the hash rather than just the executable bit.
+        let mode = file_modes_rcr
+            .get(path_rcr)
+            .copied()
+            .or_else(|| preserved_mode(options, metadata));
+        let (mode, hash) = if let Some(mode) = mode {
+            (
+                mode,
+                cache::file_entry_hash_with_mode(path_rcr, &contents_hash, mode),
+            )
+        } else {
+            (
+                default_file_mode(executable),
+                cache::file_entry_hash(path_rcr, &contents_hash, executable),
+            )
+        };
+
+        // If requested, record the modification time too, so touching the file changes the hash.
+        let (mtime, hash) = if options.preserve_mtime {
+            let mtime = file_mtime(metadata);
+            (mtime, cache::file_entry_hash_with_mtime(&hash, mtime))
+        } else {
+            (0, hash)
+        };
+        if options.trace_hashes {
+            info!(
+                "File {}: contents {}, mode {:o}{}, entry {}",
+                Path::new("/").join(path_rcr).to_string_lossy().code_str(),
+                contents_hash,
+                mode,
+                if options.preserve_mtime {
+                    format!(", modification time {}", mtime)
+                } else {
+                    String::new()
+                },
+                hash,
+            );
+        }
+        content_hashes.push((path_rcr.to_owned(), ManifestEntry { hash, size }));
 
         // Add the file to the archive and return.
-        add_file(
-            builder,
-            visited_paths_rcr,
-            excluded_input_paths_rcr,
-            path_rcr,
-            file,
-            metadata.len(),
-            executable,
-        )
+        timed(options, Phase::Append, || {
+            add_file(
+                builder,
+                visited_paths_rcr,
+                excluded_input_paths_rcr,
+                path_rcr,
+                data,
+                size,
+                mode,
+                mtime,
+            )
+        })
     } else if metadata.file_type().is_symlink() {
         // It's a symlink. Read the target path.
         let target_path = read_link(path_cd).map_err(failure::system(format!(
@@ -235,7 +996,16 @@ fn add_path<W: Write>(
         )))?;
 
         // Compute the hash of the symlink path and the target path.
-        content_hashes.push(cache::combine(path_rcr, &target_path));
+        let hash = cache::symlink_entry_hash(path_rcr, &target_path);
+        if options.trace_hashes {
+            info!(
+                "Symbolic link {}: target {}, entry {}",
+                Path::new("/").join(path_rcr).to_string_lossy().code_str(),
+                target_path.to_string_lossy().code_str(),
+                hash,
+            );
+        }
+        content_hashes.push((path_rcr.to_owned(), ManifestEntry { hash, size: 0 }));
 
         // Add the symlink to the archive.
         add_symlink(
@@ -246,8 +1016,25 @@ fn add_path<W: Write>(
             &target_path,
         )
     } else if metadata.file_type().is_dir() {
-        // It's a directory. Only its name is relevant for the cache key.
-        content_hashes.push(path_rcr.crypto_hash());
+        // It's a directory. Only its name is relevant for the cache key, unless its mode is
+        // preserved.
+        let (mode, hash) = if let Some(mode) = preserved_mode(options, metadata) {
+            (mode, cache::directory_entry_hash_with_mode(path_rcr, mode))
+        } else {
+            (
+                DEFAULT_DIRECTORY_MODE,
+                cache::directory_entry_hash(path_rcr),
+            )
+        };
+        if options.trace_hashes {
+            info!(
+                "Directory {}: mode {:o}, entry {}",
+                Path::new("/").join(path_rcr).to_string_lossy().code_str(),
+                mode,
+                hash,
+            );
+        }
+        content_hashes.push((path_rcr.to_owned(), ManifestEntry { hash, size: 0 }));
 
         // Add the directory to the archive.
         add_directory(
@@ -255,6 +1042,7 @@ fn add_path<W: Write>(
             visited_paths_rcr,
             excluded_input_paths_rcr,
             path_rcr,
+            mode,
         )
     } else {
         Err(Failure::User(
@@ -267,41 +1055,133 @@ fn add_path<W: Write>(
     }
 }
 
-// Construct a tar archive and return a hash of its contents. This function does not follow symbolic
-// links.
-#[allow(clippy::similar_names)]
+// Construct a tar archive and return a hash of its contents along with a manifest of the hashes of
+// the individual paths. This function does not follow symbolic links.
+#[allow(clippy::too_many_arguments)]
 pub fn create<W: Write>(
     spinner_message: &str,
     writer: W,
     input_paths_rsd: &[PathBuf],
     excluded_input_paths_rsd: &[PathBuf],
     source_dir_cd: &Path,
-    destination_dir_acr: &Path,
+    destination_dir: &Path,
+    options: &Options,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(W, String, Manifest), Failure> {
+    create_from_sources(
+        spinner_message,
+        writer,
+        &[(source_dir_cd, input_paths_rsd)],
+        excluded_input_paths_rsd,
+        destination_dir,
+        options,
+        interrupted,
+    )
+}
+
+// Like `create`, but the input paths come from several source directories. The paths from each
+// source directory are placed relative to `destination_dir` as if they had all come from the same
+// one. It's an error if two source directories provide the same path, unless both are directories.
+pub fn create_from_sources<W: Write>(
+    spinner_message: &str,
+    writer: W,
+    sources_cd: &[(&Path, &[PathBuf])],
+    excluded_input_paths_rsd: &[PathBuf],
+    destination_dir: &Path,
+    options: &Options,
     interrupted: &Arc<AtomicBool>,
-) -> Result<(W, String), Failure> {
+) -> Result<(W, String, Manifest), Failure> {
     // Render a spinner animation in the terminal.
     let _guard = spin(spinner_message);
 
-    // This vector will store all the hashes of the contents and metadata of all the files in the
-    // archive. In the end, we will sort this vector and then take the hash of the whole thing.
-    let mut content_hashes = vec![];
-
-    // This set is used to avoid adding the same path to the archive multiple times, which could
-    // otherwise easily happen since we explicitly add all ancestor directories for every entry
-    // added to the archive.
-    let mut visited_paths = HashSet::new();
-
     // This builder will be responsible for writing to the tar file.
     let mut builder = Builder::new(writer);
+    let mut state = State::default();
+
+    for (source_dir_cd, input_paths_rsd) in sources_cd {
+        append(
+            &mut builder,
+            &mut state,
+            input_paths_rsd,
+            excluded_input_paths_rsd,
+            source_dir_cd,
+            destination_dir,
+            options,
+            interrupted,
+        )?;
+    }
+
+    finish(
+        builder,
+        state,
+        excluded_input_paths_rsd,
+        destination_dir,
+        options,
+    )
+}
+
+// Copy an archive produced by `create` or `finish` without its end-of-archive marker. More entries
+// can then be appended to the copy with `append`, given a `Builder` for the copy and the `State`
+// from before the original archive was finished.
+pub fn copy_unfinished<R: Read, W: Write>(
+    archive: &mut R,
+    archive_size: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    io::copy(
+        &mut archive.take(archive_size.saturating_sub(END_OF_ARCHIVE_SIZE)),
+        writer,
+    )
+    .map(|_| ())
+}
+
+// Add some paths to an unfinished archive. Adding paths over several calls produces the same
+// archive and hash as adding them all in one call, provided the other arguments are the same for
+// each call.
+#[allow(clippy::similar_names)]
+#[allow(clippy::too_many_arguments)]
+pub fn append<W: Write>(
+    builder: &mut Builder<W>,
+    state: &mut State,
+    input_paths_rsd: &[PathBuf],
+    excluded_input_paths_rsd: &[PathBuf],
+    source_dir_cd: &Path,
+    destination_dir: &Path,
+    options: &Options,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // Normalize the destination directory up front. Everything below, including the hashes, uses
+    // the normalized path, so equivalent ways of specifying it produce identical archives.
+    let destination_dir_acr = &normalize_destination_dir_acr(destination_dir);
+
+    let State {
+        content_hashes,
+        visited_paths,
+        origins,
+        files,
+    } = state;
 
     // Add `destination_dir_acr` to the archive.
     add_directory(
-        &mut builder,
-        &mut visited_paths,
+        builder,
+        visited_paths,
         &[], // Always create `destination_dir_acr`, even if it's denied by `excluded_input_paths`.
-        strip_root_rcr(&destination_dir_acr),
+        strip_root_rcr(destination_dir_acr),
+        DEFAULT_DIRECTORY_MODE,
     )?;
 
+    // Convert the paths with overridden modes to be relative to the container filesystem root.
+    let file_modes_rcr = options
+        .file_modes
+        .iter()
+        .map(|(path_rsd, mode)| {
+            (
+                strip_root_rcr(&destination_dir_acr.join(path_rsd)).to_owned(),
+                *mode,
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
     // Convert the `excluded_input_paths` to be relative to the container filesystem root.
     let excluded_input_paths_rcr = excluded_input_paths_rsd
         .iter()
@@ -310,44 +1190,62 @@ pub fn create<W: Write>(
         })
         .collect::<Vec<_>>();
 
+    // If there's a directory to skip, find the canonical source directory so the paths that are
+    // reached can be compared with it.
+    let source_dir_canonical = options
+        .skipped_dir
+        .as_ref()
+        .and_then(|_| canonicalize(source_dir_cd).ok());
+
     // Add each path to the archive.
     for input_path_rsd in input_paths_rsd {
+        // If the archive is taking too long, give up.
+        check_deadline(options, content_hashes.len())?;
+
         // The original `input_path` is relative to `source_dir_cd`. Here we make it relative to the
         // current working directory instead.
         let input_path_cd = source_dir_cd.join(input_path_rsd);
 
         // Fetch filesystem metadata for `input_path`.
-        let input_path_metadata =
-            symlink_metadata(&input_path_cd).map_err(failure::system(format!(
-                "Unable to fetch filesystem metadata for {}.",
-                input_path_cd.to_string_lossy().code_str(),
-            )))?;
+        let input_path_metadata = timed(options, Phase::Traverse, || {
+            symlink_metadata(&input_path_cd)
+        })
+        .map_err(failure::system(format!(
+            "Unable to fetch filesystem metadata for {}.",
+            input_path_cd.to_string_lossy().code_str(),
+        )))?;
 
         // Check what type of filesystem object the path corresponds to.
         if input_path_metadata.is_dir() {
-            // It's a directory. Traverse it. As an optimization, we use `filter_entry` to avoid
-            // descending into directories which are denied by `excluded_input_paths`.
-            let mut iterator = WalkDir::new(&input_path_cd).into_iter();
+            // It's a directory. Traverse it in a fixed order, so the archive doesn't depend on the
+            // order in which the filesystem lists directory entries. As an optimization, we use
+            // `skip_current_dir` to avoid descending into directories which are denied by
+            // `excluded_input_paths`.
+            let mut iterator = WalkDir::new(&input_path_cd).sort_by_file_name().into_iter();
             loop {
                 // If the user wants to stop the operation, quit now.
                 if interrupted.load(Ordering::SeqCst) {
                     return Err(Failure::Interrupted);
                 }
 
+                // If the archive is taking too long, give up.
+                check_deadline(options, content_hashes.len())?;
+
                 // Unwrap the entry.
-                let entry = if let Some(entry) = iterator.next() {
-                    entry
-                } else {
-                    break;
-                }
-                .map_err(failure::user(format!(
-                    "Unable to traverse directory {}.",
-                    input_path_cd.to_string_lossy().code_str(),
-                )))?;
+                let entry =
+                    if let Some(entry) = timed(options, Phase::Traverse, || iterator.next()) {
+                        entry
+                    } else {
+                        break;
+                    }
+                    .map_err(failure::user(format!(
+                        "Unable to traverse directory {}.",
+                        input_path_cd.to_string_lossy().code_str(),
+                    )))?;
 
                 // Compute the path relative to the container filesystem root.
                 let entry_path_acr =
-                    destination_dir_acr.join(entry.path().strip_prefix(&source_dir_cd).map_err(
+                    destination_dir_acr.join(entry.path().strip_prefix(source_dir_cd).map_err(
                         failure::system(format!(
                             "Unable to relativize path {} with respect to {}.",
                             entry.path().to_string_lossy().code_str(),
@@ -357,10 +1255,12 @@ pub fn create<W: Write>(
                 let entry_path_rcr = strip_root_rcr(&entry_path_acr);
 
                 // Fetch the metadata for this entry.
-                let entry_metadata = entry.metadata().map_err(failure::system(format!(
-                    "Unable to fetch filesystem metadata for {}.",
-                    entry.path().to_string_lossy().code_str(),
-                )))?;
+                let entry_metadata = timed(options, Phase::Traverse, || entry.metadata()).map_err(
+                    failure::system(format!(
+                        "Unable to fetch filesystem metadata for {}.",
+                        entry.path().to_string_lossy().code_str(),
+                    )),
+                )?;
 
                 // Don't add this path if it's denied by `excluded_input_paths`.
                 if entry_metadata.is_dir()
@@ -370,49 +1270,1956 @@ pub fn create<W: Write>(
                     continue;
                 }
 
-                // Add the path to the archive.
-                add_path(
-                    &mut builder,
-                    &mut content_hashes,
-                    &mut visited_paths,
-                    &excluded_input_paths_rcr,
-                    entry.path(),
+                // Don't add the directory to skip, if this is it.
+                if entry_metadata.is_dir()
+                    && skipped(
+                        options,
+                        source_dir_canonical.as_deref(),
+                        source_dir_cd,
+                        entry.path(),
+                    )
+                {
+                    iterator.skip_current_dir();
+                    continue;
+                }
+
+                // Don't add version control metadata, if requested. Git submodules and worktrees
+                // have a `.git` file rather than a directory, so the type doesn't matter.
+                if options.exclude_vcs_directories
+                    && entry.depth() > 0
+                    && VCS_DIRECTORIES
+                        .iter()
+                        .any(|name| entry.file_name() == OsStr::new(name))
+                {
+                    if entry_metadata.is_dir() {
+                        iterator.skip_current_dir();
+                    }
+                    continue;
+                }
+
+                // Don't add this path if it's denied by the exclusion filter.
+                if ExclusionFilter::excludes(
+                    options.exclusion_filter.as_ref(),
                     entry_path_rcr,
                     &entry_metadata,
-                )?;
+                ) {
+                    continue;
+                }
+
+                // Add the path to the archive, unless it's a directory that was already provided by
+                // another source directory.
+                if check_origin(
+                    origins,
+                    &excluded_input_paths_rcr,
+                    source_dir_cd,
+                    entry_path_rcr,
+                    entry_metadata.is_dir(),
+                )? {
+                    add_path(
+                        builder,
+                        content_hashes,
+                        visited_paths,
+                        &excluded_input_paths_rcr,
+                        entry.path(),
+                        entry_path_rcr,
+                        &entry_metadata,
+                        &file_modes_rcr,
+                        options,
+                        interrupted,
+                    )?;
+                    if !entry_metadata.is_dir()
+                        && !path_excluded(&excluded_input_paths_rcr, entry_path_rcr)
+                    {
+                        *files += 1;
+                    }
+                }
             }
         } else {
             // It's not a directory, so hopefully it's a file or symlink. Add it to the archive.
+            let input_path_acr =
+                destination_dir_acr.join(input_path_cd.strip_prefix(source_dir_cd).map_err(
+                    failure::system(format!(
+                        "Unable to relativize path {} with respect to {}.",
+                        input_path_cd.to_string_lossy().code_str(),
+                        source_dir_cd.to_string_lossy().code_str(),
+                    )),
+                )?);
+            let input_path_rcr = strip_root_rcr(&input_path_acr);
+            if ExclusionFilter::excludes(
+                options.exclusion_filter.as_ref(),
+                input_path_rcr,
+                &input_path_metadata,
+            ) {
+                continue;
+            }
+            check_origin(
+                origins,
+                &excluded_input_paths_rcr,
+                source_dir_cd,
+                input_path_rcr,
+                false,
+            )?;
             add_path(
-                &mut builder,
-                &mut content_hashes,
-                &mut visited_paths,
+                builder,
+                content_hashes,
+                visited_paths,
                 &excluded_input_paths_rcr,
                 &input_path_cd,
-                strip_root_rcr(
-                    &destination_dir_acr.join(input_path_cd.strip_prefix(&source_dir_cd).map_err(
-                        failure::system(format!(
-                            "Unable to relativize path {} with respect to {}.",
-                            input_path_cd.to_string_lossy().code_str(),
-                            source_dir_cd.to_string_lossy().code_str(),
-                        )),
-                    )?),
-                ),
+                input_path_rcr,
                 &input_path_metadata,
+                &file_modes_rcr,
+                options,
+                interrupted,
             )?;
+            if !path_excluded(&excluded_input_paths_rcr, input_path_rcr) {
+                *files += 1;
+            }
         }
     }
 
-    // Sort the file hashes to ensure the directory traversal order doesn't matter.
-    content_hashes.sort();
+    Ok(())
+}
 
-    // Return the tar file and the hash of its contents.
+// Determine whether a path which was reached while traversing the input paths is (or is inside)
+// `Options::skipped_dir`, and warn if so. Since directories are traversed without following
+// symbolic links, joining the path onto the canonical source directory is enough to make it
+// canonical too.
+fn skipped(
+    options: &Options,
+    source_dir_canonical: Option<&Path>,
+    source_dir_cd: &Path,
+    path_cd: &Path,
+) -> bool {
+    let (skipped_dir, source_dir_canonical, path_rsd) = match (
+        &options.skipped_dir,
+        source_dir_canonical,
+        path_cd.strip_prefix(source_dir_cd),
+    ) {
+        (Some(skipped_dir), Some(source_dir_canonical), Ok(path_rsd)) => {
+            (skipped_dir, source_dir_canonical, path_rsd)
+        }
+        _ => return false,
+    };
+
+    if !source_dir_canonical.join(path_rsd).starts_with(skipped_dir) {
+        return false;
+    }
+
+    warn!(
+        "Skipping {}, since it's the Toast cache directory. Pass {} to include it anyway.",
+        path_cd.to_string_lossy().code_str(),
+        "--include-cache-dir".code_str(),
+    );
+
+    true
+}
+
+// Add the files which don't exist on the host to an archive, check the archive if requested, and
+// finish it. Return what `create` returns.
+#[allow(clippy::similar_names)]
+pub fn finish<W: Write>(
+    mut builder: Builder<W>,
+    state: State,
+    excluded_input_paths_rsd: &[PathBuf],
+    destination_dir: &Path,
+    options: &Options,
+) -> Result<(W, String, Manifest), Failure> {
+    let destination_dir_acr = &normalize_destination_dir_acr(destination_dir);
+    let State {
+        mut content_hashes,
+        mut visited_paths,
+        files,
+        ..
+    } = state;
+
+    // If requested, make sure some files were found, since otherwise the input paths are probably
+    // wrong.
+    if options.require_files && files == 0 && options.synthetic_files.is_empty() {
+        return Err(Failure::User(
+            format!(
+                "The input paths for this task don't contain any files. If that's expected, omit \
+                 {}.",
+                "--require-input-files".code_str(),
+            ),
+            None,
+        ));
+    }
+
+    // Convert the `excluded_input_paths` to be relative to the container filesystem root.
+    let excluded_input_paths_rcr = excluded_input_paths_rsd
+        .iter()
+        .map(|excluded_input_path| {
+            strip_root_rcr(&destination_dir_acr.join(excluded_input_path)).to_owned()
+        })
+        .collect::<Vec<_>>();
+
+    // Add the files which don't exist on the host.
+    for synthetic_file in &options.synthetic_files {
+        add_bytes(
+            &mut builder,
+            &mut content_hashes,
+            &mut visited_paths,
+            &excluded_input_paths_rcr,
+            strip_root_rcr(&destination_dir_acr.join(&synthetic_file.path_rsd)),
+            &synthetic_file.contents,
+            synthetic_file.executable,
+            options.content_transform.as_ref(),
+            options.trace_hashes,
+        )?;
+    }
+
+    // Make sure every overridden mode applies to something, since otherwise the path is probably a
+    // typo and the file gets the wrong mode.
+    let mut unmatched_modes_rsd = options
+        .file_modes
+        .keys()
+        .filter(|path_rsd| {
+            let path_rcr = strip_root_rcr(&destination_dir_acr.join(path_rsd)).to_owned();
+            !content_hashes
+                .iter()
+                .any(|(archived_path_rcr, _)| *archived_path_rcr == path_rcr)
+        })
+        .collect::<Vec<_>>();
+    if !unmatched_modes_rsd.is_empty() {
+        unmatched_modes_rsd.sort();
+        return Err(Failure::User(
+            format!(
+                "The following paths in {} aren't among the input files: {}.",
+                "input_file_modes".code_str(),
+                format::series(
+                    &unmatched_modes_rsd
+                        .iter()
+                        .map(|path_rsd| path_rsd.to_string_lossy().code_str().to_string())
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+            None,
+        ));
+    }
+
+    // If requested, make sure the archive can be extracted faithfully on a case-insensitive
+    // filesystem.
+    if options.case_insensitive_check {
+        if let Some((path1_rcr, path2_rcr)) = case_collision(
+            content_hashes
+                .iter()
+                .map(|(path_rcr, _)| path_rcr.as_path()),
+        ) {
+            return Err(Failure::User(
+                format!(
+                    "{} and {} differ only by case, so they would collide on a case-insensitive \
+                     filesystem.",
+                    Path::new("/").join(path1_rcr).to_string_lossy().code_str(),
+                    Path::new("/").join(path2_rcr).to_string_lossy().code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Sort the file hashes to ensure the directory traversal order doesn't matter. Only the hashes
+    // are combined, so this is already a total order on what matters: entries whose hashes are
+    // equal are indistinguishable to the fold, and the paths are incorporated into the hashes.
+    let mut sorted_hashes = content_hashes
+        .iter()
+        .map(|(_, entry)| entry.hash.as_str())
+        .collect::<Vec<_>>();
+    sorted_hashes.sort();
+
+    // Combine the sorted hashes into the hash of the archive, logging each step if requested.
+    let hash = sorted_hashes.iter().fold(String::new(), |acc, x| {
+        let combined = cache::combine(&acc, *x);
+        if options.trace_hashes {
+            info!("Combine {:?} with {} \u{2192} {}", acc, x, combined);
+        }
+        combined
+    });
+
+    // Return the tar file, the hash of its contents, and the manifest.
     Ok((
         builder
             .into_inner()
             .map_err(failure::system("Error writing tar archive."))?,
-        content_hashes
-            .iter()
-            .fold(String::new(), |acc, x| cache::combine(&acc, x)),
+        hash,
+        content_hashes.into_iter().collect(),
     ))
 }
+
+// The total size and number of the entries in an archive under one path
+#[derive(Debug, Eq, PartialEq)]
+pub struct PathUsage {
+    pub path_rsd: PathBuf,
+    pub bytes: u64,
+    pub entries: usize,
+}
+
+// Add up the sizes of the entries in an archive by the first component of their paths relative to
+// the destination directory (e.g., `target` for `target/debug/toast`), largest first. This reads
+// the whole archive, so it should only be used when there's reason to.
+pub fn usage_by_top_level_path<R: Read>(
+    reader: R,
+    destination_dir: &Path,
+) -> Result<Vec<PathUsage>, Failure> {
+    let destination_dir_acr = normalize_destination_dir_acr(destination_dir);
+    let destination_dir_rcr = strip_root_rcr(&destination_dir_acr);
+    let mut usage = HashMap::<PathBuf, (u64, usize)>::new();
+
+    let mut archive = Archive::new(reader);
+    for entry in archive
+        .entries()
+        .map_err(failure::system("Unable to read tar archive."))?
+    {
+        let entry = entry.map_err(failure::system("Unable to read tar archive entry."))?;
+        let path_rcr = entry
+            .path()
+            .map_err(failure::system("Unable to read tar archive entry path."))?;
+
+        // Skip the destination directory and its ancestors.
+        if let Some(component) = path_rcr
+            .strip_prefix(destination_dir_rcr)
+            .ok()
+            .and_then(|path_rsd| path_rsd.components().next())
+        {
+            let (bytes, entries) = usage
+                .entry(PathBuf::from(component.as_os_str()))
+                .or_default();
+            *bytes += entry.size();
+            *entries += 1;
+        }
+    }
+
+    let mut usage = usage
+        .into_iter()
+        .map(|(path_rsd, (bytes, entries))| PathUsage {
+            path_rsd,
+            bytes,
+            entries,
+        })
+        .collect::<Vec<_>>();
+    usage.sort_by(|x, y| {
+        y.bytes
+            .cmp(&x.bytes)
+            .then_with(|| x.path_rsd.cmp(&y.path_rsd))
+    });
+
+    Ok(usage)
+}
+
+// Rewrite an archive so its paths are relative to a directory rather than the root of the
+// container filesystem, leaving out that directory and its ancestors. Docker only copies files into
+// a container with a read-only root filesystem if they're going into a volume, so the archive has
+// to be extracted there rather than at the root.
+pub fn reroot<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    destination_dir: &Path,
+) -> Result<W, Failure> {
+    let destination_dir_acr = normalize_destination_dir_acr(destination_dir);
+    let destination_dir_rcr = strip_root_rcr(&destination_dir_acr);
+    let mut builder = Builder::new(writer);
+
+    let mut archive = Archive::new(reader);
+    for entry in archive
+        .entries()
+        .map_err(failure::system("Unable to read tar archive."))?
+    {
+        let mut entry = entry.map_err(failure::system("Unable to read tar archive entry."))?;
+        let path_rcr = entry
+            .path()
+            .map_err(failure::system("Unable to read tar archive entry path."))?
+            .into_owned();
+
+        if let Ok(relative_path) = path_rcr.strip_prefix(destination_dir_rcr) {
+            if relative_path.as_os_str().is_empty() {
+                continue;
+            }
+
+            // The header keeps the type, mode, modification time, and link target of the entry.
+            let mut header = entry.header().clone();
+            builder
+                .append_data(&mut header, relative_path, &mut entry)
+                .map_err(failure::system(format!(
+                    "Unable to append {} to tar archive.",
+                    relative_path.to_string_lossy().code_str(),
+                )))?;
+        }
+    }
+
+    builder
+        .into_inner()
+        .map_err(failure::system("Error writing tar archive."))
+}
+
+// The header format used for an entry in an archive
+#[cfg(test)]
+#[derive(Debug, Eq, PartialEq)]
+pub enum HeaderFormat {
+    Gnu,
+    Ustar,
+    Old, // Pre-POSIX
+}
+
+// A summary of an entry in an archive
+#[cfg(test)]
+#[derive(Debug, Eq, PartialEq)]
+pub struct EntryDescription {
+    pub path: PathBuf,
+    pub entry_type: EntryType,
+    pub format: HeaderFormat,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: u64,
+    pub link_name: Option<PathBuf>,
+}
+
+// A summary of the entries in an archive, in the order they appear
+#[cfg(test)]
+#[derive(Debug, Eq, PartialEq)]
+pub struct ArchiveDescription {
+    pub entries: Vec<EntryDescription>,
+}
+
+// Read an archive and describe its entries. This makes it possible to assert exactly what `create`
+// produced without comparing bytes.
+#[cfg(test)]
+pub fn describe<R: Read>(reader: R) -> Result<ArchiveDescription, Failure> {
+    let mut archive = Archive::new(reader);
+    let mut entries = vec![];
+
+    for entry in archive
+        .entries()
+        .map_err(failure::system("Unable to read tar archive."))?
+    {
+        let entry = entry.map_err(failure::system("Unable to read tar archive entry."))?;
+        let header = entry.header();
+
+        entries.push(EntryDescription {
+            path: entry
+                .path()
+                .map_err(failure::system("Unable to read tar archive entry path."))?
+                .into_owned(),
+            entry_type: header.entry_type(),
+            format: if header.as_gnu().is_some() {
+                HeaderFormat::Gnu
+            } else if header.as_ustar().is_some() {
+                HeaderFormat::Ustar
+            } else {
+                HeaderFormat::Old
+            },
+            size: entry.size(),
+            mode: header
+                .mode()
+                .map_err(failure::system("Unable to read tar archive entry mode."))?,
+            mtime: header
+                .mtime()
+                .map_err(failure::system("Unable to read tar archive entry mtime."))?,
+            link_name: entry
+                .link_name()
+                .map_err(failure::system(
+                    "Unable to read tar archive entry link name.",
+                ))?
+                .map(std::borrow::Cow::into_owned),
+        });
+    }
+
+    Ok(ArchiveDescription { entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        failure::Failure,
+        scan_cache::ScanCache,
+        tar::{
+            append, case_collision, copy_unfinished, create, create_from_sources, describe, finish,
+            is_transient, normalize_destination_dir_acr, normalize_line_endings, reroot, retry_io,
+            usage_by_top_level_path, ContentTransform, EntryDescription, ExclusionFilter,
+            HeaderFormat, Options, PathUsage, Phase, State, SyntheticFile, Timings, BLOCK_SIZE,
+            END_OF_ARCHIVE_SIZE,
+        },
+    };
+    use filetime::{set_file_mtime, FileTime};
+    use std::{
+        borrow::Cow,
+        collections::HashMap,
+        fs::{canonicalize, create_dir, remove_dir, remove_file, rename, write},
+        io::{self, ErrorKind},
+        path::{Path, PathBuf},
+        sync::{atomic::AtomicBool, Arc},
+        time::{Duration, Instant},
+    };
+    use tar::{Builder, EntryType};
+    use tempfile::tempdir;
+
+    fn directory(path: &str) -> EntryDescription {
+        EntryDescription {
+            path: Path::new(path).to_owned(),
+            entry_type: EntryType::Directory,
+            format: HeaderFormat::Gnu,
+            size: 0,
+            mode: 0o777,
+            mtime: 0,
+            link_name: None,
+        }
+    }
+
+    fn file(path: &str, size: u64, mode: u32) -> EntryDescription {
+        EntryDescription {
+            path: Path::new(path).to_owned(),
+            entry_type: EntryType::Regular,
+            format: HeaderFormat::Gnu,
+            size,
+            mode,
+            mtime: 0,
+            link_name: None,
+        }
+    }
+
+    fn error_message() -> String {
+        "Error.".to_owned()
+    }
+
+    fn archive_with_destination(
+        source_dir: &Path,
+        input_paths: &[PathBuf],
+        excluded_input_paths: &[PathBuf],
+        destination_dir: &Path,
+    ) -> (Vec<EntryDescription>, String) {
+        let (bytes, hash, _) = create(
+            "Testing\u{2026}",
+            vec![],
+            input_paths,
+            excluded_input_paths,
+            source_dir,
+            destination_dir,
+            &Options::default(),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        (describe(&bytes[..]).unwrap().entries, hash)
+    }
+
+    fn archive(
+        source_dir: &Path,
+        input_paths: &[PathBuf],
+        excluded_input_paths: &[PathBuf],
+    ) -> Vec<EntryDescription> {
+        archive_with_destination(
+            source_dir,
+            input_paths,
+            excluded_input_paths,
+            Path::new("/scratch"),
+        )
+        .0
+    }
+
+    #[test]
+    fn describe_empty() {
+        let source_dir = tempdir().unwrap();
+
+        assert_eq!(
+            archive(source_dir.path(), &[], &[]),
+            vec![directory("scratch")]
+        );
+    }
+
+    #[test]
+    fn describe_files() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+
+        assert_eq!(
+            archive(
+                source_dir.path(),
+                &[Path::new("foo/bar.txt").to_owned()],
+                &[]
+            ),
+            vec![
+                directory("scratch"),
+                directory("scratch/foo"),
+                file("scratch/foo/bar.txt", 3, 0o666),
+            ],
+        );
+    }
+
+    #[test]
+    fn describe_excluded_input_paths() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+        create_dir(source_dir.path().join("foo/baz")).unwrap();
+        write(source_dir.path().join("foo/baz/qux.txt"), "qux").unwrap();
+
+        assert_eq!(
+            archive(
+                source_dir.path(),
+                &[Path::new("foo").to_owned()],
+                &[Path::new("foo/baz").to_owned()],
+            ),
+            vec![
+                directory("scratch"),
+                directory("scratch/foo"),
+                file("scratch/foo/bar.txt", 3, 0o666),
+            ],
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn describe_executable_and_symlink() {
+        use std::{
+            fs::{set_permissions, Permissions},
+            os::unix::fs::{symlink, PermissionsExt},
+        };
+
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("foo.sh"), "echo foo").unwrap();
+        set_permissions(
+            source_dir.path().join("foo.sh"),
+            Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        symlink("foo.sh", source_dir.path().join("bar")).unwrap();
+
+        assert_eq!(
+            archive(
+                source_dir.path(),
+                &[Path::new("foo.sh").to_owned(), Path::new("bar").to_owned()],
+                &[],
+            ),
+            vec![
+                directory("scratch"),
+                file("scratch/foo.sh", 8, 0o777),
+                EntryDescription {
+                    path: Path::new("scratch/bar").to_owned(),
+                    entry_type: EntryType::Symlink,
+                    format: HeaderFormat::Gnu,
+                    size: 0,
+                    mode: 0o777,
+                    mtime: 0,
+                    link_name: Some(Path::new("foo.sh").to_owned()),
+                },
+            ],
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn describe_dangling_symlink() {
+        use std::os::unix::fs::symlink;
+
+        let source_dir = tempdir().unwrap();
+        symlink("foo.txt", source_dir.path().join("bar")).unwrap();
+        let input_paths = [Path::new("bar").to_owned()];
+
+        // The symbolic link is archived as is, even though its target doesn't exist.
+        let (entries, hash) =
+            archive_with_destination(source_dir.path(), &input_paths, &[], Path::new("/scratch"));
+        assert_eq!(
+            entries,
+            vec![
+                directory("scratch"),
+                EntryDescription {
+                    path: Path::new("scratch/bar").to_owned(),
+                    entry_type: EntryType::Symlink,
+                    format: HeaderFormat::Gnu,
+                    size: 0,
+                    mode: 0o777,
+                    mtime: 0,
+                    link_name: Some(Path::new("foo.txt").to_owned()),
+                },
+            ],
+        );
+
+        // Its target is part of the hash.
+        remove_file(source_dir.path().join("bar")).unwrap();
+        symlink("baz.txt", source_dir.path().join("bar")).unwrap();
+        assert_ne!(
+            archive_with_destination(source_dir.path(), &input_paths, &[], Path::new("/scratch")).1,
+            hash,
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn describe_source_dir_symlink() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        create_dir(&source_dir).unwrap();
+        create_dir(source_dir.join("foo")).unwrap();
+        write(source_dir.join("foo/bar.txt"), "bar").unwrap();
+        symlink(&source_dir, dir.path().join("link")).unwrap();
+
+        // The source directory is used as given rather than canonicalized, so reaching it through a
+        // symbolic link makes no difference.
+        let input_paths = [Path::new("foo").to_owned()];
+        assert_eq!(
+            archive_with_destination(
+                &dir.path().join("link"),
+                &input_paths,
+                &[],
+                Path::new("/scratch"),
+            ),
+            archive_with_destination(&source_dir, &input_paths, &[], Path::new("/scratch")),
+        );
+    }
+
+    #[test]
+    fn normalize_destination_dir_acr_root() {
+        for destination_dir in &["", ".", "/", "/.", "./", "/.."] {
+            assert_eq!(
+                normalize_destination_dir_acr(Path::new(destination_dir)),
+                Path::new("/"),
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_destination_dir_acr_nested() {
+        for destination_dir in &[
+            "/foo/bar",
+            "foo/bar",
+            "./foo/./bar/",
+            "/foo//bar",
+            "/foo/baz/../bar",
+        ] {
+            assert_eq!(
+                normalize_destination_dir_acr(Path::new(destination_dir)),
+                Path::new("/foo/bar"),
+            );
+        }
+    }
+
+    #[test]
+    fn describe_destination_dir_current() {
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("foo.txt"), "foo").unwrap();
+        let input_paths = [Path::new("foo.txt").to_owned()];
+
+        let (root_entries, root_hash) =
+            archive_with_destination(source_dir.path(), &input_paths, &[], Path::new("/"));
+
+        for destination_dir in &["", "."] {
+            let (entries, hash) = archive_with_destination(
+                source_dir.path(),
+                &input_paths,
+                &[],
+                Path::new(destination_dir),
+            );
+
+            assert_eq!(entries, vec![file("foo.txt", 3, 0o666)]);
+            assert_eq!(entries, root_entries);
+            assert_eq!(hash, root_hash);
+        }
+    }
+
+    #[test]
+    fn describe_destination_dir_trailing_current() {
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("foo.txt"), "foo").unwrap();
+        let input_paths = [Path::new("foo.txt").to_owned()];
+
+        let (entries, hash) =
+            archive_with_destination(source_dir.path(), &input_paths, &[], Path::new("/scratch"));
+        let (dot_entries, dot_hash) = archive_with_destination(
+            source_dir.path(),
+            &input_paths,
+            &[],
+            Path::new("/scratch/."),
+        );
+
+        assert_eq!(dot_entries, entries);
+        assert_eq!(dot_hash, hash);
+    }
+
+    #[test]
+    fn is_transient_not_found() {
+        assert!(!is_transient(&io::Error::from(ErrorKind::NotFound)));
+    }
+
+    #[test]
+    fn is_transient_timed_out() {
+        assert!(is_transient(&io::Error::from(ErrorKind::TimedOut)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ownership_reason_other_user() {
+        use crate::tar::ownership_reason;
+
+        assert_eq!(
+            ownership_reason(0o100_600, 1001, 1000),
+            "The file has mode `0600` and is owned by UID 1001, but Toast is running as UID 1000.",
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ownership_reason_current_user() {
+        use crate::tar::ownership_reason;
+
+        assert_eq!(
+            ownership_reason(0o100_200, 1000, 1000),
+            "The file has mode `0200` and is owned by the current user (UID 1000).",
+        );
+    }
+
+    #[test]
+    fn retry_io_transient() {
+        let mut attempts = 0;
+
+        let result = retry_io(2, &Arc::new(AtomicBool::new(false)), error_message, || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(io::Error::from(ErrorKind::TimedOut))
+            } else {
+                Ok(attempts)
+            }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn retry_io_exhausted() {
+        let mut attempts = 0;
+
+        let result: Result<(), Failure> =
+            retry_io(2, &Arc::new(AtomicBool::new(false)), error_message, || {
+                attempts += 1;
+                Err(io::Error::from(ErrorKind::TimedOut))
+            });
+
+        assert!(matches!(result, Err(Failure::System(_, _))));
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn retry_io_permanent() {
+        let mut attempts = 0;
+
+        let result: Result<(), Failure> =
+            retry_io(2, &Arc::new(AtomicBool::new(false)), error_message, || {
+                attempts += 1;
+                Err(io::Error::from(ErrorKind::NotFound))
+            });
+
+        assert!(matches!(result, Err(Failure::System(_, _))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn retry_io_interrupted() {
+        let mut attempts = 0;
+
+        let result: Result<(), Failure> =
+            retry_io(2, &Arc::new(AtomicBool::new(true)), error_message, || {
+                attempts += 1;
+                Err(io::Error::from(ErrorKind::TimedOut))
+            });
+
+        assert!(matches!(result, Err(Failure::Interrupted)));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn case_collision_none() {
+        assert_eq!(
+            case_collision(vec![Path::new("scratch/foo"), Path::new("scratch/bar")]),
+            None,
+        );
+    }
+
+    #[test]
+    fn case_collision_some() {
+        assert_eq!(
+            case_collision(vec![
+                Path::new("scratch/readme"),
+                Path::new("scratch/foo"),
+                Path::new("scratch/README"),
+            ]),
+            Some((Path::new("scratch/README"), Path::new("scratch/readme"))),
+        );
+    }
+
+    #[test]
+    fn create_case_insensitive_check() {
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("README"), "foo").unwrap();
+        write(source_dir.path().join("readme"), "bar").unwrap();
+        let input_paths = [
+            Path::new("README").to_owned(),
+            Path::new("readme").to_owned(),
+        ];
+
+        let create_with_check = |case_insensitive_check| {
+            create(
+                "Testing\u{2026}",
+                vec![],
+                &input_paths,
+                &[],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options {
+                    case_insensitive_check,
+                    ..Options::default()
+                },
+                &Arc::new(AtomicBool::new(false)),
+            )
+        };
+
+        assert!(create_with_check(false).is_ok());
+        assert!(matches!(create_with_check(true), Err(Failure::User(_, _)),));
+    }
+
+    #[test]
+    fn create_synthetic_files() {
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("foo.txt"), "foo").unwrap();
+
+        let (bytes, _, _) = create(
+            "Testing\u{2026}",
+            vec![],
+            &[Path::new("foo.txt").to_owned()],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                synthetic_files: vec![SyntheticFile {
+                    path_rsd: Path::new("bar/baz.sh").to_owned(),
+                    contents: b"echo baz".to_vec(),
+                    executable: true,
+                }],
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            describe(&bytes[..]).unwrap().entries,
+            vec![
+                directory("scratch"),
+                file("scratch/foo.txt", 3, 0o666),
+                directory("scratch/bar"),
+                file("scratch/bar/baz.sh", 8, 0o777),
+            ],
+        );
+    }
+
+    #[test]
+    fn create_synthetic_files_hash() {
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("foo.txt"), "foo").unwrap();
+
+        let (_, real_hash) = archive_with_destination(
+            source_dir.path(),
+            &[Path::new("foo.txt").to_owned()],
+            &[],
+            Path::new("/scratch"),
+        );
+
+        let empty_dir = tempdir().unwrap();
+        let (_, synthetic_hash, _) = create(
+            "Testing\u{2026}",
+            vec![],
+            &[],
+            &[],
+            empty_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                synthetic_files: vec![SyntheticFile {
+                    path_rsd: Path::new("foo.txt").to_owned(),
+                    contents: b"foo".to_vec(),
+                    executable: false,
+                }],
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(synthetic_hash, real_hash);
+    }
+
+    #[test]
+    fn create_synthetic_files_conflict() {
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("foo.txt"), "foo").unwrap();
+
+        let result = create(
+            "Testing\u{2026}",
+            vec![],
+            &[Path::new("foo.txt").to_owned()],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                synthetic_files: vec![SyntheticFile {
+                    path_rsd: Path::new("foo.txt").to_owned(),
+                    contents: b"bar".to_vec(),
+                    executable: false,
+                }],
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        assert!(matches!(result, Err(Failure::User(_, _))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_unsupported_file_type() {
+        use std::os::unix::net::UnixListener;
+
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+        let _listener = UnixListener::bind(source_dir.path().join("foo/baz.sock")).unwrap();
+
+        let result = create(
+            "Testing\u{2026}",
+            vec![],
+            &[Path::new("foo").to_owned()],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options::default(),
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        // The socket must not be skipped silently.
+        match result {
+            Err(Failure::User(message, _)) => assert!(message.contains("baz.sock")),
+            _ => panic!("The socket was not rejected."),
+        }
+    }
+
+    #[test]
+    fn create_file_modes() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.sh"), "bar").unwrap();
+        write(source_dir.path().join("foo/baz.txt"), "baz").unwrap();
+        let input_paths = [
+            Path::new("foo/bar.sh").to_owned(),
+            Path::new("foo/baz.txt").to_owned(),
+        ];
+
+        let mut file_modes = HashMap::new();
+        file_modes.insert(Path::new("foo/bar.sh").to_owned(), 0o755);
+
+        let (bytes, hash, _) = create(
+            "Testing\u{2026}",
+            vec![],
+            &input_paths,
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                file_modes,
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            describe(&bytes[..]).unwrap().entries,
+            vec![
+                directory("scratch"),
+                directory("scratch/foo"),
+                file("scratch/foo/bar.sh", 3, 0o755),
+                file("scratch/foo/baz.txt", 3, 0o666),
+            ],
+        );
+
+        let (_, default_hash) =
+            archive_with_destination(source_dir.path(), &input_paths, &[], Path::new("/scratch"));
+        assert_ne!(hash, default_hash);
+    }
+
+    #[test]
+    fn create_file_modes_unmatched() {
+        let source_dir = tempdir().unwrap();
+        write(source_dir.path().join("foo.sh"), "foo").unwrap();
+        let input_paths = [Path::new("foo.sh").to_owned()];
+
+        let mut file_modes = HashMap::new();
+        file_modes.insert(Path::new("fooo.sh").to_owned(), 0o755);
+
+        let result = create(
+            "Testing\u{2026}",
+            vec![],
+            &input_paths,
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                file_modes,
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        assert!(result.unwrap_err().to_string().contains("fooo.sh"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_preserve_permissions() {
+        use std::{
+            fs::{set_permissions, Permissions},
+            os::unix::fs::PermissionsExt,
+        };
+
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+        write(source_dir.path().join("foo/baz.sh"), "baz").unwrap();
+        set_permissions(
+            source_dir.path().join("foo/bar.txt"),
+            Permissions::from_mode(0o640),
+        )
+        .unwrap();
+        set_permissions(source_dir.path().join("foo"), Permissions::from_mode(0o700)).unwrap();
+        let input_paths = [Path::new("foo").to_owned()];
+
+        let mut file_modes = HashMap::new();
+        file_modes.insert(Path::new("foo/baz.sh").to_owned(), 0o755);
+
+        let (bytes, hash, _) = create(
+            "Testing\u{2026}",
+            vec![],
+            &input_paths,
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                file_modes,
+                preserve_permissions: true,
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        // Restore the mode of the directory so the temporary directory can be cleaned up.
+        set_permissions(source_dir.path().join("foo"), Permissions::from_mode(0o755)).unwrap();
+
+        // Overridden modes still take precedence, and implicitly added ancestors keep the default.
+        assert_eq!(
+            describe(&bytes[..]).unwrap().entries,
+            vec![
+                directory("scratch"),
+                EntryDescription {
+                    mode: 0o700,
+                    ..directory("scratch/foo")
+                },
+                file("scratch/foo/bar.txt", 3, 0o640),
+                file("scratch/foo/baz.sh", 3, 0o755),
+            ],
+        );
+
+        let (_, default_hash) =
+            archive_with_destination(source_dir.path(), &input_paths, &[], Path::new("/scratch"));
+        assert_ne!(hash, default_hash);
+    }
+
+    #[test]
+    fn create_content_transform() {
+        let input_paths = [
+            Path::new("foo.txt").to_owned(),
+            Path::new("stamp.h").to_owned(),
+        ];
+
+        let create_with_stamp = |stamp: &str| {
+            let source_dir = tempdir().unwrap();
+            write(source_dir.path().join("foo.txt"), "foo").unwrap();
+            write(source_dir.path().join("stamp.h"), stamp).unwrap();
+
+            let (bytes, hash, _) = create(
+                "Testing\u{2026}",
+                vec![],
+                &input_paths,
+                &[],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options {
+                    synthetic_files: vec![SyntheticFile {
+                        path_rsd: Path::new("bar/stamp.h").to_owned(),
+                        contents: stamp.as_bytes().to_vec(),
+                        executable: false,
+                    }],
+                    content_transform: Some(ContentTransform(Arc::new(|path, contents| {
+                        if path.ends_with("stamp.h") {
+                            Cow::Owned(b"0".to_vec())
+                        } else {
+                            Cow::Borrowed(contents)
+                        }
+                    }))),
+                    ..Options::default()
+                },
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap();
+
+            (describe(&bytes[..]).unwrap().entries, hash)
+        };
+
+        let (entries, hash) = create_with_stamp("1234567890");
+        assert_eq!(
+            entries,
+            vec![
+                directory("scratch"),
+                file("scratch/foo.txt", 3, 0o666),
+                file("scratch/stamp.h", 1, 0o666),
+                directory("scratch/bar"),
+                file("scratch/bar/stamp.h", 1, 0o666),
+            ],
+        );
+
+        // Only the rewritten contents are part of the hash.
+        assert_eq!(create_with_stamp("1234567891").1, hash);
+    }
+
+    #[test]
+    fn normalize_line_endings_text() {
+        assert_eq!(
+            normalize_line_endings(b"foo\r\nbar\rbaz\r\n"),
+            &b"foo\nbar\rbaz\n"[..],
+        );
+        assert!(matches!(
+            normalize_line_endings(b"foo\nbar\n"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn normalize_line_endings_binary() {
+        assert_eq!(
+            normalize_line_endings(b"foo\r\n\0bar\r\n"),
+            &b"foo\r\n\0bar\r\n"[..],
+        );
+    }
+
+    #[test]
+    fn create_normalize_line_endings() {
+        let input_paths = [
+            Path::new("foo.txt").to_owned(),
+            Path::new("bar.bin").to_owned(),
+        ];
+
+        let create_with_line_ending = |line_ending: &str| {
+            let source_dir = tempdir().unwrap();
+            write(
+                source_dir.path().join("foo.txt"),
+                format!("foo{}", line_ending),
+            )
+            .unwrap();
+            write(source_dir.path().join("bar.bin"), "bar\r\n").unwrap();
+
+            let (bytes, hash, _) = create(
+                "Testing\u{2026}",
+                vec![],
+                &input_paths,
+                &[],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options {
+                    content_transform: Some(ContentTransform::normalize_line_endings(
+                        Path::new("/scratch"),
+                        &[Path::new("*.txt").to_owned()],
+                    )),
+                    ..Options::default()
+                },
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap();
+
+            (describe(&bytes[..]).unwrap().entries, hash)
+        };
+
+        let (entries, hash) = create_with_line_ending("\r\n");
+        assert_eq!(
+            entries,
+            vec![
+                directory("scratch"),
+                file("scratch/foo.txt", 4, 0o666),
+                file("scratch/bar.bin", 5, 0o666),
+            ],
+        );
+
+        // Files with either line ending have the same hash.
+        assert_eq!(create_with_line_ending("\n").1, hash);
+    }
+
+    #[test]
+    fn create_exclude_vcs_directories() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        create_dir(source_dir.path().join("foo/.git")).unwrap();
+        write(source_dir.path().join("foo/.git/HEAD"), "main").unwrap();
+        create_dir(source_dir.path().join("foo/bar")).unwrap();
+        write(source_dir.path().join("foo/bar/.git"), "gitdir: ..").unwrap();
+        write(source_dir.path().join("foo/bar/baz.txt"), "baz").unwrap();
+        create_dir(source_dir.path().join(".hg")).unwrap();
+        write(source_dir.path().join(".hg/store"), "qux").unwrap();
+
+        let (bytes, _, _) = create(
+            "Testing\u{2026}",
+            vec![],
+            &[Path::new("foo").to_owned(), Path::new(".hg").to_owned()],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                exclude_vcs_directories: true,
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        // Metadata inside the input directories is left out, but an input path which is itself a
+        // metadata directory is added.
+        assert_eq!(
+            describe(&bytes[..]).unwrap().entries,
+            vec![
+                directory("scratch"),
+                directory("scratch/foo"),
+                directory("scratch/foo/bar"),
+                file("scratch/foo/bar/baz.txt", 3, 0o666),
+                directory("scratch/.hg"),
+                file("scratch/.hg/store", 3, 0o666),
+            ],
+        );
+    }
+
+    #[test]
+    fn create_skipped_dir() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+        create_dir(source_dir.path().join("foo/cache")).unwrap();
+        write(source_dir.path().join("foo/cache/baz.txt"), "baz").unwrap();
+
+        let (bytes, _, _) = create(
+            "Testing\u{2026}",
+            vec![],
+            &[Path::new("foo").to_owned()],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                skipped_dir: Some(
+                    canonicalize(source_dir.path())
+                        .unwrap()
+                        .join("foo")
+                        .join("cache"),
+                ),
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            describe(&bytes[..]).unwrap().entries,
+            vec![
+                directory("scratch"),
+                directory("scratch/foo"),
+                file("scratch/foo/bar.txt", 3, 0o666),
+            ],
+        );
+    }
+
+    #[test]
+    fn create_require_files() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        create_dir(source_dir.path().join("foo/bar")).unwrap();
+
+        let create_foo = || {
+            create(
+                "Testing\u{2026}",
+                vec![],
+                &[Path::new("foo").to_owned()],
+                &[Path::new("foo/bar/baz.txt").to_owned()],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options {
+                    require_files: true,
+                    ..Options::default()
+                },
+                &Arc::new(AtomicBool::new(false)),
+            )
+        };
+
+        // Directories alone don't count, and neither do excluded files.
+        assert!(matches!(create_foo(), Err(Failure::User(_, _))));
+        write(source_dir.path().join("foo/bar/baz.txt"), "baz").unwrap();
+        assert!(matches!(create_foo(), Err(Failure::User(_, _))));
+
+        write(source_dir.path().join("foo/bar/qux.txt"), "qux").unwrap();
+        assert!(create_foo().is_ok());
+    }
+
+    #[test]
+    fn create_trace_hashes() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+
+        let create_with_trace_hashes = |trace_hashes| {
+            create(
+                "Testing\u{2026}",
+                vec![],
+                &[Path::new("foo").to_owned()],
+                &[],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options {
+                    trace_hashes,
+                    ..Options::default()
+                },
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap()
+        };
+
+        // Tracing doesn't affect the archive or its hash.
+        let (bytes, hash, _) = create_with_trace_hashes(false);
+        let (traced_bytes, traced_hash, _) = create_with_trace_hashes(true);
+        assert_eq!(traced_bytes, bytes);
+        assert_eq!(traced_hash, hash);
+    }
+
+    #[test]
+    fn create_structural_changes() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        create_dir(source_dir.path().join("foo/bar")).unwrap();
+        write(source_dir.path().join("foo/bar/baz.txt"), "baz").unwrap();
+
+        let hash = || {
+            create(
+                "Testing\u{2026}",
+                vec![],
+                &[Path::new("foo").to_owned()],
+                &[],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options::default(),
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap()
+            .1
+        };
+
+        // Renaming a file, deleting it, and then deleting the directory it was in each change the
+        // hash, since every entry contributes its path, including directories.
+        let mut hashes = vec![hash()];
+        rename(
+            source_dir.path().join("foo/bar/baz.txt"),
+            source_dir.path().join("foo/bar/qux.txt"),
+        )
+        .unwrap();
+        hashes.push(hash());
+        remove_file(source_dir.path().join("foo/bar/qux.txt")).unwrap();
+        hashes.push(hash());
+        remove_dir(source_dir.path().join("foo/bar")).unwrap();
+        hashes.push(hash());
+
+        for (i, hash1) in hashes.iter().enumerate() {
+            for hash2 in &hashes[i + 1..] {
+                assert_ne!(hash1, hash2);
+            }
+        }
+    }
+
+    #[test]
+    fn create_deadline() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+
+        let create_with_deadline = |deadline| {
+            create(
+                "Testing\u{2026}",
+                vec![],
+                &[Path::new("foo").to_owned()],
+                &[],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options {
+                    deadline,
+                    ..Options::default()
+                },
+                &Arc::new(AtomicBool::new(false)),
+            )
+        };
+
+        // A deadline in the past is an error, not an interruption.
+        assert!(matches!(
+            create_with_deadline(Some(Instant::now())),
+            Err(Failure::User(_, _)),
+        ));
+        assert!(create_with_deadline(Some(Instant::now() + Duration::from_secs(3600))).is_ok());
+    }
+
+    #[test]
+    fn create_exclusion_filter() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("data")).unwrap();
+        write(source_dir.path().join("foo.txt"), "foo").unwrap();
+        write(source_dir.path().join("huge.bin"), [0; 100]).unwrap();
+        write(source_dir.path().join("data/bar.txt"), "bar").unwrap();
+        write(source_dir.path().join("data/huge.bin"), [0; 100]).unwrap();
+
+        let (bytes, _, manifest) = create(
+            "Testing\u{2026}",
+            vec![],
+            &[
+                Path::new("foo.txt").to_owned(),
+                Path::new("huge.bin").to_owned(),
+                Path::new("data").to_owned(),
+            ],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                exclusion_filter: Some(ExclusionFilter(Arc::new(|_, metadata| {
+                    metadata.len() > 10
+                }))),
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            describe(&bytes[..]).unwrap().entries,
+            vec![
+                directory("scratch"),
+                file("scratch/foo.txt", 3, 0o666),
+                directory("scratch/data"),
+                file("scratch/data/bar.txt", 3, 0o666),
+            ],
+        );
+        assert!(!manifest.contains_key(Path::new("scratch/huge.bin")));
+        assert!(!manifest.contains_key(Path::new("scratch/data/huge.bin")));
+    }
+
+    #[test]
+    fn create_from_sources_merged() {
+        let repo_dir = tempdir().unwrap();
+        create_dir(repo_dir.path().join("src")).unwrap();
+        write(repo_dir.path().join("src/main.rs"), "main").unwrap();
+
+        let protos_dir = tempdir().unwrap();
+        create_dir(protos_dir.path().join("src")).unwrap();
+        write(protos_dir.path().join("src/foo.rs"), "foo").unwrap();
+
+        let (bytes, hash, _) = create_from_sources(
+            "Testing\u{2026}",
+            vec![],
+            &[
+                (protos_dir.path(), &[Path::new("src").to_owned()]),
+                (repo_dir.path(), &[Path::new("src").to_owned()]),
+            ],
+            &[],
+            Path::new("/scratch"),
+            &Options::default(),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            describe(&bytes[..]).unwrap().entries,
+            vec![
+                directory("scratch"),
+                directory("scratch/src"),
+                file("scratch/src/foo.rs", 3, 0o666),
+                file("scratch/src/main.rs", 4, 0o666),
+            ],
+        );
+
+        // The hash is the same as if all the files had come from one source directory.
+        write(repo_dir.path().join("src/foo.rs"), "foo").unwrap();
+        let (_, single_hash) = archive_with_destination(
+            repo_dir.path(),
+            &[Path::new("src").to_owned()],
+            &[],
+            Path::new("/scratch"),
+        );
+        assert_eq!(hash, single_hash);
+    }
+
+    #[test]
+    fn create_from_sources_collision() {
+        let repo_dir = tempdir().unwrap();
+        write(repo_dir.path().join("foo.txt"), "foo").unwrap();
+
+        let protos_dir = tempdir().unwrap();
+        write(protos_dir.path().join("foo.txt"), "bar").unwrap();
+
+        let result = create_from_sources(
+            "Testing\u{2026}",
+            vec![],
+            &[
+                (repo_dir.path(), &[Path::new("foo.txt").to_owned()]),
+                (protos_dir.path(), &[Path::new("foo.txt").to_owned()]),
+            ],
+            &[],
+            Path::new("/scratch"),
+            &Options::default(),
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        match result {
+            Err(Failure::User(message, _)) => assert!(message.contains("/scratch/foo.txt")),
+            _ => panic!("The collision was not detected."),
+        }
+    }
+
+    #[test]
+    fn create_timings() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+
+        let timings = Arc::new(Timings::default());
+        create(
+            "Testing\u{2026}",
+            vec![],
+            &[Path::new("foo").to_owned()],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options {
+                timings: Some(timings.clone()),
+                ..Options::default()
+            },
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        for phase in Phase::ALL {
+            assert!(timings.total(phase) > Duration::ZERO, "{}", phase.name());
+        }
+    }
+
+    #[test]
+    fn create_scan_cache() {
+        let cache_dir = tempdir().unwrap();
+        let source_dir = tempdir().unwrap();
+        let path = source_dir.path().join("foo.txt");
+        let input_paths = [Path::new("foo.txt").to_owned()];
+        let scan_cache = Arc::new(ScanCache::load(cache_dir.path()));
+
+        let create_with_contents = |contents| {
+            write(&path, contents).unwrap();
+            set_file_mtime(&path, FileTime::from_unix_time(1_234_567_890, 0)).unwrap();
+
+            let (bytes, hash, _) = create(
+                "Testing\u{2026}",
+                vec![],
+                &input_paths,
+                &[],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options {
+                    scan_cache: Some(scan_cache.clone()),
+                    ..Options::default()
+                },
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap();
+
+            (bytes, hash)
+        };
+
+        // A change which keeps the modification time and size goes unnoticed, since the hash from
+        // the first run is trusted. The archive still has the new contents.
+        let (_, hash) = create_with_contents("foo");
+        let (bytes, other_hash) = create_with_contents("bar");
+        assert_eq!(hash, other_hash);
+        assert!(bytes.windows(3).any(|window| window == b"bar"));
+
+        // Changing the size is noticed.
+        let (_, other_hash) = create_with_contents("barbaz");
+        assert_ne!(hash, other_hash);
+    }
+
+    #[test]
+    fn create_preserve_mtime() {
+        let source_dir = tempdir().unwrap();
+        let path = source_dir.path().join("foo.txt");
+        write(&path, "foo").unwrap();
+        let input_paths = [Path::new("foo.txt").to_owned()];
+
+        let create_with_mtime = |mtime| {
+            set_file_mtime(&path, FileTime::from_unix_time(mtime, 0)).unwrap();
+
+            let (bytes, hash, _) = create(
+                "Testing\u{2026}",
+                vec![],
+                &input_paths,
+                &[],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options {
+                    preserve_mtime: true,
+                    ..Options::default()
+                },
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap();
+
+            (describe(&bytes[..]).unwrap().entries, hash)
+        };
+
+        let (entries, hash) = create_with_mtime(1_234_567_890);
+        assert_eq!(
+            entries,
+            vec![
+                directory("scratch"),
+                EntryDescription {
+                    mtime: 1_234_567_890,
+                    ..file("scratch/foo.txt", 3, 0o666)
+                },
+            ],
+        );
+
+        // Touching the file changes the hash.
+        let (_, touched_hash) = create_with_mtime(1_234_567_891);
+        assert_ne!(hash, touched_hash);
+
+        // Without the option, the modification time is ignored.
+        let (entries, default_hash) =
+            archive_with_destination(source_dir.path(), &input_paths, &[], Path::new("/scratch"));
+        assert_eq!(
+            entries,
+            vec![directory("scratch"), file("scratch/foo.txt", 3, 0o666)],
+        );
+        assert_ne!(hash, default_hash);
+    }
+
+    #[test]
+    fn append_copy_unfinished() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+        write(source_dir.path().join("baz.txt"), "baz").unwrap();
+        let input_paths = [Path::new("foo").to_owned(), Path::new("baz.txt").to_owned()];
+        let options = Options {
+            synthetic_files: vec![SyntheticFile {
+                path_rsd: Path::new("qux.txt").to_owned(),
+                contents: b"qux".to_vec(),
+                executable: false,
+            }],
+            ..Options::default()
+        };
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        // Archive the first path and finish the archive.
+        let mut builder = Builder::new(vec![]);
+        let mut state = State::default();
+        append(
+            &mut builder,
+            &mut state,
+            &input_paths[..1],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &Options::default(),
+            &interrupted,
+        )
+        .unwrap();
+        let (base_bytes, _, _) = finish(
+            builder,
+            state.clone(),
+            &[],
+            Path::new("/scratch"),
+            &Options::default(),
+        )
+        .unwrap();
+
+        // Extend a copy of that archive with the second path.
+        let mut bytes = vec![];
+        copy_unfinished(&mut &base_bytes[..], base_bytes.len() as u64, &mut bytes).unwrap();
+        let mut builder = Builder::new(bytes);
+        append(
+            &mut builder,
+            &mut state,
+            &input_paths[1..],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &options,
+            &interrupted,
+        )
+        .unwrap();
+        let (bytes, hash, manifest) =
+            finish(builder, state, &[], Path::new("/scratch"), &options).unwrap();
+
+        // The result is the same as archiving both paths at once.
+        let (expected_bytes, expected_hash, expected_manifest) = create(
+            "Testing\u{2026}",
+            vec![],
+            &input_paths,
+            &[],
+            source_dir.path(),
+            Path::new("/scratch"),
+            &options,
+            &interrupted,
+        )
+        .unwrap();
+        assert_eq!(bytes, expected_bytes);
+        assert_eq!(hash, expected_hash);
+        assert_eq!(manifest, expected_manifest);
+    }
+
+    #[test]
+    fn create_reproducible() {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let input_paths = [Path::new("foo").to_owned(), Path::new("baz.txt").to_owned()];
+
+        // Build the same tree twice, in a different order and with different modification times.
+        let archive_tree = |reverse: bool, mtime: i64| {
+            let source_dir = tempdir().unwrap();
+            let mut files = vec![
+                ("foo/bar.txt", "bar"),
+                ("foo/qux.txt", "qux"),
+                ("baz.txt", ""),
+            ];
+            if reverse {
+                files.reverse();
+            }
+            create_dir(source_dir.path().join("foo")).unwrap();
+            for (path, contents) in files {
+                write(source_dir.path().join(path), contents).unwrap();
+                set_file_mtime(
+                    source_dir.path().join(path),
+                    FileTime::from_unix_time(mtime, 0),
+                )
+                .unwrap();
+            }
+
+            create(
+                "Testing\u{2026}",
+                vec![],
+                &input_paths,
+                &[],
+                source_dir.path(),
+                Path::new("/scratch"),
+                &Options::default(),
+                &interrupted,
+            )
+            .unwrap()
+        };
+        let (bytes1, hash1, _) = archive_tree(false, 1_000_000_000);
+        let (bytes2, hash2, _) = archive_tree(true, 2_000_000_000);
+
+        // The archives are identical down to the padding and the end-of-archive marker.
+        assert_eq!(bytes1, bytes2);
+        assert_eq!(hash1, hash2);
+        assert_eq!(bytes1.len() as u64 % BLOCK_SIZE, 0);
+        assert!(bytes1[bytes1.len() - END_OF_ARCHIVE_SIZE as usize..]
+            .iter()
+            .all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn usage_by_top_level_path_sorted() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+        write(source_dir.path().join("foo/baz.txt"), "baz").unwrap();
+        write(source_dir.path().join("qux.txt"), "quxquxqux!").unwrap();
+
+        let (bytes, _, _) = create(
+            "Testing\u{2026}",
+            vec![],
+            &[Path::new("foo").to_owned(), Path::new("qux.txt").to_owned()],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch/code"),
+            &Options::default(),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            usage_by_top_level_path(&bytes[..], Path::new("/scratch/code")).unwrap(),
+            vec![
+                PathUsage {
+                    path_rsd: Path::new("qux.txt").to_owned(),
+                    bytes: 10,
+                    entries: 1,
+                },
+                PathUsage {
+                    path_rsd: Path::new("foo").to_owned(),
+                    bytes: 6,
+                    entries: 3,
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn reroot_strips_destination_dir() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo/bar.txt"), "bar").unwrap();
+        write(source_dir.path().join("qux.txt"), "qux").unwrap();
+
+        let (bytes, _, _) = create(
+            "Testing\u{2026}",
+            vec![],
+            &[Path::new("foo").to_owned(), Path::new("qux.txt").to_owned()],
+            &[],
+            source_dir.path(),
+            Path::new("/scratch/code"),
+            &Options::default(),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        let rerooted = reroot(&bytes[..], vec![], Path::new("/scratch")).unwrap();
+
+        // Everything but the `scratch` directory itself is kept, relative to it.
+        let expected = describe(&bytes[..])
+            .unwrap()
+            .entries
+            .into_iter()
+            .filter_map(|entry| {
+                let path = entry.path.strip_prefix("scratch").ok()?.to_owned();
+                if path.as_os_str().is_empty() {
+                    None
+                } else {
+                    Some(EntryDescription { path, ..entry })
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(describe(&rerooted[..]).unwrap().entries, expected);
+        assert_eq!(
+            expected
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new("code").to_owned(),
+                Path::new("code/foo").to_owned(),
+                Path::new("code/foo/bar.txt").to_owned(),
+                Path::new("code/qux.txt").to_owned(),
+            ],
+        );
+    }
+}
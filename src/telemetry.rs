@@ -0,0 +1,336 @@
+use crate::{cache::CryptoHash, failure, failure::Failure, format::CodeStr, json::Value};
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Tracing spans are written as JSON lines. Each line is an OTLP/JSON `ExportTraceServiceRequest`
+// containing one span, which is the format that OpenTelemetry's file exporter writes and its OTLP
+// JSON file receiver reads. That way, a collector can forward the spans to a tracing backend
+// without Toast having to link an OpenTelemetry SDK.
+
+// The name of the service and instrumentation scope that the spans are reported under
+const SERVICE_NAME: &str = "toast";
+
+// The kind of every span, as defined by OTLP (`SPAN_KIND_INTERNAL`)
+const SPAN_KIND_INTERNAL: u64 = 1;
+
+// The lengths (in hexadecimal digits) of trace and span IDs, as defined by OpenTelemetry
+const TRACE_ID_LENGTH: usize = 32;
+const SPAN_ID_LENGTH: usize = 16;
+
+// A tracer hands out spans and writes them to a file when they end. If there is no file, spans are
+// discarded.
+pub struct Tracer {
+    file: Option<Mutex<File>>,
+    trace_id: String,
+    spans_started: AtomicU64,
+}
+
+impl Tracer {
+    // Create a tracer which writes to the given file, if any. The file is truncated.
+    pub fn new(path: Option<&Path>) -> Result<Self, Failure> {
+        let file = path
+            .map(|path| {
+                File::create(path)
+                    .map(Mutex::new)
+                    .map_err(failure::user(format!(
+                        "Unable to create file {}.",
+                        path.to_string_lossy().code_str(),
+                    )))
+            })
+            .transpose()?;
+
+        // The trace ID only needs to be unique, not unpredictable.
+        let trace_id = format!("{} {}", process::id(), unix_nanos(SystemTime::now())).crypto_hash()
+            [..TRACE_ID_LENGTH]
+            .to_owned();
+
+        Ok(Self {
+            file,
+            trace_id,
+            spans_started: AtomicU64::new(0),
+        })
+    }
+
+    // Start a span with no parent.
+    pub fn span(&self, name: &str) -> Span<'_> {
+        self.start_span(name, None)
+    }
+
+    fn start_span(&self, name: &str, parent_span_id: Option<String>) -> Span<'_> {
+        let index = self.spans_started.fetch_add(1, Ordering::SeqCst);
+
+        Span {
+            tracer: self,
+            span_id: format!("{} {}", self.trace_id, index).crypto_hash()[..SPAN_ID_LENGTH]
+                .to_owned(),
+            parent_span_id,
+            name: name.to_owned(),
+            start_time: SystemTime::now(),
+            attributes: vec![],
+        }
+    }
+
+    // Write a span which has ended.
+    fn write(&self, span: &Span, end_time: SystemTime) {
+        if let Some(file) = &self.file {
+            let mut fields = vec![
+                ("traceId".to_owned(), Value::String(self.trace_id.clone())),
+                ("spanId".to_owned(), Value::String(span.span_id.clone())),
+            ];
+            if let Some(parent_span_id) = &span.parent_span_id {
+                fields.push((
+                    "parentSpanId".to_owned(),
+                    Value::String(parent_span_id.clone()),
+                ));
+            }
+            fields.extend(vec![
+                ("name".to_owned(), Value::String(span.name.clone())),
+                ("kind".to_owned(), Value::Number(SPAN_KIND_INTERNAL)),
+                // OTLP/JSON encodes 64-bit integers as strings.
+                (
+                    "startTimeUnixNano".to_owned(),
+                    Value::String(unix_nanos(span.start_time).to_string()),
+                ),
+                (
+                    "endTimeUnixNano".to_owned(),
+                    Value::String(unix_nanos(end_time).to_string()),
+                ),
+                ("attributes".to_owned(), key_values(&span.attributes)),
+            ]);
+
+            // Wrap the span in the resource and instrumentation scope it belongs to.
+            let request = Value::Object(vec![(
+                "resourceSpans".to_owned(),
+                Value::Array(vec![Value::Object(vec![
+                    (
+                        "resource".to_owned(),
+                        Value::Object(vec![(
+                            "attributes".to_owned(),
+                            key_values(&[(
+                                "service.name".to_owned(),
+                                Value::String(SERVICE_NAME.to_owned()),
+                            )]),
+                        )]),
+                    ),
+                    (
+                        "scopeSpans".to_owned(),
+                        Value::Array(vec![Value::Object(vec![
+                            (
+                                "scope".to_owned(),
+                                Value::Object(vec![(
+                                    "name".to_owned(),
+                                    Value::String(SERVICE_NAME.to_owned()),
+                                )]),
+                            ),
+                            (
+                                "spans".to_owned(),
+                                Value::Array(vec![Value::Object(fields)]),
+                            ),
+                        ])]),
+                    ),
+                ])]),
+            )]);
+
+            // Telemetry is best-effort, so a failure to write a span shouldn't fail the build. The
+            // `unwrap` will only fail if a panic already occurred.
+            if let Err(e) = writeln!(file.lock().unwrap(), "{}", request) {
+                debug!("Unable to write span. Reason: {}", e);
+            }
+        }
+    }
+}
+
+// Convert attributes to an OTLP list of key-value pairs.
+fn key_values(attributes: &[(String, Value)]) -> Value {
+    Value::Array(
+        attributes
+            .iter()
+            .map(|(key, value)| {
+                Value::Object(vec![
+                    ("key".to_owned(), Value::String(key.clone())),
+                    ("value".to_owned(), any_value(value)),
+                ])
+            })
+            .collect(),
+    )
+}
+
+// Convert an attribute value to an OTLP `AnyValue`.
+fn any_value(value: &Value) -> Value {
+    let (field, value) = match value {
+        Value::Bool(b) => ("boolValue", Value::Bool(*b)),
+        Value::Number(n) => ("intValue", Value::String(n.to_string())),
+        Value::String(s) => ("stringValue", Value::String(s.clone())),
+        Value::Array(elements) => (
+            "arrayValue",
+            Value::Object(vec![(
+                "values".to_owned(),
+                Value::Array(elements.iter().map(any_value).collect()),
+            )]),
+        ),
+        Value::Object(fields) => (
+            "kvlistValue",
+            Value::Object(vec![("values".to_owned(), key_values(fields))]),
+        ),
+    };
+
+    Value::Object(vec![(field.to_owned(), value)])
+}
+
+// A span measures one phase of the work. It ends when it's dropped.
+pub struct Span<'a> {
+    tracer: &'a Tracer,
+    span_id: String,
+    parent_span_id: Option<String>,
+    name: String,
+    start_time: SystemTime,
+    attributes: Vec<(String, Value)>,
+}
+
+impl<'a> Span<'a> {
+    // Start a span nested within this one.
+    pub fn child(&self, name: &str) -> Span<'a> {
+        self.tracer.start_span(name, Some(self.span_id.clone()))
+    }
+
+    // Attach some information to the span.
+    pub fn set_attribute(&mut self, key: &str, value: Value) {
+        self.attributes.push((key.to_owned(), value));
+    }
+}
+
+impl<'a> Drop for Span<'a> {
+    fn drop(&mut self) {
+        self.tracer.write(self, SystemTime::now());
+    }
+}
+
+// Convert a point in time to the number of nanoseconds since the Unix epoch.
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{json::Value, telemetry::Tracer};
+    use serde::Deserialize;
+    use std::fs::read_to_string;
+    use tempfile::tempdir;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct ExportTraceServiceRequest {
+        resource_spans: Vec<ResourceSpans>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct ResourceSpans {
+        resource: Resource,
+        scope_spans: Vec<ScopeSpans>,
+    }
+
+    #[derive(Deserialize)]
+    struct Resource {
+        attributes: Vec<KeyValue>,
+    }
+
+    #[derive(Deserialize)]
+    struct ScopeSpans {
+        spans: Vec<SpanRecord>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct SpanRecord {
+        trace_id: String,
+        span_id: String,
+        parent_span_id: Option<String>,
+        name: String,
+        kind: u64,
+        start_time_unix_nano: String,
+        end_time_unix_nano: String,
+        attributes: Vec<KeyValue>,
+    }
+
+    #[derive(Deserialize)]
+    struct KeyValue {
+        key: String,
+        value: serde_yaml::Value,
+    }
+
+    #[test]
+    fn tracer_nested_spans() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("spans.jsonl");
+
+        {
+            let tracer = Tracer::new(Some(&path)).unwrap();
+            let root = tracer.span("foo");
+            let mut child = root.child("bar");
+            child.set_attribute("baz", Value::Number(42));
+            child.set_attribute("qux", Value::Bool(true));
+        }
+
+        let requests = read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_yaml::from_str::<ExportTraceServiceRequest>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        // Each line reports one span under the same resource.
+        let mut spans = vec![];
+        for mut request in requests {
+            assert_eq!(request.resource_spans.len(), 1);
+            let mut resource_spans = request.resource_spans.pop().unwrap();
+            assert_eq!(resource_spans.resource.attributes[0].key, "service.name");
+            assert_eq!(resource_spans.scope_spans.len(), 1);
+            spans.append(&mut resource_spans.scope_spans.pop().unwrap().spans);
+        }
+
+        // Spans are written when they end, so the child comes first.
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].name, "bar");
+        assert_eq!(spans[1].name, "foo");
+        assert_eq!(spans[0].kind, 1);
+        assert_eq!(spans[0].trace_id, spans[1].trace_id);
+        assert_eq!(spans[0].trace_id.len(), 32);
+        assert_eq!(spans[0].span_id.len(), 16);
+        assert_ne!(spans[0].span_id, spans[1].span_id);
+        assert_eq!(spans[0].parent_span_id.as_ref(), Some(&spans[1].span_id));
+        assert_eq!(spans[1].parent_span_id, None);
+        assert!(
+            spans[0].start_time_unix_nano.parse::<u64>().unwrap()
+                <= spans[0].end_time_unix_nano.parse::<u64>().unwrap(),
+        );
+
+        // Integers are encoded as strings.
+        assert_eq!(spans[0].attributes[0].key, "baz");
+        assert_eq!(
+            spans[0].attributes[0].value["intValue"],
+            serde_yaml::Value::String("42".to_owned()),
+        );
+        assert_eq!(spans[0].attributes[1].key, "qux");
+        assert_eq!(
+            spans[0].attributes[1].value["boolValue"],
+            serde_yaml::Value::Bool(true),
+        );
+    }
+
+    #[test]
+    fn tracer_disabled() {
+        let tracer = Tracer::new(None).unwrap();
+        let mut span = tracer.span("foo");
+        span.set_attribute("bar", Value::Bool(true));
+    }
+}
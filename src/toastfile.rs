@@ -1,10 +1,10 @@
-use crate::{failure::Failure, format, format::CodeStr};
+use crate::{failure::Failure, format, format::CodeStr, glob, tags};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::{self, Display, Formatter};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     env,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 // The default location for commands and files copied into the container
@@ -81,21 +81,182 @@ impl<'de> Deserialize<'de> for MappingPath {
     }
 }
 
+// This struct represents the permission bits of a file. In a toastfile, it's written in octal
+// (e.g., `755`).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct FileMode(pub u32);
+
+impl Display for FileMode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:o}", self.0)
+    }
+}
+
+impl Serialize for FileMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(format!("{}", self).as_str())
+    }
+}
+
+struct FileModeVisitor;
+
+impl<'de> serde::de::Visitor<'de> for FileModeVisitor {
+    type Value = FileMode;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("an octal file mode")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        match u32::from_str_radix(v, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(FileMode(mode)),
+            _ => Err(E::custom(format!(
+                "Illegal file mode {}. Expected an octal number such as {}.",
+                v.code_str(),
+                "755".code_str(),
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FileMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(FileModeVisitor)
+    }
+}
+
+// This enum represents the command of a task. A string is run by a shell, whereas a list is a
+// program and its arguments, which are run directly, so they don't need to be quoted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    Shell(String),
+    Exec(Vec<String>), // Must not be empty [ref:command_nonempty]
+}
+
+impl Command {
+    // Determine whether there's nothing to run.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Shell(command) => command.is_empty(),
+            Self::Exec(args) => args.is_empty(),
+        }
+    }
+
+    // Pass extra arguments (e.g., from the command line) to the command. A list of arguments is
+    // extended with them, and so are the arguments for the entrypoint if `use_entrypoint` is
+    // enabled. A shell command gets them as its positional parameters (i.e., `$@`).
+    pub fn with_arguments(&self, arguments: &[String], use_entrypoint: bool) -> Self {
+        match self {
+            Self::Exec(args) => Self::Exec(args.iter().chain(arguments).cloned().collect()),
+            Self::Shell(command) if use_entrypoint => Self::Exec(
+                Some(command)
+                    .filter(|command| !command.is_empty())
+                    .into_iter()
+                    .chain(arguments)
+                    .cloned()
+                    .collect(),
+            ),
+            Self::Shell(command) => Self::Shell(format!(
+                "set -- {}\n{}",
+                arguments
+                    .iter()
+                    .map(|argument| format!("'{}'", argument.replace('\'', "'\\''")))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                command,
+            )),
+        }
+    }
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Self::Shell(String::new())
+    }
+}
+
+impl Serialize for Command {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Shell(command) => serializer.serialize_str(command),
+            Self::Exec(args) => serializer.collect_seq(args),
+        }
+    }
+}
+
+struct CommandVisitor;
+
+impl<'de> serde::de::Visitor<'de> for CommandVisitor {
+    type Value = Command;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a shell command or a list of arguments")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Command::Shell(v.to_owned()))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut args = vec![];
+        while let Some(arg) = seq.next_element()? {
+            args.push(arg);
+        }
+        Ok(Command::Exec(args))
+    }
+}
+
+impl<'de> Deserialize<'de> for Command {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(CommandVisitor)
+    }
+}
+
 // This struct represents a task.
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Task {
     pub description: Option<String>,
 
+    // Must be valid tags [ref:tags_valid]
+    #[serde(default)]
+    pub tags: Vec<String>,
+
     // Must point to valid task names [ref:dependencies_exist] and the dependency DAG must not form
     // cycles [ref:tasks_dag]
     #[serde(default)]
     pub dependencies: Vec<String>,
 
+    // Must point to valid task names [ref:on_failure_exist]
+    #[serde(default)]
+    pub on_failure: Vec<String>,
+
     // Must be disabled if any of the following conditions hold:
     // - `mount_paths` is nonempty [ref:mount_paths_nand_cache]
     // - `ports` is nonempty [ref:ports_nand_cache]
     // - `extra_docker_arguments` is nonempty [ref:extra_docker_arguments_nand_cache]
+    // - `read_only` is enabled [ref:read_only_nand_cache]
     #[serde(default = "default_task_cache")]
     pub cache: bool,
 
@@ -103,14 +264,54 @@ pub struct Task {
     #[serde(default)] // [tag:default_environment]
     pub environment: HashMap<String, Option<String>>,
 
+    // Must be relative [ref:env_file_relative]
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+
     // Must be relative [ref:input_paths_relative]
     #[serde(default)]
     pub input_paths: Vec<PathBuf>,
 
+    // Must be relative [ref:optional_input_paths_relative]
+    #[serde(default)]
+    pub optional_input_paths: Vec<PathBuf>,
+
+    // Must be relative [ref:input_paths_from_relative]
+    #[serde(default)]
+    pub input_paths_from: Option<PathBuf>,
+
     // Must be relative [ref:excluded_input_paths_relative]
     #[serde(default)]
     pub excluded_input_paths: Vec<PathBuf>,
 
+    // Keys must be relative [ref:generated_input_files_relative] and must not also be copied from
+    //   the host [ref:generated_input_files_unique]
+    #[serde(default)]
+    pub generated_input_files: BTreeMap<PathBuf, String>,
+
+    // Keys must be relative [ref:input_file_modes_relative]
+    #[serde(default)]
+    pub input_file_modes: BTreeMap<PathBuf, FileMode>,
+
+    #[serde(default)]
+    pub preserve_mtime: bool,
+
+    #[serde(default)]
+    pub preserve_permissions: bool,
+
+    // Must be relative [ref:normalize_line_endings_relative]
+    #[serde(default)]
+    pub normalize_line_endings: Vec<PathBuf>,
+
+    #[serde(default)]
+    pub include_vcs: bool,
+
+    #[serde(default)]
+    pub excluded_larger_than: Option<u64>,
+
+    #[serde(default)]
+    pub excluded_mtime_before: Option<u64>,
+
     // Must be relative [ref:output_paths_relative]
     #[serde(default)]
     pub output_paths: Vec<PathBuf>,
@@ -119,6 +320,10 @@ pub struct Task {
     #[serde(default)]
     pub output_paths_on_failure: Vec<PathBuf>,
 
+    // Must be relative [ref:expect_outputs_relative]
+    #[serde(default)]
+    pub expect_outputs: Vec<PathBuf>,
+
     // Can be relative or absolute (absolute paths are allowed in order to support mounting the
     //   Docker socket, which is usually located at `/var/run/docker.sock`)
     // Must not contain `,` [ref:mount_paths_no_commas]
@@ -135,6 +340,22 @@ pub struct Task {
     #[serde(default)] // [tag:default_ports]
     pub ports: Vec<String>,
 
+    // Must be disabled if `cache` is enabled [ref:read_only_nand_cache], and if enabled, the
+    //   location of the input files must be within `writable_paths` [ref:location_writable]
+    #[serde(default)]
+    pub read_only: bool,
+
+    // Relative paths are relative to `location`
+    // Must not contain `,` [ref:writable_paths_no_commas]
+    // Must be empty unless `read_only` is enabled [ref:writable_paths_read_only]
+    #[serde(default)]
+    pub writable_paths: Vec<PathBuf>,
+
+    // Where the input files are copied to in the container, if not `location`
+    // Must be absolute [ref:input_location_absolute]
+    #[serde(default)]
+    pub input_location: Option<PathBuf>,
+
     // Must be absolute [ref:location_absolute]
     #[serde(default = "default_task_location")]
     pub location: PathBuf,
@@ -142,8 +363,21 @@ pub struct Task {
     #[serde(default = "default_task_user")]
     pub user: String,
 
+    // Must be absolute [ref:shell_absolute] and must not be set if `use_entrypoint` is enabled
+    //   [ref:shell_nand_use_entrypoint] or `command` is a list [ref:shell_nand_command_exec]
+    #[serde(default)]
+    pub shell: Option<String>,
+
+    #[serde(default)]
+    pub use_entrypoint: bool,
+
+    #[serde(default)]
+    pub command: Command,
+
+    // Nonzero exit codes which don't count as failures (e.g., for a linter which exits with 1 when
+    // it finds problems)
     #[serde(default)]
-    pub command: String,
+    pub allowed_exit_codes: Vec<u8>,
 
     // Must be empty if `cache` is enabled [ref:extra_docker_arguments_nand_cache]
     #[serde(default)]
@@ -170,8 +404,12 @@ fn default_task_user() -> String {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Toastfile {
+    // If pinned by digest, the digest must be well-formed [ref:image_digest_valid]
     pub image: String,
 
+    #[serde(default)]
+    pub resolve_image_digest: bool,
+
     // If present, must point to a task [ref:valid_default]
     pub default: Option<String>,
 
@@ -184,9 +422,15 @@ pub fn parse(toastfile_data: &str) -> Result<Toastfile, Failure> {
     let toastfile: Toastfile =
         serde_yaml::from_str(toastfile_data).map_err(|e| Failure::User(format!("{}", e), None))?;
 
+    // Make sure the image reference is valid.
+    check_image(&toastfile.image)?;
+
     // Make sure the dependencies are valid.
     check_dependencies(&toastfile)?;
 
+    // Make sure the failure handlers are valid.
+    check_failure_handlers(&toastfile)?;
+
     // Make sure each task is valid.
     for (name, task) in &toastfile.tasks {
         check_task(name, task)?;
@@ -196,6 +440,75 @@ pub fn parse(toastfile_data: &str) -> Result<Toastfile, Failure> {
     Ok(toastfile)
 }
 
+// Parse the contents of a file named by `input_paths_from`, which lists one path per line. Blank
+// lines are skipped. Each path is returned with its line number (starting from 1) for reporting
+// errors.
+pub fn parse_input_paths_from(contents: &str) -> Vec<(usize, PathBuf)> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| (index + 1, PathBuf::from(line)))
+        .collect()
+}
+
+// Return the digest that an image reference is pinned to, if any (e.g., `sha256:…` for
+// `ubuntu@sha256:…`).
+pub fn image_digest(image: &str) -> Option<&str> {
+    image.split_once('@').map(|(_, digest)| digest)
+}
+
+// Return the tag of an image reference, ignoring any digest, or `latest` if it has no tag. Registry
+// hosts can have ports (e.g., `localhost:5000/ubuntu`), so only a colon after the last slash
+// introduces a tag.
+pub fn image_tag(image: &str) -> &str {
+    let name = image.split('@').next().unwrap_or(image);
+    let name = name.rsplit('/').next().unwrap_or(name);
+    name.split_once(':').map_or("latest", |(_, tag)| tag)
+}
+
+// Parse the contents of a file named by `env_file`, which assigns variables in the dotenv format:
+// one `NAME=value` per line, optionally preceded by `export`. Values may be wrapped in single or
+// double quotes, which are removed. Blank lines and lines starting with `#` are skipped.
+pub fn parse_env_file(contents: &str) -> Result<Vec<(String, String)>, Failure> {
+    let mut variables = vec![];
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let assignment = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = match assignment.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => (name.trim(), value.trim()),
+            _ => {
+                return Err(Failure::User(
+                    format!(
+                        "Line {} isn't of the form {}.",
+                        index + 1,
+                        "NAME=value".code_str(),
+                    ),
+                    None,
+                ));
+            }
+        };
+
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|value| value.strip_suffix(*quote))
+            })
+            .unwrap_or(value);
+
+        variables.push((name.to_owned(), value.to_owned()));
+    }
+
+    Ok(variables)
+}
+
 // Fetch the variables for a task from the environment.
 pub fn environment(task: &Task) -> Result<HashMap<String, String>, Vec<&str>> {
     // The result will be a map from variable name to value.
@@ -229,6 +542,56 @@ pub fn environment(task: &Task) -> Result<HashMap<String, String>, Vec<&str>> {
     }
 }
 
+// Determine where the input files of a task are copied to in the container.
+pub fn input_location(task: &Task) -> &Path {
+    task.input_location.as_deref().unwrap_or(&task.location)
+}
+
+// Determine which of the `writable_paths` of a task contains the location of its input files (see
+// `input_location`), if any. The result is an absolute path in the container.
+pub fn location_writable_path(task: &Task) -> Option<PathBuf> {
+    let location = input_location(task);
+
+    task.writable_paths
+        .iter()
+        .map(|path| task.location.join(path))
+        .find(|path| location.starts_with(path))
+}
+
+// Check that the digest of an image reference is well-formed, if it's pinned by one. Only the
+// algorithms registered by the OCI image specification are supported. [tag:image_digest_valid]
+fn check_image(image: &str) -> Result<(), Failure> {
+    if let Some(digest) = image_digest(image) {
+        let is_hex = |encoded: &str, len| {
+            encoded.len() == len
+                && encoded
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+        };
+
+        let valid = match digest.split_once(':') {
+            Some(("sha256", encoded)) => is_hex(encoded, 64),
+            Some(("sha512", encoded)) => is_hex(encoded, 128),
+            _ => false,
+        };
+
+        if !valid {
+            return Err(Failure::User(
+                format!(
+                    "Image {} has an invalid digest. A digest is {} or {} followed by the \
+                     hash in lowercase hexadecimal.",
+                    image.code_str(),
+                    "sha256:".code_str(),
+                    "sha512:".code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 // Check that all dependencies exist and form a DAG (no cycles).
 #[allow(clippy::too_many_lines)]
 fn check_dependencies<'a>(toastfile: &'a Toastfile) -> Result<(), Failure> {
@@ -249,7 +612,7 @@ fn check_dependencies<'a>(toastfile: &'a Toastfile) -> Result<(), Failure> {
                 // [tag:dependencies_exist]
                 violations
                     .entry(task.clone())
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(dependency.clone());
             }
         }
@@ -313,12 +676,8 @@ fn check_dependencies<'a>(toastfile: &'a Toastfile) -> Result<(), Failure> {
         let mut ancestors_set: HashSet<&'a str> = HashSet::new();
         let mut ancestors_stack: Vec<&'a str> = vec![];
 
-        // Keep going as long as there are more nodes to process. [tag:toastfile_frontier_nonempty]
-        while !frontier.is_empty() {
-            // Take the top task from the frontier. This is safe due to
-            // [ref:toastfile_frontier_nonempty].
-            let (task, task_depth) = frontier.pop().unwrap();
-
+        // Keep going as long as there are more nodes to process.
+        while let Some((task, task_depth)) = frontier.pop() {
             // Update the ancestors set and stack.
             for _ in 0..ancestors_stack.len() - task_depth {
                 // The `unwrap` is safe because `ancestors_stack.len()` is positive in every
@@ -382,6 +741,51 @@ fn check_dependencies<'a>(toastfile: &'a Toastfile) -> Result<(), Failure> {
     Ok(())
 }
 
+// Check that the tasks listed in `on_failure` exist.
+fn check_failure_handlers(toastfile: &Toastfile) -> Result<(), Failure> {
+    // Map from task to vector of invalid failure handlers
+    let mut violations: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+
+    for (task, task_data) in &toastfile.tasks {
+        for handler in &task_data.on_failure {
+            if !toastfile.tasks.contains_key(handler) {
+                // [tag:on_failure_exist]
+                violations.entry(task).or_default().push(handler);
+            }
+        }
+    }
+
+    if !violations.is_empty() {
+        return Err(Failure::User(
+            format!(
+                "The following tasks have invalid failure handlers: {}.",
+                format::series(
+                    violations
+                        .iter()
+                        .map(|(task, handlers)| {
+                            format!(
+                                "{} ({})",
+                                task.code_str(),
+                                format::series(
+                                    handlers
+                                        .iter()
+                                        .map(|handler| format!("{}", handler.code_str()))
+                                        .collect::<Vec<_>>()
+                                        .as_ref(),
+                                ),
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .as_ref(),
+                ),
+            ),
+            None,
+        ));
+    }
+
+    Ok(())
+}
+
 // Check if a path is an absolute Linux path.
 #[cfg(unix)]
 fn is_absolute_linux_path(path: &Path) -> bool {
@@ -395,7 +799,7 @@ fn is_absolute_linux_path(path: &Path) -> bool {
 }
 
 // Check if a path is a relative Linux path.
-fn is_relative_linux_path(path: &Path) -> bool {
+pub fn is_relative_linux_path(path: &Path) -> bool {
     path.is_relative() && !path.has_root()
 }
 
@@ -417,6 +821,27 @@ fn check_task(name: &str, task: &Task) -> Result<(), Failure> {
         }
     }
 
+    // Check that the tags can be used in tag expressions. [tag:tags_valid]
+    for tag in &task.tags {
+        if !tags::is_valid(tag) {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has an invalid tag {}. Tags must consist of letters, digits, {}, \
+                     {}, and {}, and they must not be {}, {}, or {}.",
+                    name.code_str(),
+                    tag.code_str(),
+                    "-".code_str(),
+                    "_".code_str(),
+                    ".".code_str(),
+                    "and".code_str(),
+                    "or".code_str(),
+                    "not".code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
     // Check that `input_paths` are relative. [tag:input_paths_relative]
     for path in &task.input_paths {
         if !is_relative_linux_path(path) {
@@ -432,6 +857,51 @@ fn check_task(name: &str, task: &Task) -> Result<(), Failure> {
         }
     }
 
+    // Check that `optional_input_paths` are relative. [tag:optional_input_paths_relative]
+    for path in &task.optional_input_paths {
+        if !is_relative_linux_path(path) {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has an absolute {}: {}.",
+                    name.code_str(),
+                    "optional_input_path".code_str(),
+                    path.to_string_lossy().code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Check that `env_file` is relative. [tag:env_file_relative]
+    if let Some(path) = &task.env_file {
+        if !is_relative_linux_path(path) {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has an absolute {}: {}.",
+                    name.code_str(),
+                    "env_file".code_str(),
+                    path.to_string_lossy().code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Check that `input_paths_from` is relative. [tag:input_paths_from_relative]
+    if let Some(path) = &task.input_paths_from {
+        if !is_relative_linux_path(path) {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has an absolute {}: {}.",
+                    name.code_str(),
+                    "input_paths_from".code_str(),
+                    path.to_string_lossy().code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
     // Check that `excluded_input_paths` are relative. [tag:excluded_input_paths_relative]
     for path in &task.excluded_input_paths {
         if !is_relative_linux_path(path) {
@@ -447,14 +917,15 @@ fn check_task(name: &str, task: &Task) -> Result<(), Failure> {
         }
     }
 
-    // Check that `output_paths` are relative. [tag:output_paths_relative]
-    for path in &task.output_paths {
+    // Check that the paths of `generated_input_files` are relative.
+    // [tag:generated_input_files_relative]
+    for path in task.generated_input_files.keys() {
         if !is_relative_linux_path(path) {
             return Err(Failure::User(
                 format!(
                     "Task {} has an absolute path in {}: {}.",
                     name.code_str(),
-                    "output_paths".code_str(),
+                    "generated_input_files".code_str(),
                     path.to_string_lossy().code_str(),
                 ),
                 None,
@@ -462,14 +933,14 @@ fn check_task(name: &str, task: &Task) -> Result<(), Failure> {
         }
     }
 
-    // Check that `output_paths_on_failure` are relative. [tag:output_paths_on_failure_relative]
-    for path in &task.output_paths_on_failure {
+    // Check that the paths of `input_file_modes` are relative. [tag:input_file_modes_relative]
+    for path in task.input_file_modes.keys() {
         if !is_relative_linux_path(path) {
             return Err(Failure::User(
                 format!(
                     "Task {} has an absolute path in {}: {}.",
                     name.code_str(),
-                    "output_paths_on_failure".code_str(),
+                    "input_file_modes".code_str(),
                     path.to_string_lossy().code_str(),
                 ),
                 None,
@@ -477,34 +948,245 @@ fn check_task(name: &str, task: &Task) -> Result<(), Failure> {
         }
     }
 
-    // Check `mount_paths`.
-    for path in &task.mount_paths {
-        // Check that the path doesn't contain any commas. [tag:mount_paths_no_commas]
-        if path.container_path.to_string_lossy().contains(',')
-            || path.host_path.to_string_lossy().contains(',')
-        {
+    // Check that `normalize_line_endings` are relative. [tag:normalize_line_endings_relative]
+    for path in &task.normalize_line_endings {
+        if !is_relative_linux_path(path) {
             return Err(Failure::User(
                 format!(
-                    "Mount path {} of task {} has a {}.",
-                    format!("{}", path).code_str(),
+                    "Task {} has an absolute path in {}: {}.",
                     name.code_str(),
-                    ",".code_str(),
+                    "normalize_line_endings".code_str(),
+                    path.to_string_lossy().code_str(),
                 ),
                 None,
             ));
         }
     }
 
-    // Check that `location` is absolute. [tag:location_absolute]
-    if !is_absolute_linux_path(&task.location) {
-        return Err(Failure::User(
-            format!(
-                "Task {} has a relative {}: {}.",
-                name.code_str(),
-                "location".code_str(),
-                task.location.to_string_lossy().code_str(),
-            ),
-            None,
+    // Check that `output_paths` are relative. [tag:output_paths_relative]
+    for path in &task.output_paths {
+        if !is_relative_linux_path(path) {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has an absolute path in {}: {}.",
+                    name.code_str(),
+                    "output_paths".code_str(),
+                    path.to_string_lossy().code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Check that `output_paths_on_failure` are relative. [tag:output_paths_on_failure_relative]
+    for path in &task.output_paths_on_failure {
+        if !is_relative_linux_path(path) {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has an absolute path in {}: {}.",
+                    name.code_str(),
+                    "output_paths_on_failure".code_str(),
+                    path.to_string_lossy().code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Check that output patterns start with a directory. Everything under the part of a pattern
+    // before the first wildcard is copied out of the container to be matched on the host, so
+    // without such a directory, the whole `location` would be copied.
+    for (field, paths) in &[
+        ("output_paths", &task.output_paths),
+        ("output_paths_on_failure", &task.output_paths_on_failure),
+    ] {
+        for path in paths.iter() {
+            let (prefix, pattern) = glob::split_literal_prefix(path);
+            if pattern.is_some()
+                && !prefix
+                    .components()
+                    .any(|component| matches!(component, Component::Normal(_)))
+            {
+                return Err(Failure::User(
+                    format!(
+                        "Task {} has a pattern in {} which doesn't start with a directory: {}. \
+                         Put the files in a directory and match them there, e.g., {}.",
+                        name.code_str(),
+                        field.code_str(),
+                        path.to_string_lossy().code_str(),
+                        Path::new("dist").join(path).to_string_lossy().code_str(),
+                    ),
+                    None,
+                ));
+            }
+        }
+    }
+
+    // Check that `expect_outputs` are relative. [tag:expect_outputs_relative]
+    for path in &task.expect_outputs {
+        if !is_relative_linux_path(path) {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has an absolute path in {}: {}.",
+                    name.code_str(),
+                    "expect_outputs".code_str(),
+                    path.to_string_lossy().code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Check `mount_paths`.
+    for path in &task.mount_paths {
+        // Check that the path doesn't contain any commas. [tag:mount_paths_no_commas]
+        if path.container_path.to_string_lossy().contains(',')
+            || path.host_path.to_string_lossy().contains(',')
+        {
+            return Err(Failure::User(
+                format!(
+                    "Mount path {} of task {} has a {}.",
+                    format!("{}", path).code_str(),
+                    name.code_str(),
+                    ",".code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Check `writable_paths`.
+    for path in &task.writable_paths {
+        // Check that the path doesn't contain any commas. [tag:writable_paths_no_commas]
+        if path.to_string_lossy().contains(',') {
+            return Err(Failure::User(
+                format!(
+                    "Writable path {} of task {} has a {}.",
+                    path.to_string_lossy().code_str(),
+                    name.code_str(),
+                    ",".code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Check that `writable_paths` are only given for tasks with a read-only root filesystem, since
+    // they're only needed then. [tag:writable_paths_read_only]
+    if !task.writable_paths.is_empty() && !task.read_only {
+        return Err(Failure::User(
+            format!(
+                "Task {} has {} but does not enable {}.",
+                name.code_str(),
+                "writable_paths".code_str(),
+                "read_only".code_str(),
+            ),
+            None,
+        ));
+    }
+
+    // Check that `location` is absolute. [tag:location_absolute]
+    if !is_absolute_linux_path(&task.location) {
+        return Err(Failure::User(
+            format!(
+                "Task {} has a relative {}: {}.",
+                name.code_str(),
+                "location".code_str(),
+                task.location.to_string_lossy().code_str(),
+            ),
+            None,
+        ));
+    }
+
+    // Check that `input_location` is absolute. [tag:input_location_absolute]
+    if let Some(input_location) = &task.input_location {
+        if !is_absolute_linux_path(input_location) {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has a relative {}: {}.",
+                    name.code_str(),
+                    "input_location".code_str(),
+                    input_location.to_string_lossy().code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Check that the input files can be copied into a task with a read-only root filesystem, which
+    // Docker only allows for paths within volumes. [tag:location_writable]
+    if task.read_only && location_writable_path(task).is_none() {
+        return Err(Failure::User(
+            format!(
+                "Task {} enables {}, so its {} ({}) must be within one of its {}.",
+                name.code_str(),
+                "read_only".code_str(),
+                if task.input_location.is_some() {
+                    "input_location"
+                } else {
+                    "location"
+                }
+                .code_str(),
+                input_location(task).to_string_lossy().code_str(),
+                "writable_paths".code_str(),
+            ),
+            None,
+        ));
+    }
+
+    if let Some(shell) = &task.shell {
+        // Check that `shell` is absolute. [tag:shell_absolute]
+        if !is_absolute_linux_path(Path::new(shell)) {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has a relative {}: {}.",
+                    name.code_str(),
+                    "shell".code_str(),
+                    shell.code_str(),
+                ),
+                None,
+            ));
+        }
+
+        // Check that `shell` isn't set along with `use_entrypoint`, since the command doesn't run
+        // in a shell then. [tag:shell_nand_use_entrypoint]
+        if task.use_entrypoint {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has a {} but also enables {}.",
+                    name.code_str(),
+                    "shell".code_str(),
+                    "use_entrypoint".code_str(),
+                ),
+                None,
+            ));
+        }
+
+        // Check that `shell` isn't set along with a list of arguments for `command`, since the
+        // command doesn't run in a shell then either. [tag:shell_nand_command_exec]
+        if let Command::Exec(_) = task.command {
+            return Err(Failure::User(
+                format!(
+                    "Task {} has a {} but its {} is a list of arguments.",
+                    name.code_str(),
+                    "shell".code_str(),
+                    "command".code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    // Check that a list of arguments for `command` isn't empty, since then there would be no
+    // program to run. An empty string is used for no command. [tag:command_nonempty]
+    if task.command == Command::Exec(vec![]) {
+        return Err(Failure::User(
+            format!(
+                "Task {} has an empty list of arguments for {}.",
+                name.code_str(),
+                "command".code_str(),
+            ),
+            None,
         ));
     }
 
@@ -549,6 +1231,21 @@ fn check_task(name: &str, task: &Task) -> Result<(), Failure> {
         ));
     }
 
+    // If a task has a read-only root filesystem, then caching should be disabled, since what it
+    // writes to `writable_paths` isn't part of the image. [tag:read_only_nand_cache]
+    if task.read_only && task.cache {
+        return Err(Failure::User(
+            format!(
+                "Task {} enables {} but does not disable caching. \
+                 To fix this, set {} for this task.",
+                name.code_str(),
+                "read_only".code_str(),
+                "cache: false".code_str(),
+            ),
+            None,
+        ));
+    }
+
     // If we made it this far, the task is valid.
     Ok(())
 }
@@ -556,10 +1253,15 @@ fn check_task(name: &str, task: &Task) -> Result<(), Failure> {
 #[cfg(test)]
 mod tests {
     use crate::toastfile::{
-        check_dependencies, check_task, environment, parse, MappingPath, Task, Toastfile,
+        check_dependencies, check_task, environment, image_digest, image_tag, parse,
+        parse_env_file, parse_input_paths_from, Command, FileMode, MappingPath, Task, Toastfile,
         DEFAULT_LOCATION, DEFAULT_USER,
     };
-    use std::{collections::HashMap, env, path::Path};
+    use std::{
+        collections::{BTreeMap, HashMap},
+        env,
+        path::Path,
+    };
 
     #[test]
     fn parse_empty() {
@@ -571,6 +1273,7 @@ tasks: {}
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks: HashMap::new(),
         };
@@ -592,25 +1295,112 @@ tasks:
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
+                dependencies: vec![],
+                on_failure: vec![],
+                cache: true,
+                environment: HashMap::new(),
+                env_file: None,
+                input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
+                excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                expect_outputs: vec![],
+                mount_paths: vec![],
+                mount_readonly: false,
+                ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
+                location: Path::new(DEFAULT_LOCATION).to_owned(),
+                user: DEFAULT_USER.to_owned(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
+                extra_docker_arguments: vec![],
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
+            default: None,
+            tasks,
+        };
+
+        assert_eq!(parse(input).unwrap(), toastfile);
+    }
+
+    #[test]
+    fn parse_command_exec() {
+        let input = r#"
+image: encom:os-12
+tasks:
+  foo:
+    command: [python, script.py, "arg with spaces"]
+    "#
+        .trim();
+
+        let mut tasks = HashMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                tags: vec![],
                 dependencies: vec![],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::Exec(vec![
+                    "python".to_owned(),
+                    "script.py".to_owned(),
+                    "arg with spaces".to_owned(),
+                ]),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -623,26 +1413,48 @@ tasks:
     fn parse_comprehensive_task() {
         let input = r#"
 image: encom:os-12
+resolve_image_digest: true
 default: bar
 tasks:
   foo: {}
   bar:
     description: Reticulate splines.
+    tags:
+      - plugh
+      - thud
     dependencies:
       - foo
+    on_failure:
+      - foo
     cache: false
     environment:
       SPAM: monty
       HAM: null
       EGGS: null
+    env_file: .env
     input_paths:
       - qux
       - quux
       - quuz
+    optional_input_paths:
+      - '**/*.patch'
+    input_paths_from: paths.txt
     excluded_input_paths:
       - spam
       - ham
       - eggs
+    generated_input_files:
+      version.txt: 1.2.3
+    input_file_modes:
+      build.sh: 755
+      secret.txt: '0600'
+    preserve_mtime: true
+    preserve_permissions: true
+    normalize_line_endings:
+      - '**/*.txt'
+    include_vcs: true
+    excluded_larger_than: 1048576
+    excluded_mtime_before: 1700000000
     output_paths:
       - corge
       - grault
@@ -651,6 +1463,8 @@ tasks:
       - fnord
       - smurf
       - xyzzy
+    expect_outputs:
+      - corge/thud
     mount_paths:
       - wibble
       - /wobble
@@ -660,9 +1474,15 @@ tasks:
       - 3000
       - 3001
       - 3002
+    read_only: true
+    writable_paths:
+      - /code
+    input_location: /code/src
     location: /code
     user: waldo
+    shell: /bin/bash
     command: flob
+    allowed_exit_codes: [1, 2]
     extra_docker_arguments:
       - --cpus
       - '4'
@@ -674,24 +1494,51 @@ tasks:
         environment.insert("HAM".to_owned(), None);
         environment.insert("EGGS".to_owned(), None);
 
+        let mut generated_input_files = BTreeMap::new();
+        generated_input_files.insert(Path::new("version.txt").to_owned(), "1.2.3".to_owned());
+
+        let mut input_file_modes = BTreeMap::new();
+        input_file_modes.insert(Path::new("build.sh").to_owned(), FileMode(0o755));
+        input_file_modes.insert(Path::new("secret.txt").to_owned(), FileMode(0o600));
+
         let mut tasks = HashMap::new();
         tasks.insert(
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec![],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
@@ -699,19 +1546,32 @@ tasks:
             "bar".to_owned(),
             Task {
                 description: Some("Reticulate splines.".to_owned()),
+                tags: vec!["plugh".to_owned(), "thud".to_owned()],
                 dependencies: vec!["foo".to_owned()],
+                on_failure: vec!["foo".to_owned()],
                 cache: false,
                 environment,
+                env_file: Some(Path::new(".env").to_owned()),
                 input_paths: vec![
                     Path::new("qux").to_owned(),
                     Path::new("quux").to_owned(),
                     Path::new("quuz").to_owned(),
                 ],
+                optional_input_paths: vec![Path::new("**/*.patch").to_owned()],
+                input_paths_from: Some(Path::new("paths.txt").to_owned()),
                 excluded_input_paths: vec![
                     Path::new("spam").to_owned(),
                     Path::new("ham").to_owned(),
                     Path::new("eggs").to_owned(),
                 ],
+                generated_input_files,
+                input_file_modes,
+                preserve_mtime: true,
+                preserve_permissions: true,
+                normalize_line_endings: vec![Path::new("**/*.txt").to_owned()],
+                include_vcs: true,
+                excluded_larger_than: Some(1_048_576),
+                excluded_mtime_before: Some(1_700_000_000),
                 output_paths: vec![
                     Path::new("corge").to_owned(),
                     Path::new("grault").to_owned(),
@@ -722,6 +1582,7 @@ tasks:
                     Path::new("smurf").to_owned(),
                     Path::new("xyzzy").to_owned(),
                 ],
+                expect_outputs: vec![Path::new("corge/thud").to_owned()],
                 mount_paths: vec![
                     MappingPath {
                         host_path: Path::new("wibble").to_owned(),
@@ -738,15 +1599,22 @@ tasks:
                 ],
                 mount_readonly: true,
                 ports: vec!["3000".to_owned(), "3001".to_owned(), "3002".to_owned()],
+                read_only: true,
+                writable_paths: vec![Path::new("/code").to_owned()],
+                input_location: Some(Path::new("/code/src").to_owned()),
                 location: Path::new("/code").to_owned(),
                 user: "waldo".to_owned(),
-                command: "flob".to_owned(),
+                shell: Some("/bin/bash".to_owned()),
+                use_entrypoint: false,
+                command: Command::Shell("flob".to_owned()),
+                allowed_exit_codes: vec![1, 2],
                 extra_docker_arguments: vec!["--cpus".to_owned(), "4".to_owned()],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: true,
             default: Some("bar".to_owned()),
             tasks,
         };
@@ -755,55 +1623,245 @@ tasks:
     }
 
     #[test]
-    fn environment_empty() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: Path::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command: String::new(),
-            extra_docker_arguments: vec![],
-        };
+    fn parse_invalid_file_mode() {
+        let input = r#"
+image: encom:os-12
+tasks:
+  foo:
+    input_file_modes:
+      build.sh: rwx
+    "#
+        .trim();
 
-        assert_eq!(environment(&task), Ok(HashMap::new()));
+        assert!(parse(input).is_err());
     }
 
     #[test]
-    fn environment_default_overridden() {
-        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
-        // tests clobbering environment variables used by other threads.
-        let mut env_map = HashMap::new();
-        env_map.insert("foo1".to_owned(), Some("bar".to_owned()));
+    fn parse_invalid_failure_handler() {
+        let input = r#"
+image: encom:os-12
+tasks:
+  foo:
+    on_failure:
+      - bar
+    "#
+        .trim();
 
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: env_map,
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: Path::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command: String::new(),
-            extra_docker_arguments: vec![],
-        };
+        let result = parse(input);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("bar"));
+    }
 
-        let mut expected = HashMap::new();
-        expected.insert("foo1".to_owned(), "baz".to_owned());
+    #[test]
+    fn parse_image_digest() {
+        let input = format!(
+            "image: encom:os-12@sha256:{}\ntasks: {{}}",
+            "0123456789abcdef".repeat(4),
+        );
+
+        assert!(parse(&input).is_ok());
+    }
+
+    #[test]
+    fn parse_invalid_image_digest() {
+        for digest in [
+            "sha256:0123456789abcdef",
+            &format!("sha256:{}", "0123456789ABCDEF".repeat(4)),
+            &format!("md5:{}", "0123456789abcdef".repeat(4)),
+            "latest",
+        ] {
+            let input = format!("image: encom:os-12@{}\ntasks: {{}}", digest);
+
+            let result = parse(&input);
+            assert!(result.is_err());
+            assert!(result.unwrap_err().to_string().contains("digest"));
+        }
+    }
+
+    #[test]
+    fn image_digest_pinned() {
+        assert_eq!(image_digest("encom@sha256:abc"), Some("sha256:abc"));
+        assert_eq!(image_digest("encom:os-12"), None);
+    }
+
+    #[test]
+    fn image_tag_explicit() {
+        assert_eq!(image_tag("encom:os-12"), "os-12");
+        assert_eq!(image_tag("localhost:5000/encom:os-12"), "os-12");
+        assert_eq!(image_tag("encom:os-12@sha256:abc"), "os-12");
+    }
+
+    #[test]
+    fn image_tag_implicit() {
+        assert_eq!(image_tag("encom"), "latest");
+        assert_eq!(image_tag("localhost:5000/encom"), "latest");
+        assert_eq!(image_tag("encom@sha256:abc"), "latest");
+    }
+
+    #[test]
+    fn parse_env_file_assignments() {
+        let contents = "
+# Comment
+FOO=foo
+export BAR = bar baz
+
+QUX=\"qux # not a comment\"
+QUUX='quux'
+CORGE=
+";
+
+        assert_eq!(
+            parse_env_file(contents).unwrap(),
+            vec![
+                ("FOO".to_owned(), "foo".to_owned()),
+                ("BAR".to_owned(), "bar baz".to_owned()),
+                ("QUX".to_owned(), "qux # not a comment".to_owned()),
+                ("QUUX".to_owned(), "quux".to_owned()),
+                ("CORGE".to_owned(), String::new()),
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_env_file_invalid() {
+        let result = parse_env_file("FOO=foo\nBAR\n");
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Line 2"));
+
+        assert!(parse_env_file("=foo").is_err());
+    }
+
+    #[test]
+    fn command_with_arguments_shell() {
+        assert_eq!(
+            Command::Shell("echo \"$@\"".to_owned())
+                .with_arguments(&["foo bar".to_owned(), "it's".to_owned()], false),
+            Command::Shell("set -- 'foo bar' 'it'\\''s'\necho \"$@\"".to_owned()),
+        );
+    }
+
+    #[test]
+    fn command_with_arguments_exec() {
+        assert_eq!(
+            Command::Exec(vec!["echo".to_owned()]).with_arguments(&["foo bar".to_owned()], false),
+            Command::Exec(vec!["echo".to_owned(), "foo bar".to_owned()]),
+        );
+    }
+
+    #[test]
+    fn command_with_arguments_entrypoint() {
+        assert_eq!(
+            Command::Shell("foo".to_owned()).with_arguments(&["bar".to_owned()], true),
+            Command::Exec(vec!["foo".to_owned(), "bar".to_owned()]),
+        );
+        assert_eq!(
+            Command::default().with_arguments(&["bar".to_owned()], true),
+            Command::Exec(vec!["bar".to_owned()]),
+        );
+    }
+
+    #[test]
+    fn parse_input_paths_from_lines() {
+        assert_eq!(
+            parse_input_paths_from("foo.txt\n\n  \nbar/baz.rs\r\nqux\n"),
+            vec![
+                (1, Path::new("foo.txt").to_owned()),
+                (4, Path::new("bar/baz.rs").to_owned()),
+                (5, Path::new("qux").to_owned()),
+            ],
+        );
+    }
+
+    #[test]
+    fn environment_empty() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        assert_eq!(environment(&task), Ok(HashMap::new()));
+    }
+
+    #[test]
+    fn environment_default_overridden() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo1".to_owned(), Some("bar".to_owned()));
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: env_map,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let mut expected = HashMap::new();
+        expected.insert("foo1".to_owned(), "baz".to_owned());
 
         env::set_var("foo1", "baz");
         assert_eq!(env::var("foo1"), Ok("baz".to_owned()));
@@ -819,19 +1877,39 @@ tasks:
 
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: env_map,
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -852,19 +1930,39 @@ tasks:
 
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: env_map,
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -875,6 +1973,59 @@ tasks:
         assert_eq!(result.unwrap_err()[0].to_owned(), "foo3");
     }
 
+    #[test]
+    fn environment_undeclared() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo4".to_owned(), None);
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: env_map,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let mut expected = HashMap::new();
+        expected.insert("foo4".to_owned(), "bar".to_owned());
+
+        env::set_var("foo4", "bar");
+        env::set_var("foo5", "baz");
+        assert_eq!(environment(&task), Ok(expected));
+    }
+
     #[test]
     fn check_dependencies_valid_default() {
         let mut tasks = HashMap::new();
@@ -882,25 +2033,46 @@ tasks:
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec![],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: Some("foo".to_owned()),
             tasks,
         };
@@ -915,25 +2087,46 @@ tasks:
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec![],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: Some("bar".to_owned()),
             tasks,
         };
@@ -947,6 +2140,7 @@ tasks:
     fn check_dependencies_empty() {
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks: HashMap::new(),
         };
@@ -961,25 +2155,46 @@ tasks:
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec![],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -994,19 +2209,39 @@ tasks:
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec![],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
@@ -1014,25 +2249,46 @@ tasks:
             "bar".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec!["foo".to_owned()],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -1047,19 +2303,39 @@ tasks:
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec![],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
@@ -1067,25 +2343,46 @@ tasks:
             "bar".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec!["foo".to_owned(), "baz".to_owned()],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -1102,25 +2399,46 @@ tasks:
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec!["foo".to_owned()],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -1137,19 +2455,39 @@ tasks:
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec!["bar".to_owned()],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
@@ -1157,25 +2495,46 @@ tasks:
             "bar".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec!["foo".to_owned()],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -1192,19 +2551,39 @@ tasks:
             "foo".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec!["baz".to_owned()],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
@@ -1212,19 +2591,39 @@ tasks:
             "bar".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec!["foo".to_owned()],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
@@ -1232,25 +2631,46 @@ tasks:
             "baz".to_owned(),
             Task {
                 description: None,
+                tags: vec![],
                 dependencies: vec!["bar".to_owned()],
+                on_failure: vec![],
                 cache: true,
                 environment: HashMap::new(),
+                env_file: None,
                 input_paths: vec![],
+                optional_input_paths: vec![],
+                input_paths_from: None,
                 excluded_input_paths: vec![],
+                generated_input_files: BTreeMap::new(),
+                input_file_modes: BTreeMap::new(),
+                preserve_mtime: false,
+                preserve_permissions: false,
+                normalize_line_endings: vec![],
+                include_vcs: false,
+                excluded_larger_than: None,
+                excluded_mtime_before: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                expect_outputs: vec![],
                 mount_paths: vec![],
                 mount_readonly: false,
                 ports: vec![],
+                read_only: false,
+                writable_paths: vec![],
+                input_location: None,
                 location: Path::new(DEFAULT_LOCATION).to_owned(),
                 user: DEFAULT_USER.to_owned(),
-                command: String::new(),
+                shell: None,
+                use_entrypoint: false,
+                command: Command::default(),
+                allowed_exit_codes: vec![],
                 extra_docker_arguments: vec![],
             },
         );
 
         let toastfile = Toastfile {
             image: "encom:os-12".to_owned(),
+            resolve_image_digest: false,
             default: None,
             tasks,
         };
@@ -1261,27 +2681,695 @@ tasks:
     }
 
     #[test]
-    fn check_task_environment_ok() {
-        let mut environment = HashMap::new();
-        environment.insert("corge".to_owned(), None);
-        environment.insert("grault".to_owned(), None);
-        environment.insert("garply".to_owned(), None);
-
+    fn check_task_environment_ok() {
+        let mut environment = HashMap::new();
+        environment.insert("corge".to_owned(), None);
+        environment.insert("grault".to_owned(), None);
+        environment.insert("garply".to_owned(), None);
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        assert!(check_task("foo", &task).is_ok());
+    }
+
+    #[test]
+    fn check_task_environment_equals() {
+        let mut environment = HashMap::new();
+        environment.insert("corge".to_owned(), None);
+        environment.insert("gra=ult".to_owned(), None);
+        environment.insert("garply".to_owned(), None);
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment,
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains('='));
+    }
+
+    #[test]
+    fn check_task_tags_invalid() {
+        let task = Task {
+            description: None,
+            tags: vec!["lint".to_owned(), "not".to_owned()],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("not"));
+    }
+
+    #[test]
+    fn check_task_paths_ok() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![Path::new("bar").to_owned()],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![Path::new("baz").to_owned()],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![Path::new("qux").to_owned()],
+            output_paths_on_failure: vec![Path::new("quux").to_owned()],
+            expect_outputs: vec![],
+            mount_paths: vec![
+                MappingPath {
+                    host_path: Path::new("quuy").to_owned(),
+                    container_path: Path::new("quuz").to_owned(),
+                },
+                MappingPath {
+                    host_path: Path::new("quuy").to_owned(),
+                    container_path: Path::new("/quuz").to_owned(),
+                },
+                MappingPath {
+                    host_path: Path::new("quuy").to_owned(),
+                    container_path: Path::new("/quuz").to_owned(),
+                },
+                MappingPath {
+                    host_path: Path::new("/quuy").to_owned(),
+                    container_path: Path::new("/quuz").to_owned(),
+                },
+            ],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        assert!(check_task("foo", &task).is_ok());
+    }
+
+    #[test]
+    fn check_task_paths_absolute_input_paths() {
+        #[cfg(unix)]
+        let absolute_path = "/bar";
+
+        #[cfg(windows)]
+        let absolute_path = "C:\\bar";
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![Path::new(absolute_path).to_owned()],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains(absolute_path));
+    }
+
+    #[test]
+    fn check_task_paths_absolute_optional_input_paths() {
+        #[cfg(unix)]
+        let absolute_path = "/bar";
+
+        #[cfg(windows)]
+        let absolute_path = "C:\\bar";
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![Path::new(absolute_path).to_owned()],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains(absolute_path));
+    }
+
+    #[test]
+    fn check_task_paths_absolute_input_paths_from() {
+        #[cfg(unix)]
+        let absolute_path = "/bar";
+
+        #[cfg(windows)]
+        let absolute_path = "C:\\bar";
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: Some(Path::new(absolute_path).to_owned()),
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains(absolute_path));
+    }
+
+    #[test]
+    fn check_task_paths_absolute_excluded_input_paths() {
+        #[cfg(unix)]
+        let absolute_path = "/bar";
+
+        #[cfg(windows)]
+        let absolute_path = "C:\\bar";
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![Path::new(absolute_path).to_owned()],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains(absolute_path));
+    }
+
+    #[test]
+    fn check_task_paths_absolute_generated_input_files() {
+        #[cfg(unix)]
+        let absolute_path = "/bar";
+
+        #[cfg(windows)]
+        let absolute_path = "C:\\bar";
+
+        let mut generated_input_files = BTreeMap::new();
+        generated_input_files.insert(Path::new(absolute_path).to_owned(), "baz".to_owned());
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files,
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains(absolute_path));
+    }
+
+    #[test]
+    fn check_task_paths_absolute_output_paths() {
+        #[cfg(unix)]
+        let absolute_path = "/bar";
+
+        #[cfg(windows)]
+        let absolute_path = "C:\\bar";
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![Path::new(absolute_path).to_owned()],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains(absolute_path));
+    }
+
+    #[test]
+    fn check_task_paths_absolute_output_paths_on_failure() {
+        #[cfg(unix)]
+        let absolute_path = "/bar";
+
+        #[cfg(windows)]
+        let absolute_path = "C:\\bar";
+
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![Path::new(absolute_path).to_owned()],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains(absolute_path));
+    }
+
+    #[test]
+    fn check_task_output_pattern_without_directory() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![Path::new("*.tar.gz").to_owned()],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("*.tar.gz"));
+    }
+
+    #[test]
+    fn check_task_output_pattern_current_directory() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![Path::new("./**/*.xml").to_owned()],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("./**/*.xml"));
+    }
+
+    #[test]
+    fn check_task_output_pattern_with_directory() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
-            cache: true,
-            environment,
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
-            output_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![Path::new("dist/*.tar.gz").to_owned()],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -1289,272 +3377,417 @@ tasks:
     }
 
     #[test]
-    fn check_task_environment_equals() {
-        let mut environment = HashMap::new();
-        environment.insert("corge".to_owned(), None);
-        environment.insert("gra=ult".to_owned(), None);
-        environment.insert("garply".to_owned(), None);
+    fn check_task_paths_absolute_expect_outputs() {
+        #[cfg(unix)]
+        let absolute_path = "/bar";
+
+        #[cfg(windows)]
+        let absolute_path = "C:\\bar";
 
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
-            cache: true,
-            environment,
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![Path::new(absolute_path).to_owned()],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let result = check_task("foo", &task);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains('='));
+        assert!(result.unwrap_err().to_string().contains(absolute_path));
     }
 
     #[test]
-    fn check_task_paths_ok() {
+    fn check_task_paths_mount_paths_comma() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
-            cache: false,
+            on_failure: vec![],
+            cache: true,
             environment: HashMap::new(),
-            input_paths: vec![Path::new("bar").to_owned()],
-            excluded_input_paths: vec![Path::new("baz").to_owned()],
-            output_paths: vec![Path::new("qux").to_owned()],
-            output_paths_on_failure: vec![Path::new("quux").to_owned()],
-            mount_paths: vec![
-                MappingPath {
-                    host_path: Path::new("quuy").to_owned(),
-                    container_path: Path::new("quuz").to_owned(),
-                },
-                MappingPath {
-                    host_path: Path::new("quuy").to_owned(),
-                    container_path: Path::new("/quuz").to_owned(),
-                },
-                MappingPath {
-                    host_path: Path::new("quuy").to_owned(),
-                    container_path: Path::new("/quuz").to_owned(),
-                },
-                MappingPath {
-                    host_path: Path::new("/quuy").to_owned(),
-                    container_path: Path::new("/quuz").to_owned(),
-                },
-            ],
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![MappingPath {
+                host_path: Path::new("bar,baz").to_owned(),
+                container_path: Path::new("bar,baz").to_owned(),
+            }],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
-        assert!(check_task("foo", &task).is_ok());
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("bar,baz"));
     }
 
     #[test]
-    fn check_task_paths_absolute_input_paths() {
-        #[cfg(unix)]
-        let absolute_path = "/bar";
-
-        #[cfg(windows)]
-        let absolute_path = "C:\\bar";
-
+    fn check_task_paths_relative_location() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
-            input_paths: vec![Path::new(absolute_path).to_owned()],
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
-            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new("code").to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let result = check_task("foo", &task);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains(absolute_path));
+        assert!(result.unwrap_err().to_string().contains("code"));
     }
 
     #[test]
-    fn check_task_paths_absolute_excluded_input_paths() {
-        #[cfg(unix)]
-        let absolute_path = "/bar";
-
-        #[cfg(windows)]
-        let absolute_path = "C:\\bar";
-
+    fn check_task_paths_relative_input_location() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
-            excluded_input_paths: vec![Path::new(absolute_path).to_owned()],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: Some(Path::new("code").to_owned()),
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let result = check_task("foo", &task);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains(absolute_path));
+        assert!(result.unwrap_err().to_string().contains("code"));
     }
 
     #[test]
-    fn check_task_paths_absolute_output_paths() {
-        #[cfg(unix)]
-        let absolute_path = "/bar";
-
-        #[cfg(windows)]
-        let absolute_path = "C:\\bar";
-
+    fn check_task_relative_shell() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
-            cache: false,
+            on_failure: vec![],
+            cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
-            output_paths: vec![Path::new(absolute_path).to_owned()],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: Some("bin/bash".to_owned()),
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let result = check_task("foo", &task);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains(absolute_path));
+        assert!(result.unwrap_err().to_string().contains("bin/bash"));
     }
 
     #[test]
-    fn check_task_paths_absolute_output_paths_on_failure() {
-        #[cfg(unix)]
-        let absolute_path = "/bar";
-
-        #[cfg(windows)]
-        let absolute_path = "C:\\bar";
-
+    fn check_task_shell_with_use_entrypoint() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
-            cache: false,
+            on_failure: vec![],
+            cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
-            output_paths_on_failure: vec![Path::new(absolute_path).to_owned()],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: Some("/bin/bash".to_owned()),
+            use_entrypoint: true,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let result = check_task("foo", &task);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains(absolute_path));
+        assert!(result.unwrap_err().to_string().contains("use_entrypoint"));
     }
 
     #[test]
-    fn check_task_paths_mount_paths_comma() {
+    fn check_task_shell_with_command_exec() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
-            mount_paths: vec![MappingPath {
-                host_path: Path::new("bar,baz").to_owned(),
-                container_path: Path::new("bar,baz").to_owned(),
-            }],
+            expect_outputs: vec![],
+            mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: Some("/bin/bash".to_owned()),
+            use_entrypoint: false,
+            command: Command::Exec(vec!["flob".to_owned()]),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let result = check_task("foo", &task);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("bar,baz"));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("list of arguments"));
     }
 
     #[test]
-    fn check_task_paths_relative_location() {
+    fn check_task_command_exec_empty() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
-            location: Path::new("code").to_owned(),
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::Exec(vec![]),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
         let result = check_task("foo", &task);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("code"));
+        assert!(result.unwrap_err().to_string().contains("empty list"));
     }
 
     #[test]
     fn check_task_caching_enabled_with_mount_paths() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![MappingPath {
                 host_path: Path::new("bar").to_owned(),
                 container_path: Path::new("bar").to_owned(),
             }],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -1567,22 +3800,42 @@ tasks:
     fn check_task_caching_disabled_with_mount_paths() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: false,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![MappingPath {
                 host_path: Path::new("bar").to_owned(),
                 container_path: Path::new("bar").to_owned(),
             }],
             mount_readonly: false,
             ports: vec!["3000:80".to_owned()],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -1593,19 +3846,39 @@ tasks:
     fn check_task_caching_enabled_with_ports() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec!["3000:80".to_owned()],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -1618,19 +3891,39 @@ tasks:
     fn check_task_caching_disabled_with_ports() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: false,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec!["3000:80".to_owned()],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec![],
         };
 
@@ -1641,19 +3934,39 @@ tasks:
     fn check_task_caching_enabled_with_extra_docker_arguments() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: true,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec!["--cpus".to_owned(), "4".to_owned()],
         };
 
@@ -1666,22 +3979,313 @@ tasks:
     fn check_task_caching_disabled_with_extra_docker_arguments() {
         let task = Task {
             description: None,
+            tags: vec![],
             dependencies: vec![],
+            on_failure: vec![],
             cache: false,
             environment: HashMap::new(),
+            env_file: None,
             input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
             excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            expect_outputs: vec![],
             mount_paths: vec![],
             mount_readonly: false,
             ports: vec![],
+            read_only: false,
+            writable_paths: vec![],
+            input_location: None,
             location: Path::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
-            command: String::new(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
             extra_docker_arguments: vec!["--cpus".to_owned(), "4".to_owned()],
         };
 
         assert!(check_task("foo", &task).is_ok());
     }
+
+    #[test]
+    fn check_task_read_only_ok() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: true,
+            writable_paths: vec![Path::new(".").to_owned(), Path::new("/tmp").to_owned()],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        assert!(check_task("foo", &task).is_ok());
+    }
+
+    #[test]
+    fn check_task_read_only_location_not_writable() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: true,
+            writable_paths: vec![Path::new("/tmp").to_owned()],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("location"));
+    }
+
+    #[test]
+    fn check_task_read_only_input_location_not_writable() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: true,
+            writable_paths: vec![Path::new("/tmp").to_owned()],
+            input_location: Some(Path::new("/src").to_owned()),
+            location: Path::new("/tmp").to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("input_location"));
+    }
+
+    #[test]
+    fn check_task_writable_paths_without_read_only() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: false,
+            writable_paths: vec![Path::new("/tmp").to_owned()],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("read_only"));
+    }
+
+    #[test]
+    fn check_task_writable_paths_comma() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: false,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: true,
+            writable_paths: vec![
+                Path::new(DEFAULT_LOCATION).to_owned(),
+                Path::new("/tmp,foo").to_owned(),
+            ],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains(','));
+    }
+
+    #[test]
+    fn check_task_caching_enabled_with_read_only() {
+        let task = Task {
+            description: None,
+            tags: vec![],
+            dependencies: vec![],
+            on_failure: vec![],
+            cache: true,
+            environment: HashMap::new(),
+            env_file: None,
+            input_paths: vec![],
+            optional_input_paths: vec![],
+            input_paths_from: None,
+            excluded_input_paths: vec![],
+            generated_input_files: BTreeMap::new(),
+            input_file_modes: BTreeMap::new(),
+            preserve_mtime: false,
+            preserve_permissions: false,
+            normalize_line_endings: vec![],
+            include_vcs: false,
+            excluded_larger_than: None,
+            excluded_mtime_before: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            expect_outputs: vec![],
+            mount_paths: vec![],
+            mount_readonly: false,
+            ports: vec![],
+            read_only: true,
+            writable_paths: vec![Path::new(DEFAULT_LOCATION).to_owned()],
+            input_location: None,
+            location: Path::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            shell: None,
+            use_entrypoint: false,
+            command: Command::default(),
+            allowed_exit_codes: vec![],
+            extra_docker_arguments: vec![],
+        };
+
+        let result = check_task("foo", &task);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("read_only"));
+    }
 }